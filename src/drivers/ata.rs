@@ -0,0 +1,419 @@
+//! # ATA Disks
+//!
+//! PIO driver for the drives of the two legacy IDE channels, named hda and hdb for the
+//! master and slave of the primary channel and hdc and hdd for the secondary one. Drives are
+//! found with IDENTIFY at boot, then sectors are addressed with LBA28, or LBA48 past the
+//! first 128GB when the drive supports it.
+//!
+//! Every sector of a transfer is announced by an interrupt. The task doing the transfer
+//! sleeps until the handler wakes it up, except the idle task which cannot block and halts
+//! until the interrupt comes instead. Transfers on a channel are serialized by its lock.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use spin::Mutex;
+
+use super::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::exceptions::errno::{Errno, EIO};
+use crate::exceptions::interrupts::{self, InterruptIndex, PICS, TICKS};
+use crate::task::scheduler::{self, IDLE_PID};
+use crate::tools::debug::LogLevel;
+use crate::tools::io::{inb, inw, outb, outw};
+
+// Registers, from the I/O base of the channel
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+// Device control register, at the control base of the channel
+const CONTROL_NO_INTERRUPTS: u8 = 0x02;
+
+const STATUS_ERR: u8 = 0x01;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_DF: u8 = 0x20;
+const STATUS_BSY: u8 = 0x80;
+
+const COMMAND_READ: u8 = 0x20;
+const COMMAND_READ_EXT: u8 = 0x24;
+const COMMAND_WRITE: u8 = 0x30;
+const COMMAND_WRITE_EXT: u8 = 0x34;
+const COMMAND_FLUSH: u8 = 0xe7;
+const COMMAND_FLUSH_EXT: u8 = 0xea;
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+// Sectors LBA28 can address, and the most a command can transfer
+const LBA28_SECTORS: u64 = 1 << 28;
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+
+// Status reads before giving up on a busy drive
+const POLL_LIMIT: u32 = 1_000_000;
+// Timer ticks the idle task waits for an interrupt, about 5 seconds
+const IRQ_TIMEOUT: u32 = 91;
+
+const NO_WAITER: u32 = u32::MAX;
+
+struct Channel {
+	base: u16,
+	control: u16,
+	interrupt: InterruptIndex,
+	lock: Mutex<()>,
+	irq_received: AtomicBool,
+	// Status read by the interrupt handler, which acknowledges the interrupt
+	irq_status: AtomicU8,
+	waiter: AtomicU32,
+}
+
+static CHANNELS: [Channel; 2] = [
+	Channel::new(0x1f0, 0x3f6, InterruptIndex::PrimaryAtaHardDisk),
+	Channel::new(0x170, 0x376, InterruptIndex::SecondaryAtaHardDisk),
+];
+
+impl Channel {
+	const fn new(base: u16, control: u16, interrupt: InterruptIndex) -> Channel {
+		Channel {
+			base,
+			control,
+			interrupt,
+			lock: Mutex::new(()),
+			irq_received: AtomicBool::new(false),
+			irq_status: AtomicU8::new(0),
+			waiter: AtomicU32::new(NO_WAITER),
+		}
+	}
+
+	fn read(&self, register: u16) -> u8 {
+		unsafe { inb(self.base + register) }
+	}
+
+	fn write(&self, register: u16, value: u8) {
+		unsafe { outb(self.base + register, value) }
+	}
+
+	// The alternate status does not acknowledge interrupts, and reading it 4 times gives
+	// the drive the 400ns it needs to update its status after a command
+	fn alternate_status(&self) -> u8 {
+		for _ in 0..4 {
+			unsafe { inb(self.control) };
+		}
+		unsafe { inb(self.control) }
+	}
+
+	fn wait_not_busy(&self) -> Result<u8, Errno> {
+		for _ in 0..POLL_LIMIT {
+			let status = self.alternate_status();
+			if status & STATUS_BSY == 0 {
+				return Ok(status);
+			}
+		}
+		log!(LogLevel::Error, "ATA: drive at {:#x} timed out", self.base);
+		Err(EIO)
+	}
+
+	// Polls until the bits of `mask` are set, EIO on an error or timeout
+	fn poll(&self, mask: u8) -> Result<u8, Errno> {
+		for _ in 0..POLL_LIMIT {
+			let status = self.wait_not_busy()?;
+			if status & (STATUS_ERR | STATUS_DF) != 0 {
+				return Err(self.error(status));
+			}
+			if status & mask == mask {
+				return Ok(status);
+			}
+		}
+		log!(LogLevel::Error, "ATA: drive at {:#x} timed out", self.base);
+		Err(EIO)
+	}
+
+	fn error(&self, status: u8) -> Errno {
+		log!(
+			LogLevel::Error,
+			"ATA: drive at {:#x} failed, status {:#x}, error {:#x}",
+			self.base,
+			status,
+			self.read(REG_ERROR)
+		);
+		EIO
+	}
+
+	// Sleeps until the next interrupt of the channel and returns the status it read.
+	// Interrupts stay disabled between checking for it and sleeping so it cannot be missed.
+	fn wait_irq(&self) -> Result<u8, Errno> {
+		let received = interrupts::without_interrupts(|| {
+			let start = TICKS.load(Ordering::SeqCst);
+			while !self.irq_received.swap(false, Ordering::SeqCst) {
+				let pid = scheduler::current_pid();
+				if pid != IDLE_PID {
+					self.waiter.store(pid, Ordering::SeqCst);
+					scheduler::block();
+					continue;
+				}
+				if TICKS.load(Ordering::SeqCst).wrapping_sub(start) > IRQ_TIMEOUT {
+					return false;
+				}
+				unsafe { core::arch::asm!("sti", "hlt", "cli", options(nomem, nostack)) };
+			}
+			true
+		});
+		self.waiter.store(NO_WAITER, Ordering::SeqCst);
+		if !received {
+			log!(LogLevel::Error, "ATA: no interrupt from the drive at {:#x}", self.base);
+			return Err(EIO);
+		}
+		let status = self.irq_status.load(Ordering::SeqCst);
+		if status & (STATUS_ERR | STATUS_DF) != 0 {
+			return Err(self.error(status));
+		}
+		Ok(status)
+	}
+
+	fn handle_irq(&self) {
+		self.irq_status.store(self.read(REG_STATUS), Ordering::SeqCst);
+		self.irq_received.store(true, Ordering::SeqCst);
+		let waiter = self.waiter.load(Ordering::SeqCst);
+		if waiter != NO_WAITER {
+			scheduler::wake(waiter);
+		}
+	}
+}
+
+pub struct AtaDrive {
+	name: String,
+	channel: &'static Channel,
+	slave: bool,
+	lba48: bool,
+	sectors: u64,
+}
+
+impl AtaDrive {
+	// Loads the registers addressing `count` sectors from `sector` and sends the `lba28`
+	// command, or `lba48` when the sectors are past what LBA28 reaches
+	fn command(&self, sector: u64, count: usize, lba28: u8, lba48: u8) -> Result<(), Errno> {
+		let channel = self.channel;
+		let slave = (self.slave as u8) << 4;
+		channel.wait_not_busy()?;
+		channel.irq_received.store(false, Ordering::SeqCst);
+		if sector + count as u64 <= LBA28_SECTORS {
+			channel.write(REG_DRIVE, 0xe0 | slave | ((sector >> 24) as u8 & 0x0f));
+			channel.write(REG_SECTOR_COUNT, count as u8);
+			channel.write(REG_LBA_LOW, sector as u8);
+			channel.write(REG_LBA_MID, (sector >> 8) as u8);
+			channel.write(REG_LBA_HIGH, (sector >> 16) as u8);
+			channel.write(REG_COMMAND, lba28);
+		} else {
+			// The high bytes go first, the registers are two deep
+			channel.write(REG_DRIVE, 0x40 | slave);
+			channel.write(REG_SECTOR_COUNT, (count >> 8) as u8);
+			channel.write(REG_LBA_LOW, (sector >> 24) as u8);
+			channel.write(REG_LBA_MID, (sector >> 32) as u8);
+			channel.write(REG_LBA_HIGH, (sector >> 40) as u8);
+			channel.write(REG_SECTOR_COUNT, count as u8);
+			channel.write(REG_LBA_LOW, sector as u8);
+			channel.write(REG_LBA_MID, (sector >> 8) as u8);
+			channel.write(REG_LBA_HIGH, (sector >> 16) as u8);
+			channel.write(REG_COMMAND, lba48);
+		}
+		Ok(())
+	}
+
+	fn read_chunk(&self, sector: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+		self.command(sector, buffer.len() / SECTOR_SIZE, COMMAND_READ, COMMAND_READ_EXT)?;
+		for chunk in buffer.chunks_exact_mut(SECTOR_SIZE) {
+			self.channel.wait_irq()?;
+			for word in chunk.chunks_exact_mut(2) {
+				word.copy_from_slice(&unsafe { inw(self.channel.base + REG_DATA) }.to_le_bytes());
+			}
+		}
+		Ok(())
+	}
+
+	// The drive asks for the first sector with DRQ, for the next ones and to tell it is
+	// done with an interrupt
+	fn write_chunk(&self, sector: u64, buffer: &[u8]) -> Result<(), Errno> {
+		self.command(sector, buffer.len() / SECTOR_SIZE, COMMAND_WRITE, COMMAND_WRITE_EXT)?;
+		for (index, chunk) in buffer.chunks_exact(SECTOR_SIZE).enumerate() {
+			if index == 0 {
+				self.channel.poll(STATUS_DRQ)?;
+			} else {
+				self.channel.wait_irq()?;
+			}
+			for word in chunk.chunks_exact(2) {
+				unsafe { outw(self.channel.base + REG_DATA, u16::from_le_bytes([word[0], word[1]])) };
+			}
+		}
+		self.channel.wait_irq()?;
+		Ok(())
+	}
+}
+
+impl BlockDevice for AtaDrive {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn sector_size(&self) -> usize {
+		SECTOR_SIZE
+	}
+
+	fn sector_count(&self) -> u64 {
+		self.sectors
+	}
+
+	fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+		block::check_range(self, sector, buffer.len())?;
+		let _guard = self.channel.lock.lock();
+		let mut sector = sector;
+		for chunk in buffer.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+			self.read_chunk(sector, chunk)?;
+			sector += (chunk.len() / SECTOR_SIZE) as u64;
+		}
+		Ok(())
+	}
+
+	fn write_sectors(&self, sector: u64, buffer: &[u8]) -> Result<(), Errno> {
+		block::check_range(self, sector, buffer.len())?;
+		let _guard = self.channel.lock.lock();
+		let mut sector = sector;
+		for chunk in buffer.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+			self.write_chunk(sector, chunk)?;
+			sector += (chunk.len() / SECTOR_SIZE) as u64;
+		}
+		Ok(())
+	}
+
+	fn flush(&self) -> Result<(), Errno> {
+		let _guard = self.channel.lock.lock();
+		let channel = self.channel;
+		channel.wait_not_busy()?;
+		channel.irq_received.store(false, Ordering::SeqCst);
+		channel.write(REG_DRIVE, 0xe0 | ((self.slave as u8) << 4));
+		channel.write(REG_COMMAND, if self.lba48 { COMMAND_FLUSH_EXT } else { COMMAND_FLUSH });
+		channel.wait_irq().map(drop)
+	}
+}
+
+// Model name from IDENTIFY words 27 to 46, stored with the bytes of each word swapped
+fn model_name(identify: &[u16; 256]) -> String {
+	let mut model = String::new();
+	for word in &identify[27..47] {
+		model.push((word >> 8) as u8 as char);
+		model.push((word & 0xff) as u8 as char);
+	}
+	String::from(model.trim())
+}
+
+// Sends IDENTIFY to a drive of `channel` with its interrupts off, None if there is no ATA
+// drive there
+fn identify(channel: &'static Channel, slave: bool) -> Option<AtaDrive> {
+	channel.write(REG_DRIVE, 0xa0 | ((slave as u8) << 4));
+	channel.alternate_status();
+	for register in [REG_SECTOR_COUNT, REG_LBA_LOW, REG_LBA_MID, REG_LBA_HIGH] {
+		channel.write(register, 0);
+	}
+	channel.write(REG_COMMAND, COMMAND_IDENTIFY);
+	// No drive, or no controller at all with a floating bus
+	let status = channel.alternate_status();
+	if status == 0 || status == 0xff {
+		return None;
+	}
+	channel.wait_not_busy().ok()?;
+	// ATAPI and SATA devices set a signature here and abort the command
+	if channel.read(REG_LBA_MID) != 0 || channel.read(REG_LBA_HIGH) != 0 {
+		return None;
+	}
+	channel.poll(STATUS_DRQ).ok()?;
+
+	let mut data = [0; 256];
+	for word in data.iter_mut() {
+		*word = unsafe { inw(channel.base + REG_DATA) };
+	}
+	let lba48 = data[83] & (1 << 10) != 0;
+	let sectors = match lba48 {
+		true => data[100..104]
+			.iter()
+			.rev()
+			.fold(0, |sectors, &word| (sectors << 16) | word as u64),
+		false => ((data[61] as u64) << 16) | data[60] as u64,
+	};
+	let index = CHANNELS.iter().position(|other| core::ptr::eq(other, channel))? * 2 + slave as usize;
+	let name = format!("hd{}", (b'a' + index as u8) as char);
+	log!(
+		LogLevel::Info,
+		"ATA: {} is {}, {} sectors{}",
+		name,
+		model_name(&data),
+		sectors,
+		if lba48 { " with LBA48" } else { "" }
+	);
+	Some(AtaDrive {
+		name,
+		channel,
+		slave,
+		lba48,
+		sectors,
+	})
+}
+
+// Called by the interrupt handlers of both channels
+pub fn handle_irq(interrupt: InterruptIndex) {
+	if let Some(channel) = CHANNELS
+		.iter()
+		.find(|channel| channel.interrupt.as_u8() == interrupt.as_u8())
+	{
+		channel.handle_irq();
+	}
+}
+
+// Looks for drives on both channels and registers them as block devices
+pub fn init() {
+	for channel in CHANNELS.iter() {
+		unsafe { outb(channel.control, CONTROL_NO_INTERRUPTS) };
+		let mut found = false;
+		for slave in [false, true] {
+			if let Some(drive) = identify(channel, slave) {
+				block::register(Arc::new(drive));
+				found = true;
+			}
+		}
+		if found {
+			unsafe {
+				outb(channel.control, 0);
+				PICS.lock().unmask(channel.interrupt.as_u8());
+			}
+		}
+	}
+}
+
+pub fn ata_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the ATA driver\n");
+
+	let Some(disk) = block::find("hda") else {
+		log!(LogLevel::Info, "No disk on hda, run qemu with -hda to test the driver\n");
+		return;
+	};
+
+	// The disk is the user's, only reads are tried on it
+	log!(LogLevel::Info, "Reads should be stable\n");
+	let Some(last) = disk.sector_count().checked_sub(2) else {
+		log!(LogLevel::Info, "The disk on hda is too small to test the driver\n");
+		return;
+	};
+	let mut first = [0; 2 * SECTOR_SIZE];
+	disk.read_sectors(last, &mut first).expect("read failed");
+	let mut again = [0; 2 * SECTOR_SIZE];
+	disk.read_sectors(last, &mut again).expect("read failed");
+	assert!(first == again);
+
+	log!(LogLevel::Info, "Transfers past the end of the disk should fail\n");
+	assert!(disk.read_sectors(last, &mut [0; 3 * SECTOR_SIZE]).is_err());
+	assert!(disk.read_sectors(0, &mut [0; 100]).is_err());
+	log!(LogLevel::Info, "\t\tEnd of ATA driver test\n");
+}
@@ -0,0 +1,99 @@
+//! # Block Devices
+//!
+//! Disks are read and written by whole sectors through the `BlockDevice` trait. Drivers
+//! register the devices they find under a name such as "hda" for filesystems to look them
+//! up. `RamDisk` keeps its sectors in memory, for tests and images built at runtime.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::exceptions::errno::{Errno, EINVAL};
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub trait BlockDevice: Send + Sync {
+	fn name(&self) -> &str;
+
+	// Size of a sector in bytes, transfers are made of whole sectors
+	fn sector_size(&self) -> usize;
+
+	fn sector_count(&self) -> u64;
+
+	// Reads the sectors from `sector` on into `buffer`, whose length is a multiple of the
+	// sector size
+	fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> Result<(), Errno>;
+
+	fn write_sectors(&self, sector: u64, buffer: &[u8]) -> Result<(), Errno>;
+
+	// Waits until what was written reaches the medium
+	fn flush(&self) -> Result<(), Errno> {
+		Ok(())
+	}
+}
+
+// EINVAL unless `length` bytes from `sector` are whole sectors inside `device`
+pub fn check_range(device: &dyn BlockDevice, sector: u64, length: usize) -> Result<u64, Errno> {
+	let sector_size = device.sector_size();
+	if length % sector_size != 0 {
+		return Err(EINVAL);
+	}
+	let count = (length / sector_size) as u64;
+	match sector.checked_add(count) {
+		Some(end) if end <= device.sector_count() => Ok(count),
+		_ => Err(EINVAL),
+	}
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+pub fn register(device: Arc<dyn BlockDevice>) {
+	DEVICES.lock().push(device);
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+	DEVICES.lock().iter().find(|device| device.name() == name).cloned()
+}
+
+pub struct RamDisk {
+	name: String,
+	data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+	pub fn new(name: &str, sectors: usize) -> Arc<RamDisk> {
+		Arc::new(RamDisk {
+			name: String::from(name),
+			data: Mutex::new(alloc::vec![0; sectors * SECTOR_SIZE]),
+		})
+	}
+}
+
+impl BlockDevice for RamDisk {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn sector_size(&self) -> usize {
+		SECTOR_SIZE
+	}
+
+	fn sector_count(&self) -> u64 {
+		(self.data.lock().len() / SECTOR_SIZE) as u64
+	}
+
+	fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+		check_range(self, sector, buffer.len())?;
+		let start = sector as usize * SECTOR_SIZE;
+		buffer.copy_from_slice(&self.data.lock()[start..start + buffer.len()]);
+		Ok(())
+	}
+
+	fn write_sectors(&self, sector: u64, buffer: &[u8]) -> Result<(), Errno> {
+		check_range(self, sector, buffer.len())?;
+		let start = sector as usize * SECTOR_SIZE;
+		self.data.lock()[start..start + buffer.len()].copy_from_slice(buffer);
+		Ok(())
+	}
+}
@@ -0,0 +1,63 @@
+//! # Character Devices
+//!
+//! Character devices are streams of bytes, or windows on memory for `/dev/mem`, driven
+//! through the same `FileOps` as any open file. Drivers register them under a name and a
+//! major/minor device number, and devfs shows every registered device in `/dev`. The major
+//! number picks the driver and the minor number the device it drives, as on Linux.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::exceptions::errno::{Errno, EEXIST};
+use crate::fs::file::FileOps;
+use crate::fs::inode::make_dev;
+
+#[derive(Clone)]
+pub struct CharDevice {
+	pub name: String,
+	pub rdev: u32,
+	// Permission bits of the device node
+	pub mode: u32,
+	pub ops: Arc<dyn FileOps>,
+}
+
+static DEVICES: Mutex<Vec<CharDevice>> = Mutex::new(Vec::new());
+
+// Registers `ops` as the device `major`:`minor`, EEXIST if the name or the number is taken
+pub fn register(
+	name: &str,
+	major: u32,
+	minor: u32,
+	mode: u32,
+	ops: Arc<dyn FileOps>,
+) -> Result<(), Errno> {
+	let rdev = make_dev(major, minor);
+	let mut devices = DEVICES.lock();
+	if devices
+		.iter()
+		.any(|device| device.name == name || device.rdev == rdev)
+	{
+		return Err(EEXIST);
+	}
+	devices.push(CharDevice {
+		name: String::from(name),
+		rdev,
+		mode,
+		ops,
+	});
+	Ok(())
+}
+
+// Device called `name` and its index in registration order
+pub fn find(name: &str) -> Option<(usize, CharDevice)> {
+	let devices = DEVICES.lock();
+	let index = devices.iter().position(|device| device.name == name)?;
+	Some((index, devices[index].clone()))
+}
+
+// Device `index` in registration order
+pub fn get(index: usize) -> Option<CharDevice> {
+	DEVICES.lock().get(index).cloned()
+}
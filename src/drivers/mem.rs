@@ -0,0 +1,150 @@
+//! # Memory Devices
+//!
+//! The devices of major 1: `/dev/mem` reads and writes physical memory for debugging,
+//! `/dev/null` discards what is written and is always at end of file, `/dev/zero` reads
+//! as zeros and `/dev/random` as a xorshift stream that gets stirred with the time stamp
+//! counter and with whatever is written to it.
+
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::chrdev;
+use crate::exceptions::errno::Errno;
+use crate::fs::file::{File, FileOps};
+use crate::memory::kmem_managment::{HK_OFST, LOWMEM_END, PMM};
+use crate::tools::librs::rdtsc;
+
+const MEM_MAJOR: u32 = 1;
+const MEM_MINOR: u32 = 1;
+const NULL_MINOR: u32 = 3;
+const ZERO_MINOR: u32 = 5;
+const RANDOM_MINOR: u32 = 8;
+
+struct Mem;
+
+impl Mem {
+	// Part of `length` bytes from physical address `offset` that is mapped at HK_OFST
+	fn range(offset: u64, length: usize) -> Option<(usize, usize)> {
+		let end = PMM.lock().memory_size.min(LOWMEM_END) as u64;
+		if offset >= end {
+			return None;
+		}
+		let start = offset as usize;
+		Some((start, length.min((end - offset) as usize)))
+	}
+}
+
+impl FileOps for Mem {
+	fn read(&self, file: &File, buffer: &mut [u8]) -> Result<usize, Errno> {
+		let mut offset = file.offset();
+		let Some((start, length)) = Mem::range(*offset, buffer.len()) else {
+			return Ok(0);
+		};
+		let source = (start as u32 + HK_OFST) as *const u8;
+		unsafe { core::ptr::copy_nonoverlapping(source, buffer.as_mut_ptr(), length) };
+		*offset += length as u64;
+		Ok(length)
+	}
+
+	fn write(&self, file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		let mut offset = file.offset();
+		let Some((start, length)) = Mem::range(*offset, buffer.len()) else {
+			return Ok(0);
+		};
+		let destination = (start as u32 + HK_OFST) as *mut u8;
+		unsafe { core::ptr::copy_nonoverlapping(buffer.as_ptr(), destination, length) };
+		*offset += length as u64;
+		Ok(length)
+	}
+
+	fn seekable(&self) -> bool {
+		true
+	}
+}
+
+struct Null;
+
+impl FileOps for Null {
+	fn read(&self, _file: &File, _buffer: &mut [u8]) -> Result<usize, Errno> {
+		Ok(0)
+	}
+
+	fn write(&self, _file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		Ok(buffer.len())
+	}
+
+	// lseek succeeds and the offset stays at 0, as on Linux
+	fn seekable(&self) -> bool {
+		true
+	}
+}
+
+struct Zero;
+
+impl FileOps for Zero {
+	fn read(&self, _file: &File, buffer: &mut [u8]) -> Result<usize, Errno> {
+		buffer.fill(0);
+		Ok(buffer.len())
+	}
+
+	fn write(&self, _file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		Ok(buffer.len())
+	}
+
+	fn seekable(&self) -> bool {
+		true
+	}
+}
+
+// Not an entropy pool, only unpredictable enough for tests and seeds
+struct Random {
+	state: Mutex<u64>,
+}
+
+impl Random {
+	fn stir(state: &mut u64, value: u64) {
+		*state ^= value.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+		if *state == 0 {
+			*state = 0x9e37_79b9_7f4a_7c15;
+		}
+	}
+}
+
+impl FileOps for Random {
+	fn read(&self, _file: &File, buffer: &mut [u8]) -> Result<usize, Errno> {
+		let mut state = self.state.lock();
+		Random::stir(&mut state, rdtsc());
+		for chunk in buffer.chunks_mut(8) {
+			*state ^= *state << 13;
+			*state ^= *state >> 7;
+			*state ^= *state << 17;
+			chunk.copy_from_slice(&state.to_le_bytes()[..chunk.len()]);
+		}
+		Ok(buffer.len())
+	}
+
+	fn write(&self, _file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		let mut state = self.state.lock();
+		for chunk in buffer.chunks(8) {
+			let mut bytes = [0; 8];
+			bytes[..chunk.len()].copy_from_slice(chunk);
+			Random::stir(&mut state, u64::from_le_bytes(bytes));
+		}
+		Ok(buffer.len())
+	}
+}
+
+pub fn init() {
+	let random = Arc::new(Random {
+		state: Mutex::new(0),
+	});
+	let devices: [(&str, u32, u32, Arc<dyn FileOps>); 4] = [
+		("mem", MEM_MINOR, 0o640, Arc::new(Mem)),
+		("null", NULL_MINOR, 0o666, Arc::new(Null)),
+		("zero", ZERO_MINOR, 0o666, Arc::new(Zero)),
+		("random", RANDOM_MINOR, 0o666, random),
+	];
+	for (name, minor, mode, ops) in devices {
+		chrdev::register(name, MEM_MAJOR, minor, mode, ops).expect("Memory device taken");
+	}
+}
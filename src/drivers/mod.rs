@@ -0,0 +1,6 @@
+pub mod ata;
+pub mod block;
+pub mod chrdev;
+pub mod mem;
+pub mod serial;
+pub mod tty;
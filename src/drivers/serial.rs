@@ -0,0 +1,404 @@
+//! # Serial Port
+//!
+//! Interrupt driven driver for the 16550 UART of COM1. Received bytes are queued by the
+//! IRQ 4 handler until someone reads them, and written bytes are queued until the transmit
+//! FIFO has room, the handler refilling it each time it runs empty. Writes made with
+//! interrupts disabled cannot wait for the handler and push the queue out by polling.
+//!
+//! The kernel log keeps writing to the port by polling through `tools::debug`, which works
+//! before this driver is up and in a panic. With `console=ttyS0` on the command line the
+//! shell is also run over COM1, see `shell::serial`, and only errors are logged there.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::exceptions::errno::{Errno, EINVAL};
+use crate::exceptions::interrupts::{self, InterruptIndex, PICS};
+use crate::multiboot;
+use crate::tools::debug::LogLevel;
+use crate::tools::io::{inb, outb};
+use crate::tools::irqlock::IrqMutex;
+use crate::tools::ring_buffer::RingBuffer;
+
+const COM1: u16 = 0x3f8;
+
+// Registers, from the I/O base of the port
+const REG_DATA: u16 = 0;
+const REG_INTERRUPT_ENABLE: u16 = 1;
+const REG_INTERRUPT_ID: u16 = 2;
+const REG_FIFO_CONTROL: u16 = 2;
+const REG_LINE_CONTROL: u16 = 3;
+const REG_MODEM_CONTROL: u16 = 4;
+const REG_LINE_STATUS: u16 = 5;
+const REG_MODEM_STATUS: u16 = 6;
+// With the divisor latch access bit set in the line control register
+const REG_DIVISOR_LOW: u16 = 0;
+const REG_DIVISOR_HIGH: u16 = 1;
+
+const IER_RECEIVED: u8 = 0x01;
+const IER_TRANSMIT_EMPTY: u8 = 0x02;
+const IER_LINE_STATUS: u8 = 0x04;
+
+const IIR_NO_INTERRUPT: u8 = 0x01;
+const IIR_ID_MASK: u8 = 0x0e;
+const IIR_MODEM_STATUS: u8 = 0x00;
+const IIR_TRANSMIT_EMPTY: u8 = 0x02;
+const IIR_RECEIVED: u8 = 0x04;
+const IIR_LINE_STATUS: u8 = 0x06;
+const IIR_TIMEOUT: u8 = 0x0c;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+
+// Enables and clears both FIFOs, interrupts come once 14 bytes are received
+const FCR_ENABLE: u8 = 0xc7;
+
+const LCR_PARITY_ENABLE: u8 = 0x08;
+const LCR_PARITY_EVEN: u8 = 0x10;
+const LCR_DIVISOR_LATCH: u8 = 0x80;
+
+const MCR_DTR: u8 = 0x01;
+const MCR_RTS: u8 = 0x02;
+// Routes the interrupt of the UART to the PIC on PCs
+const MCR_OUT2: u8 = 0x08;
+const MCR_LOOPBACK: u8 = 0x10;
+
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+
+const CLOCK_RATE: u32 = 115200;
+const FIFO_SIZE: usize = 16;
+const RX_BUFFER_SIZE: usize = 1024;
+const TX_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+	None,
+	Odd,
+	Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+	pub baud: u32,
+	// 5 to 8
+	pub data_bits: u8,
+	pub parity: Parity,
+	// 1 or 2
+	pub stop_bits: u8,
+}
+
+impl LineConfig {
+	pub const DEFAULT: LineConfig = LineConfig {
+		baud: 38400,
+		data_bits: 8,
+		parity: Parity::None,
+		stop_bits: 1,
+	};
+
+	// Divisor of the UART clock and line control register, EINVAL for what the UART
+	// cannot do
+	fn registers(&self) -> Result<(u16, u8), Errno> {
+		if self.baud == 0 || CLOCK_RATE % self.baud != 0 {
+			return Err(EINVAL);
+		}
+		if !(5..=8).contains(&self.data_bits) || !(1..=2).contains(&self.stop_bits) {
+			return Err(EINVAL);
+		}
+		let mut line_control = (self.data_bits - 5) | ((self.stop_bits - 1) << 2);
+		match self.parity {
+			Parity::None => {}
+			Parity::Odd => line_control |= LCR_PARITY_ENABLE,
+			Parity::Even => line_control |= LCR_PARITY_ENABLE | LCR_PARITY_EVEN,
+		}
+		Ok(((CLOCK_RATE / self.baud) as u16, line_control))
+	}
+}
+
+struct Port {
+	base: u16,
+	// Bytes the transmitter takes at once, 1 without a working FIFO
+	fifo_size: usize,
+	interrupt_enable: u8,
+	config: LineConfig,
+	rx: RingBuffer<RX_BUFFER_SIZE>,
+	tx: RingBuffer<TX_BUFFER_SIZE>,
+	// Received bytes lost because nobody read the queue in time
+	dropped: usize,
+	ready: bool,
+}
+
+static PORT: IrqMutex<Port> = IrqMutex::new(Port::new(COM1));
+
+// Whether the shell also reads and writes on COM1
+static CONSOLE: AtomicBool = AtomicBool::new(false);
+
+impl Port {
+	const fn new(base: u16) -> Port {
+		Port {
+			base,
+			fifo_size: 1,
+			interrupt_enable: 0,
+			config: LineConfig::DEFAULT,
+			rx: RingBuffer::new(),
+			tx: RingBuffer::new(),
+			dropped: 0,
+			ready: false,
+		}
+	}
+
+	fn read(&self, register: u16) -> u8 {
+		unsafe { inb(self.base + register) }
+	}
+
+	fn write(&self, register: u16, value: u8) {
+		unsafe { outb(self.base + register, value) }
+	}
+
+	fn set_interrupts(&mut self, interrupt_enable: u8) {
+		self.interrupt_enable = interrupt_enable;
+		self.write(REG_INTERRUPT_ENABLE, interrupt_enable);
+	}
+
+	fn apply(&mut self, config: LineConfig) -> Result<(), Errno> {
+		let (divisor, line_control) = config.registers()?;
+		self.flush();
+		self.write(REG_LINE_CONTROL, LCR_DIVISOR_LATCH);
+		self.write(REG_DIVISOR_LOW, divisor as u8);
+		self.write(REG_DIVISOR_HIGH, (divisor >> 8) as u8);
+		self.write(REG_LINE_CONTROL, line_control);
+		self.config = config;
+		Ok(())
+	}
+
+	fn receive(&mut self) {
+		while self.read(REG_LINE_STATUS) & LSR_DATA_READY != 0 {
+			let byte = self.read(REG_DATA);
+			if !self.rx.push(byte) {
+				self.dropped += 1;
+			}
+		}
+	}
+
+	// Refills the transmit FIFO if it is empty, and asks for an interrupt when it is empty
+	// again as long as there is more to send
+	fn transmit(&mut self) {
+		if self.read(REG_LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0 {
+			for _ in 0..self.fifo_size {
+				match self.tx.pop() {
+					Some(byte) => self.write(REG_DATA, byte),
+					None => break,
+				}
+			}
+		}
+		let interrupt_enable = match self.tx.is_empty() {
+			true => self.interrupt_enable & !IER_TRANSMIT_EMPTY,
+			false => self.interrupt_enable | IER_TRANSMIT_EMPTY,
+		};
+		if self.ready && interrupt_enable != self.interrupt_enable {
+			self.set_interrupts(interrupt_enable);
+		}
+	}
+
+	// Sends everything queued by polling the transmitter
+	fn flush(&mut self) {
+		while !self.tx.is_empty() {
+			self.transmit();
+		}
+		while self.read(REG_LINE_STATUS) & LSR_TRANSMIT_EMPTY == 0 {}
+	}
+
+	fn handle_irq(&mut self) {
+		loop {
+			let interrupt_id = self.read(REG_INTERRUPT_ID);
+			if interrupt_id & IIR_NO_INTERRUPT != 0 {
+				break;
+			}
+			match interrupt_id & IIR_ID_MASK {
+				IIR_RECEIVED | IIR_TIMEOUT => self.receive(),
+				IIR_TRANSMIT_EMPTY => self.transmit(),
+				IIR_LINE_STATUS => {
+					self.read(REG_LINE_STATUS);
+				}
+				IIR_MODEM_STATUS => {
+					self.read(REG_MODEM_STATUS);
+				}
+				_ => break,
+			}
+		}
+	}
+}
+
+pub fn init() {
+	let mut port = PORT.lock();
+	port.set_interrupts(0);
+	port.apply(LineConfig::DEFAULT)
+		.expect("Invalid default serial configuration");
+	port.write(REG_FIFO_CONTROL, FCR_ENABLE);
+	// Only the 16550A reports both FIFO bits, the FIFO of the plain 16550 is broken
+	if port.read(REG_INTERRUPT_ID) & IIR_FIFO_ENABLED == IIR_FIFO_ENABLED {
+		port.fifo_size = FIFO_SIZE;
+	}
+	port.write(REG_MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+	// Drop whatever arrived before and clear pending line and modem status
+	port.receive();
+	port.rx.clear();
+	port.read(REG_LINE_STATUS);
+	port.read(REG_MODEM_STATUS);
+	port.set_interrupts(IER_RECEIVED | IER_LINE_STATUS);
+	port.ready = true;
+	let fifo_size = port.fifo_size;
+	drop(port);
+
+	unsafe {
+		PICS.lock().unmask(InterruptIndex::Com1.as_u8());
+	}
+	let console = multiboot::cmdline()
+		.split(' ')
+		.any(|argument| argument == "console=ttyS0");
+	CONSOLE.store(console, Ordering::Relaxed);
+	log!(
+		LogLevel::Info,
+		"COM1 at {} baud with a {} byte FIFO{}",
+		LineConfig::DEFAULT.baud,
+		fifo_size,
+		if console {
+			", serial console enabled"
+		} else {
+			""
+		}
+	);
+}
+
+pub fn handle_irq() {
+	PORT.lock().handle_irq();
+}
+
+pub fn is_console() -> bool {
+	CONSOLE.load(Ordering::Relaxed)
+}
+
+pub fn config() -> LineConfig {
+	PORT.lock().config
+}
+
+// Changes the speed and framing, once what was queued has been sent with the old ones
+pub fn configure(config: LineConfig) -> Result<(), Errno> {
+	PORT.lock().apply(config)
+}
+
+// Moves received bytes to `buffer`, returns how many there were
+pub fn read(buffer: &mut [u8]) -> usize {
+	let mut port = PORT.lock();
+	let mut read = 0;
+	while read < buffer.len() {
+		match port.rx.pop() {
+			Some(byte) => buffer[read] = byte,
+			None => break,
+		}
+		read += 1;
+	}
+	read
+}
+
+// Queues `bytes` for sending, pushing the queue out by polling when it is full or when the
+// interrupt cannot come
+pub fn write(bytes: &[u8]) {
+	let synchronous = !interrupts::are_enabled();
+	let mut port = PORT.lock();
+	for &byte in bytes {
+		if !port.tx.push(byte) {
+			port.flush();
+			port.tx.push(byte);
+		}
+	}
+	match synchronous || !port.ready {
+		true => port.flush(),
+		false => port.transmit(),
+	}
+}
+
+// Output of the shell on the serial console, with the line endings of a terminal
+pub struct ConsoleWriter;
+
+impl fmt::Write for ConsoleWriter {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for (index, line) in s.split('\n').enumerate() {
+			if index > 0 {
+				write(b"\r\n");
+			}
+			write(line.as_bytes());
+		}
+		Ok(())
+	}
+}
+
+pub fn serial_test() {
+	use crate::exceptions::interrupts::TICKS;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the serial driver\n");
+
+	log!(
+		LogLevel::Info,
+		"Configurations the UART cannot do should be refused\n"
+	);
+	let default = config();
+	for config in [
+		LineConfig { baud: 7, ..default },
+		LineConfig { baud: 0, ..default },
+		LineConfig {
+			data_bits: 9,
+			..default
+		},
+		LineConfig {
+			stop_bits: 3,
+			..default
+		},
+	] {
+		assert!(configure(config) == Err(EINVAL));
+	}
+	assert!(config() == default);
+	let slow = LineConfig {
+		baud: 9600,
+		parity: Parity::Even,
+		..default
+	};
+	assert!(slow.registers() == Ok((12, 0x1b)));
+	let odd = LineConfig {
+		data_bits: 7,
+		parity: Parity::Odd,
+		stop_bits: 2,
+		..default
+	};
+	assert!(odd.registers() == Ok((3, 0x0e)));
+
+	// Nothing may be logged in loopback mode, the log would be looped back too
+	log!(
+		LogLevel::Info,
+		"Bytes sent in loopback mode should be received\n"
+	);
+	let mut received = [0; 64];
+	let message = b"The quick brown fox jumps over the lazy dog, twice over the fifo";
+	{
+		let port = PORT.lock();
+		port.write(
+			REG_MODEM_CONTROL,
+			MCR_DTR | MCR_RTS | MCR_OUT2 | MCR_LOOPBACK,
+		);
+	}
+	read(&mut received);
+	let mut done = 0;
+	write(message);
+	let start = TICKS.load(Ordering::SeqCst);
+	while done < message.len() && TICKS.load(Ordering::SeqCst).wrapping_sub(start) < 18 {
+		// The interrupt line may be cut in loopback mode, handle the UART by hand as well
+		handle_irq();
+		done += read(&mut received[done..]);
+	}
+	{
+		let port = PORT.lock();
+		port.write(REG_MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+	}
+	assert!(&received[..done] == message);
+	assert!(PORT.lock().dropped == 0);
+	log!(LogLevel::Info, "\t\tEnd of serial driver test\n");
+}
@@ -0,0 +1,791 @@
+//! # Terminals
+//!
+//! The terminals of major 4: `/dev/tty0` to `/dev/tty4` are the VGA screens switched with
+//! F1 to F5, `/dev/ttyS0` is COM1. Output to a screen that is not shown lands in its saved
+//! rows and appears when the user switches to it.
+//!
+//! Every terminal runs the input it gets through a line discipline set up with the termios
+//! ioctls. In canonical mode the line is edited here with the erase, word erase and kill
+//! characters and readers get it once it is complete, in raw mode they get the bytes as they
+//! come. The interrupt, quit and suspend characters signal the foreground process group of
+//! the terminal, and tasks of its session outside that group get SIGTTIN when they read and
+//! SIGTTOU when they change the settings, or write with TOSTOP set.
+//!
+//! Keystrokes and serial bytes only reach a terminal while a task reads it or its foreground
+//! group is alive, the kernel shell gets them otherwise.
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::{chrdev, serial};
+use crate::exceptions::errno::{Errno, EAGAIN, EINTR, EINVAL, EIO, ENOTTY, ENXIO, EPERM, ESRCH};
+use crate::exceptions::interrupts;
+use crate::fs::file::{File, FileOps};
+use crate::memory::uaccess;
+use crate::task::scheduler::{self, WaitTarget, IDLE_PID};
+use crate::task::signal::{self, SIGCONT, SIGHUP, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU};
+use crate::task::task::{Pid, TaskState};
+use crate::tools::debug::LogLevel;
+use crate::tools::irqlock::IrqMutex;
+use crate::tools::vga::{self, NUM_SCREENS, VGA_COLUMNS, VGA_ROWS, WRITER};
+
+const TTY_MAJOR: u32 = 4;
+const SERIAL_MINOR: u32 = 64;
+
+// Index of ttyS0, the screens come first
+pub const SERIAL_TTY: usize = NUM_SCREENS;
+
+// Longest line in canonical mode and most bytes waiting for readers, as on Linux
+const MAX_CANON: usize = 255;
+const MAX_INPUT: usize = 4096;
+
+// ioctl requests, with the numbers of Linux
+pub const TCGETS: u32 = 0x5401;
+pub const TCSETS: u32 = 0x5402;
+pub const TCSETSW: u32 = 0x5403;
+pub const TCSETSF: u32 = 0x5404;
+pub const TIOCSCTTY: u32 = 0x540e;
+pub const TIOCGPGRP: u32 = 0x540f;
+pub const TIOCSPGRP: u32 = 0x5410;
+pub const TIOCGWINSZ: u32 = 0x5413;
+pub const FIONREAD: u32 = 0x541b;
+pub const TIOCNOTTY: u32 = 0x5422;
+pub const TIOCGSID: u32 = 0x5429;
+pub const VT_ACTIVATE: u32 = 0x5606;
+
+// c_iflag
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+
+// c_oflag
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+
+// c_cflag, only kept for the programs that read it back
+const B38400: u32 = 0o17;
+const CS8: u32 = 0o60;
+const CREAD: u32 = 0o200;
+
+// c_lflag
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const TOSTOP: u32 = 0o400;
+pub const ECHOCTL: u32 = 0o1000;
+pub const IEXTEN: u32 = 0o100000;
+
+// Indexes in c_cc, a character set to 0 is disabled
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+const NCCS: usize = 19;
+
+// struct termios of the Linux i386 ioctls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Termios {
+	pub iflag: u32,
+	pub oflag: u32,
+	pub cflag: u32,
+	pub lflag: u32,
+	pub line: u8,
+	pub cc: [u8; NCCS],
+}
+
+impl Termios {
+	// Settings of a terminal that was just opened: canonical mode with echo and signals
+	const DEFAULT: Termios = Termios {
+		iflag: ICRNL,
+		oflag: OPOST | ONLCR,
+		cflag: B38400 | CS8 | CREAD,
+		lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | IEXTEN,
+		line: 0,
+		cc: [
+			0x03, 0x1c, 0x7f, 0x15, 0x04, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0x0f, 0x17, 0x16, 0,
+			0, 0,
+		],
+	};
+
+	fn is_set(&self, character: usize, byte: u8) -> bool {
+		self.cc[character] != 0 && self.cc[character] == byte
+	}
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct WinSize {
+	rows: u16,
+	columns: u16,
+	x_pixels: u16,
+	y_pixels: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Device {
+	Screen(usize),
+	Serial,
+}
+
+impl Device {
+	fn write(&self, bytes: &[u8]) {
+		match *self {
+			Device::Screen(display) => WRITER.lock().write_display(display, bytes),
+			Device::Serial => serial::write(bytes),
+		}
+	}
+
+	fn window_size(&self) -> WinSize {
+		let (rows, columns) = match self {
+			Device::Screen(_) => (VGA_ROWS, VGA_COLUMNS),
+			// The other end decides, report the usual size of a terminal
+			Device::Serial => (24, 80),
+		};
+		WinSize {
+			rows: rows as u16,
+			columns: columns as u16,
+			x_pixels: 0,
+			y_pixels: 0,
+		}
+	}
+}
+
+struct Tty {
+	device: Device,
+	termios: Termios,
+	// Bytes readers can take. In canonical mode they only get the completed lines.
+	input: VecDeque<u8>,
+	// Length from the start of `input` to the end of each completed line. A line ended by
+	// the end of file character has no byte for it, so an empty one reads as end of file.
+	line_ends: VecDeque<usize>,
+	// Line being edited in canonical mode
+	line: Vec<u8>,
+	// Session the terminal controls and its foreground process group, 0 for none
+	session: Pid,
+	foreground: Pid,
+	readers: Vec<Pid>,
+}
+
+impl Tty {
+	const fn new(device: Device) -> Tty {
+		Tty {
+			device,
+			termios: Termios::DEFAULT,
+			input: VecDeque::new(),
+			line_ends: VecDeque::new(),
+			line: Vec::new(),
+			session: 0,
+			foreground: 0,
+			readers: Vec::new(),
+		}
+	}
+
+	fn canonical(&self) -> bool {
+		self.termios.lflag & ICANON != 0
+	}
+
+	fn flush_input(&mut self) {
+		self.input.clear();
+		self.line_ends.clear();
+		self.line.clear();
+	}
+
+	// Bytes a read can take right away
+	fn readable(&self) -> usize {
+		if self.canonical() {
+			self.line_ends.back().copied().unwrap_or(0)
+		} else {
+			self.input.len()
+		}
+	}
+
+	fn complete_line(&mut self) {
+		self.input.extend(self.line.drain(..));
+		self.line_ends.push_back(self.input.len());
+	}
+
+	// Applies new settings. Leaving canonical mode hands the edited line to the readers, and
+	// entering it keeps what was already typed readable.
+	fn set_termios(&mut self, termios: Termios) {
+		let was_canonical = self.canonical();
+		self.termios = termios;
+		if was_canonical && !self.canonical() {
+			self.input.extend(self.line.drain(..));
+			self.line_ends.clear();
+		} else if !was_canonical && self.canonical() && !self.input.is_empty() {
+			self.line_ends.push_back(self.input.len());
+		}
+	}
+
+	// Output processing of OPOST
+	fn process_output(&self, bytes: &[u8]) -> Vec<u8> {
+		let oflag = self.termios.oflag;
+		let mut output = Vec::with_capacity(bytes.len());
+		for &byte in bytes {
+			if byte == b'\n' && oflag & (OPOST | ONLCR) == OPOST | ONLCR {
+				output.push(b'\r');
+			}
+			output.push(byte);
+		}
+		output
+	}
+
+	fn echo(&self, byte: u8, echo: &mut Vec<u8>) {
+		if self.termios.lflag & ECHOCTL != 0 && is_control(byte) {
+			echo.extend_from_slice(&[b'^', byte ^ 0x40]);
+		} else {
+			echo.push(byte);
+		}
+	}
+
+	// Takes the last byte of the line back, on the screen too with ECHOE
+	fn erase(&mut self, echo: &mut Vec<u8>) -> bool {
+		let Some(byte) = self.line.pop() else {
+			return false;
+		};
+		let lflag = self.termios.lflag;
+		if lflag & (ECHO | ECHOE) == ECHO | ECHOE {
+			let width = if lflag & ECHOCTL != 0 && is_control(byte) {
+				2
+			} else {
+				1
+			};
+			for _ in 0..width {
+				echo.extend_from_slice(b"\x08 \x08");
+			}
+		}
+		true
+	}
+
+	// Runs `byte` through the line discipline, the bytes to echo land in `echo`. Returns the
+	// signal it stands for, if any.
+	fn receive(&mut self, byte: u8, echo: &mut Vec<u8>) -> Option<u32> {
+		let Termios { iflag, lflag, .. } = self.termios;
+		let byte = match byte {
+			b'\r' if iflag & IGNCR != 0 => return None,
+			b'\r' if iflag & ICRNL != 0 => b'\n',
+			b'\n' if iflag & INLCR != 0 => b'\r',
+			byte => byte,
+		};
+		let echoing = lflag & ECHO != 0;
+
+		if lflag & ISIG != 0 {
+			let termios = &self.termios;
+			let signal = if termios.is_set(VINTR, byte) {
+				Some(SIGINT)
+			} else if termios.is_set(VQUIT, byte) {
+				Some(SIGQUIT)
+			} else if termios.is_set(VSUSP, byte) {
+				Some(SIGTSTP)
+			} else {
+				None
+			};
+			if let Some(signal) = signal {
+				if lflag & NOFLSH == 0 {
+					self.flush_input();
+				}
+				if echoing {
+					self.echo(byte, echo);
+				}
+				return Some(signal);
+			}
+		}
+
+		if !self.canonical() {
+			if self.input.len() < MAX_INPUT {
+				self.input.push_back(byte);
+				if echoing {
+					self.echo(byte, echo);
+				}
+			}
+			return None;
+		}
+
+		let termios = self.termios;
+		if termios.is_set(VERASE, byte) {
+			self.erase(echo);
+		} else if termios.is_set(VWERASE, byte) && lflag & IEXTEN != 0 {
+			while self.line.last() == Some(&b' ') && self.erase(echo) {}
+			while self.line.last().is_some_and(|&last| last != b' ') && self.erase(echo) {}
+		} else if termios.is_set(VKILL, byte) {
+			if echoing && lflag & ECHOE != 0 {
+				while self.erase(echo) {}
+			} else {
+				self.line.clear();
+				if echoing && lflag & ECHOK != 0 {
+					self.echo(byte, echo);
+					echo.push(b'\n');
+				}
+			}
+		} else if termios.is_set(VEOF, byte) {
+			self.complete_line();
+		} else if byte == b'\n' || termios.is_set(VEOL, byte) {
+			if echoing || (byte == b'\n' && lflag & ECHONL != 0) {
+				self.echo(byte, echo);
+			}
+			self.line.push(byte);
+			self.complete_line();
+		} else if self.line.len() < MAX_CANON && self.input.len() < MAX_INPUT {
+			self.line.push(byte);
+			if echoing {
+				self.echo(byte, echo);
+			}
+		}
+		None
+	}
+
+	// Moves what a read can take into `buffer`, None when the reader has to wait. A canonical
+	// read stops at the end of a line. A raw one waits for a byte unless VMIN is 0, VTIME is
+	// not supported.
+	fn take(&mut self, buffer: &mut [u8]) -> Option<usize> {
+		if buffer.is_empty() {
+			return Some(0);
+		}
+		let count = if self.canonical() {
+			(*self.line_ends.front()?).min(buffer.len())
+		} else if !self.input.is_empty() {
+			self.input.len().min(buffer.len())
+		} else if self.termios.cc[VMIN] == 0 {
+			return Some(0);
+		} else {
+			return None;
+		};
+		for (slot, byte) in buffer.iter_mut().zip(self.input.drain(..count)) {
+			*slot = byte;
+		}
+		for end in self.line_ends.iter_mut() {
+			*end -= count;
+		}
+		if self.line_ends.front() == Some(&0) {
+			self.line_ends.pop_front();
+		}
+		Some(count)
+	}
+}
+
+fn is_control(byte: u8) -> bool {
+	(byte < 0x20 && byte != b'\t' && byte != b'\n') || byte == 0x7f
+}
+
+static TTYS: [IrqMutex<Tty>; NUM_SCREENS + 1] = [
+	IrqMutex::new(Tty::new(Device::Screen(0))),
+	IrqMutex::new(Tty::new(Device::Screen(1))),
+	IrqMutex::new(Tty::new(Device::Screen(2))),
+	IrqMutex::new(Tty::new(Device::Screen(3))),
+	IrqMutex::new(Tty::new(Device::Screen(4))),
+	IrqMutex::new(Tty::new(Device::Serial)),
+];
+
+fn group_alive(pgid: Pid, session: Option<Pid>) -> bool {
+	scheduler::task_list().iter().any(|task| {
+		task.pgid == pgid
+			&& session.map_or(true, |session| task.sid == session)
+			&& !matches!(task.state, TaskState::Zombie | TaskState::Dead)
+	})
+}
+
+fn session_alive(session: Pid) -> bool {
+	scheduler::task_list().iter().any(|task| {
+		task.sid == session && !matches!(task.state, TaskState::Zombie | TaskState::Dead)
+	})
+}
+
+// True when the input of terminal `index` should go to its line discipline rather than to
+// the kernel shell: a task waits on it or its foreground process group is alive
+pub fn wants_input(index: usize) -> bool {
+	let (foreground, waiting) = {
+		let tty = TTYS[index].lock();
+		(tty.foreground, !tty.readers.is_empty())
+	};
+	waiting || (foreground != 0 && group_alive(foreground, None))
+}
+
+// Feeds `bytes` typed on terminal `index` to its line discipline. The readers stay listed
+// until their read returns, so the next keystrokes keep coming here.
+pub fn input(index: usize, bytes: &[u8]) {
+	let mut signals = Vec::new();
+	let (device, echo, foreground, readers) = {
+		let mut tty = TTYS[index].lock();
+		let mut echo = Vec::new();
+		for &byte in bytes {
+			if let Some(signal) = tty.receive(byte, &mut echo) {
+				signals.push(signal);
+			}
+		}
+		let echo = tty.process_output(&echo);
+		(tty.device, echo, tty.foreground, tty.readers.clone())
+	};
+	device.write(&echo);
+	if foreground != 0 {
+		for signal in signals {
+			let _ = signal::send_group(foreground, signal);
+		}
+	}
+	for reader in readers {
+		scheduler::wake(reader);
+	}
+}
+
+// Job control: a task of the session of terminal `index` outside its foreground group gets
+// `signal` for its whole group instead of going on, unless it blocks or ignores it. Then a
+// read fails with EIO while a write or a change of settings goes through.
+fn check_background(index: usize, signal: u32) -> Result<(), Errno> {
+	let (session, foreground) = {
+		let tty = TTYS[index].lock();
+		(tty.session, tty.foreground)
+	};
+	let (pgid, sid, refused) = scheduler::with_current(|task| {
+		(task.pgid, task.sid, task.signals.blocks_or_ignores(signal))
+	});
+	if session == 0 || sid != session || foreground == 0 || pgid == foreground {
+		return Ok(());
+	}
+	if refused {
+		return match signal {
+			SIGTTIN => Err(EIO),
+			_ => Ok(()),
+		};
+	}
+	signal::send_group(pgid, signal)?;
+	Err(EINTR)
+}
+
+fn read(index: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+	check_background(index, SIGTTIN)?;
+	let pid = scheduler::current_pid();
+	// Interrupts stay disabled between finding nothing to read and sleeping, so the wake up
+	// of `input` cannot come in between
+	let result = interrupts::without_interrupts(|| loop {
+		{
+			let mut tty = TTYS[index].lock();
+			if let Some(read) = tty.take(buffer) {
+				break Ok(read);
+			}
+			// The idle task cannot sleep
+			if pid == IDLE_PID {
+				break Err(EAGAIN);
+			}
+			if scheduler::with_current(|task| task.signals.has_deliverable()) {
+				break Err(EINTR);
+			}
+			if !tty.readers.contains(&pid) {
+				tty.readers.push(pid);
+			}
+		}
+		scheduler::block();
+	});
+	TTYS[index].lock().readers.retain(|&reader| reader != pid);
+	result
+}
+
+fn write(index: usize, buffer: &[u8]) -> Result<usize, Errno> {
+	if TTYS[index].lock().termios.lflag & TOSTOP != 0 {
+		check_background(index, SIGTTOU)?;
+	}
+	let (device, output) = {
+		let tty = TTYS[index].lock();
+		(tty.device, tty.process_output(buffer))
+	};
+	device.write(&output);
+	Ok(buffer.len())
+}
+
+fn set_termios(index: usize, termios: Termios, flush: bool) -> Result<(), Errno> {
+	check_background(index, SIGTTOU)?;
+	let readers = {
+		let mut tty = TTYS[index].lock();
+		if flush {
+			tty.flush_input();
+		}
+		tty.set_termios(termios);
+		tty.readers.clone()
+	};
+	// Readers check again, a read may not have to wait anymore
+	for reader in readers {
+		scheduler::wake(reader);
+	}
+	Ok(())
+}
+
+// Makes terminal `index` the controlling terminal of the session of the caller, which must
+// lead it. A terminal stays taken as long as a task of its session is alive.
+fn set_controlling(index: usize) -> Result<(), Errno> {
+	let (pid, pgid, sid) = scheduler::with_current(|task| (task.pid, task.pgid, task.sid));
+	let session = TTYS[index].lock().session;
+	if session == sid {
+		return Ok(());
+	}
+	if pid != sid || TTYS.iter().any(|tty| tty.lock().session == sid) {
+		return Err(EPERM);
+	}
+	if session != 0 && session_alive(session) {
+		return Err(EPERM);
+	}
+	let mut tty = TTYS[index].lock();
+	tty.session = sid;
+	tty.foreground = pgid;
+	Ok(())
+}
+
+// The session leader gives up its controlling terminal, the foreground group is hung up
+fn release_controlling(index: usize) -> Result<(), Errno> {
+	let (pid, sid) = scheduler::with_current(|task| (task.pid, task.sid));
+	let foreground = {
+		let mut tty = TTYS[index].lock();
+		if tty.session != sid {
+			return Err(ENOTTY);
+		}
+		if pid != sid {
+			return Ok(());
+		}
+		tty.session = 0;
+		core::mem::take(&mut tty.foreground)
+	};
+	if foreground != 0 {
+		let _ = signal::send_group(foreground, SIGHUP);
+		let _ = signal::send_group(foreground, SIGCONT);
+	}
+	Ok(())
+}
+
+// Foreground process group of terminal `index`, which must control the session of the caller
+fn foreground(index: usize) -> Result<Pid, Errno> {
+	let sid = scheduler::with_current(|task| task.sid);
+	let tty = TTYS[index].lock();
+	if tty.session != sid {
+		return Err(ENOTTY);
+	}
+	Ok(tty.foreground)
+}
+
+fn set_foreground(index: usize, pgid: i32) -> Result<(), Errno> {
+	let sid = scheduler::with_current(|task| task.sid);
+	foreground(index)?;
+	check_background(index, SIGTTOU)?;
+	if pgid <= 0 {
+		return Err(EINVAL);
+	}
+	if !group_alive(pgid as Pid, Some(sid)) {
+		return Err(EPERM);
+	}
+	TTYS[index].lock().foreground = pgid as Pid;
+	Ok(())
+}
+
+fn ioctl(index: usize, request: u32, argument: u32) -> Result<u32, Errno> {
+	let device = TTYS[index].lock().device;
+	match request {
+		TCGETS => {
+			let termios = TTYS[index].lock().termios;
+			uaccess::write_user(argument, &termios)?;
+		}
+		// The output is written out before the call returns, so TCSETSW has nothing to wait for
+		TCSETS | TCSETSW | TCSETSF => {
+			let termios = uaccess::read_user(argument)?;
+			set_termios(index, termios, request == TCSETSF)?;
+		}
+		TIOCSCTTY => set_controlling(index)?,
+		TIOCNOTTY => release_controlling(index)?,
+		TIOCGPGRP => uaccess::write_user(argument, &foreground(index)?)?,
+		TIOCSPGRP => set_foreground(index, uaccess::read_user(argument)?)?,
+		TIOCGSID => {
+			let sid = scheduler::with_current(|task| task.sid);
+			if TTYS[index].lock().session != sid {
+				return Err(ENOTTY);
+			}
+			uaccess::write_user(argument, &sid)?;
+		}
+		FIONREAD => {
+			let readable = TTYS[index].lock().readable() as u32;
+			uaccess::write_user(argument, &readable)?;
+		}
+		TIOCGWINSZ => uaccess::write_user(argument, &device.window_size())?,
+		// Shows the screen `argument`, numbered like the devices
+		VT_ACTIVATE => match device {
+			Device::Screen(_) if (argument as usize) < NUM_SCREENS => {
+				vga::change_display(argument as usize)
+			}
+			Device::Screen(_) => return Err(ENXIO),
+			Device::Serial => return Err(ENOTTY),
+		},
+		_ => return Err(ENOTTY),
+	}
+	Ok(0)
+}
+
+struct TtyOps {
+	index: usize,
+}
+
+impl FileOps for TtyOps {
+	fn read(&self, _file: &File, buffer: &mut [u8]) -> Result<usize, Errno> {
+		read(self.index, buffer)
+	}
+
+	fn write(&self, _file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		write(self.index, buffer)
+	}
+
+	fn ioctl(&self, _file: &File, request: u32, argument: u32) -> Result<u32, Errno> {
+		ioctl(self.index, request, argument)
+	}
+}
+
+pub fn init() {
+	for display in 0..NUM_SCREENS {
+		let name = format!("tty{}", display);
+		let ops = Arc::new(TtyOps { index: display });
+		chrdev::register(&name, TTY_MAJOR, display as u32, 0o620, ops).expect("Terminal taken");
+	}
+	let ops = Arc::new(TtyOps { index: SERIAL_TTY });
+	chrdev::register("ttyS0", TTY_MAJOR, SERIAL_MINOR, 0o660, ops).expect("Terminal taken");
+}
+
+// Terminal the tests type on, its screen is not shown during boot
+const TEST_TTY: usize = 3;
+// jmp $
+const LOOP_CODE: [u8; 2] = [0xeb, 0xfe];
+
+static TEST_DONE: AtomicU32 = AtomicU32::new(0);
+static TEST_LINE: IrqMutex<[u8; 8]> = IrqMutex::new([0; 8]);
+
+fn read_line(buffer: &mut [u8]) -> &[u8] {
+	let read = read(TEST_TTY, buffer).expect("Terminal read failed");
+	&buffer[..read]
+}
+
+fn reader_thread() {
+	let mut line = [0; 8];
+	let read = read(TEST_TTY, &mut line).expect("Terminal read failed");
+	assert!(read == 5);
+	*TEST_LINE.lock() = line;
+	TEST_DONE.store(1, Ordering::SeqCst);
+}
+
+fn wait_for(pid: Pid, untraced: bool) -> u32 {
+	let (child, status) = scheduler::wait(WaitTarget::Pid(pid), false, untraced)
+		.expect("wait failed")
+		.expect("wait returned without a child");
+	assert!(child == pid);
+	status
+}
+
+// Parent of the user program, a kernel thread since the idle task never waits
+fn job_control_thread() {
+	use crate::task::elf;
+	use crate::task::task::{signaled_status, stopped_status};
+
+	log!(
+		LogLevel::Info,
+		"A session leader should take the terminal\n"
+	);
+	let current = scheduler::current_pid();
+	assert!(scheduler::setsid() == Err(EPERM));
+	assert!(set_controlling(TEST_TTY) == Ok(()));
+	assert!(foreground(TEST_TTY) == Ok(current));
+
+	let image = elf::test_image(&LOOP_CODE);
+	let pid = elf::spawn("loop", image.into(), &["loop"], &[]).expect("Failed to spawn loop");
+	assert!(scheduler::with_task(pid, |task| task.pgid == pid && task.sid == pid) == Some(true));
+	assert!(scheduler::setpgid(pid, current) == Err(EPERM));
+	assert!(scheduler::setpgid(pid + 1000, 0) == Err(ESRCH));
+	// The program leads a session of its own, it cannot be put in the foreground
+	assert!(set_foreground(TEST_TTY, pid as i32) == Err(EPERM));
+	assert!(release_controlling(TEST_TTY) == Ok(()));
+	assert!(foreground(TEST_TTY) == Err(ENOTTY));
+
+	log!(
+		LogLevel::Info,
+		"Suspend and interrupt should signal the foreground group\n"
+	);
+	TTYS[TEST_TTY].lock().foreground = pid;
+	assert!(wants_input(TEST_TTY));
+	input(TEST_TTY, b"\x1a");
+	assert!(wait_for(pid, true) == stopped_status(SIGTSTP));
+	assert!(signal::send(pid, SIGCONT) == Ok(()));
+	input(TEST_TTY, b"\x03");
+	assert!(wait_for(pid, true) == signaled_status(SIGINT, false));
+	assert!(!wants_input(TEST_TTY));
+	assert!(signal::send_group(pid, SIGINT) == Err(ESRCH));
+	TTYS[TEST_TTY].lock().foreground = 0;
+
+	TEST_DONE.store(1, Ordering::SeqCst);
+}
+
+fn wait_test_done() {
+	while TEST_DONE.swap(0, Ordering::SeqCst) == 0 {
+		scheduler::yield_now();
+	}
+}
+
+pub fn tty_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting terminals\n");
+	let mut buffer = [0; 16];
+
+	log!(
+		LogLevel::Info,
+		"Canonical mode should edit lines and stop reads at their end\n"
+	);
+	input(TEST_TTY, b"hel\x7flo\r");
+	assert!(read_line(&mut buffer) == b"helo\n");
+	input(TEST_TTY, b"abc\x15xy z\x17w\n");
+	assert!(read_line(&mut buffer) == b"xy w\n");
+	input(TEST_TTY, b"abcdef\n\x04");
+	assert!(read_line(&mut buffer[..4]) == b"abcd");
+	assert!(read_line(&mut buffer) == b"ef\n");
+	assert!(read_line(&mut buffer).is_empty());
+	input(TEST_TTY, b"1\n2");
+	assert!(TTYS[TEST_TTY].lock().readable() == 2);
+	assert!(read_line(&mut buffer) == b"1\n");
+	assert!(read(TEST_TTY, &mut buffer) == Err(EAGAIN));
+
+	log!(
+		LogLevel::Info,
+		"Raw mode should hand the bytes over as they come\n"
+	);
+	let mut raw = Termios::DEFAULT;
+	raw.lflag &= !(ICANON | ECHO);
+	raw.cc[VMIN] = 0;
+	assert!(set_termios(TEST_TTY, raw, true) == Ok(()));
+	assert!(read_line(&mut buffer).is_empty());
+	input(TEST_TTY, b"q\r\x7f");
+	assert!(read_line(&mut buffer) == b"q\n\x7f");
+	input(TEST_TTY, b"x");
+	assert!(set_termios(TEST_TTY, Termios::DEFAULT, false) == Ok(()));
+	assert!(read_line(&mut buffer) == b"x");
+	assert!(TTYS[TEST_TTY].lock().process_output(b"a\n") == b"a\r\n");
+
+	log!(
+		LogLevel::Info,
+		"A reader should sleep until its line is complete\n"
+	);
+	assert!(!wants_input(TEST_TTY));
+	let reader = scheduler::spawn("tty-reader", reader_thread).expect("Failed to spawn thread");
+	while scheduler::with_task(reader, |task| task.state) != Some(TaskState::Blocked) {
+		scheduler::yield_now();
+	}
+	assert!(wants_input(TEST_TTY));
+	input(TEST_TTY, b"wa");
+	input(TEST_TTY, b"ke\n");
+	wait_test_done();
+	assert!(&TEST_LINE.lock()[..5] == b"wake\n");
+
+	scheduler::spawn("tty-test", job_control_thread).expect("Failed to spawn thread");
+	wait_test_done();
+
+	log!(LogLevel::Info, "\t\tEnd of terminal test\n");
+}
@@ -2,16 +2,15 @@
 use crate::tools::debug::LogLevel;
 use crate::tools::io::inb;
 use crate::{
+	drivers::{ata, serial},
 	exceptions::{
 		keyboard::{BUFFER_HEAD, KEYBRD_INTP_RECEIVED, SCANCODE_BUFFER},
 		pic8259::ChainedPics,
 	},
-	memory::{
-		page_directory::{PAGE_SIZE, PAGE_TABLES_ADDR},
-		page_table::PageTable,
-		page_table_entry::FlagTablePages,
-		kmem_managment::PMM,
-	},
+	memory::address_space::{self, PageFaultError},
+	memory::uaccess,
+	task::scheduler,
+	task::signal::{self, SIGFPE, SIGILL, SIGSEGV},
 };
 use core::arch::asm;
 use core::sync::atomic::{AtomicU32, Ordering};
@@ -23,6 +22,16 @@ pub static TICKS: AtomicU32 = AtomicU32::new(0);
 
 pub const PIC_1_OFF: u8 = 32;
 
+// Vectors of the exceptions and traps that are counted
+pub const DIVIDE_ERROR: u8 = 0;
+pub const INVALID_OPCODE: u8 = 6;
+pub const GENERAL_PROTECTION: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+pub const SYSCALL: u8 = 0x80;
+
+// Interrupts received by vector, for /proc/interrupts
+static COUNTS: [AtomicU32; 256] = [const { AtomicU32::new(0) }; 256];
+
 pub static PICS: Mutex<ChainedPics> =
 	Mutex::new(unsafe { ChainedPics::new_contiguous(PIC_1_OFF) });
 
@@ -67,8 +76,74 @@ pub struct InterruptStackFrame {
 	pub ss: u32,
 }
 
-pub extern "C" fn divide_by_zero(stack_frame: &mut InterruptStackFrame) {
-	handle_panic(&"Divide By Zero", Some(stack_frame));
+// Registers in the order pushed by pushad
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct GeneralRegs {
+	pub edi: u32,
+	pub esi: u32,
+	pub ebp: u32,
+	pub esp: u32,
+	pub ebx: u32,
+	pub edx: u32,
+	pub ecx: u32,
+	pub eax: u32,
+}
+
+// Stack layout built by the TRAP stubs and syscall_stub in idt.s. error_code holds the
+// error code of the exception or the syscall number, user_esp and user_ss are only valid
+// when the CPU came from ring 3.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+	pub gs: u32,
+	pub fs: u32,
+	pub es: u32,
+	pub ds: u32,
+	pub regs: GeneralRegs,
+	pub error_code: u32,
+	pub eip: u32,
+	pub cs: u32,
+	pub eflags: u32,
+	pub user_esp: u32,
+	pub user_ss: u32,
+}
+
+impl TrapFrame {
+	pub fn from_user(&self) -> bool {
+		self.cs & 3 == 3
+	}
+
+	// The part pushed by the CPU, for handle_panic
+	pub fn stack_frame(&self) -> &InterruptStackFrame {
+		unsafe { &*(&self.eip as *const u32 as *const InterruptStackFrame) }
+	}
+}
+
+pub fn count(vector: u8) {
+	COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn counts() -> [u32; 256] {
+	core::array::from_fn(|vector| COUNTS[vector].load(Ordering::Relaxed))
+}
+
+// Called by every TRAP stub and syscall_stub before going back to the interrupted code
+#[no_mangle]
+pub extern "C" fn return_from_trap(frame: &mut TrapFrame) {
+	if frame.from_user() {
+		signal::handle_pending(frame);
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn divide_by_zero(frame: &mut TrapFrame) {
+	count(DIVIDE_ERROR);
+	if frame.from_user() {
+		signal::force(SIGFPE, frame.eip);
+		return;
+	}
+	handle_panic(&"Divide By Zero", Some(frame.stack_frame()));
 }
 
 pub extern "C" fn debug(stack_frame: &mut InterruptStackFrame) {
@@ -104,8 +179,14 @@ pub extern "C" fn bound_range_exceeded(stack_frame: &mut InterruptStackFrame) {
 	);
 }
 
-pub extern "C" fn invalid_opcode(stack_frame: &mut InterruptStackFrame) {
-	handle_panic(&"Invalid Opcode", Some(stack_frame));
+#[no_mangle]
+pub extern "C" fn invalid_opcode(frame: &mut TrapFrame) {
+	count(INVALID_OPCODE);
+	if frame.from_user() {
+		signal::force(SIGILL, frame.eip);
+		return;
+	}
+	handle_panic(&"Invalid Opcode", Some(frame.stack_frame()));
 }
 
 pub extern "C" fn coprocessor_not_available(stack_frame: &mut InterruptStackFrame) {
@@ -140,96 +221,77 @@ pub extern "C" fn stack_fault(stack_frame: &mut InterruptStackFrame) {
 	handle_panic(&"Stack Fault", Some(stack_frame));
 }
 
-pub extern "C" fn general_protection_fault(stack_frame: &mut InterruptStackFrame) {
-	handle_panic(&"General Protection Fault", Some(stack_frame));
+#[no_mangle]
+pub extern "C" fn general_protection_fault(frame: &mut TrapFrame) {
+	count(GENERAL_PROTECTION);
+	if frame.from_user() {
+		signal::force(SIGSEGV, 0);
+		return;
+	}
+	handle_panic(&"General Protection Fault", Some(frame.stack_frame()));
 }
 
 #[no_mangle]
-pub extern "C" fn page_fault(_stack_frame: &mut InterruptStackFrame, error_code: u32) {
+pub extern "C" fn page_fault(frame: &mut TrapFrame) {
+	count(PAGE_FAULT);
+	let error_code = frame.error_code;
 	let faulting_address: u32;
 	unsafe {
 		asm!("mov {}, cr2", out(reg) faulting_address, options(nostack, preserves_flags));
 	}
 
-	let present = error_code & 0x1 != 0;
-	let write = error_code & 0x2 != 0;
-	let user = error_code & 0x4 != 0;
-	let reserved = error_code & 0x8 != 0;
-	let instruction_fetch = error_code & 0x10 != 0;
-
-	log!(
-		LogLevel::Alert,
-		"Page Fault at address {:#x}\tError Code: {:#x}",
-		faulting_address,
-		error_code
-	);
-
-	if !present {
-		log!(LogLevel::Info, "Page not present");
-		handle_not_present_page_fault(faulting_address as usize);
-	} else {
-		if write {
-			log!(LogLevel::Error, "Attempted to write to a read-only page");
-		} else if user {
-			log!(
-				LogLevel::Error,
-				"Attempted to access kernel page from user mode"
-			);
-		} else if reserved {
-			log!(
-				LogLevel::Panic,
-				"Reserved bit violation in page table entry"
-			);
-			panic!("Kernel panic: Reserved bit violation");
-		} else if instruction_fetch {
-			log!(
-				LogLevel::Panic,
-				"Instruction fetch from a non-executable page"
-			);
-			panic!("Kernel panic: Attempted to execute non-executable memory");
-		} else {
-			log!(LogLevel::Error, "Unknown page fault");
+	let error = PageFaultError::from_bits_truncate(error_code);
+	if let Some(address_space) = address_space::current() {
+		if address_space.handle_page_fault(faulting_address, error) {
+			return;
 		}
 	}
-}
-
-fn handle_not_present_page_fault(faulting_address: usize) {
-	let pd_index = faulting_address >> 22;
-	let pt_index = (faulting_address >> 12) & 0x3FF;
-
-	println_srl!("Page directory index: {}", pd_index);
-	println_srl!("Page table index: {}", pt_index);
-
-	let page_table_addr = unsafe { PAGE_TABLES_ADDR + (pd_index * PAGE_SIZE) as u32 };
-	let page_table = unsafe { &mut *(page_table_addr as *mut PageTable) };
 
-	println_srl!("Page table address: {:#x}, page_table ", page_table_addr);
-	println_srl!("Page table address: {:#x}, page_table ", page_table_addr);
-	let frame = PMM.lock().allocate_frame();
-	match frame {
-		Ok(frame) => {
-			println_srl!("Allocated frame: {:#x}", frame);
-
-			let page_table_entry = &mut page_table.entries[pt_index];
-			page_table_entry
-				.set_frame_address(frame, FlagTablePages::PRESENT | FlagTablePages::WRITABLE);
-			println_srl!("Updated page table entry: {:#x}", page_table_entry.value());
-
-			unsafe {
-				let cr3: u32;
-				asm!("mov {}, cr3", out(reg) cr3);
-				asm!("mov cr3, {}", in(reg) cr3);
-			}
-		}
-		Err(e) => {
-			log!(
-				LogLevel::Error,
-				"Failed to allocate frame for page table entry: {}",
-				e
-			);
+	// A bad user pointer met by one of the user copy routines
+	if !error.contains(PageFaultError::USER) {
+		if let Some(fixup) = uaccess::search_exception_table(frame.eip) {
+			frame.eip = fixup;
 			return;
 		}
 	}
+
+	if frame.from_user() {
+		log!(
+			LogLevel::Debug,
+			"Page fault at {:#x} from user code at {:#x}",
+			faulting_address,
+			frame.eip
+		);
+		signal::force(SIGSEGV, faulting_address);
+		return;
+	}
+
+	handle_panic(
+		&format_args!(
+			"Page Fault at address {:#x}, EIP {:#x}, error code {:#x}: {}, {}, {} mode{}{}",
+			faulting_address,
+			frame.eip,
+			error_code,
+			if error.contains(PageFaultError::PRESENT) {
+				"protection violation"
+			} else {
+				"page not present"
+			},
+			if error.contains(PageFaultError::WRITE) { "write" } else { "read" },
+			if error.contains(PageFaultError::USER) { "user" } else { "kernel" },
+			if error.contains(PageFaultError::RESERVED) {
+				", reserved bit set"
+			} else {
+				""
+			},
+			if error.contains(PageFaultError::INSTRUCTION_FETCH) {
+				", instruction fetch"
+			} else {
+				""
+			},
+		),
+		Some(frame.stack_frame()),
+	);
 }
 
 pub extern "C" fn reserved(stack_frame: &mut InterruptStackFrame) {
@@ -264,81 +326,57 @@ pub extern "C" fn virtualization_exception(stack_frame: &mut InterruptStackFrame
 	);
 }
 
-pub fn timer_intp(_stack_frame: &mut InterruptStackFrame) {
+#[no_mangle]
+pub extern "C" fn timer_intp(_frame: &mut TrapFrame) {
+	count(InterruptIndex::Timer.as_u8());
 	unsafe {
 		PICS.lock()
 			.notify_end_of_intp(InterruptIndex::Timer.as_u8());
 	}
 	TICKS.fetch_add(1, Ordering::SeqCst);
+	scheduler::tick();
 }
 
-pub fn keybrd_intp(_stack_frame: &mut InterruptStackFrame) {
-	let scancode: u8 = unsafe { inb(0x60) };
-
+#[no_mangle]
+pub extern "C" fn com1_intp(_frame: &mut TrapFrame) {
+	count(InterruptIndex::Com1.as_u8());
+	serial::handle_irq();
 	unsafe {
-		SCANCODE_BUFFER[BUFFER_HEAD] = scancode;
-		BUFFER_HEAD = (BUFFER_HEAD + 1) % SCANCODE_BUFFER.len();
-		KEYBRD_INTP_RECEIVED.store(true, Ordering::SeqCst);
 		PICS.lock()
-			.notify_end_of_intp(InterruptIndex::Keyboard.as_u8());
+			.notify_end_of_intp(InterruptIndex::Com1.as_u8());
 	}
 }
 
-pub fn syscall_intp(_stack_frame: &mut InterruptStackFrame) {
-	use crate::exceptions::syscalls::{syscall, GeneralRegs};
-
-	let mut registers = GeneralRegs {
-		eax: 0,
-		ebx: 0,
-		ecx: 0,
-		edx: 0,
-		esi: 0,
-		edi: 0,
-		ebp: 0,
-	};
+#[no_mangle]
+pub extern "C" fn primary_ata_intp(_frame: &mut TrapFrame) {
+	count(InterruptIndex::PrimaryAtaHardDisk.as_u8());
+	ata::handle_irq(InterruptIndex::PrimaryAtaHardDisk);
+	unsafe {
+		PICS.lock()
+			.notify_end_of_intp(InterruptIndex::PrimaryAtaHardDisk.as_u8());
+	}
+}
 
+#[no_mangle]
+pub extern "C" fn secondary_ata_intp(_frame: &mut TrapFrame) {
+	count(InterruptIndex::SecondaryAtaHardDisk.as_u8());
+	ata::handle_irq(InterruptIndex::SecondaryAtaHardDisk);
 	unsafe {
-		asm!(
-			"mov [{}], eax",
-			"mov [{}], ebx",
-			"mov [{}], ecx",
-			"mov [{}], edx",
-			"mov [{}], esi",
-			"mov [{}], edi",
-			"mov [{}], ebp",
-
-			in(reg) &mut registers.eax,
-			in(reg) &mut registers.ebx,
-			in(reg) &mut registers.ecx,
-			in(reg) &mut registers.edx,
-			in(reg) &mut registers.esi,
-			in(reg) &mut registers.edi,
-			in(reg) &mut registers.ebp,
-			options(preserves_flags, nostack)
-		);
+		PICS.lock()
+			.notify_end_of_intp(InterruptIndex::SecondaryAtaHardDisk.as_u8());
 	}
+}
 
-	syscall(&mut registers);
+pub fn keybrd_intp(_stack_frame: &mut InterruptStackFrame) {
+	count(InterruptIndex::Keyboard.as_u8());
+	let scancode: u8 = unsafe { inb(0x60) };
 
 	unsafe {
-		asm!(
-			"mov eax, [{}]",
-			"mov ebx, [{}]",
-			"mov ecx, [{}]",
-			"mov edx, [{}]",
-			"mov esi, [{}]",
-			"mov edi, [{}]",
-			"mov ebp, [{}]",
-
-			in(reg) &registers.eax,
-			in(reg) &registers.ebx,
-			in(reg) &registers.ecx,
-			in(reg) &registers.edx,
-			in(reg) &registers.esi,
-			in(reg) &registers.edi,
-			in(reg) &registers.ebp,
-			options(preserves_flags, nostack)
-		);
+		SCANCODE_BUFFER[BUFFER_HEAD] = scancode;
+		BUFFER_HEAD = (BUFFER_HEAD + 1) % SCANCODE_BUFFER.len();
+		KEYBRD_INTP_RECEIVED.store(true, Ordering::SeqCst);
+		PICS.lock()
+			.notify_end_of_intp(InterruptIndex::Keyboard.as_u8());
 	}
 }
 
@@ -367,3 +405,22 @@ pub fn disable() {
 		asm!("cli", options(preserves_flags, nostack));
 	}
 }
+
+pub fn are_enabled() -> bool {
+	let eflags: u32;
+	unsafe {
+		asm!("pushfd", "pop {}", out(reg) eflags, options(preserves_flags));
+	}
+	eflags & 0x200 != 0
+}
+
+// Runs `f` with interrupts disabled and restores the previous state afterwards
+pub fn without_interrupts<F: FnOnce() -> R, R>(f: F) -> R {
+	let enabled = are_enabled();
+	disable();
+	let result = f();
+	if enabled {
+		enable();
+	}
+	result
+}
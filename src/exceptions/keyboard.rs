@@ -1,6 +1,8 @@
+use crate::drivers::tty;
 use crate::shell;
 use crate::shell::history::HISTORY;
 use crate::shell::prints::print_welcome_message;
+use crate::task::signal::{self, SIGINT};
 use crate::tools::vga::WRITER;
 use crate::tools::{prompt, vga};
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -56,6 +58,22 @@ pub fn process_keyboard_input() {
 			if !escape_prefix {
 				c = scancode_to_char(scancode);
 			}
+
+			// Keys go to the terminal of the screen when a program reads it
+			let display = WRITER.lock().current_display;
+			if tty::wants_input(display) {
+				let byte = [terminal_byte(c)];
+				let bytes = match c {
+					b'\0' => terminal_sequence(scancode),
+					_ => Some(&byte[..]),
+				};
+				if let Some(bytes) = bytes {
+					ESCAPE_PREFIX_RECEIVED.store(false, Ordering::SeqCst);
+					tty::input(display, bytes);
+					continue;
+				}
+			}
+
 			if c == b'\0' {
 				update_modifier_state(scancode);
 				continue;
@@ -69,7 +87,7 @@ pub fn process_keyboard_input() {
 				if c == b'l' {
 					shell::builtins::clear();
 					prompt::init();
-				} else if c == b'c' {
+				} else if c == b'c' && !signal::signal_foreground(SIGINT) {
 					prompt::init();
 				}
 			}
@@ -155,6 +173,35 @@ fn update_modifier_state(scancode: u8) {
 	}
 }
 
+// Byte a terminal gets for character `c`, Enter sends a carriage return like on a PC console
+fn terminal_byte(c: u8) -> u8 {
+	match c {
+		b'\n' => b'\r',
+		0x40..=0x7f if CTRL_PRESSED.load(Ordering::SeqCst) => c & 0x1f,
+		c => c,
+	}
+}
+
+// Bytes a terminal gets for the keys that have no character, as the Linux console sends them
+fn terminal_sequence(scancode: u8) -> Option<&'static [u8]> {
+	match scancode {
+		0x1c => Some(b"\r"),
+		0x0e => Some(b"\x7f"),
+		0x0f => Some(b"\t"),
+		0x48 => Some(b"\x1b[A"),
+		0x50 => Some(b"\x1b[B"),
+		0x4d => Some(b"\x1b[C"),
+		0x4b => Some(b"\x1b[D"),
+		0x47 => Some(b"\x1b[1~"),
+		0x4f => Some(b"\x1b[4~"),
+		0x52 => Some(b"\x1b[2~"),
+		0x53 => Some(b"\x1b[3~"),
+		0x49 => Some(b"\x1b[5~"),
+		0x51 => Some(b"\x1b[6~"),
+		_ => None,
+	}
+}
+
 fn change_keyboard_layout() {
 	if KEYBOARD_LAYOUT.load(Ordering::SeqCst) == QWERTY {
 		KEYBOARD_LAYOUT.store(AZERTY, Ordering::SeqCst);
@@ -3,4 +3,5 @@ pub mod interrupts;
 pub mod pic8259;
 pub mod syscalls;
 pub mod panic;
-pub mod keyboard;
\ No newline at end of file
+pub mod keyboard;
+pub mod errno;
\ No newline at end of file
@@ -98,6 +98,18 @@ impl ChainedPics {
 		self.pics[1].write_mask(mask2);
 	}
 
+	// Lets the interrupt `interrupt_id` through, and the cascade for those of the second PIC
+	pub unsafe fn unmask(&mut self, interrupt_id: u8) {
+		let [mut mask1, mut mask2] = self.read_masks();
+		if self.pics[1].handles_intp(interrupt_id) {
+			mask2 &= !(1 << (interrupt_id - self.pics[1].off));
+			mask1 &= !(1 << 2);
+		} else if self.pics[0].handles_intp(interrupt_id) {
+			mask1 &= !(1 << (interrupt_id - self.pics[0].off));
+		}
+		self.write_masks(mask1, mask2);
+	}
+
 	pub fn handles_intp(&self, interrupt_id: u8) -> bool {
 		self.pics.iter().any(|p| p.handles_intp(interrupt_id))
 	}
@@ -0,0 +1,301 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::io::get_file;
+use super::process::read_user_string;
+use super::{SyscallParameters, SyscallResult};
+use crate::exceptions::errno::{Errno, EINVAL, ENAMETOOLONG, EOVERFLOW, ERANGE};
+use crate::fs::file::{O_CLOEXEC, O_CREAT, O_TRUNC, O_WRONLY};
+use crate::fs::inode::{DirEntry, Metadata};
+use crate::fs::{self, PATH_MAX};
+use crate::memory::uaccess;
+use crate::task::scheduler;
+
+// struct stat of the stat, lstat and fstat syscalls
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Stat {
+	dev: u32,
+	ino: u32,
+	mode: u16,
+	nlink: u16,
+	uid: u16,
+	gid: u16,
+	rdev: u32,
+	size: u32,
+	block_size: u32,
+	blocks: u32,
+	atime: u32,
+	atime_nsec: u32,
+	mtime: u32,
+	mtime_nsec: u32,
+	ctime: u32,
+	ctime_nsec: u32,
+	unused: [u32; 2],
+}
+
+// struct stat64, its 64 bit fields are only 4 byte aligned on i386
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Stat64 {
+	dev: u64,
+	pad0: u32,
+	short_ino: u32,
+	mode: u32,
+	nlink: u32,
+	uid: u32,
+	gid: u32,
+	rdev: u64,
+	pad3: u32,
+	size: i64,
+	block_size: u32,
+	blocks: u64,
+	atime: u32,
+	atime_nsec: u32,
+	mtime: u32,
+	mtime_nsec: u32,
+	ctime: u32,
+	ctime_nsec: u32,
+	ino: u64,
+}
+
+// Fixed part of struct linux_dirent and struct linux_dirent64, before the name
+const DIRENT_HEADER: usize = 10;
+const DIRENT64_HEADER: usize = 19;
+
+pub fn read_path(address: u32) -> Result<String, Errno> {
+	read_user_string(address, PATH_MAX, ENAMETOOLONG)
+}
+
+pub fn sys_open(params: &mut SyscallParameters) -> SyscallResult {
+	let path = read_path(params.arg(0))?;
+	let flags = params.arg(1);
+	let file = fs::open(&path, flags, params.arg(2))?;
+	scheduler::with_current(|task| task.files.insert(file, flags & O_CLOEXEC != 0))
+}
+
+pub fn sys_creat(params: &mut SyscallParameters) -> SyscallResult {
+	let path = read_path(params.arg(0))?;
+	let file = fs::open(&path, O_CREAT | O_WRONLY | O_TRUNC, params.arg(1))?;
+	scheduler::with_current(|task| task.files.insert(file, false))
+}
+
+pub fn sys_close(params: &mut SyscallParameters) -> SyscallResult {
+	let file = scheduler::with_current(|task| task.files.close(params.arg(0)))?;
+	drop(file);
+	Ok(0)
+}
+
+fn to_stat(metadata: &Metadata) -> Result<Stat, Errno> {
+	let narrow = |value: u32| u16::try_from(value).map_err(|_| EOVERFLOW);
+	Ok(Stat {
+		dev: metadata.dev,
+		ino: u32::try_from(metadata.ino).map_err(|_| EOVERFLOW)?,
+		mode: narrow(metadata.file_type.mode_bits() | metadata.mode)?,
+		nlink: narrow(metadata.nlink)?,
+		uid: narrow(metadata.uid)?,
+		gid: narrow(metadata.gid)?,
+		rdev: metadata.rdev,
+		size: u32::try_from(metadata.size)
+			.ok()
+			.filter(|&size| size <= i32::MAX as u32)
+			.ok_or(EOVERFLOW)?,
+		block_size: metadata.block_size,
+		blocks: u32::try_from(metadata.blocks).map_err(|_| EOVERFLOW)?,
+		atime: metadata.atime,
+		atime_nsec: 0,
+		mtime: metadata.mtime,
+		mtime_nsec: 0,
+		ctime: metadata.ctime,
+		ctime_nsec: 0,
+		unused: [0; 2],
+	})
+}
+
+fn to_stat64(metadata: &Metadata) -> Stat64 {
+	Stat64 {
+		dev: metadata.dev as u64,
+		pad0: 0,
+		short_ino: metadata.ino as u32,
+		mode: metadata.file_type.mode_bits() | metadata.mode,
+		nlink: metadata.nlink,
+		uid: metadata.uid,
+		gid: metadata.gid,
+		rdev: metadata.rdev as u64,
+		pad3: 0,
+		size: metadata.size as i64,
+		block_size: metadata.block_size,
+		blocks: metadata.blocks,
+		atime: metadata.atime,
+		atime_nsec: 0,
+		mtime: metadata.mtime,
+		mtime_nsec: 0,
+		ctime: metadata.ctime,
+		ctime_nsec: 0,
+		ino: metadata.ino,
+	}
+}
+
+// There are no symbolic links yet, so lstat and stat are the same syscall
+pub fn sys_stat(params: &mut SyscallParameters) -> SyscallResult {
+	let metadata = fs::stat(&read_path(params.arg(0))?)?;
+	uaccess::write_user(params.arg(1), &to_stat(&metadata)?)?;
+	Ok(0)
+}
+
+pub fn sys_fstat(params: &mut SyscallParameters) -> SyscallResult {
+	let metadata = get_file(params.arg(0))?.metadata();
+	uaccess::write_user(params.arg(1), &to_stat(&metadata)?)?;
+	Ok(0)
+}
+
+pub fn sys_stat64(params: &mut SyscallParameters) -> SyscallResult {
+	let metadata = fs::stat(&read_path(params.arg(0))?)?;
+	uaccess::write_user(params.arg(1), &to_stat64(&metadata))?;
+	Ok(0)
+}
+
+pub fn sys_fstat64(params: &mut SyscallParameters) -> SyscallResult {
+	let metadata = get_file(params.arg(0))?.metadata();
+	uaccess::write_user(params.arg(1), &to_stat64(&metadata))?;
+	Ok(0)
+}
+
+// struct linux_dirent: inode, offset of the next entry, record length, the name and the
+// type in the last byte of the record
+fn dirent(entry: &DirEntry, next: u64) -> Result<Vec<u8>, Errno> {
+	let ino = u32::try_from(entry.ino).map_err(|_| EOVERFLOW)?;
+	let next = u32::try_from(next).map_err(|_| EOVERFLOW)?;
+	let len = (DIRENT_HEADER + entry.name.len() + 2).next_multiple_of(4);
+	let mut record = alloc::vec![0; len];
+	record[0..4].copy_from_slice(&ino.to_le_bytes());
+	record[4..8].copy_from_slice(&next.to_le_bytes());
+	record[8..10].copy_from_slice(&(len as u16).to_le_bytes());
+	record[DIRENT_HEADER..DIRENT_HEADER + entry.name.len()].copy_from_slice(entry.name.as_bytes());
+	record[len - 1] = entry.file_type.dirent_type();
+	Ok(record)
+}
+
+// struct linux_dirent64: inode, offset of the next entry, record length, type and the name
+fn dirent64(entry: &DirEntry, next: u64) -> Result<Vec<u8>, Errno> {
+	let len = (DIRENT64_HEADER + entry.name.len() + 1).next_multiple_of(8);
+	let mut record = alloc::vec![0; len];
+	record[0..8].copy_from_slice(&entry.ino.to_le_bytes());
+	record[8..16].copy_from_slice(&next.to_le_bytes());
+	record[16..18].copy_from_slice(&(len as u16).to_le_bytes());
+	record[18] = entry.file_type.dirent_type();
+	record[DIRENT64_HEADER..DIRENT64_HEADER + entry.name.len()]
+		.copy_from_slice(entry.name.as_bytes());
+	Ok(record)
+}
+
+// Fills the user buffer with as many records as fit, EINVAL if not even the first one does
+fn getdents(
+	params: &SyscallParameters,
+	record: fn(&DirEntry, u64) -> Result<Vec<u8>, Errno>,
+) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	let buf_ptr = params.arg(1);
+	let count = params.arg(2) as usize;
+
+	let mut buffer = Vec::new();
+	let mut full = false;
+	file.read_dir(|entry, next| {
+		let record = record(entry, next)?;
+		if buffer.len() + record.len() > count {
+			full = true;
+			return Ok(false);
+		}
+		buffer.extend_from_slice(&record);
+		Ok(true)
+	})?;
+	if buffer.is_empty() && full {
+		return Err(EINVAL);
+	}
+	uaccess::copy_to_user(buf_ptr, &buffer)?;
+	Ok(buffer.len() as u32)
+}
+
+pub fn sys_getdents(params: &mut SyscallParameters) -> SyscallResult {
+	getdents(params, dirent)
+}
+
+pub fn sys_getdents64(params: &mut SyscallParameters) -> SyscallResult {
+	getdents(params, dirent64)
+}
+
+pub fn sys_mkdir(params: &mut SyscallParameters) -> SyscallResult {
+	fs::mkdir(&read_path(params.arg(0))?, params.arg(1))?;
+	Ok(0)
+}
+
+pub fn sys_unlink(params: &mut SyscallParameters) -> SyscallResult {
+	fs::unlink(&read_path(params.arg(0))?)?;
+	Ok(0)
+}
+
+pub fn sys_rmdir(params: &mut SyscallParameters) -> SyscallResult {
+	fs::rmdir(&read_path(params.arg(0))?)?;
+	Ok(0)
+}
+
+pub fn sys_link(params: &mut SyscallParameters) -> SyscallResult {
+	let old_path = read_path(params.arg(0))?;
+	let new_path = read_path(params.arg(1))?;
+	fs::link(&old_path, &new_path)?;
+	Ok(0)
+}
+
+fn truncate(path: u32, length: i64) -> SyscallResult {
+	let length = u64::try_from(length).map_err(|_| EINVAL)?;
+	fs::truncate(&read_path(path)?, length)?;
+	Ok(0)
+}
+
+fn ftruncate(fd: u32, length: i64) -> SyscallResult {
+	let length = u64::try_from(length).map_err(|_| EINVAL)?;
+	let file = get_file(fd)?;
+	if !file.writable() {
+		return Err(EINVAL);
+	}
+	file.inode.truncate(length)?;
+	Ok(0)
+}
+
+pub fn sys_truncate(params: &mut SyscallParameters) -> SyscallResult {
+	truncate(params.arg(0), params.arg(1) as i32 as i64)
+}
+
+pub fn sys_ftruncate(params: &mut SyscallParameters) -> SyscallResult {
+	ftruncate(params.arg(0), params.arg(1) as i32 as i64)
+}
+
+// The 64 bit length comes in two arguments, low half first
+pub fn sys_truncate64(params: &mut SyscallParameters) -> SyscallResult {
+	let length = ((params.arg(2) as u64) << 32) | params.arg(1) as u64;
+	truncate(params.arg(0), length as i64)
+}
+
+pub fn sys_ftruncate64(params: &mut SyscallParameters) -> SyscallResult {
+	let length = ((params.arg(2) as u64) << 32) | params.arg(1) as u64;
+	ftruncate(params.arg(0), length as i64)
+}
+
+pub fn sys_chdir(params: &mut SyscallParameters) -> SyscallResult {
+	fs::chdir(&read_path(params.arg(0))?)?;
+	Ok(0)
+}
+
+// Returns the length of the path including its terminating NUL
+pub fn sys_getcwd(params: &mut SyscallParameters) -> SyscallResult {
+	let buf_ptr = params.arg(0);
+	let size = params.arg(1) as usize;
+	let cwd = scheduler::with_current(|task| task.cwd.clone());
+	let mut path = cwd.map_or_else(|| String::from("/"), |cwd| cwd.path());
+	path.push('\0');
+	if path.len() > size {
+		return Err(ERANGE);
+	}
+	uaccess::copy_to_user(buf_ptr, path.as_bytes())?;
+	Ok(path.len() as u32)
+}
@@ -0,0 +1,143 @@
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+use super::{SyscallParameters, SyscallResult};
+use crate::exceptions::errno::{Errno, EINVAL, EOVERFLOW};
+use crate::fs::file::File;
+use crate::memory::uaccess;
+use crate::task::scheduler;
+
+const IOV_MAX: usize = 1024;
+// User buffers are copied through the kernel this many bytes at a time
+const CHUNK_SIZE: usize = 256;
+// Longest transfer of a single read or write, so the count fits in the return value
+const MAX_RW_COUNT: usize = i32::MAX as usize;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IoVec {
+	base: u32,
+	len: u32,
+}
+
+// Open file behind `fd` in the table of the current task
+pub fn get_file(fd: u32) -> Result<Arc<File>, Errno> {
+	scheduler::with_current(|task| task.files.get(fd))
+}
+
+// A transfer that fails after moving some bytes returns what it moved, like on Linux
+fn partial(done: usize, error: Errno) -> Result<usize, Errno> {
+	match done {
+		0 => Err(error),
+		done => Ok(done),
+	}
+}
+
+fn write_file(file: &File, buf_ptr: u32, count: usize) -> Result<usize, Errno> {
+	let mut chunk = [0u8; CHUNK_SIZE];
+	let mut done = 0;
+	while done < count {
+		let len = (count - done).min(CHUNK_SIZE);
+		let source = buf_ptr.wrapping_add(done as u32);
+		if let Err(errno) = uaccess::copy_from_user(&mut chunk[..len], source) {
+			return partial(done, errno);
+		}
+		let written = match file.write(&chunk[..len]) {
+			Ok(written) => written,
+			Err(errno) => return partial(done, errno),
+		};
+		done += written;
+		if written < len {
+			break;
+		}
+	}
+	Ok(done)
+}
+
+fn read_file(file: &File, buf_ptr: u32, count: usize) -> Result<usize, Errno> {
+	let mut chunk = [0u8; CHUNK_SIZE];
+	let mut done = 0;
+	while done < count {
+		let len = (count - done).min(CHUNK_SIZE);
+		let read = match file.read(&mut chunk[..len]) {
+			Ok(read) => read,
+			Err(errno) => return partial(done, errno),
+		};
+		let destination = buf_ptr.wrapping_add(done as u32);
+		if let Err(errno) = uaccess::copy_to_user(destination, &chunk[..read]) {
+			return partial(done, errno);
+		}
+		done += read;
+		if read < len {
+			break;
+		}
+	}
+	Ok(done)
+}
+
+pub fn sys_write(params: &mut SyscallParameters) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	let count = (params.arg(2) as usize).min(MAX_RW_COUNT);
+	Ok(write_file(&file, params.arg(1), count)? as u32)
+}
+
+pub fn sys_writev(params: &mut SyscallParameters) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	let iov = params.arg(1);
+	let count = params.arg(2) as usize;
+	if count > IOV_MAX {
+		return Err(EINVAL);
+	}
+
+	let mut written: usize = 0;
+	for index in 0..count {
+		let vector_ptr = iov.wrapping_add((index * size_of::<IoVec>()) as u32);
+		let vector: IoVec = uaccess::read_user(vector_ptr)?;
+		let len = vector.len as usize;
+		if len > MAX_RW_COUNT - written {
+			return Err(EINVAL);
+		}
+		let done = match write_file(&file, vector.base, len) {
+			Ok(done) => done,
+			Err(errno) => return partial(written, errno).map(|done| done as u32),
+		};
+		written += done;
+		if done < len {
+			break;
+		}
+	}
+	Ok(written as u32)
+}
+
+pub fn sys_read(params: &mut SyscallParameters) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	let count = (params.arg(2) as usize).min(MAX_RW_COUNT);
+	Ok(read_file(&file, params.arg(1), count)? as u32)
+}
+
+pub fn sys_lseek(params: &mut SyscallParameters) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	let offset = params.arg(1) as i32 as i64;
+	let position = file.seek(offset, params.arg(2))?;
+	// The offset has moved even if it cannot be returned, as on Linux
+	u32::try_from(position)
+		.ok()
+		.filter(|&position| position <= i32::MAX as u32)
+		.ok_or(EOVERFLOW)
+}
+
+// The argument is a value or a user pointer depending on the request, the device checks it
+pub fn sys_ioctl(params: &mut SyscallParameters) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	file.ioctl(params.arg(1), params.arg(2))
+}
+
+// lseek with a 64 bit offset split in two arguments, the result is stored at `result_ptr`
+pub fn sys_llseek(params: &mut SyscallParameters) -> SyscallResult {
+	let file = get_file(params.arg(0))?;
+	let offset = (((params.arg(1) as u64) << 32) | params.arg(2) as u64) as i64;
+	let result_ptr = params.arg(3);
+	let position = file.seek(offset, params.arg(4))?;
+	uaccess::write_user(result_ptr, &position)?;
+	Ok(0)
+}
@@ -0,0 +1,104 @@
+use super::{SyscallParameters, SyscallResult};
+use crate::exceptions::errno::{EINVAL, ENODEV, ENOMEM};
+use crate::memory::address_space::{self, USER_SPACE_END};
+use crate::memory::page_directory::PAGE_SIZE;
+use crate::memory::vma::{Vma, VmaBacking, VmaFlags, MAX_STACK_SIZE};
+
+const PROT_READ: u32 = 0x1;
+const PROT_WRITE: u32 = 0x2;
+const PROT_EXEC: u32 = 0x4;
+
+const MAP_SHARED: u32 = 0x01;
+const MAP_PRIVATE: u32 = 0x02;
+const MAP_FIXED: u32 = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+
+// Mappings without a hint go below the room reserved for the stack
+const MMAP_TOP: u32 = USER_SPACE_END - MAX_STACK_SIZE - PAGE_SIZE as u32;
+
+pub fn sys_brk(params: &mut SyscallParameters) -> SyscallResult {
+	let space = address_space::current().ok_or(ENOMEM)?;
+	let requested = params.arg(0);
+	if requested == 0 {
+		return Ok(space.current_brk());
+	}
+	Ok(space.brk(requested))
+}
+
+fn vma_flags(prot: u32) -> VmaFlags {
+	let mut flags = VmaFlags::empty();
+	if prot & PROT_READ != 0 {
+		flags |= VmaFlags::READ;
+	}
+	if prot & PROT_WRITE != 0 {
+		flags |= VmaFlags::WRITE;
+	}
+	if prot & PROT_EXEC != 0 {
+		flags |= VmaFlags::EXEC;
+	}
+	flags
+}
+
+// Only private anonymous mappings are supported until files and shared VMAs exist
+pub fn sys_mmap2(params: &mut SyscallParameters) -> SyscallResult {
+	let address = params.arg(0);
+	let length = params.arg(1);
+	let prot = params.arg(2);
+	let flags = params.arg(3);
+
+	if length == 0 || (flags & MAP_SHARED != 0) == (flags & MAP_PRIVATE != 0) {
+		return Err(EINVAL);
+	}
+	// A private VMA would be copied on fork, and the processes stop seeing each other's writes
+	if flags & MAP_SHARED != 0 {
+		return Err(EINVAL);
+	}
+	if flags & MAP_ANONYMOUS == 0 {
+		return Err(ENODEV);
+	}
+	let length = length
+		.checked_next_multiple_of(PAGE_SIZE as u32)
+		.ok_or(ENOMEM)?;
+
+	let space = address_space::current().ok_or(ENOMEM)?;
+	let start = if flags & MAP_FIXED != 0 {
+		if address % PAGE_SIZE as u32 != 0 {
+			return Err(EINVAL);
+		}
+		match address.checked_add(length) {
+			Some(end) if end <= USER_SPACE_END => space.unmap_range(address, end),
+			_ => return Err(ENOMEM),
+		}
+		address
+	} else {
+		let hint = address & !(PAGE_SIZE as u32 - 1);
+		let hint_free = hint != 0
+			&& hint.checked_add(length).is_some_and(|end| {
+				end <= USER_SPACE_END && !space.vmas().iter().any(|vma| vma.overlaps(hint, end))
+			});
+		if hint_free {
+			hint
+		} else {
+			space.find_free_range(length, MMAP_TOP).ok_or(ENOMEM)?
+		}
+	};
+
+	let vma = Vma::new(start, start + length, vma_flags(prot), VmaBacking::Anonymous);
+	space.add_vma(vma).map_err(|_| ENOMEM)?;
+	Ok(start)
+}
+
+pub fn sys_munmap(params: &mut SyscallParameters) -> SyscallResult {
+	let address = params.arg(0);
+	let length = params.arg(1);
+	if address % PAGE_SIZE as u32 != 0 || length == 0 {
+		return Err(EINVAL);
+	}
+	let end = match address.checked_add(length) {
+		Some(end) if end <= USER_SPACE_END => end,
+		_ => return Err(EINVAL),
+	};
+	let space = address_space::current().ok_or(EINVAL)?;
+	space.unmap_range(address, end);
+	Ok(0)
+}
@@ -0,0 +1,238 @@
+//! # Syscalls
+//!
+//! `int 0x80` follows the Linux i386 ABI: the syscall number is in EAX, the arguments in EBX,
+//! ECX, EDX, ESI, EDI and EBP, and the result comes back in EAX, a negated errno on failure.
+//! Numbers without a handler return `-ENOSYS` and are reported once on the serial log.
+
+pub mod fs;
+pub mod io;
+pub mod memory;
+pub mod numbers;
+pub mod process;
+pub mod signal;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::errno::{Errno, EBADF, EFAULT, ENOSYS, EPERM, ESPIPE};
+use super::interrupts::{self, TrapFrame};
+use crate::tools::debug::LogLevel;
+use numbers::*;
+
+pub struct SyscallParameters<'a> {
+	pub frame: &'a mut TrapFrame,
+}
+
+impl SyscallParameters<'_> {
+	// Arguments are passed in ebx, ecx, edx, esi, edi and ebp
+	pub fn arg(&self, index: usize) -> u32 {
+		let regs = &self.frame.regs;
+		match index {
+			0 => regs.ebx,
+			1 => regs.ecx,
+			2 => regs.edx,
+			3 => regs.esi,
+			4 => regs.edi,
+			5 => regs.ebp,
+			_ => panic!("Syscall argument {} out of range", index),
+		}
+	}
+}
+
+// Value returned in EAX on success, failures return the negated errno
+pub type SyscallResult = Result<u32, Errno>;
+
+type SyscallFn = fn(&mut SyscallParameters) -> SyscallResult;
+
+const fn syscall_table() -> [Option<SyscallFn>; NR_SYSCALLS] {
+	let mut table: [Option<SyscallFn>; NR_SYSCALLS] = [None; NR_SYSCALLS];
+	table[SYS_EXIT] = Some(process::sys_exit);
+	table[SYS_FORK] = Some(process::sys_fork);
+	table[SYS_READ] = Some(io::sys_read);
+	table[SYS_WRITE] = Some(io::sys_write);
+	table[SYS_OPEN] = Some(fs::sys_open);
+	table[SYS_CLOSE] = Some(fs::sys_close);
+	table[SYS_WAITPID] = Some(process::sys_waitpid);
+	table[SYS_CREAT] = Some(fs::sys_creat);
+	table[SYS_LINK] = Some(fs::sys_link);
+	table[SYS_UNLINK] = Some(fs::sys_unlink);
+	table[SYS_EXECVE] = Some(process::sys_execve);
+	table[SYS_CHDIR] = Some(fs::sys_chdir);
+	table[SYS_LSEEK] = Some(io::sys_lseek);
+	table[SYS_GETPID] = Some(process::sys_getpid);
+	table[SYS_KILL] = Some(signal::sys_kill);
+	table[SYS_MKDIR] = Some(fs::sys_mkdir);
+	table[SYS_RMDIR] = Some(fs::sys_rmdir);
+	table[SYS_BRK] = Some(memory::sys_brk);
+	table[SYS_IOCTL] = Some(io::sys_ioctl);
+	table[SYS_SETPGID] = Some(process::sys_setpgid);
+	table[SYS_GETPPID] = Some(process::sys_getppid);
+	table[SYS_GETPGRP] = Some(process::sys_getpgrp);
+	table[SYS_SETSID] = Some(process::sys_setsid);
+	table[SYS_SIGACTION] = Some(signal::sys_sigaction);
+	table[SYS_MUNMAP] = Some(memory::sys_munmap);
+	table[SYS_TRUNCATE] = Some(fs::sys_truncate);
+	table[SYS_FTRUNCATE] = Some(fs::sys_ftruncate);
+	table[SYS_STAT] = Some(fs::sys_stat);
+	table[SYS_LSTAT] = Some(fs::sys_stat);
+	table[SYS_FSTAT] = Some(fs::sys_fstat);
+	table[SYS_WAIT4] = Some(process::sys_wait4);
+	table[SYS_SIGRETURN] = Some(signal::sys_sigreturn);
+	table[SYS_SIGPROCMASK] = Some(signal::sys_sigprocmask);
+	table[SYS_GETPGID] = Some(process::sys_getpgid);
+	table[SYS_LLSEEK] = Some(io::sys_llseek);
+	table[SYS_GETDENTS] = Some(fs::sys_getdents);
+	table[SYS_WRITEV] = Some(io::sys_writev);
+	table[SYS_GETSID] = Some(process::sys_getsid);
+	table[SYS_SCHED_YIELD] = Some(process::sys_sched_yield);
+	table[SYS_RT_SIGRETURN] = Some(signal::sys_rt_sigreturn);
+	table[SYS_RT_SIGACTION] = Some(signal::sys_rt_sigaction);
+	table[SYS_RT_SIGPROCMASK] = Some(signal::sys_rt_sigprocmask);
+	table[SYS_GETCWD] = Some(fs::sys_getcwd);
+	table[SYS_MMAP2] = Some(memory::sys_mmap2);
+	table[SYS_TRUNCATE64] = Some(fs::sys_truncate64);
+	table[SYS_FTRUNCATE64] = Some(fs::sys_ftruncate64);
+	table[SYS_STAT64] = Some(fs::sys_stat64);
+	table[SYS_LSTAT64] = Some(fs::sys_stat64);
+	table[SYS_FSTAT64] = Some(fs::sys_fstat64);
+	table[SYS_GETDENTS64] = Some(fs::sys_getdents64);
+	table[SYS_GETTID] = Some(process::sys_getpid);
+	table[SYS_SET_THREAD_AREA] = Some(process::sys_set_thread_area);
+	table[SYS_EXIT_GROUP] = Some(process::sys_exit);
+	table[SYS_SET_TID_ADDRESS] = Some(process::sys_set_tid_address);
+	table
+}
+
+static SYSCALL_TABLE: [Option<SyscallFn>; NR_SYSCALLS] = syscall_table();
+
+// One bit per syscall number, set once the missing handler has been reported
+static REPORTED: [AtomicU32; NR_SYSCALLS.div_ceil(32)] =
+	[const { AtomicU32::new(0) }; NR_SYSCALLS.div_ceil(32)];
+
+fn report_unimplemented(number: usize) {
+	let bit = 1 << (number % 32);
+	if REPORTED[number / 32].fetch_or(bit, Ordering::Relaxed) & bit == 0 {
+		let name = match SYSCALL_NAMES[number] {
+			"" => "unknown",
+			name => name,
+		};
+		log!(
+			LogLevel::Warning,
+			"Syscall {} ({}) is not implemented",
+			number,
+			name
+		);
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn syscall_handler(frame: &mut TrapFrame) {
+	interrupts::count(interrupts::SYSCALL);
+	let result = syscall(frame);
+	frame.regs.eax = match result {
+		Ok(value) => value,
+		Err(errno) => (-errno) as u32,
+	};
+}
+
+pub fn syscall(frame: &mut TrapFrame) -> SyscallResult {
+	let number = frame.regs.eax as usize;
+	let syscall_func = match SYSCALL_TABLE.get(number) {
+		Some(Some(func)) => *func,
+		Some(None) => {
+			report_unimplemented(number);
+			return Err(ENOSYS);
+		}
+		None => {
+			log!(LogLevel::Warning, "Syscall {:#x} out of range", number);
+			return Err(ENOSYS);
+		}
+	};
+
+	log!(
+		LogLevel::Debug,
+		"INT 0x80 (syscall) called with eax {} ({})",
+		number,
+		SYSCALL_NAMES[number]
+	);
+
+	let mut params = SyscallParameters { frame };
+	syscall_func(&mut params)
+}
+
+// Issues `int 0x80` from the kernel, returns the raw value of EAX
+pub fn kernel_syscall(num: u32, arg0: u32, arg1: u32, arg2: u32) -> u32 {
+	let result: u32;
+	unsafe {
+		core::arch::asm!(
+			"push ebx",
+			"mov ebx, {arg0}",
+			"int 0x80",
+			"pop ebx",
+			arg0 = in(reg) arg0,
+			inlateout("eax") num => result,
+			in("ecx") arg1,
+			in("edx") arg2,
+		);
+	}
+	result
+}
+
+pub fn syscall_test() {
+	use crate::fs::fd::FdTable;
+	use crate::memory::address_space::{self, AddressSpace};
+	use crate::memory::uaccess;
+	use crate::memory::vma::{Vma, VmaBacking, VmaFlags};
+	use crate::task::scheduler;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the syscall entry\n");
+
+	log!(LogLevel::Info, "Unknown and unimplemented syscalls should return -ENOSYS\n");
+	assert!(kernel_syscall(0xdead, 0, 0, 0) as i32 == -ENOSYS);
+	assert!(kernel_syscall(0, 0, 0, 0) as i32 == -ENOSYS);
+	// The second call finds the syscall reported and stays quiet
+	assert!(kernel_syscall(0, 0, 0, 0) as i32 == -ENOSYS);
+	assert!(REPORTED[0].load(Ordering::Relaxed) & 1 == 1);
+
+	log!(LogLevel::Info, "exit from a kernel thread should return -EPERM\n");
+	assert!(kernel_syscall(SYS_EXIT as u32, 0, 0, 0) as i32 == -EPERM);
+	assert!(kernel_syscall(SYS_EXIT_GROUP as u32, 0, 0, 0) as i32 == -EPERM);
+
+	log!(LogLevel::Info, "write should return the number of bytes written in eax\n");
+	let mut space =
+		alloc::boxed::Box::new(AddressSpace::new().expect("Failed to create address space"));
+	space
+		.add_vma(Vma::new(
+			0x1000_0000,
+			0x1000_1000,
+			VmaFlags::READ | VmaFlags::WRITE,
+			VmaBacking::Anonymous,
+		))
+		.unwrap();
+	space.activate();
+	let files = scheduler::with_current(|task| core::mem::replace(&mut task.files, FdTable::console()));
+	let message = b"syscall ok\n";
+	uaccess::copy_to_user(0x1000_0000, message).unwrap();
+	let written = kernel_syscall(SYS_WRITE as u32, 1, 0x1000_0000, message.len() as u32);
+	assert!(written as usize == message.len());
+	assert!(kernel_syscall(SYS_WRITE as u32, 42, 0, 0) as i32 == -EBADF);
+
+	log!(LogLevel::Info, "write from a kernel or unmapped buffer should return -EFAULT\n");
+	let kernel_buffer = message.as_ptr() as u32;
+	assert!(kernel_syscall(SYS_WRITE as u32, 1, kernel_buffer, 4) as i32 == -EFAULT);
+	assert!(kernel_syscall(SYS_WRITE as u32, 1, 0x1000_0ffc, 8) as i32 == -EFAULT);
+
+	log!(LogLevel::Info, "Descriptors should come from the table of the task\n");
+	assert!(kernel_syscall(SYS_READ as u32, 0, 0x1000_0000, 16) == 0);
+	assert!(kernel_syscall(SYS_CLOSE as u32, 1, 0, 0) == 0);
+	assert!(kernel_syscall(SYS_WRITE as u32, 1, 0x1000_0000, 4) as i32 == -EBADF);
+	assert!(kernel_syscall(SYS_LSEEK as u32, 2, 0, 0) as i32 == -ESPIPE);
+	scheduler::with_current(|task| task.files = files);
+	address_space::switch_to_kernel();
+	drop(space);
+
+	log!(LogLevel::Info, "getpid should return the pid of the calling task\n");
+	assert!(kernel_syscall(SYS_GETPID as u32, 0, 0, 0) == scheduler::current_pid());
+
+	log!(LogLevel::Info, "\t\tEnd of syscall entry test\n");
+}
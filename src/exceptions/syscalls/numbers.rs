@@ -0,0 +1,146 @@
+//! # Syscall Numbers
+//!
+//! Numbering of the Linux i386 syscall table. Only the numbers the kernel implements get a
+//! constant, `SYSCALL_NAMES` covers the whole table so unimplemented calls can be reported.
+
+pub const SYS_EXIT: usize = 1;
+pub const SYS_FORK: usize = 2;
+pub const SYS_READ: usize = 3;
+pub const SYS_WRITE: usize = 4;
+pub const SYS_OPEN: usize = 5;
+pub const SYS_CLOSE: usize = 6;
+pub const SYS_WAITPID: usize = 7;
+pub const SYS_CREAT: usize = 8;
+pub const SYS_LINK: usize = 9;
+pub const SYS_UNLINK: usize = 10;
+pub const SYS_EXECVE: usize = 11;
+pub const SYS_CHDIR: usize = 12;
+pub const SYS_LSEEK: usize = 19;
+pub const SYS_GETPID: usize = 20;
+pub const SYS_KILL: usize = 37;
+pub const SYS_MKDIR: usize = 39;
+pub const SYS_RMDIR: usize = 40;
+pub const SYS_BRK: usize = 45;
+pub const SYS_IOCTL: usize = 54;
+pub const SYS_SETPGID: usize = 57;
+pub const SYS_GETPPID: usize = 64;
+pub const SYS_GETPGRP: usize = 65;
+pub const SYS_SETSID: usize = 66;
+pub const SYS_SIGACTION: usize = 67;
+pub const SYS_MUNMAP: usize = 91;
+pub const SYS_TRUNCATE: usize = 92;
+pub const SYS_FTRUNCATE: usize = 93;
+pub const SYS_STAT: usize = 106;
+pub const SYS_LSTAT: usize = 107;
+pub const SYS_FSTAT: usize = 108;
+pub const SYS_WAIT4: usize = 114;
+pub const SYS_SIGRETURN: usize = 119;
+pub const SYS_SIGPROCMASK: usize = 126;
+pub const SYS_GETPGID: usize = 132;
+pub const SYS_LLSEEK: usize = 140;
+pub const SYS_GETDENTS: usize = 141;
+pub const SYS_WRITEV: usize = 146;
+pub const SYS_GETSID: usize = 147;
+pub const SYS_SCHED_YIELD: usize = 158;
+pub const SYS_RT_SIGRETURN: usize = 173;
+pub const SYS_RT_SIGACTION: usize = 174;
+pub const SYS_RT_SIGPROCMASK: usize = 175;
+pub const SYS_GETCWD: usize = 183;
+pub const SYS_MMAP2: usize = 192;
+pub const SYS_TRUNCATE64: usize = 193;
+pub const SYS_FTRUNCATE64: usize = 194;
+pub const SYS_STAT64: usize = 195;
+pub const SYS_LSTAT64: usize = 196;
+pub const SYS_FSTAT64: usize = 197;
+pub const SYS_GETDENTS64: usize = 220;
+pub const SYS_GETTID: usize = 224;
+pub const SYS_SET_THREAD_AREA: usize = 243;
+pub const SYS_EXIT_GROUP: usize = 252;
+pub const SYS_SET_TID_ADDRESS: usize = 258;
+
+pub const NR_SYSCALLS: usize = 387;
+
+// Empty names are holes in the table
+pub static SYSCALL_NAMES: [&str; NR_SYSCALLS] = [
+	"restart_syscall", "exit", "fork", "read", "write", "open", "close", "waitpid",
+	"creat", "link", "unlink", "execve", "chdir", "time", "mknod", "chmod",
+	"lchown", "break", "oldstat", "lseek", "getpid", "mount", "umount", "setuid",
+	"getuid", "stime", "ptrace", "alarm", "oldfstat", "pause", "utime", "stty",
+	"gtty", "access", "nice", "ftime", "sync", "kill", "rename", "mkdir",
+	"rmdir", "dup", "pipe", "times", "prof", "brk", "setgid", "getgid",
+	"signal", "geteuid", "getegid", "acct", "umount2", "lock", "ioctl", "fcntl",
+	"mpx", "setpgid", "ulimit", "oldolduname", "umask", "chroot", "ustat", "dup2",
+	"getppid", "getpgrp", "setsid", "sigaction", "sgetmask", "ssetmask", "setreuid", "setregid",
+	"sigsuspend", "sigpending", "sethostname", "setrlimit", "getrlimit", "getrusage",
+	"gettimeofday", "settimeofday",
+	"getgroups", "setgroups", "select", "symlink", "oldlstat", "readlink", "uselib", "swapon",
+	"reboot", "readdir", "mmap", "munmap", "truncate", "ftruncate", "fchmod", "fchown",
+	"getpriority", "setpriority", "profil", "statfs", "fstatfs", "ioperm", "socketcall",
+	"syslog",
+	"setitimer", "getitimer", "stat", "lstat", "fstat", "olduname", "iopl", "vhangup",
+	"idle", "vm86old", "wait4", "swapoff", "sysinfo", "ipc", "fsync", "sigreturn",
+	"clone", "setdomainname", "uname", "modify_ldt", "adjtimex", "mprotect", "sigprocmask",
+	"create_module",
+	"init_module", "delete_module", "get_kernel_syms", "quotactl", "getpgid", "fchdir",
+	"bdflush", "sysfs",
+	"personality", "afs_syscall", "setfsuid", "setfsgid", "_llseek", "getdents", "_newselect",
+	"flock",
+	"msync", "readv", "writev", "getsid", "fdatasync", "_sysctl", "mlock", "munlock",
+	"mlockall", "munlockall", "sched_setparam", "sched_getparam", "sched_setscheduler",
+	"sched_getscheduler", "sched_yield", "sched_get_priority_max",
+	"sched_get_priority_min", "sched_rr_get_interval", "nanosleep", "mremap", "setresuid",
+	"getresuid", "vm86", "query_module",
+	"poll", "nfsservctl", "setresgid", "getresgid", "prctl", "rt_sigreturn", "rt_sigaction",
+	"rt_sigprocmask",
+	"rt_sigpending", "rt_sigtimedwait", "rt_sigqueueinfo", "rt_sigsuspend", "pread64",
+	"pwrite64", "chown", "getcwd",
+	"capget", "capset", "sigaltstack", "sendfile", "getpmsg", "putpmsg", "vfork", "ugetrlimit",
+	"mmap2", "truncate64", "ftruncate64", "stat64", "lstat64", "fstat64", "lchown32",
+	"getuid32",
+	"getgid32", "geteuid32", "getegid32", "setreuid32", "setregid32", "getgroups32",
+	"setgroups32", "fchown32",
+	"setresuid32", "getresuid32", "setresgid32", "getresgid32", "chown32", "setuid32",
+	"setgid32", "setfsuid32",
+	"setfsgid32", "pivot_root", "mincore", "madvise", "getdents64", "fcntl64", "", "",
+	"gettid", "readahead", "setxattr", "lsetxattr", "fsetxattr", "getxattr", "lgetxattr",
+	"fgetxattr",
+	"listxattr", "llistxattr", "flistxattr", "removexattr", "lremovexattr", "fremovexattr",
+	"tkill", "sendfile64",
+	"futex", "sched_setaffinity", "sched_getaffinity", "set_thread_area", "get_thread_area",
+	"io_setup", "io_destroy", "io_getevents",
+	"io_submit", "io_cancel", "fadvise64", "", "exit_group", "lookup_dcookie", "epoll_create",
+	"epoll_ctl",
+	"epoll_wait", "remap_file_pages", "set_tid_address", "timer_create", "timer_settime",
+	"timer_gettime", "timer_getoverrun", "timer_delete",
+	"clock_settime", "clock_gettime", "clock_getres", "clock_nanosleep", "statfs64",
+	"fstatfs64", "tgkill", "utimes",
+	"fadvise64_64", "vserver", "mbind", "get_mempolicy", "set_mempolicy", "mq_open",
+	"mq_unlink", "mq_timedsend",
+	"mq_timedreceive", "mq_notify", "mq_getsetattr", "kexec_load", "waitid", "", "add_key",
+	"request_key",
+	"keyctl", "ioprio_set", "ioprio_get", "inotify_init", "inotify_add_watch",
+	"inotify_rm_watch", "migrate_pages", "openat",
+	"mkdirat", "mknodat", "fchownat", "futimesat", "fstatat64", "unlinkat", "renameat",
+	"linkat",
+	"symlinkat", "readlinkat", "fchmodat", "faccessat", "pselect6", "ppoll", "unshare",
+	"set_robust_list",
+	"get_robust_list", "splice", "sync_file_range", "tee", "vmsplice", "move_pages", "getcpu",
+	"epoll_pwait",
+	"utimensat", "signalfd", "timerfd_create", "eventfd", "fallocate", "timerfd_settime",
+	"timerfd_gettime", "signalfd4",
+	"eventfd2", "epoll_create1", "dup3", "pipe2", "inotify_init1", "preadv", "pwritev",
+	"rt_tgsigqueueinfo",
+	"perf_event_open", "recvmmsg", "fanotify_init", "fanotify_mark", "prlimit64",
+	"name_to_handle_at", "open_by_handle_at", "clock_adjtime",
+	"syncfs", "sendmmsg", "setns", "process_vm_readv", "process_vm_writev", "kcmp",
+	"finit_module", "sched_setattr",
+	"sched_getattr", "renameat2", "seccomp", "getrandom", "memfd_create", "bpf", "execveat",
+	"socket",
+	"socketpair", "bind", "connect", "listen", "accept4", "getsockopt", "setsockopt",
+	"getsockname",
+	"getpeername", "sendto", "sendmsg", "recvfrom", "recvmsg", "shutdown", "userfaultfd",
+	"membarrier",
+	"mlock2", "copy_file_range", "preadv2", "pwritev2", "pkey_mprotect", "pkey_alloc",
+	"pkey_free", "statx",
+	"arch_prctl", "io_pgetevents", "rseq",
+];
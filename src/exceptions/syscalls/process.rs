@@ -0,0 +1,304 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use super::{SyscallParameters, SyscallResult};
+use crate::exceptions::errno::{
+	Errno, E2BIG, EACCES, ECHILD, EINVAL, ENOEXEC, ENOMEM, EPERM, ESRCH,
+};
+use crate::exceptions::interrupts::GeneralRegs;
+use crate::fs::{self, inode::FileType};
+use crate::gdt::{TlsSegment, TLS_ENTRY};
+use crate::memory::uaccess;
+use crate::task::elf;
+use crate::task::scheduler::{self, WaitTarget};
+use crate::task::task::{exit_status, Pid};
+use crate::tools::debug::LogLevel;
+
+// struct user_desc as passed to set_thread_area
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct UserDesc {
+	entry_number: u32,
+	base_addr: u32,
+	limit: u32,
+	flags: u32,
+}
+
+const USER_DESC_SEG_32BIT: u32 = 1 << 0;
+const USER_DESC_LIMIT_IN_PAGES: u32 = 1 << 4;
+const USER_DESC_SEG_NOT_PRESENT: u32 = 1 << 5;
+
+const WNOHANG: u32 = 1;
+const WUNTRACED: u32 = 2;
+// Size of struct rusage, which wait4 fills with zeros
+const RUSAGE_SIZE: usize = 18 * size_of::<u32>();
+
+const MAX_ARG_STRINGS: usize = 1024;
+
+pub fn sys_exit(params: &mut SyscallParameters) -> SyscallResult {
+	// A kernel thread, such as the shell trying syscalls, would never come back
+	if !params.frame.from_user() {
+		return Err(EPERM);
+	}
+	log!(
+		LogLevel::Debug,
+		"Syscall exit called with code {}",
+		params.arg(0)
+	);
+	scheduler::exit(exit_status(params.arg(0)));
+}
+
+pub fn sys_getpid(_params: &mut SyscallParameters) -> SyscallResult {
+	Ok(scheduler::current_pid())
+}
+
+pub fn sys_getppid(_params: &mut SyscallParameters) -> SyscallResult {
+	Ok(scheduler::with_current(|task| task.ppid))
+}
+
+pub fn sys_setpgid(params: &mut SyscallParameters) -> SyscallResult {
+	let (pid, pgid) = (params.arg(0) as i32, params.arg(1) as i32);
+	if pid < 0 || pgid < 0 {
+		return Err(EINVAL);
+	}
+	scheduler::setpgid(pid as Pid, pgid as Pid).map(|_| 0)
+}
+
+// Process group of `pid`, or of the caller when 0
+pub fn sys_getpgid(params: &mut SyscallParameters) -> SyscallResult {
+	match params.arg(0) {
+		0 => Ok(scheduler::with_current(|task| task.pgid)),
+		pid => scheduler::with_task(pid, |task| task.pgid).ok_or(ESRCH),
+	}
+}
+
+pub fn sys_getpgrp(_params: &mut SyscallParameters) -> SyscallResult {
+	Ok(scheduler::with_current(|task| task.pgid))
+}
+
+pub fn sys_setsid(_params: &mut SyscallParameters) -> SyscallResult {
+	scheduler::setsid()
+}
+
+pub fn sys_getsid(params: &mut SyscallParameters) -> SyscallResult {
+	match params.arg(0) {
+		0 => Ok(scheduler::with_current(|task| task.sid)),
+		pid => scheduler::with_task(pid, |task| task.sid).ok_or(ESRCH),
+	}
+}
+
+// The child gets a copy of the descriptor table, both share the open files
+pub fn sys_fork(params: &mut SyscallParameters) -> SyscallResult {
+	if !params.frame.from_user() {
+		return Err(EINVAL);
+	}
+	scheduler::fork(params.frame).map_err(|error| {
+		log!(LogLevel::Error, "fork failed: {}", error);
+		ENOMEM
+	})
+}
+
+fn wait(pid: i32, status_ptr: u32, options: u32) -> SyscallResult {
+	if options & !(WNOHANG | WUNTRACED) != 0 {
+		return Err(EINVAL);
+	}
+	let target = match pid {
+		-1 => WaitTarget::Any,
+		0 => WaitTarget::Group(scheduler::with_current(|task| task.pgid)),
+		pid if pid > 0 => WaitTarget::Pid(pid as Pid),
+		pid => WaitTarget::Group(pid.unsigned_abs()),
+	};
+
+	let nohang = options & WNOHANG != 0;
+	match scheduler::wait(target, nohang, options & WUNTRACED != 0)? {
+		Some((child, status)) => {
+			if status_ptr != 0 {
+				uaccess::write_user(status_ptr, &status)?;
+			}
+			Ok(child)
+		}
+		None => Ok(0),
+	}
+}
+
+pub fn sys_waitpid(params: &mut SyscallParameters) -> SyscallResult {
+	wait(params.arg(0) as i32, params.arg(1), params.arg(2))
+}
+
+pub fn sys_wait4(params: &mut SyscallParameters) -> SyscallResult {
+	let rusage = params.arg(3);
+	let child = wait(params.arg(0) as i32, params.arg(1), params.arg(2))?;
+	if rusage != 0 {
+		uaccess::copy_to_user(rusage, &[0; RUSAGE_SIZE])?;
+	}
+	Ok(child)
+}
+
+pub fn read_user_string(address: u32, max_len: usize, error: Errno) -> Result<String, Errno> {
+	let mut buffer = alloc::vec![0; max_len];
+	let len = uaccess::strncpy_from_user(&mut buffer, address)?;
+	if len == buffer.len() {
+		return Err(error);
+	}
+	buffer.truncate(len);
+	String::from_utf8(buffer).map_err(|_| EINVAL)
+}
+
+// Reads a NULL terminated array of strings such as argv, a NULL array is empty
+fn read_user_strings(address: u32) -> Result<Vec<String>, Errno> {
+	let mut strings = Vec::new();
+	if address == 0 {
+		return Ok(strings);
+	}
+	let mut total = 0;
+	loop {
+		let entry = address.wrapping_add((strings.len() * size_of::<u32>()) as u32);
+		let pointer: u32 = uaccess::read_user(entry)?;
+		if pointer == 0 {
+			return Ok(strings);
+		}
+		if strings.len() == MAX_ARG_STRINGS {
+			return Err(E2BIG);
+		}
+		let string = read_user_string(pointer, elf::MAX_ARGS_SIZE - total, E2BIG)?;
+		total += string.len() + 1;
+		strings.push(string);
+	}
+}
+
+// Replaces the image of the calling task, the syscall returns straight into the new entry
+// point with every register cleared
+pub fn sys_execve(params: &mut SyscallParameters) -> SyscallResult {
+	if !params.frame.from_user() {
+		return Err(EINVAL);
+	}
+	let path = super::fs::read_path(params.arg(0))?;
+	let argv = read_user_strings(params.arg(1))?;
+	let envp = read_user_strings(params.arg(2))?;
+	if fs::stat(&path)?.file_type != FileType::Regular {
+		return Err(EACCES);
+	}
+	let data = fs::read_file(&path)?.into();
+
+	let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+	let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+	let (space, entry, stack) = elf::load_program(data, &argv, &envp).map_err(|error| {
+		log!(LogLevel::Error, "execve {}: {}", path, error);
+		ENOEXEC
+	})?;
+
+	let name = path.rsplit('/').next().unwrap_or(&path);
+	let (old_space, closed) = scheduler::with_current(|task| {
+		task.name = String::from(name);
+		task.tls = None;
+		task.signals.exec();
+		let old_space = task.address_space.replace(space);
+		if let Some(space) = &task.address_space {
+			space.activate();
+		}
+		(old_space, task.files.exec())
+	});
+	crate::gdt::set_tls(None);
+	drop(old_space);
+	drop(closed);
+
+	let frame = &mut *params.frame;
+	frame.regs = GeneralRegs::default();
+	frame.fs = 0;
+	frame.gs = 0;
+	frame.eip = entry;
+	frame.user_esp = stack;
+	Ok(0)
+}
+
+pub fn sys_sched_yield(_params: &mut SyscallParameters) -> SyscallResult {
+	scheduler::yield_now();
+	Ok(0)
+}
+
+// Threads are not supported yet, the address to clear on exit is ignored
+pub fn sys_set_tid_address(_params: &mut SyscallParameters) -> SyscallResult {
+	Ok(scheduler::current_pid())
+}
+
+// Every task has a single TLS entry, an entry_number of -1 asks the kernel to pick it
+pub fn sys_set_thread_area(params: &mut SyscallParameters) -> SyscallResult {
+	let desc_ptr = params.arg(0);
+	let mut desc: UserDesc = uaccess::read_user(desc_ptr)?;
+
+	if desc.entry_number != u32::MAX && desc.entry_number != TLS_ENTRY as u32 {
+		return Err(ESRCH);
+	}
+	if desc.flags & USER_DESC_SEG_32BIT == 0 && desc.flags & USER_DESC_SEG_NOT_PRESENT == 0 {
+		return Err(EINVAL);
+	}
+
+	let segment = (desc.flags & USER_DESC_SEG_NOT_PRESENT == 0).then_some(TlsSegment {
+		base: desc.base_addr,
+		limit: desc.limit,
+		limit_in_pages: desc.flags & USER_DESC_LIMIT_IN_PAGES != 0,
+	});
+	scheduler::with_current(|task| task.tls = segment);
+	crate::gdt::set_tls(segment);
+
+	desc.entry_number = TLS_ENTRY as u32;
+	uaccess::write_user(desc_ptr, &desc)?;
+	Ok(0)
+}
+
+// exit(41)
+const EXIT_CODE: [u8; 14] = [
+	0xb8, 0x01, 0x00, 0x00, 0x00, 0xbb, 0x29, 0x00, 0x00, 0x00, 0xcd, 0x80, 0xeb, 0xfe,
+];
+// Forks, the child runs execve("/bin/exit", NULL, NULL) and the parent exits with the exit
+// code of the child plus one, collected with waitpid
+const FORK_CODE: [u8; 85] = [
+	0xb8, 0x02, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x85, 0xc0, 0x74, 0x1e, 0x89, 0xc3, 0x83, 0xec,
+	0x04, 0x89, 0xe1, 0x31, 0xd2, 0xb8, 0x07, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x8b, 0x1c, 0x24,
+	0xc1, 0xeb, 0x08, 0x43, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xcd, 0x80, 0xe8, 0x00, 0x00, 0x00,
+	0x00, 0x5b, 0x81, 0xc3, 0x1d, 0x00, 0x00, 0x00, 0x31, 0xc9, 0x31, 0xd2, 0xb8, 0x0b, 0x00,
+	0x00, 0x00, 0xcd, 0x80, 0x89, 0xc3, 0xf7, 0xdb, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xcd, 0x80,
+	0x2f, 0x62, 0x69, 0x6e, 0x2f, 0x65, 0x78, 0x69, 0x74, 0x00,
+];
+
+static TEST_STATUS: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+// Parent of the user program, a kernel thread since the idle task never waits
+fn process_test_thread() {
+	let image = elf::test_image(&FORK_CODE);
+	let pid = elf::spawn("fork-test", image.into(), &["fork-test"], &[])
+		.expect("Failed to spawn the fork test");
+	let (child, status) = scheduler::wait(WaitTarget::Pid(pid), false, false)
+		.expect("wait failed")
+		.expect("wait returned without a child");
+	assert!(child == pid);
+	assert!(scheduler::wait(WaitTarget::Any, true, false) == Err(ECHILD));
+	TEST_STATUS.store(status, core::sync::atomic::Ordering::SeqCst);
+}
+
+pub fn process_test() {
+	use core::sync::atomic::Ordering;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the process lifecycle\n");
+
+	log!(LogLevel::Info, "fork, execve, exit and waitpid from a user program\n");
+	fs::write_file("/bin/exit", &elf::test_image(&EXIT_CODE), 0o755)
+		.expect("Failed to write /bin/exit");
+	let tasks = scheduler::task_list().len();
+	scheduler::spawn("process-test", process_test_thread).expect("Failed to spawn thread");
+	while TEST_STATUS.load(Ordering::SeqCst) == 0 {
+		scheduler::yield_now();
+	}
+	println_srl!("\tParent exited with status {:#x}", TEST_STATUS.load(Ordering::SeqCst));
+	assert!(TEST_STATUS.load(Ordering::SeqCst) == exit_status(42));
+
+	log!(LogLevel::Info, "Every task should be gone once reaped\n");
+	while scheduler::task_list().len() != tasks {
+		scheduler::yield_now();
+		scheduler::reap();
+	}
+
+	log!(LogLevel::Info, "\t\tEnd of process lifecycle test\n");
+}
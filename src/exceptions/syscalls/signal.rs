@@ -0,0 +1,184 @@
+use core::mem::size_of;
+
+use super::{SyscallParameters, SyscallResult};
+use crate::exceptions::errno::{Errno, EINVAL, EPERM, ESRCH};
+use crate::memory::uaccess;
+use crate::task::scheduler;
+use crate::task::signal::{self, SigAction, SigSet};
+use crate::task::task::{Pid, TaskState};
+
+const SIG_BLOCK: u32 = 0;
+const SIG_UNBLOCK: u32 = 1;
+const SIG_SETMASK: u32 = 2;
+
+// struct old_sigaction as passed to sigaction, its mask only covers the first 32 signals
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct OldSigAction {
+	handler: u32,
+	mask: u32,
+	flags: u32,
+	restorer: u32,
+}
+
+// kill with pid -1 reaches every user task but init and the caller, 0 the process group of
+// the caller and the other negative pids the group -pid.
+pub fn sys_kill(params: &mut SyscallParameters) -> SyscallResult {
+	let pid = params.arg(0) as i32;
+	let signal = params.arg(1);
+
+	match pid {
+		pid if pid > 0 => signal::send(pid as Pid, signal).map(|_| 0),
+		-1 => {
+			let current = scheduler::current_pid();
+			let mut result = Err(ESRCH);
+			for task in scheduler::task_list() {
+				if task.pid == current || task.pid == scheduler::INIT_PID {
+					continue;
+				}
+				if matches!(task.state, TaskState::Zombie | TaskState::Dead) {
+					continue;
+				}
+				match signal::send(task.pid, signal) {
+					Ok(()) => result = Ok(0),
+					Err(EPERM) => {}
+					Err(errno) => return Err(errno),
+				}
+			}
+			result
+		}
+		0 => signal::send_group(scheduler::with_current(|task| task.pgid), signal).map(|_| 0),
+		pid => signal::send_group(pid.unsigned_abs(), signal).map(|_| 0),
+	}
+}
+
+// Swaps the action of `signal` for `new` if given, returns the previous one
+fn change_action(signal: u32, new: Option<SigAction>) -> Result<SigAction, Errno> {
+	if signal == 0 || signal as usize > signal::NSIG {
+		return Err(EINVAL);
+	}
+	scheduler::with_current(|task| {
+		let old = task.signals.action(signal);
+		if let Some(action) = new {
+			task.signals.set_action(signal, action)?;
+		}
+		Ok(old)
+	})
+}
+
+pub fn sys_sigaction(params: &mut SyscallParameters) -> SyscallResult {
+	let signal = params.arg(0);
+	let new_ptr = params.arg(1);
+	let old_ptr = params.arg(2);
+
+	let new = match new_ptr {
+		0 => None,
+		address => {
+			let action: OldSigAction = uaccess::read_user(address)?;
+			Some(SigAction {
+				handler: action.handler,
+				flags: action.flags,
+				restorer: action.restorer,
+				mask: action.mask as SigSet,
+			})
+		}
+	};
+	let old = change_action(signal, new)?;
+	if old_ptr != 0 {
+		let action = OldSigAction {
+			handler: old.handler,
+			mask: old.mask as u32,
+			flags: old.flags,
+			restorer: old.restorer,
+		};
+		uaccess::write_user(old_ptr, &action)?;
+	}
+	Ok(0)
+}
+
+pub fn sys_rt_sigaction(params: &mut SyscallParameters) -> SyscallResult {
+	let signal = params.arg(0);
+	let new_ptr = params.arg(1);
+	let old_ptr = params.arg(2);
+	if params.arg(3) as usize != size_of::<SigSet>() {
+		return Err(EINVAL);
+	}
+
+	let new = match new_ptr {
+		0 => None,
+		address => Some(uaccess::read_user::<SigAction>(address)?),
+	};
+	let old = change_action(signal, new)?;
+	if old_ptr != 0 {
+		uaccess::write_user(old_ptr, &old)?;
+	}
+	Ok(0)
+}
+
+// Applies `how` with `set` to the blocked signals, returns the previous mask
+fn change_mask(how: u32, set: Option<SigSet>) -> Result<SigSet, Errno> {
+	if set.is_some() && !matches!(how, SIG_BLOCK | SIG_UNBLOCK | SIG_SETMASK) {
+		return Err(EINVAL);
+	}
+	Ok(scheduler::with_current(|task| {
+		let old = task.signals.blocked;
+		match (how, set) {
+			(SIG_BLOCK, Some(set)) => task.signals.set_blocked(old | set),
+			(SIG_UNBLOCK, Some(set)) => task.signals.set_blocked(old & !set),
+			(SIG_SETMASK, Some(set)) => task.signals.set_blocked(set),
+			_ => {}
+		}
+		old
+	}))
+}
+
+pub fn sys_sigprocmask(params: &mut SyscallParameters) -> SyscallResult {
+	let how = params.arg(0);
+	let set_ptr = params.arg(1);
+	let old_ptr = params.arg(2);
+
+	let set = match set_ptr {
+		0 => None,
+		address => {
+			// Only the first 32 signals are covered, the others keep their state
+			let set: u32 = uaccess::read_user(address)?;
+			let blocked = scheduler::with_current(|task| task.signals.blocked);
+			Some(match how {
+				SIG_SETMASK => (blocked & !(u32::MAX as SigSet)) | set as SigSet,
+				_ => set as SigSet,
+			})
+		}
+	};
+	let old = change_mask(how, set)?;
+	if old_ptr != 0 {
+		uaccess::write_user(old_ptr, &(old as u32))?;
+	}
+	Ok(0)
+}
+
+pub fn sys_rt_sigprocmask(params: &mut SyscallParameters) -> SyscallResult {
+	let how = params.arg(0);
+	let set_ptr = params.arg(1);
+	let old_ptr = params.arg(2);
+	if params.arg(3) as usize != size_of::<SigSet>() {
+		return Err(EINVAL);
+	}
+
+	let set = match set_ptr {
+		0 => None,
+		address => Some(uaccess::read_user::<SigSet>(address)?),
+	};
+	let old = change_mask(how, set)?;
+	if old_ptr != 0 {
+		uaccess::write_user(old_ptr, &old)?;
+	}
+	Ok(0)
+}
+
+pub fn sys_sigreturn(params: &mut SyscallParameters) -> SyscallResult {
+	Ok(signal::sigreturn(params.frame, false))
+}
+
+pub fn sys_rt_sigreturn(params: &mut SyscallParameters) -> SyscallResult {
+	Ok(signal::sigreturn(params.frame, true))
+}
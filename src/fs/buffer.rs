@@ -0,0 +1,217 @@
+//! # Buffer Cache
+//!
+//! Caches the blocks of a block device for the filesystem mounted on it. A block is a
+//! multiple of the sector size, read on first use and kept in a `Buffer` shared by everyone
+//! using it. Writes only mark the buffer dirty: it goes back to the device when it is evicted
+//! to make room for another block or when the cache is synced.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::drivers::block::BlockDevice;
+use crate::exceptions::errno::{Errno, EINVAL};
+
+pub struct Buffer {
+	block: u64,
+	data: Mutex<Vec<u8>>,
+	dirty: Mutex<bool>,
+}
+
+impl Buffer {
+	pub fn read<F: FnOnce(&[u8]) -> R, R>(&self, f: F) -> R {
+		f(&self.data.lock())
+	}
+
+	// Changes the block, which is written back later
+	pub fn write<F: FnOnce(&mut [u8]) -> R, R>(&self, f: F) -> R {
+		let result = f(&mut self.data.lock());
+		*self.dirty.lock() = true;
+		result
+	}
+}
+
+struct Entry {
+	buffer: Arc<Buffer>,
+	last_used: u64,
+}
+
+struct State {
+	entries: BTreeMap<u64, Entry>,
+	clock: u64,
+}
+
+pub struct BufferCache {
+	device: Arc<dyn BlockDevice>,
+	block_size: usize,
+	// Buffers kept, more are only cached while they are in use
+	capacity: usize,
+	state: Mutex<State>,
+}
+
+impl BufferCache {
+	pub fn new(
+		device: Arc<dyn BlockDevice>,
+		block_size: usize,
+		capacity: usize,
+	) -> Result<BufferCache, Errno> {
+		if block_size == 0 || block_size % device.sector_size() != 0 {
+			return Err(EINVAL);
+		}
+		Ok(BufferCache {
+			device,
+			block_size,
+			capacity: capacity.max(1),
+			state: Mutex::new(State {
+				entries: BTreeMap::new(),
+				clock: 0,
+			}),
+		})
+	}
+
+	pub fn block_count(&self) -> u64 {
+		self.device.sector_count() * self.device.sector_size() as u64 / self.block_size as u64
+	}
+
+	fn first_sector(&self, block: u64) -> u64 {
+		block * (self.block_size / self.device.sector_size()) as u64
+	}
+
+	fn write_back(&self, buffer: &Buffer) -> Result<(), Errno> {
+		let mut dirty = buffer.dirty.lock();
+		if *dirty {
+			self.device
+				.write_sectors(self.first_sector(buffer.block), &buffer.data.lock())?;
+			*dirty = false;
+		}
+		Ok(())
+	}
+
+	// Drops least recently used buffers nobody holds until the cache is back to its capacity
+	fn evict(&self, state: &mut State) -> Result<(), Errno> {
+		while state.entries.len() > self.capacity {
+			let victim = state
+				.entries
+				.iter()
+				.filter(|(_, entry)| Arc::strong_count(&entry.buffer) == 1)
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(&block, _)| block);
+			let Some(block) = victim else {
+				break;
+			};
+			self.write_back(&state.entries[&block].buffer)?;
+			state.entries.remove(&block);
+		}
+		Ok(())
+	}
+
+	// Buffer of `block`, read from the device unless it is cached
+	pub fn get(&self, block: u64) -> Result<Arc<Buffer>, Errno> {
+		if block >= self.block_count() {
+			return Err(EINVAL);
+		}
+		let mut state = self.state.lock();
+		state.clock += 1;
+		let clock = state.clock;
+		if let Some(entry) = state.entries.get_mut(&block) {
+			entry.last_used = clock;
+			return Ok(entry.buffer.clone());
+		}
+
+		let mut data = alloc::vec![0; self.block_size];
+		self.device.read_sectors(self.first_sector(block), &mut data)?;
+		let buffer = Arc::new(Buffer {
+			block,
+			data: Mutex::new(data),
+			dirty: Mutex::new(false),
+		});
+		state.entries.insert(
+			block,
+			Entry {
+				buffer: buffer.clone(),
+				last_used: clock,
+			},
+		);
+		self.evict(&mut state)?;
+		Ok(buffer)
+	}
+
+	// Copies `buffer.len()` bytes from `offset` in `block`
+	pub fn read(&self, block: u64, offset: usize, buffer: &mut [u8]) -> Result<(), Errno> {
+		if offset + buffer.len() > self.block_size {
+			return Err(EINVAL);
+		}
+		self.get(block)?
+			.read(|data| buffer.copy_from_slice(&data[offset..offset + buffer.len()]));
+		Ok(())
+	}
+
+	pub fn write(&self, block: u64, offset: usize, buffer: &[u8]) -> Result<(), Errno> {
+		if offset + buffer.len() > self.block_size {
+			return Err(EINVAL);
+		}
+		self.get(block)?
+			.write(|data| data[offset..offset + buffer.len()].copy_from_slice(buffer));
+		Ok(())
+	}
+
+	// Writes every dirty buffer back and flushes the device
+	pub fn sync(&self) -> Result<(), Errno> {
+		let buffers: Vec<Arc<Buffer>> = self
+			.state
+			.lock()
+			.entries
+			.values()
+			.map(|entry| entry.buffer.clone())
+			.collect();
+		for buffer in buffers {
+			self.write_back(&buffer)?;
+		}
+		self.device.flush()
+	}
+}
+
+pub fn buffer_test() {
+	use crate::drivers::block::{RamDisk, SECTOR_SIZE};
+	use crate::tools::debug::LogLevel;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the buffer cache\n");
+
+	let disk = RamDisk::new("ram-test", 16);
+	let cache = BufferCache::new(disk.clone(), 2 * SECTOR_SIZE, 2).expect("cache failed");
+	assert!(cache.block_count() == 8);
+	assert!(BufferCache::new(disk.clone(), 100, 2).err() == Some(EINVAL));
+
+	log!(LogLevel::Info, "Writes should stay in the cache until it is synced\n");
+	cache.write(1, 510, b"abcd").unwrap();
+	let mut sectors = [0; 2 * SECTOR_SIZE];
+	disk.read_sectors(2, &mut sectors).unwrap();
+	assert!(sectors.iter().all(|&byte| byte == 0));
+	let mut read = [0; 4];
+	cache.read(1, 510, &mut read).unwrap();
+	assert!(&read == b"abcd");
+	cache.sync().unwrap();
+	disk.read_sectors(2, &mut sectors).unwrap();
+	assert!(&sectors[510..514] == b"abcd");
+
+	log!(LogLevel::Info, "Evicted buffers should be written back, used ones kept\n");
+	let held = cache.get(0).unwrap();
+	held.write(|data| data[0] = 1);
+	cache.write(2, 0, b"x").unwrap();
+	cache.write(3, 0, b"y").unwrap();
+	cache.write(4, 0, b"z").unwrap();
+	disk.read_sectors(4, &mut sectors).unwrap();
+	assert!(sectors[0] == b'x');
+	assert!(Arc::ptr_eq(&held, &cache.get(0).unwrap()));
+	drop(held);
+	cache.sync().unwrap();
+	disk.read_sectors(0, &mut sectors).unwrap();
+	assert!(sectors[0] == 1);
+
+	log!(LogLevel::Info, "Accesses out of the device should fail\n");
+	assert!(cache.get(8).err() == Some(EINVAL));
+	assert!(cache.write(0, 2 * SECTOR_SIZE - 1, b"no").err() == Some(EINVAL));
+	log!(LogLevel::Info, "\t\tEnd of buffer cache test\n");
+}
@@ -0,0 +1,47 @@
+//! # Console File
+//!
+//! The text console as a character device. Writes are printed on the screen, reads return
+//! end of file since the keyboard input is consumed by the shell. User tasks start with
+//! stdin, stdout and stderr open on it.
+
+use alloc::sync::Arc;
+
+use super::file::{File, FileOps};
+use super::inode::{make_dev, FileType, Inode, Metadata};
+use crate::exceptions::errno::Errno;
+
+const CONSOLE_MAJOR: u32 = 5;
+const CONSOLE_MINOR: u32 = 1;
+
+struct ConsoleInode;
+
+impl Inode for ConsoleInode {
+	fn metadata(&self) -> Metadata {
+		let mut metadata = Metadata::new(0, 0, FileType::CharDevice, 0o620);
+		metadata.rdev = make_dev(CONSOLE_MAJOR, CONSOLE_MINOR);
+		metadata
+	}
+
+	fn file_ops(&self) -> Option<Arc<dyn FileOps>> {
+		Some(Arc::new(ConsoleOps))
+	}
+}
+
+struct ConsoleOps;
+
+impl FileOps for ConsoleOps {
+	fn read(&self, _file: &File, _buffer: &mut [u8]) -> Result<usize, Errno> {
+		Ok(0)
+	}
+
+	fn write(&self, _file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		for &char_byte in buffer {
+			print!("{}", char_byte as char);
+		}
+		Ok(buffer.len())
+	}
+}
+
+pub fn open(flags: u32) -> Arc<File> {
+	File::new(Arc::new(ConsoleInode), flags)
+}
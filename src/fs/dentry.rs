@@ -0,0 +1,182 @@
+//! # Dentries and Mounts
+//!
+//! A `Dentry` is an inode seen through the path that reached it: its name and the dentry of
+//! the directory it was found in, which is what `..` and getcwd walk back up. Dentries are
+//! built during lookups and dropped with the last reference, there is no cache.
+//!
+//! Mounts are recorded by the device and inode number of the directory they cover. When a
+//! lookup lands on such a directory it continues at the root of the mounted filesystem,
+//! whose dentry borrows the name and parent of the covered one so `..` leads back out.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::inode::{InodeRef, Metadata, SuperBlock};
+use crate::exceptions::errno::{Errno, EBUSY, EINVAL, ENOENT, ENOTDIR};
+
+pub struct Dentry {
+	pub name: String,
+	pub inode: InodeRef,
+	parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+	pub fn metadata(&self) -> Metadata {
+		self.inode.metadata()
+	}
+
+	// The root is its own parent
+	pub fn parent(self: &Arc<Self>) -> Arc<Dentry> {
+		self.parent.clone().unwrap_or_else(|| self.clone())
+	}
+
+	// Dentry for `inode`, found as `name` in this directory
+	pub fn child(self: &Arc<Self>, name: &str, inode: InodeRef) -> Arc<Dentry> {
+		let mut dentry = Arc::new(Dentry {
+			name: String::from(name),
+			inode,
+			parent: Some(self.clone()),
+		});
+		while let Some(root) = mounted_on(&dentry) {
+			dentry = root;
+		}
+		dentry
+	}
+
+	// Entry `name` of this directory, `.` and `..` included
+	pub fn lookup(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>, Errno> {
+		if !self.metadata().is_dir() {
+			return Err(ENOTDIR);
+		}
+		match name {
+			"" | "." => Ok(self.clone()),
+			".." => Ok(self.parent()),
+			_ => Ok(self.child(name, self.inode.lookup(name)?)),
+		}
+	}
+
+	// Absolute path of this dentry
+	pub fn path(&self) -> String {
+		let mut names = Vec::new();
+		let mut dentry = self;
+		while let Some(parent) = &dentry.parent {
+			names.push(dentry.name.as_str());
+			dentry = parent;
+		}
+		if names.is_empty() {
+			return String::from("/");
+		}
+		let mut path = String::new();
+		for name in names.iter().rev() {
+			path.push('/');
+			path.push_str(name);
+		}
+		path
+	}
+}
+
+struct Mount {
+	// Device and inode number of the covered directory, None for the root filesystem
+	covered: Option<(u32, u64)>,
+	root: Arc<Dentry>,
+	superblock: Arc<dyn SuperBlock>,
+}
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+static ROOT: Mutex<Option<Arc<Dentry>>> = Mutex::new(None);
+
+// Root dentry of the filesystem mounted on `dentry`, the latest one if there are several
+fn mounted_on(dentry: &Dentry) -> Option<Arc<Dentry>> {
+	let metadata = dentry.metadata();
+	let key = Some((metadata.dev, metadata.ino));
+	MOUNTS
+		.lock()
+		.iter()
+		.rev()
+		.find(|mount| mount.covered == key)
+		.map(|mount| mount.root.clone())
+}
+
+pub fn root() -> Result<Arc<Dentry>, Errno> {
+	ROOT.lock().clone().ok_or(ENOENT)
+}
+
+pub fn mount_root(superblock: Arc<dyn SuperBlock>) -> Result<(), Errno> {
+	let mut root = ROOT.lock();
+	if root.is_some() {
+		return Err(EBUSY);
+	}
+	let dentry = Arc::new(Dentry {
+		name: String::from("/"),
+		inode: superblock.root(),
+		parent: None,
+	});
+	*root = Some(dentry.clone());
+	MOUNTS.lock().push(Mount {
+		covered: None,
+		root: dentry,
+		superblock,
+	});
+	Ok(())
+}
+
+// Mounts `superblock` over the directory `target`
+pub fn mount(target: &Arc<Dentry>, superblock: Arc<dyn SuperBlock>) -> Result<(), Errno> {
+	let metadata = target.metadata();
+	if !metadata.is_dir() {
+		return Err(ENOTDIR);
+	}
+	let root = Arc::new(Dentry {
+		name: target.name.clone(),
+		inode: superblock.root(),
+		parent: target.parent.clone(),
+	});
+	MOUNTS.lock().push(Mount {
+		covered: Some((metadata.dev, metadata.ino)),
+		root,
+		superblock,
+	});
+	Ok(())
+}
+
+// True for the root of a mounted filesystem
+pub fn is_mount_root(dentry: &Arc<Dentry>) -> bool {
+	MOUNTS.lock().iter().any(|mount| Arc::ptr_eq(&mount.root, dentry))
+}
+
+// Detaches the filesystem whose root is `root`, the root filesystem stays
+pub fn umount(root: &Arc<Dentry>) -> Result<(), Errno> {
+	let mut mounts = MOUNTS.lock();
+	let index = mounts
+		.iter()
+		.position(|mount| mount.covered.is_some() && Arc::ptr_eq(&mount.root, root))
+		.ok_or(EINVAL)?;
+	let mount = mounts.remove(index);
+	drop(mounts);
+	// Files still open keep the filesystem alive, what was written so far goes to the disk
+	mount.superblock.sync()
+}
+
+// Writes back the data of every mounted filesystem
+pub fn sync() -> Result<(), Errno> {
+	let superblocks: Vec<Arc<dyn SuperBlock>> = MOUNTS
+		.lock()
+		.iter()
+		.map(|mount| mount.superblock.clone())
+		.collect();
+	for superblock in superblocks {
+		superblock.sync()?;
+	}
+	Ok(())
+}
+
+// Mount points and filesystem types, in mount order
+pub fn mounts() -> Vec<(String, &'static str)> {
+	MOUNTS
+		.lock()
+		.iter()
+		.map(|mount| (mount.root.path(), mount.superblock.fs_type()))
+		.collect()
+}
@@ -0,0 +1,165 @@
+//! # devfs
+//!
+//! Filesystem mounted on `/dev` with a node for every registered character device. The
+//! directory is read from the registry each time, so devices registered after the mount show
+//! up too. Opening a node hands out the `FileOps` of its driver.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use super::file::FileOps;
+use super::inode::{anonymous_dev, DirEntry, FileType, Inode, InodeRef, Metadata, SuperBlock};
+use crate::drivers::chrdev::{self, CharDevice};
+use crate::exceptions::errno::{Errno, ENOENT};
+
+const ROOT_INO: u64 = 1;
+
+struct DevDirectory {
+	dev: u32,
+}
+
+impl Inode for DevDirectory {
+	fn metadata(&self) -> Metadata {
+		let mut metadata = Metadata::new(self.dev, ROOT_INO, FileType::Directory, 0o755);
+		metadata.nlink = 2;
+		metadata
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, Errno> {
+		match chrdev::find(name) {
+			Some((index, device)) => Ok(Arc::new(DevNode {
+				dev: self.dev,
+				ino: ROOT_INO + 1 + index as u64,
+				device,
+			})),
+			None => Err(ENOENT),
+		}
+	}
+
+	fn read_dir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+		let directory = |name: &str| DirEntry {
+			name: String::from(name),
+			ino: ROOT_INO,
+			file_type: FileType::Directory,
+		};
+		Ok(match index {
+			0 => Some(directory(".")),
+			1 => Some(directory("..")),
+			_ => chrdev::get(index - 2).map(|device| DirEntry {
+				name: device.name,
+				ino: ROOT_INO + 1 + (index - 2) as u64,
+				file_type: FileType::CharDevice,
+			}),
+		})
+	}
+}
+
+struct DevNode {
+	dev: u32,
+	ino: u64,
+	device: CharDevice,
+}
+
+impl Inode for DevNode {
+	fn metadata(&self) -> Metadata {
+		let mut metadata =
+			Metadata::new(self.dev, self.ino, FileType::CharDevice, self.device.mode);
+		metadata.rdev = self.device.rdev;
+		metadata
+	}
+
+	fn file_ops(&self) -> Option<Arc<dyn FileOps>> {
+		Some(self.device.ops.clone())
+	}
+}
+
+pub struct DevFs {
+	root: InodeRef,
+}
+
+impl DevFs {
+	pub fn new() -> Arc<DevFs> {
+		Arc::new(DevFs {
+			root: Arc::new(DevDirectory {
+				dev: anonymous_dev(),
+			}),
+		})
+	}
+}
+
+impl SuperBlock for DevFs {
+	fn fs_type(&self) -> &'static str {
+		"devfs"
+	}
+
+	fn root(&self) -> InodeRef {
+		self.root.clone()
+	}
+}
+
+pub fn devfs_test() {
+	use super::file::{O_RDONLY, O_RDWR, O_WRONLY, SEEK_SET};
+	use super::inode::make_dev;
+	use crate::drivers::tty::{TIOCGWINSZ, VT_ACTIVATE};
+	use crate::exceptions::errno::{EFAULT, ENOTTY, ENXIO, ESPIPE};
+	use crate::memory::kmem_managment::HK_OFST;
+	use crate::tools::debug::LogLevel;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting devfs\n");
+
+	log!(LogLevel::Info, "Nodes should carry the device numbers\n");
+	let null = super::stat("/dev/null").unwrap();
+	assert!(null.file_type == FileType::CharDevice && null.rdev == make_dev(1, 3));
+	assert!(super::stat("/dev/ttyS0").unwrap().rdev == make_dev(4, 64));
+	assert!(super::stat("/dev/tty5").err() == Some(ENOENT));
+	let directory = super::lookup("/dev").unwrap();
+	let count = (0..)
+		.map_while(|index| directory.inode.read_dir(index).unwrap())
+		.filter(|entry| entry.name.starts_with("tty"))
+		.count();
+	assert!(count == 6);
+
+	log!(
+		LogLevel::Info,
+		"null, zero and random should behave as on Linux\n"
+	);
+	let null = super::open("/dev/null", O_RDWR, 0).unwrap();
+	assert!(null.write(b"gone") == Ok(4));
+	assert!(null.read(&mut [0; 4]) == Ok(0));
+	let zero = super::open("/dev/zero", O_RDONLY, 0).unwrap();
+	let mut buffer = [0xff; 16];
+	assert!(zero.read(&mut buffer) == Ok(16) && buffer == [0; 16]);
+	let random = super::open("/dev/random", O_RDWR, 0).unwrap();
+	let (mut first, mut second) = ([0; 16], [0; 16]);
+	random.read(&mut first).unwrap();
+	assert!(random.write(b"seed") == Ok(4));
+	random.read(&mut second).unwrap();
+	assert!(first != second && first != [0; 16]);
+	assert!(null.ioctl(0, 0) == Err(ENOTTY));
+
+	log!(
+		LogLevel::Info,
+		"/dev/mem should read physical memory at the offset\n"
+	);
+	let mem = super::open("/dev/mem", O_RDONLY, 0).unwrap();
+	let address = 0x100000;
+	assert!(mem.seek(address, SEEK_SET) == Ok(address as u64));
+	let mut physical = [0; 8];
+	assert!(mem.read(&mut physical) == Ok(8));
+	let mapped = unsafe { *((address as u32 + HK_OFST) as *const [u8; 8]) };
+	assert!(physical == mapped);
+
+	log!(
+		LogLevel::Info,
+		"Terminals should take writes and their ioctls\n"
+	);
+	let tty = super::open("/dev/tty4", O_WRONLY, 0).unwrap();
+	assert!(tty.write(b"devfs test\n") == Ok(11));
+	assert!(tty.seek(0, SEEK_SET) == Err(ESPIPE));
+	assert!(tty.ioctl(TIOCGWINSZ, 0) == Err(EFAULT));
+	assert!(tty.ioctl(VT_ACTIVATE, 9) == Err(ENXIO));
+	let serial = super::open("/dev/ttyS0", O_WRONLY, 0).unwrap();
+	assert!(serial.write(b"devfs test on ttyS0\n") == Ok(20));
+	log!(LogLevel::Info, "\t\tEnd of devfs test\n");
+}
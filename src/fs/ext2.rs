@@ -0,0 +1,1459 @@
+//! # ext2
+//!
+//! Second extended filesystem on a block device, so disk images made by `mke2fs` on the host
+//! can be mounted and inspected afterwards with `debugfs`. Every block goes through a buffer
+//! cache and reaches the disk when it is evicted or synced. Any block size is supported,
+//! and so is the file type stored in directory entries. Features that need metadata this
+//! driver would not keep up to date, such as journals or extents, are refused at mount.
+//!
+//! The blocks of a file come from the 12 direct pointers of its inode, then from the
+//! single, double and triple indirect blocks. Unmapped blocks are holes that read as zeros.
+//! Blocks and inodes are allocated from the bitmaps of the group holding the inode when it
+//! has room. An inode whose last link is removed is freed along with its blocks when the
+//! last reference to it goes away.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::buffer::BufferCache;
+use super::inode::{
+	anonymous_dev, make_dev, DirEntry, FileType, Inode, InodeRef, Metadata, SuperBlock,
+};
+use crate::drivers::block::BlockDevice;
+use crate::exceptions::errno::{
+	Errno, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY,
+	EPERM, EXDEV,
+};
+use crate::exceptions::interrupts::TICKS;
+use crate::tools::debug::LogLevel;
+
+const SUPERBLOCK_OFFSET: usize = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xef53;
+const ROOT_INO: u32 = 2;
+// Revision 0 has fixed values for what revision 1 stores in the superblock
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+// Superblock fields
+const SB_INODES_COUNT: usize = 0;
+const SB_BLOCKS_COUNT: usize = 4;
+const SB_FREE_BLOCKS: usize = 12;
+const SB_FREE_INODES: usize = 16;
+const SB_FIRST_DATA_BLOCK: usize = 20;
+const SB_LOG_BLOCK_SIZE: usize = 24;
+const SB_BLOCKS_PER_GROUP: usize = 32;
+const SB_INODES_PER_GROUP: usize = 40;
+const SB_MNT_COUNT: usize = 52;
+const SB_MAGIC: usize = 56;
+const SB_STATE: usize = 58;
+const SB_REV_LEVEL: usize = 76;
+const SB_FIRST_INO: usize = 84;
+const SB_INODE_SIZE: usize = 88;
+const SB_FEATURE_INCOMPAT: usize = 96;
+const SB_FEATURE_RO_COMPAT: usize = 100;
+
+// Cleanly unmounted, cleared while mounted so fsck checks a filesystem that was not
+const STATE_VALID: u16 = 1;
+
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+const RO_COMPAT_BTREE_DIR: u32 = 0x4;
+
+// Group descriptor fields
+const GROUP_DESC_SIZE: usize = 32;
+const GD_BLOCK_BITMAP: usize = 0;
+const GD_INODE_BITMAP: usize = 4;
+const GD_INODE_TABLE: usize = 8;
+const GD_FREE_BLOCKS: usize = 12;
+const GD_FREE_INODES: usize = 14;
+const GD_USED_DIRS: usize = 16;
+
+// Directory hashed by ext3 and later, the index goes stale once entries are added linearly
+const INDEX_FL: u32 = 0x1000;
+
+const DIRECT_BLOCKS: usize = 12;
+const DIRENT_HEADER: usize = 8;
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+const NAME_MAX: usize = 255;
+
+// Buffers the cache of a mounted filesystem keeps
+const CACHE_BLOCKS: usize = 256;
+
+fn get_u16(bytes: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn get_u32(bytes: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes([
+		bytes[offset],
+		bytes[offset + 1],
+		bytes[offset + 2],
+		bytes[offset + 3],
+	])
+}
+
+fn put_u16(bytes: &mut [u8], offset: usize, value: u16) {
+	bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+	bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// There is no real time clock yet, times are seconds since boot
+fn now() -> u32 {
+	TICKS.load(core::sync::atomic::Ordering::Relaxed) / 18
+}
+
+fn file_type(mode: u16) -> FileType {
+	match mode as u32 & 0o170000 {
+		0o010000 => FileType::Fifo,
+		0o020000 => FileType::CharDevice,
+		0o040000 => FileType::Directory,
+		0o060000 => FileType::BlockDevice,
+		0o120000 => FileType::Symlink,
+		0o140000 => FileType::Socket,
+		_ => FileType::Regular,
+	}
+}
+
+// File type code of directory entries, 0 when unknown
+fn dirent_code(file_type: FileType) -> u8 {
+	match file_type {
+		FileType::Regular => 1,
+		FileType::Directory => 2,
+		FileType::CharDevice => 3,
+		FileType::BlockDevice => 4,
+		FileType::Fifo => 5,
+		FileType::Socket => 6,
+		FileType::Symlink => 7,
+	}
+}
+
+// Directory entries are 4 byte aligned
+fn record_len(name_len: usize) -> usize {
+	(DIRENT_HEADER + name_len).next_multiple_of(4)
+}
+
+// The first 128 bytes of an on-disk inode, what follows is left untouched
+#[derive(Clone)]
+struct DiskInode {
+	mode: u16,
+	uid: u16,
+	size: u64,
+	atime: u32,
+	ctime: u32,
+	mtime: u32,
+	dtime: u32,
+	gid: u16,
+	links: u16,
+	// Allocated size in 512 byte units, indirect blocks included
+	sectors: u32,
+	flags: u32,
+	block: [u32; 15],
+}
+
+impl DiskInode {
+	fn new(mode: u16) -> DiskInode {
+		let time = now();
+		DiskInode {
+			mode,
+			uid: 0,
+			size: 0,
+			atime: time,
+			ctime: time,
+			mtime: time,
+			dtime: 0,
+			gid: 0,
+			links: 1,
+			sectors: 0,
+			flags: 0,
+			block: [0; 15],
+		}
+	}
+
+	fn decode(raw: &[u8]) -> DiskInode {
+		let mode = get_u16(raw, 0);
+		let mut size = get_u32(raw, 4) as u64;
+		// The high half of the size of regular files takes the place of the directory ACL
+		if file_type(mode) == FileType::Regular {
+			size |= (get_u32(raw, 108) as u64) << 32;
+		}
+		let mut block = [0; 15];
+		for (index, pointer) in block.iter_mut().enumerate() {
+			*pointer = get_u32(raw, 40 + 4 * index);
+		}
+		DiskInode {
+			mode,
+			uid: get_u16(raw, 2),
+			size,
+			atime: get_u32(raw, 8),
+			ctime: get_u32(raw, 12),
+			mtime: get_u32(raw, 16),
+			dtime: get_u32(raw, 20),
+			gid: get_u16(raw, 24),
+			links: get_u16(raw, 26),
+			sectors: get_u32(raw, 28),
+			flags: get_u32(raw, 32),
+			block,
+		}
+	}
+
+	fn encode(&self, raw: &mut [u8]) {
+		put_u16(raw, 0, self.mode);
+		put_u16(raw, 2, self.uid);
+		put_u32(raw, 4, self.size as u32);
+		put_u32(raw, 8, self.atime);
+		put_u32(raw, 12, self.ctime);
+		put_u32(raw, 16, self.mtime);
+		put_u32(raw, 20, self.dtime);
+		put_u16(raw, 24, self.gid);
+		put_u16(raw, 26, self.links);
+		put_u32(raw, 28, self.sectors);
+		put_u32(raw, 32, self.flags);
+		for (index, &pointer) in self.block.iter().enumerate() {
+			put_u32(raw, 40 + 4 * index, pointer);
+		}
+		if self.file_type() == FileType::Regular {
+			put_u32(raw, 108, (self.size >> 32) as u32);
+		}
+	}
+
+	fn file_type(&self) -> FileType {
+		file_type(self.mode)
+	}
+}
+
+// Directory entry as found in a directory block
+struct Record {
+	offset: usize,
+	ino: u32,
+	rec_len: usize,
+	name: String,
+	file_type: u8,
+}
+
+fn parse_records(data: &[u8]) -> Result<Vec<Record>, Errno> {
+	let mut records = Vec::new();
+	let mut offset = 0;
+	while offset + DIRENT_HEADER <= data.len() {
+		let rec_len = get_u16(data, offset + 4) as usize;
+		let name_len = data[offset + 6] as usize;
+		if rec_len < DIRENT_HEADER + name_len || offset + rec_len > data.len() || rec_len % 4 != 0 {
+			log!(LogLevel::Error, "ext2: corrupted directory entry");
+			return Err(EIO);
+		}
+		let name = &data[offset + DIRENT_HEADER..offset + DIRENT_HEADER + name_len];
+		records.push(Record {
+			offset,
+			ino: get_u32(data, offset),
+			rec_len,
+			name: String::from_utf8_lossy(name).into_owned(),
+			file_type: data[offset + 7],
+		});
+		offset += rec_len;
+	}
+	Ok(records)
+}
+
+// Part of the filesystem every inode refers to
+struct Shared {
+	dev: u32,
+	cache: BufferCache,
+	block_size: usize,
+	inode_size: usize,
+	blocks_count: u32,
+	inodes_count: u32,
+	blocks_per_group: u32,
+	inodes_per_group: u32,
+	first_data_block: u32,
+	first_ino: u32,
+	group_count: u32,
+	// Directory entries carry the file type
+	filetype: bool,
+	// State found at mount, restored at unmount
+	state: u16,
+	// Serializes the updates of the bitmaps and free counts
+	alloc_lock: Mutex<()>,
+	// Inodes in use by number, so everyone shares the same one
+	inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+impl Shared {
+	fn sectors_per_block(&self) -> u32 {
+		(self.block_size / 512) as u32
+	}
+
+	fn pointers_per_block(&self) -> u64 {
+		(self.block_size / 4) as u64
+	}
+
+	// Block and offset of the superblock
+	fn superblock_position(&self) -> (u64, usize) {
+		(
+			(SUPERBLOCK_OFFSET / self.block_size) as u64,
+			SUPERBLOCK_OFFSET % self.block_size,
+		)
+	}
+
+	// Block and offset of the descriptor of `group`, in the blocks after the superblock
+	fn group_position(&self, group: u32) -> (u64, usize) {
+		let offset = group as usize * GROUP_DESC_SIZE;
+		(
+			(self.first_data_block + 1) as u64 + (offset / self.block_size) as u64,
+			offset % self.block_size,
+		)
+	}
+
+	fn group_u32(&self, group: u32, field: usize) -> Result<u32, Errno> {
+		let (block, offset) = self.group_position(group);
+		Ok(self
+			.cache
+			.get(block)?
+			.read(|data| get_u32(data, offset + field)))
+	}
+
+	fn group_u16(&self, group: u32, field: usize) -> Result<u16, Errno> {
+		let (block, offset) = self.group_position(group);
+		Ok(self
+			.cache
+			.get(block)?
+			.read(|data| get_u16(data, offset + field)))
+	}
+
+	// Adds `delta` to the counter `field` of `group`, and to the total `total` of the
+	// superblock unless it is None
+	fn adjust_counts(
+		&self,
+		group: u32,
+		field: usize,
+		total: Option<usize>,
+		delta: i32,
+	) -> Result<(), Errno> {
+		let (block, offset) = self.group_position(group);
+		self.cache.get(block)?.write(|data| {
+			let count = get_u16(data, offset + field) as i32 + delta;
+			put_u16(data, offset + field, count as u16);
+		});
+		if let Some(total) = total {
+			let (block, offset) = self.superblock_position();
+			self.cache.get(block)?.write(|data| {
+				let count = get_u32(data, offset + total) as i64 + delta as i64;
+				put_u32(data, offset + total, count as u32);
+			});
+		}
+		Ok(())
+	}
+
+	// Sets the first clear bit from `start` among the first `count` of the bitmap in
+	// `block`, None if they are all set
+	fn take_bit(&self, block: u32, start: usize, count: usize) -> Result<Option<usize>, Errno> {
+		let buffer = self.cache.get(block as u64)?;
+		let bit = buffer.read(|bitmap| {
+			(start..count.min(bitmap.len() * 8))
+				.find(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0)
+		});
+		if let Some(bit) = bit {
+			buffer.write(|bitmap| bitmap[bit / 8] |= 1 << (bit % 8));
+		}
+		Ok(bit)
+	}
+
+	fn clear_bit(&self, block: u32, bit: usize) -> Result<(), Errno> {
+		self.cache
+			.get(block as u64)?
+			.write(|bitmap| bitmap[bit / 8] &= !(1 << (bit % 8)));
+		Ok(())
+	}
+
+	// Zeroed block, from `group` when it has one
+	fn allocate_block(&self, group: u32) -> Result<u32, Errno> {
+		let _guard = self.alloc_lock.lock();
+		for group in (0..self.group_count).map(|index| (group + index) % self.group_count) {
+			if self.group_u16(group, GD_FREE_BLOCKS)? == 0 {
+				continue;
+			}
+			let first = self.first_data_block + group * self.blocks_per_group;
+			let count = self.blocks_per_group.min(self.blocks_count - first) as usize;
+			let bitmap = self.group_u32(group, GD_BLOCK_BITMAP)?;
+			if let Some(bit) = self.take_bit(bitmap, 0, count)? {
+				self.adjust_counts(group, GD_FREE_BLOCKS, Some(SB_FREE_BLOCKS), -1)?;
+				let block = first + bit as u32;
+				self.cache.get(block as u64)?.write(|data| data.fill(0));
+				return Ok(block);
+			}
+		}
+		Err(ENOSPC)
+	}
+
+	fn free_block(&self, block: u32) -> Result<(), Errno> {
+		if block < self.first_data_block || block >= self.blocks_count {
+			return Err(EIO);
+		}
+		let _guard = self.alloc_lock.lock();
+		let group = (block - self.first_data_block) / self.blocks_per_group;
+		let bit = (block - self.first_data_block) % self.blocks_per_group;
+		self.clear_bit(self.group_u32(group, GD_BLOCK_BITMAP)?, bit as usize)?;
+		self.adjust_counts(group, GD_FREE_BLOCKS, Some(SB_FREE_BLOCKS), 1)
+	}
+
+	fn allocate_inode(&self, group: u32, directory: bool) -> Result<u32, Errno> {
+		let _guard = self.alloc_lock.lock();
+		for group in (0..self.group_count).map(|index| (group + index) % self.group_count) {
+			if self.group_u16(group, GD_FREE_INODES)? == 0 {
+				continue;
+			}
+			// The first inodes are reserved
+			let start = match group {
+				0 => self.first_ino as usize - 1,
+				_ => 0,
+			};
+			let bitmap = self.group_u32(group, GD_INODE_BITMAP)?;
+			if let Some(bit) = self.take_bit(bitmap, start, self.inodes_per_group as usize)? {
+				self.adjust_counts(group, GD_FREE_INODES, Some(SB_FREE_INODES), -1)?;
+				if directory {
+					self.adjust_counts(group, GD_USED_DIRS, None, 1)?;
+				}
+				return Ok(group * self.inodes_per_group + bit as u32 + 1);
+			}
+		}
+		Err(ENOSPC)
+	}
+
+	fn free_inode(&self, ino: u32, directory: bool) -> Result<(), Errno> {
+		let _guard = self.alloc_lock.lock();
+		let group = self.inode_group(ino);
+		let bit = (ino - 1) % self.inodes_per_group;
+		self.clear_bit(self.group_u32(group, GD_INODE_BITMAP)?, bit as usize)?;
+		self.adjust_counts(group, GD_FREE_INODES, Some(SB_FREE_INODES), 1)?;
+		if directory {
+			self.adjust_counts(group, GD_USED_DIRS, None, -1)?;
+		}
+		Ok(())
+	}
+
+	fn inode_group(&self, ino: u32) -> u32 {
+		(ino - 1) / self.inodes_per_group
+	}
+
+	// Block and offset of inode `ino` in the inode table of its group
+	fn inode_position(&self, ino: u32) -> Result<(u64, usize), Errno> {
+		if ino == 0 || ino > self.inodes_count {
+			return Err(EIO);
+		}
+		let table = self.group_u32(self.inode_group(ino), GD_INODE_TABLE)?;
+		let offset = ((ino - 1) % self.inodes_per_group) as usize * self.inode_size;
+		Ok((
+			table as u64 + (offset / self.block_size) as u64,
+			offset % self.block_size,
+		))
+	}
+
+	fn read_inode(&self, ino: u32) -> Result<DiskInode, Errno> {
+		let (block, offset) = self.inode_position(ino)?;
+		Ok(self
+			.cache
+			.get(block)?
+			.read(|data| DiskInode::decode(&data[offset..offset + GOOD_OLD_INODE_SIZE])))
+	}
+
+	fn write_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), Errno> {
+		let (block, offset) = self.inode_position(ino)?;
+		self.cache
+			.get(block)?
+			.write(|data| inode.encode(&mut data[offset..offset + GOOD_OLD_INODE_SIZE]));
+		Ok(())
+	}
+
+	// Writes a new inode, clearing the fields after the first 128 bytes
+	fn init_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), Errno> {
+		let (block, offset) = self.inode_position(ino)?;
+		self.cache.get(block)?.write(|data| {
+			data[offset..offset + self.inode_size].fill(0);
+			inode.encode(&mut data[offset..offset + GOOD_OLD_INODE_SIZE]);
+		});
+		Ok(())
+	}
+
+	fn inode(self: &Arc<Self>, ino: u32) -> Result<Arc<Ext2Inode>, Errno> {
+		let mut inodes = self.inodes.lock();
+		if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+			return Ok(inode);
+		}
+		let inode = Arc::new(Ext2Inode {
+			ino,
+			fs: self.clone(),
+			data: Mutex::new(self.read_inode(ino)?),
+		});
+		inodes.insert(ino, Arc::downgrade(&inode));
+		Ok(inode)
+	}
+
+	// Block for `inode`, counted in its allocated size
+	fn new_block(&self, ino: u32, inode: &mut DiskInode) -> Result<u32, Errno> {
+		let block = self.allocate_block(self.inode_group(ino))?;
+		inode.sectors += self.sectors_per_block();
+		Ok(block)
+	}
+
+	fn check_block(&self, block: u32) -> Result<u32, Errno> {
+		match block < self.blocks_count {
+			true => Ok(block),
+			false => {
+				log!(
+					LogLevel::Error,
+					"ext2: block {} out of the filesystem",
+					block
+				);
+				Err(EIO)
+			}
+		}
+	}
+
+	// Block holding the file block `index` of `inode`, 0 for a hole. With `allocate` holes
+	// are filled, along with the indirect blocks leading to them.
+	fn map_block(
+		&self,
+		ino: u32,
+		inode: &mut DiskInode,
+		index: u64,
+		allocate: bool,
+	) -> Result<u32, Errno> {
+		if index < DIRECT_BLOCKS as u64 {
+			let slot = index as usize;
+			if inode.block[slot] == 0 && allocate {
+				inode.block[slot] = self.new_block(ino, inode)?;
+			}
+			return self.check_block(inode.block[slot]);
+		}
+
+		// Find the tree holding the block: single, double or triple indirect
+		let per = self.pointers_per_block();
+		let mut offset = index - DIRECT_BLOCKS as u64;
+		let mut depth = 1;
+		let mut span = per;
+		while offset >= span {
+			offset -= span;
+			depth += 1;
+			span *= per;
+			if depth > 3 {
+				return Err(EFBIG);
+			}
+		}
+		let slot = DIRECT_BLOCKS - 1 + depth;
+		if inode.block[slot] == 0 {
+			if !allocate {
+				return Ok(0);
+			}
+			inode.block[slot] = self.new_block(ino, inode)?;
+		}
+
+		let mut block = self.check_block(inode.block[slot])?;
+		for _ in 0..depth {
+			span /= per;
+			let entry = (offset / span) as usize * 4;
+			offset %= span;
+			let mut next = self
+				.cache
+				.get(block as u64)?
+				.read(|data| get_u32(data, entry));
+			if next == 0 {
+				if !allocate {
+					return Ok(0);
+				}
+				next = self.new_block(ino, inode)?;
+				self.cache
+					.get(block as u64)?
+					.write(|data| put_u32(data, entry, next));
+			}
+			block = self.check_block(next)?;
+		}
+		Ok(block)
+	}
+
+	// Frees the blocks of the tree under `block`, `depth` levels of indirect blocks whose
+	// first entry maps the file block `base`, except those mapping file blocks below `keep`.
+	// Returns the sectors freed and whether `block` itself went.
+	fn free_tree(
+		&self,
+		block: u32,
+		depth: u32,
+		base: u64,
+		keep: u64,
+	) -> Result<(u32, bool), Errno> {
+		let per = self.pointers_per_block();
+		let span = per.pow(depth);
+		if base + span <= keep {
+			return Ok((0, false));
+		}
+		let mut freed = 0;
+		if depth > 0 {
+			let entries: Vec<u32> = self.cache.get(block as u64)?.read(|data| {
+				(0..per as usize)
+					.map(|index| get_u32(data, index * 4))
+					.collect()
+			});
+			for (index, &entry) in entries.iter().enumerate() {
+				if entry == 0 {
+					continue;
+				}
+				let child_base = base + index as u64 * (span / per);
+				let (sectors, gone) = self.free_tree(entry, depth - 1, child_base, keep)?;
+				freed += sectors;
+				if gone && base < keep {
+					self.cache
+						.get(block as u64)?
+						.write(|data| put_u32(data, index * 4, 0));
+				}
+			}
+		}
+		if base < keep {
+			return Ok((freed, false));
+		}
+		self.free_block(block)?;
+		Ok((freed + self.sectors_per_block(), true))
+	}
+
+	// Frees the blocks of `inode` mapping file blocks from `keep` on
+	fn free_blocks(&self, inode: &mut DiskInode, keep: u64) -> Result<(), Errno> {
+		for slot in (keep as usize).min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+			if inode.block[slot] != 0 {
+				self.free_block(inode.block[slot])?;
+				inode.block[slot] = 0;
+				inode.sectors = inode.sectors.saturating_sub(self.sectors_per_block());
+			}
+		}
+		let per = self.pointers_per_block();
+		let mut base = DIRECT_BLOCKS as u64;
+		let mut span = per;
+		for depth in 1..=3 {
+			let slot = DIRECT_BLOCKS - 1 + depth as usize;
+			if inode.block[slot] != 0 {
+				let (sectors, gone) = self.free_tree(inode.block[slot], depth, base, keep)?;
+				inode.sectors = inode.sectors.saturating_sub(sectors);
+				if gone {
+					inode.block[slot] = 0;
+				}
+			}
+			base += span;
+			span *= per;
+		}
+		Ok(())
+	}
+
+	fn read_data(
+		&self,
+		ino: u32,
+		inode: &mut DiskInode,
+		offset: u64,
+		buffer: &mut [u8],
+	) -> Result<usize, Errno> {
+		if offset >= inode.size {
+			return Ok(0);
+		}
+		let len = (inode.size - offset).min(buffer.len() as u64) as usize;
+		let mut done = 0;
+		while done < len {
+			let position = offset + done as u64;
+			let block_offset = (position % self.block_size as u64) as usize;
+			let chunk = (self.block_size - block_offset).min(len - done);
+			let destination = &mut buffer[done..done + chunk];
+			match self.map_block(ino, inode, position / self.block_size as u64, false)? {
+				0 => destination.fill(0),
+				block => self.cache.read(block as u64, block_offset, destination)?,
+			}
+			done += chunk;
+		}
+		Ok(len)
+	}
+
+	// Stops at the first block that cannot be allocated, ENOSPC if nothing was written
+	fn write_data(
+		&self,
+		ino: u32,
+		inode: &mut DiskInode,
+		offset: u64,
+		buffer: &[u8],
+	) -> Result<usize, Errno> {
+		let mut done = 0;
+		while done < buffer.len() {
+			let position = offset + done as u64;
+			let block_offset = (position % self.block_size as u64) as usize;
+			let chunk = (self.block_size - block_offset).min(buffer.len() - done);
+			let block = match self.map_block(ino, inode, position / self.block_size as u64, true) {
+				Ok(block) => block,
+				Err(errno) if done == 0 => return Err(errno),
+				Err(_) => break,
+			};
+			self.cache
+				.write(block as u64, block_offset, &buffer[done..done + chunk])?;
+			done += chunk;
+		}
+		inode.size = inode.size.max(offset + done as u64);
+		Ok(done)
+	}
+}
+
+impl Drop for Shared {
+	// Puts back the state found at mount and writes everything back
+	fn drop(&mut self) {
+		let (block, offset) = self.superblock_position();
+		let result = self
+			.cache
+			.get(block)
+			.map(|buffer| buffer.write(|data| put_u16(data, offset + SB_STATE, self.state)))
+			.and_then(|_| self.cache.sync());
+		if let Err(errno) = result {
+			log!(
+				LogLevel::Error,
+				"ext2: failed to write back the filesystem: error {}",
+				errno
+			);
+		}
+	}
+}
+
+struct Ext2Inode {
+	ino: u32,
+	fs: Arc<Shared>,
+	data: Mutex<DiskInode>,
+}
+
+impl Ext2Inode {
+	fn check_dir(data: &DiskInode) -> Result<(), Errno> {
+		match data.file_type() {
+			FileType::Directory => Ok(()),
+			_ => Err(ENOTDIR),
+		}
+	}
+
+	fn check_regular(data: &DiskInode) -> Result<(), Errno> {
+		match data.file_type() {
+			FileType::Regular => Ok(()),
+			FileType::Directory => Err(EISDIR),
+			_ => Err(EINVAL),
+		}
+	}
+
+	fn save(&self, data: &DiskInode) -> Result<(), Errno> {
+		self.fs.write_inode(self.ino, data)
+	}
+
+	// Block number and records of the directory block `index`
+	fn records(&self, data: &mut DiskInode, index: u64) -> Result<(u32, Vec<Record>), Errno> {
+		let block = self.fs.map_block(self.ino, data, index, false)?;
+		if block == 0 {
+			log!(LogLevel::Error, "ext2: hole in directory {}", self.ino);
+			return Err(EIO);
+		}
+		let records = self.fs.cache.get(block as u64)?.read(parse_records)?;
+		Ok((block, records))
+	}
+
+	fn block_count(&self, data: &DiskInode) -> u64 {
+		data.size / self.fs.block_size as u64
+	}
+
+	// Inode number and file type code of the entry `name`
+	fn find(&self, data: &mut DiskInode, name: &str) -> Result<(u32, u8), Errno> {
+		for index in 0..self.block_count(data) {
+			let (_, records) = self.records(data, index)?;
+			if let Some(record) = records
+				.iter()
+				.find(|record| record.ino != 0 && record.name == name)
+			{
+				return Ok((record.ino, record.file_type));
+			}
+		}
+		Err(ENOENT)
+	}
+
+	fn write_record(
+		data: &mut [u8],
+		offset: usize,
+		ino: u32,
+		rec_len: usize,
+		name: &str,
+		code: u8,
+	) {
+		put_u32(data, offset, ino);
+		put_u16(data, offset + 4, rec_len as u16);
+		data[offset + 6] = name.len() as u8;
+		data[offset + 7] = code;
+		data[offset + DIRENT_HEADER..offset + DIRENT_HEADER + name.len()]
+			.copy_from_slice(name.as_bytes());
+	}
+
+	// Adds the entry `name` for `ino` in the first gap big enough, or in a new block
+	fn add_entry(
+		&self,
+		data: &mut DiskInode,
+		name: &str,
+		ino: u32,
+		file_type: FileType,
+	) -> Result<(), Errno> {
+		let needed = record_len(name.len());
+		let code = if self.fs.filetype {
+			dirent_code(file_type)
+		} else {
+			0
+		};
+		data.flags &= !INDEX_FL;
+		for index in 0..self.block_count(data) {
+			let (block, records) = self.records(data, index)?;
+			for record in records {
+				let used = match record.ino {
+					0 => 0,
+					_ => record_len(record.name.len()),
+				};
+				if record.rec_len - used < needed {
+					continue;
+				}
+				self.fs.cache.get(block as u64)?.write(|bytes| {
+					if used > 0 {
+						put_u16(bytes, record.offset + 4, used as u16);
+					}
+					let offset = record.offset + used;
+					Self::write_record(bytes, offset, ino, record.rec_len - used, name, code);
+				});
+				data.mtime = now();
+				return self.save(data);
+			}
+		}
+
+		let index = self.block_count(data);
+		let block = self.fs.map_block(self.ino, data, index, true)?;
+		let block_size = self.fs.block_size;
+		self.fs.cache.get(block as u64)?.write(|bytes| {
+			Self::write_record(bytes, 0, ino, block_size, name, code);
+		});
+		data.size += block_size as u64;
+		data.mtime = now();
+		self.save(data)
+	}
+
+	// Removes the entry `name`, its space goes to the entry before it in the block
+	fn remove_entry(&self, data: &mut DiskInode, name: &str) -> Result<(), Errno> {
+		for index in 0..self.block_count(data) {
+			let (block, records) = self.records(data, index)?;
+			let Some(position) = records
+				.iter()
+				.position(|record| record.ino != 0 && record.name == name)
+			else {
+				continue;
+			};
+			let record = &records[position];
+			self.fs
+				.cache
+				.get(block as u64)?
+				.write(|bytes| match position {
+					0 => put_u32(bytes, record.offset, 0),
+					_ => {
+						let previous = &records[position - 1];
+						put_u16(
+							bytes,
+							previous.offset + 4,
+							(previous.rec_len + record.rec_len) as u16,
+						);
+					}
+				});
+			data.flags &= !INDEX_FL;
+			data.mtime = now();
+			return self.save(data);
+		}
+		Err(ENOENT)
+	}
+
+	// True when only `.` and `..` are left
+	fn is_empty(&self, data: &mut DiskInode) -> Result<bool, Errno> {
+		for index in 0..self.block_count(data) {
+			let (_, records) = self.records(data, index)?;
+			if records
+				.iter()
+				.any(|record| record.ino != 0 && record.name != "." && record.name != "..")
+			{
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	fn check_name(name: &str) -> Result<(), Errno> {
+		match name.len() {
+			0 => Err(ENOENT),
+			length if length > NAME_MAX => Err(ENAMETOOLONG),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl Inode for Ext2Inode {
+	fn metadata(&self) -> Metadata {
+		let data = self.data.lock();
+		let file_type = data.file_type();
+		let mut metadata = Metadata::new(self.fs.dev, self.ino as u64, file_type, data.mode as u32);
+		metadata.nlink = data.links as u32;
+		metadata.uid = data.uid as u32;
+		metadata.gid = data.gid as u32;
+		metadata.size = data.size;
+		metadata.block_size = self.fs.block_size as u32;
+		metadata.blocks = data.sectors as u64;
+		metadata.atime = data.atime;
+		metadata.mtime = data.mtime;
+		metadata.ctime = data.ctime;
+		// Device numbers use the old 8 bit encoding in the first block pointer, or the new
+		// one in the second
+		if matches!(file_type, FileType::CharDevice | FileType::BlockDevice) {
+			metadata.rdev = match data.block[0] {
+				0 => data.block[1],
+				old => make_dev((old >> 8) & 0xff, old & 0xff),
+			};
+		}
+		metadata
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, Errno> {
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		let (ino, _) = self.find(&mut data, name)?;
+		drop(data);
+		Ok(self.fs.inode(ino)?)
+	}
+
+	fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<InodeRef, Errno> {
+		if !matches!(file_type, FileType::Regular | FileType::Directory) {
+			return Err(EINVAL);
+		}
+		Self::check_name(name)?;
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		// A removed directory still open somewhere takes no new entries
+		if data.links == 0 {
+			return Err(ENOENT);
+		}
+		match self.find(&mut data, name) {
+			Ok(_) => return Err(EEXIST),
+			Err(ENOENT) => {}
+			Err(errno) => return Err(errno),
+		}
+
+		let directory = file_type == FileType::Directory;
+		let fs = &self.fs;
+		let ino = fs.allocate_inode(fs.inode_group(self.ino), directory)?;
+		let mut inode = DiskInode::new((file_type.mode_bits() | (mode & 0o7777)) as u16);
+		let result = (|| {
+			if directory {
+				let block = fs.new_block(ino, &mut inode)?;
+				inode.block[0] = block;
+				inode.size = fs.block_size as u64;
+				inode.links = 2;
+				let (parent, block_size) = (self.ino, fs.block_size);
+				fs.cache.get(block as u64)?.write(|bytes| {
+					Self::write_record(bytes, 0, ino, record_len(1), ".", dirent_code(file_type));
+					let rest = block_size - record_len(1);
+					Self::write_record(
+						bytes,
+						record_len(1),
+						parent,
+						rest,
+						"..",
+						dirent_code(file_type),
+					);
+				});
+			}
+			fs.init_inode(ino, &inode)?;
+			self.add_entry(&mut data, name, ino, file_type)
+		})();
+		if let Err(errno) = result {
+			fs.free_blocks(&mut inode, 0)?;
+			fs.free_inode(ino, directory)?;
+			return Err(errno);
+		}
+		if directory {
+			data.links += 1;
+			self.save(&data)?;
+		}
+		drop(data);
+		Ok(fs.inode(ino)?)
+	}
+
+	fn link(&self, name: &str, target: &InodeRef) -> Result<(), Errno> {
+		Self::check_name(name)?;
+		let metadata = target.metadata();
+		if metadata.dev != self.fs.dev {
+			return Err(EXDEV);
+		}
+		if metadata.is_dir() {
+			return Err(EPERM);
+		}
+		let inode = self.fs.inode(metadata.ino as u32)?;
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		if data.links == 0 {
+			return Err(ENOENT);
+		}
+		match self.find(&mut data, name) {
+			Ok(_) => return Err(EEXIST),
+			Err(ENOENT) => {}
+			Err(errno) => return Err(errno),
+		}
+		let mut target = inode.data.lock();
+		if target.links == 0 {
+			return Err(ENOENT);
+		}
+		self.add_entry(&mut data, name, inode.ino, metadata.file_type)?;
+		target.links += 1;
+		target.ctime = now();
+		inode.save(&target)
+	}
+
+	fn unlink(&self, name: &str) -> Result<(), Errno> {
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		let (ino, _) = self.find(&mut data, name)?;
+		let inode = self.fs.inode(ino)?;
+		let mut target = inode.data.lock();
+		if target.file_type() == FileType::Directory {
+			return Err(EISDIR);
+		}
+		self.remove_entry(&mut data, name)?;
+		target.links = target.links.saturating_sub(1);
+		target.ctime = now();
+		inode.save(&target)
+	}
+
+	fn rmdir(&self, name: &str) -> Result<(), Errno> {
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		let (ino, _) = self.find(&mut data, name)?;
+		let inode = self.fs.inode(ino)?;
+		let mut target = inode.data.lock();
+		Self::check_dir(&target)?;
+		if !inode.is_empty(&mut target)? {
+			return Err(ENOTEMPTY);
+		}
+		self.remove_entry(&mut data, name)?;
+		target.links = 0;
+		inode.save(&target)?;
+		data.links = data.links.saturating_sub(1);
+		self.save(&data)
+	}
+
+	fn read_dir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		let mut left = index;
+		for block_index in 0..self.block_count(&data) {
+			let (_, records) = self.records(&mut data, block_index)?;
+			let mut used: Vec<Record> = records
+				.into_iter()
+				.filter(|record| record.ino != 0)
+				.collect();
+			if left >= used.len() {
+				left -= used.len();
+				continue;
+			}
+			let record = used.swap_remove(left);
+			let file_type = match record.file_type {
+				1 => FileType::Regular,
+				2 => FileType::Directory,
+				3 => FileType::CharDevice,
+				4 => FileType::BlockDevice,
+				5 => FileType::Fifo,
+				6 => FileType::Socket,
+				7 => FileType::Symlink,
+				_ if record.ino == self.ino => FileType::Directory,
+				_ => self.fs.read_inode(record.ino)?.file_type(),
+			};
+			return Ok(Some(DirEntry {
+				name: record.name,
+				ino: record.ino as u64,
+				file_type,
+			}));
+		}
+		Ok(None)
+	}
+
+	fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+		let mut data = self.data.lock();
+		Self::check_regular(&data)?;
+		self.fs.read_data(self.ino, &mut data, offset, buffer)
+	}
+
+	fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+		offset
+			.checked_add(buffer.len() as u64)
+			.filter(|&end| end <= MAX_FILE_SIZE)
+			.ok_or(EFBIG)?;
+		let mut data = self.data.lock();
+		Self::check_regular(&data)?;
+		let written = self.fs.write_data(self.ino, &mut data, offset, buffer);
+		data.mtime = now();
+		self.save(&data)?;
+		written
+	}
+
+	fn truncate(&self, size: u64) -> Result<(), Errno> {
+		if size > MAX_FILE_SIZE {
+			return Err(EFBIG);
+		}
+		let mut data = self.data.lock();
+		Self::check_regular(&data)?;
+		if size < data.size {
+			let block_size = self.fs.block_size as u64;
+			self.fs.free_blocks(&mut data, size.div_ceil(block_size))?;
+			// The cut off end of the last block must read as zeros if the file grows again
+			let tail = (size % block_size) as usize;
+			if tail != 0 {
+				let block = self
+					.fs
+					.map_block(self.ino, &mut data, size / block_size, false)?;
+				if block != 0 {
+					self.fs
+						.cache
+						.get(block as u64)?
+						.write(|bytes| bytes[tail..].fill(0));
+				}
+			}
+		}
+		data.size = size;
+		data.mtime = now();
+		self.save(&data)
+	}
+}
+
+impl Drop for Ext2Inode {
+	// Frees the inode and its blocks once it is neither linked nor used
+	fn drop(&mut self) {
+		let data = self.data.get_mut();
+		if data.links == 0 {
+			let directory = data.file_type() == FileType::Directory;
+			data.dtime = now().max(1);
+			data.size = 0;
+			let result = self
+				.fs
+				.free_blocks(data, 0)
+				.and_then(|_| self.fs.write_inode(self.ino, data))
+				.and_then(|_| self.fs.free_inode(self.ino, directory));
+			if let Err(errno) = result {
+				log!(
+					LogLevel::Error,
+					"ext2: failed to free inode {}: error {}",
+					self.ino,
+					errno
+				);
+			}
+		}
+		let mut inodes = self.fs.inodes.lock();
+		if inodes
+			.get(&self.ino)
+			.is_some_and(|inode| inode.strong_count() == 0)
+		{
+			inodes.remove(&self.ino);
+		}
+	}
+}
+
+pub struct Ext2Fs {
+	shared: Arc<Shared>,
+	root: Arc<Ext2Inode>,
+}
+
+impl Ext2Fs {
+	// Reads the superblock of the filesystem on `device` and marks it in use
+	pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Ext2Fs>, Errno> {
+		let sector_size = device.sector_size();
+		if SUPERBLOCK_SIZE % sector_size != 0 {
+			return Err(EINVAL);
+		}
+		let mut raw = [0; SUPERBLOCK_SIZE];
+		device.read_sectors((SUPERBLOCK_OFFSET / sector_size) as u64, &mut raw)?;
+		if get_u16(&raw, SB_MAGIC) != EXT2_MAGIC {
+			return Err(EINVAL);
+		}
+
+		let log_block_size = get_u32(&raw, SB_LOG_BLOCK_SIZE);
+		if log_block_size > 6 {
+			return Err(EINVAL);
+		}
+		let block_size = 1024 << log_block_size;
+		let revision = get_u32(&raw, SB_REV_LEVEL);
+		let (first_ino, inode_size) = match revision {
+			0 => (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE),
+			_ => (
+				get_u32(&raw, SB_FIRST_INO),
+				get_u16(&raw, SB_INODE_SIZE) as usize,
+			),
+		};
+		let incompat = get_u32(&raw, SB_FEATURE_INCOMPAT);
+		let ro_compat = get_u32(&raw, SB_FEATURE_RO_COMPAT);
+		let supported_ro = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE | RO_COMPAT_BTREE_DIR;
+		if revision > 0 && (incompat & !INCOMPAT_FILETYPE != 0 || ro_compat & !supported_ro != 0) {
+			log!(
+				LogLevel::Error,
+				"ext2: unsupported features, incompat {:#x}, ro_compat {:#x}",
+				incompat,
+				ro_compat
+			);
+			return Err(EINVAL);
+		}
+
+		let blocks_count = get_u32(&raw, SB_BLOCKS_COUNT);
+		let blocks_per_group = get_u32(&raw, SB_BLOCKS_PER_GROUP);
+		let inodes_per_group = get_u32(&raw, SB_INODES_PER_GROUP);
+		let first_data_block = get_u32(&raw, SB_FIRST_DATA_BLOCK);
+		if blocks_per_group == 0
+			|| inodes_per_group == 0
+			|| blocks_count <= first_data_block
+			|| !(GOOD_OLD_INODE_SIZE..=block_size).contains(&inode_size)
+			|| first_ino < ROOT_INO + 1
+		{
+			return Err(EINVAL);
+		}
+
+		let shared = Arc::new(Shared {
+			dev: anonymous_dev(),
+			cache: BufferCache::new(device, block_size, CACHE_BLOCKS)?,
+			block_size,
+			inode_size,
+			blocks_count,
+			inodes_count: get_u32(&raw, SB_INODES_COUNT),
+			blocks_per_group,
+			inodes_per_group,
+			first_data_block,
+			first_ino,
+			group_count: (blocks_count - first_data_block).div_ceil(blocks_per_group),
+			filetype: incompat & INCOMPAT_FILETYPE != 0,
+			state: get_u16(&raw, SB_STATE),
+			alloc_lock: Mutex::new(()),
+			inodes: Mutex::new(BTreeMap::new()),
+		});
+		if shared.cache.block_count() < blocks_count as u64 {
+			return Err(EINVAL);
+		}
+
+		let root = shared.inode(ROOT_INO)?;
+		Ext2Inode::check_dir(&root.data.lock()).map_err(|_| EINVAL)?;
+		let (block, offset) = shared.superblock_position();
+		shared.cache.get(block)?.write(|data| {
+			put_u16(data, offset + SB_STATE, shared.state & !STATE_VALID);
+			let mounts = get_u16(data, offset + SB_MNT_COUNT);
+			put_u16(data, offset + SB_MNT_COUNT, mounts.wrapping_add(1));
+		});
+		log!(
+			LogLevel::Info,
+			"ext2: {} blocks of {} bytes in {} groups, {} inodes",
+			blocks_count,
+			block_size,
+			shared.group_count,
+			shared.inodes_count
+		);
+		Ok(Arc::new(Ext2Fs { shared, root }))
+	}
+
+	// Free blocks and inodes, as counted in the superblock
+	pub fn free_counts(&self) -> Result<(u32, u32), Errno> {
+		let (block, offset) = self.shared.superblock_position();
+		Ok(self.shared.cache.get(block)?.read(|data| {
+			(
+				get_u32(data, offset + SB_FREE_BLOCKS),
+				get_u32(data, offset + SB_FREE_INODES),
+			)
+		}))
+	}
+}
+
+impl SuperBlock for Ext2Fs {
+	fn fs_type(&self) -> &'static str {
+		"ext2"
+	}
+
+	fn root(&self) -> InodeRef {
+		self.root.clone()
+	}
+
+	fn sync(&self) -> Result<(), Errno> {
+		self.shared.cache.sync()
+	}
+}
+
+pub fn ext2_test() {
+	use super::dentry;
+	use super::file::{O_CREAT, O_RDONLY, O_RDWR, SEEK_SET};
+	use crate::drivers::block::RamDisk;
+
+	// Image like `mke2fs -t ext2 -b 1024 -N 32 -I 128 -O none,filetype` of `blocks` KB: one
+	// group with its bitmaps in blocks 3 and 4, the inode table in 5 to 8 and the root
+	// directory in block 9
+	fn format(blocks: u32) -> Vec<u8> {
+		let mut image = alloc::vec![0; blocks as usize * 1024];
+		let inodes = 32;
+		let superblock = &mut image[1024..2048];
+		put_u32(superblock, SB_INODES_COUNT, inodes);
+		put_u32(superblock, SB_BLOCKS_COUNT, blocks);
+		put_u32(superblock, SB_FREE_BLOCKS, blocks - 10);
+		put_u32(superblock, SB_FREE_INODES, inodes - 10);
+		put_u32(superblock, SB_FIRST_DATA_BLOCK, 1);
+		put_u32(superblock, SB_BLOCKS_PER_GROUP, 8192);
+		// Fragments per group
+		put_u32(superblock, 36, 8192);
+		put_u32(superblock, SB_INODES_PER_GROUP, inodes);
+		put_u16(superblock, SB_MAGIC, EXT2_MAGIC);
+		put_u16(superblock, SB_STATE, STATE_VALID);
+		put_u32(superblock, SB_REV_LEVEL, 1);
+		put_u32(superblock, SB_FIRST_INO, 11);
+		put_u16(superblock, SB_INODE_SIZE, 128);
+		put_u32(superblock, SB_FEATURE_INCOMPAT, INCOMPAT_FILETYPE);
+
+		let group = &mut image[2048..2048 + GROUP_DESC_SIZE];
+		put_u32(group, GD_BLOCK_BITMAP, 3);
+		put_u32(group, GD_INODE_BITMAP, 4);
+		put_u32(group, GD_INODE_TABLE, 5);
+		put_u16(group, GD_FREE_BLOCKS, (blocks - 10) as u16);
+		put_u16(group, GD_FREE_INODES, (inodes - 10) as u16);
+		put_u16(group, GD_USED_DIRS, 1);
+
+		// Blocks 1 to 9, and inodes 1 to 10
+		image[3 * 1024] = 0xff;
+		image[3 * 1024 + 1] = 0x01;
+		image[4 * 1024] = 0xff;
+		image[4 * 1024 + 1] = 0x03;
+
+		let mut root = DiskInode::new(0o40755);
+		root.links = 2;
+		root.size = 1024;
+		root.sectors = 2;
+		root.block[0] = 9;
+		root.encode(&mut image[5 * 1024 + 128..5 * 1024 + 256]);
+		let directory = &mut image[9 * 1024..10 * 1024];
+		Ext2Inode::write_record(directory, 0, ROOT_INO, 12, ".", 2);
+		Ext2Inode::write_record(directory, 12, ROOT_INO, 1012, "..", 2);
+		image
+	}
+
+	fn names(path: &str) -> Vec<String> {
+		let directory = super::open(path, O_RDONLY, 0).unwrap();
+		let mut names = Vec::new();
+		directory
+			.read_dir(|entry, _| {
+				names.push(entry.name.clone());
+				Ok(true)
+			})
+			.unwrap();
+		names
+	}
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting ext2\n");
+
+	let disk = RamDisk::new("ext2-test", 1024);
+	let image = format(512);
+	disk.write_sectors(0, &image).unwrap();
+	assert!(Ext2Fs::new(RamDisk::new("blank", 64)).err() == Some(EINVAL));
+
+	super::mkdir("/tmp/ext2-test", 0o755).expect("mkdir failed");
+	let target = super::lookup("/tmp/ext2-test").unwrap();
+	let fs = Ext2Fs::new(disk.clone()).expect("Failed to mount ext2");
+	let initial = fs.free_counts().unwrap();
+	assert!(initial == (502, 22));
+	dentry::mount(&target, fs.clone()).expect("mount failed");
+	assert!(names("/tmp/ext2-test") == [".", ".."]);
+
+	log!(
+		LogLevel::Info,
+		"Files should read back what was written, through indirect blocks\n"
+	);
+	let file = super::open("/tmp/ext2-test/big", O_CREAT | O_RDWR, 0o644).unwrap();
+	let mut pattern = alloc::vec![0; 300 * 1024];
+	for (index, byte) in pattern.iter_mut().enumerate() {
+		*byte = (index % 251) as u8;
+	}
+	assert!(file.write(&pattern) == Ok(pattern.len()));
+	// 300 data blocks, a single indirect block and two for the double indirect tree
+	assert!(fs.free_counts().unwrap() == (initial.0 - 303, initial.1 - 1));
+	let mut read = alloc::vec![0; pattern.len()];
+	file.seek(0, SEEK_SET).unwrap();
+	assert!(file.read(&mut read) == Ok(pattern.len()));
+	assert!(read == pattern);
+
+	log!(
+		LogLevel::Info,
+		"Truncating should free blocks and zero the cut off bytes\n"
+	);
+	assert!(super::truncate("/tmp/ext2-test/big", 5000) == Ok(()));
+	assert!(fs.free_counts().unwrap().0 == initial.0 - 5);
+	assert!(super::truncate("/tmp/ext2-test/big", 6000) == Ok(()));
+	let mut buffer = [0; 1000];
+	file.seek(4500, SEEK_SET).unwrap();
+	assert!(file.read(&mut buffer) == Ok(1000));
+	assert!(buffer[..500] == pattern[4500..5000] && buffer[500..] == [0; 500]);
+
+	log!(
+		LogLevel::Info,
+		"Directories should grow and be removed only when empty\n"
+	);
+	assert!(super::mkdir("/tmp/ext2-test/dir", 0o755) == Ok(()));
+	assert!(super::stat("/tmp/ext2-test").unwrap().nlink == 3);
+	let mut expected = alloc::vec![String::from("."), String::from("..")];
+	for index in 0..20 {
+		// 13 entries of 72 bytes fit in the first block
+		let name = alloc::format!("{:-<60}{:02}", "file", index);
+		super::write_file(
+			&alloc::format!("/tmp/ext2-test/dir/{}", name),
+			name.as_bytes(),
+			0o644,
+		)
+		.unwrap();
+		expected.push(name);
+	}
+	assert!(super::stat("/tmp/ext2-test/dir").unwrap().size == 2048);
+	assert!(names("/tmp/ext2-test/dir") == expected);
+	assert!(super::rmdir("/tmp/ext2-test/dir") == Err(ENOTEMPTY));
+	assert!(super::unlink("/tmp/ext2-test/dir") == Err(EISDIR));
+	assert!(super::link("/tmp/ext2-test/big", "/tmp/ext2-test/dir/big") == Ok(()));
+	assert!(super::stat("/tmp/ext2-test/big").unwrap().nlink == 2);
+	assert!(super::link("/tmp/ext2-test/big", "/tmp/big") == Err(EXDEV));
+
+	log!(
+		LogLevel::Info,
+		"Files should still be there after a remount\n"
+	);
+	drop(file);
+	dentry::umount(&super::lookup("/tmp/ext2-test").unwrap()).expect("umount failed");
+	drop(fs);
+	let fs = Ext2Fs::new(disk.clone()).expect("Failed to remount ext2");
+	dentry::mount(&target, fs.clone()).expect("mount failed");
+	let name = &expected[16];
+	let path = alloc::format!("/tmp/ext2-test/dir/{}", name);
+	assert!(super::read_file(&path).unwrap() == name.as_bytes());
+	assert!(super::read_file("/tmp/ext2-test/dir/big").unwrap()[..5000] == pattern[..5000]);
+
+	log!(
+		LogLevel::Info,
+		"Removing everything should give every block and inode back\n"
+	);
+	for name in &expected[2..] {
+		super::unlink(&alloc::format!("/tmp/ext2-test/dir/{}", name)).unwrap();
+	}
+	super::unlink("/tmp/ext2-test/dir/big").unwrap();
+	assert!(names("/tmp/ext2-test/dir") == [".", ".."]);
+	super::rmdir("/tmp/ext2-test/dir").unwrap();
+	super::unlink("/tmp/ext2-test/big").unwrap();
+	assert!(super::stat("/tmp/ext2-test").unwrap().nlink == 2);
+	assert!(fs.free_counts().unwrap() == initial);
+	assert!(names("/tmp/ext2-test") == [".", ".."]);
+
+	dentry::umount(&super::lookup("/tmp/ext2-test").unwrap()).expect("umount failed");
+	drop(fs);
+	assert!(super::rmdir("/tmp/ext2-test") == Ok(()));
+	let mut superblock = [0; 1024];
+	disk.read_sectors(2, &mut superblock).unwrap();
+	assert!(get_u16(&superblock, SB_STATE) & STATE_VALID != 0);
+
+	log!(LogLevel::Info, "\t\tEnd of ext2 test\n");
+}
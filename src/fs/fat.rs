@@ -0,0 +1,1555 @@
+//! # FAT
+//!
+//! FAT12, FAT16 and FAT32 on a block device, so files dropped on a disk image with `mtools`
+//! can be read from the shell and the other way around. The variant is told by the number of
+//! clusters like every other implementation does. Long file names are read and written, and
+//! names that do not fit in 8.3 get a `~N` short alias. Lookups ignore the case of ASCII
+//! letters, as Windows does.
+//!
+//! FAT has no inodes: a file is identified by the position of its directory entry, which never
+//! moves since entries are only ever marked deleted. The size and the first cluster live in
+//! that entry and are written back whenever they change. All FAT copies are kept identical.
+//! On FAT32 the free cluster count of the FSInfo sector is marked unknown on the first change
+//! instead of being kept up to date.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::buffer::BufferCache;
+use super::inode::{anonymous_dev, DirEntry, FileType, Inode, InodeRef, Metadata, SuperBlock};
+use crate::drivers::block::BlockDevice;
+use crate::exceptions::errno::{
+	Errno, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY,
+};
+use crate::tools::debug::LogLevel;
+use crate::tools::librs::{get_rtc_date, get_rtc_time};
+
+// Boot sector fields
+const BS_BYTES_PER_SECTOR: usize = 11;
+const BS_SECTORS_PER_CLUSTER: usize = 13;
+const BS_RESERVED_SECTORS: usize = 14;
+const BS_FAT_COUNT: usize = 16;
+const BS_ROOT_ENTRIES: usize = 17;
+const BS_TOTAL_SECTORS_16: usize = 19;
+const BS_FAT_SIZE_16: usize = 22;
+const BS_TOTAL_SECTORS_32: usize = 32;
+const BS_FAT_SIZE_32: usize = 36;
+const BS_ROOT_CLUSTER: usize = 44;
+const BS_FSINFO_SECTOR: usize = 48;
+
+// FSInfo sector of FAT32
+const FSINFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+const FSINFO_STRUCT: usize = 484;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_UNKNOWN: u32 = u32::MAX;
+
+const ENTRY_SIZE: usize = 32;
+const DE_ATTR: usize = 11;
+const DE_NTRES: usize = 12;
+const DE_CREATE_TIME: usize = 14;
+const DE_CREATE_DATE: usize = 16;
+const DE_ACCESS_DATE: usize = 18;
+const DE_CLUSTER_HIGH: usize = 20;
+const DE_WRITE_TIME: usize = 22;
+const DE_WRITE_DATE: usize = 24;
+const DE_CLUSTER_LOW: usize = 26;
+const DE_SIZE: usize = 28;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+// First byte of the name of free entries, the end marker frees all the entries after it
+const END_OF_DIRECTORY: u8 = 0x00;
+const DELETED: u8 = 0xe5;
+// Stands for a name starting with 0xe5
+const KANJI_E5: u8 = 0x05;
+
+// Lower case base name and extension of a short name, as set by Windows NT
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT: u8 = 0x10;
+
+// Long name entries hold 13 UTF-16 units, the last one in disk order is flagged
+const LONG_NAME_UNITS: usize = 13;
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const LAST_LONG_ENTRY: u8 = 0x40;
+const LONG_NAME_MAX: usize = 255;
+
+// Characters allowed in short names besides letters and digits
+const SHORT_NAME_SPECIALS: &[u8] = b"$%'-_@~`!(){}^#&";
+const INVALID_CHARACTERS: &str = "\"*/:<>?\\|";
+
+const ROOT_INO: u64 = 1;
+const CACHE_BLOCKS: usize = 256;
+
+fn get_u16(bytes: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn get_u32(bytes: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes([
+		bytes[offset],
+		bytes[offset + 1],
+		bytes[offset + 2],
+		bytes[offset + 3],
+	])
+}
+
+fn put_u16(bytes: &mut [u8], offset: usize, value: u16) {
+	bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+	bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// Date and time of the real time clock in the FAT format, which counts years from 1980
+fn now() -> (u16, u16) {
+	let (year, month, day) = get_rtc_date();
+	let (hours, minutes, seconds) = get_rtc_time();
+	let date = ((year as u16 + 20) << 9) | ((month as u16) << 5) | day as u16;
+	let time = ((hours as u16) << 11) | ((minutes as u16) << 5) | (seconds as u16 / 2);
+	(date, time)
+}
+
+// Seconds since 1970 of a FAT date and time
+fn unix_time(date: u16, time: u16) -> u32 {
+	let year = 1980 + (date >> 9) as u32;
+	let month = ((date >> 5) & 0xf).clamp(1, 12) as u32;
+	let day = (date & 0x1f).max(1) as u32;
+	// Years start in March so leap days end them
+	let (year, month) = match month {
+		1 | 2 => (year - 1, month + 9),
+		_ => (year, month - 3),
+	};
+	let days =
+		365 * year + year / 4 - year / 100 + year / 400 + (153 * month + 2) / 5 + day - 1 - 719468;
+	days * 86400
+		+ (time >> 11) as u32 * 3600
+		+ ((time >> 5) & 0x3f) as u32 * 60
+		+ (time & 0x1f) as u32 * 2
+}
+
+// Checksum of a short name, stored in the long name entries that belong to it
+fn checksum(short: &[u8]) -> u8 {
+	short
+		.iter()
+		.fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+// Name of a short entry, in lower case where the NT flags say so
+fn decode_short(raw: &[u8]) -> String {
+	let part = |bytes: &[u8], lower: bool| -> String {
+		let mut part: String = bytes.iter().map(|&byte| byte as char).collect();
+		part.truncate(part.trim_end_matches(' ').len());
+		match lower {
+			true => part.to_ascii_lowercase(),
+			false => part,
+		}
+	};
+	let mut base = raw[..8].to_vec();
+	if base[0] == KANJI_E5 {
+		base[0] = DELETED;
+	}
+	let mut name = part(&base, raw[DE_NTRES] & NTRES_LOWER_BASE != 0);
+	let extension = part(&raw[8..11], raw[DE_NTRES] & NTRES_LOWER_EXT != 0);
+	if !extension.is_empty() {
+		name.push('.');
+		name.push_str(&extension);
+	}
+	name
+}
+
+fn is_short_character(byte: u8) -> bool {
+	byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_NAME_SPECIALS.contains(&byte)
+}
+
+// Short name and NT flags of `name` when it fits in 8.3 as it is, with each part all in upper
+// or all in lower case
+fn exact_short(name: &str) -> Option<([u8; 11], u8)> {
+	let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+	if base.is_empty() || base.len() > 8 || extension.len() > 3 || name.ends_with('.') {
+		return None;
+	}
+	let mut short = [b' '; 11];
+	let mut ntres = 0;
+	let (base_field, extension_field) = short.split_at_mut(8);
+	for (part, field, flag) in [
+		(base, base_field, NTRES_LOWER_BASE),
+		(extension, extension_field, NTRES_LOWER_EXT),
+	] {
+		let upper = part.to_ascii_uppercase();
+		if !upper.bytes().all(is_short_character) {
+			return None;
+		}
+		if part != upper {
+			match part == part.to_ascii_lowercase() {
+				true => ntres |= flag,
+				false => return None,
+			}
+		}
+		field[..part.len()].copy_from_slice(upper.as_bytes());
+	}
+	Some((short, ntres))
+}
+
+// `BASE~N.EXT` alias of a long name, unique among `existing`
+fn short_alias(name: &str, existing: &[[u8; 11]]) -> Result<[u8; 11], Errno> {
+	let convert = |part: &str| -> Vec<u8> {
+		part.chars()
+			.filter(|&c| c != ' ' && c != '.')
+			.map(|c| match c.to_ascii_uppercase() as u32 {
+				byte @ 0..=0x7f if is_short_character(byte as u8) => byte as u8,
+				_ => b'_',
+			})
+			.collect()
+	};
+	let trimmed = name.trim_start_matches('.');
+	let (base, extension) = match trimmed.rsplit_once('.') {
+		Some((base, extension)) if !base.is_empty() => (convert(base), convert(extension)),
+		_ => (convert(trimmed), Vec::new()),
+	};
+	let mut short = [b' '; 11];
+	let extension_len = extension.len().min(3);
+	short[8..8 + extension_len].copy_from_slice(&extension[..extension_len]);
+	for number in 1..1_000_000 {
+		let tail = alloc::format!("~{}", number);
+		let base_len = base.len().min(8 - tail.len());
+		short[..8].fill(b' ');
+		short[..base_len].copy_from_slice(&base[..base_len]);
+		short[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+		if !existing.contains(&short) {
+			return Ok(short);
+		}
+	}
+	Err(ENOSPC)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FatKind {
+	Fat12,
+	Fat16,
+	Fat32,
+}
+
+// Where the entries of a directory are: the fixed root directory of FAT12 and FAT16, or
+// each cluster of the chain
+struct DirSlots {
+	extents: Vec<u64>,
+	extent_size: usize,
+}
+
+impl DirSlots {
+	fn count(&self) -> usize {
+		self.extents.len() * self.extent_size / ENTRY_SIZE
+	}
+
+	fn position(&self, slot: usize) -> u64 {
+		let offset = slot * ENTRY_SIZE;
+		self.extents[offset / self.extent_size] + (offset % self.extent_size) as u64
+	}
+}
+
+// Directory entry, with the long name entries before it
+struct Entry {
+	name: String,
+	short: [u8; 11],
+	first_slot: usize,
+	slot: usize,
+	position: u64,
+	attr: u8,
+	cluster: u32,
+	size: u32,
+	date: u16,
+	time: u16,
+}
+
+// Long name being gathered from its entries, which come last part first
+struct LongName {
+	units: Vec<u16>,
+	checksum: u8,
+	first_slot: usize,
+	// Number of the entry expected next, down to 1
+	next: u8,
+}
+
+// Part of the filesystem every inode refers to
+struct Shared {
+	dev: u32,
+	// Blocks are the sectors of the filesystem
+	cache: BufferCache,
+	kind: FatKind,
+	sector_size: usize,
+	cluster_size: usize,
+	fat_start: u64,
+	fat_size: u64,
+	fat_count: u32,
+	// Fixed root directory of FAT12 and FAT16
+	root_start: u64,
+	root_entries: usize,
+	// Root directory cluster of FAT32
+	root_cluster: u32,
+	data_start: u64,
+	cluster_count: u32,
+	fsinfo: Option<u64>,
+	allocator: Mutex<Allocator>,
+	// Inodes in use by number, so everyone shares the same one
+	inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+struct Allocator {
+	// Where the search for a free cluster starts
+	next: u32,
+	fsinfo_stale: bool,
+}
+
+impl Shared {
+	// Copies the bytes at `position` of the volume into `buffer`, across sectors
+	fn read_bytes(&self, position: u64, buffer: &mut [u8]) -> Result<(), Errno> {
+		let mut done = 0;
+		while done < buffer.len() {
+			let at = position + done as u64;
+			let offset = (at % self.sector_size as u64) as usize;
+			let chunk = (self.sector_size - offset).min(buffer.len() - done);
+			self.cache.read(
+				at / self.sector_size as u64,
+				offset,
+				&mut buffer[done..done + chunk],
+			)?;
+			done += chunk;
+		}
+		Ok(())
+	}
+
+	fn write_bytes(&self, position: u64, data: &[u8]) -> Result<(), Errno> {
+		let mut done = 0;
+		while done < data.len() {
+			let at = position + done as u64;
+			let offset = (at % self.sector_size as u64) as usize;
+			let chunk = (self.sector_size - offset).min(data.len() - done);
+			self.cache.write(
+				at / self.sector_size as u64,
+				offset,
+				&data[done..done + chunk],
+			)?;
+			done += chunk;
+		}
+		Ok(())
+	}
+
+	fn zero_bytes(&self, position: u64, length: usize) -> Result<(), Errno> {
+		let mut done = 0;
+		while done < length {
+			let at = position + done as u64;
+			let offset = (at % self.sector_size as u64) as usize;
+			let chunk = (self.sector_size - offset).min(length - done);
+			self.cache
+				.get(at / self.sector_size as u64)?
+				.write(|data| data[offset..offset + chunk].fill(0));
+			done += chunk;
+		}
+		Ok(())
+	}
+
+	// Value of the FAT entries marking the end of a chain
+	fn end_of_chain(&self) -> u32 {
+		match self.kind {
+			FatKind::Fat12 => 0xfff,
+			FatKind::Fat16 => 0xffff,
+			FatKind::Fat32 => 0x0fff_ffff,
+		}
+	}
+
+	// Byte of the first FAT holding the entry of `cluster`
+	fn fat_offset(&self, cluster: u32) -> u64 {
+		match self.kind {
+			FatKind::Fat12 => (cluster + cluster / 2) as u64,
+			FatKind::Fat16 => cluster as u64 * 2,
+			FatKind::Fat32 => cluster as u64 * 4,
+		}
+	}
+
+	fn fat_entry(&self, cluster: u32) -> Result<u32, Errno> {
+		let mut raw = [0; 4];
+		let position = self.fat_start + self.fat_offset(cluster);
+		Ok(match self.kind {
+			FatKind::Fat12 => {
+				self.read_bytes(position, &mut raw[..2])?;
+				let pair = get_u16(&raw, 0) as u32;
+				match cluster % 2 {
+					0 => pair & 0xfff,
+					_ => pair >> 4,
+				}
+			}
+			FatKind::Fat16 => {
+				self.read_bytes(position, &mut raw[..2])?;
+				get_u16(&raw, 0) as u32
+			}
+			FatKind::Fat32 => {
+				self.read_bytes(position, &mut raw)?;
+				get_u32(&raw, 0) & 0x0fff_ffff
+			}
+		})
+	}
+
+	// Sets the entry of `cluster` in every FAT
+	fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), Errno> {
+		for copy in 0..self.fat_count as u64 {
+			let position = self.fat_start + copy * self.fat_size + self.fat_offset(cluster);
+			let mut raw = [0; 4];
+			match self.kind {
+				FatKind::Fat12 => {
+					// Entries share the byte in the middle of the pair
+					self.read_bytes(position, &mut raw[..2])?;
+					let pair = get_u16(&raw, 0);
+					let pair = match cluster % 2 {
+						0 => (pair & 0xf000) | (value as u16 & 0xfff),
+						_ => (pair & 0x000f) | ((value as u16) << 4),
+					};
+					put_u16(&mut raw, 0, pair);
+					self.write_bytes(position, &raw[..2])?;
+				}
+				FatKind::Fat16 => {
+					put_u16(&mut raw, 0, value as u16);
+					self.write_bytes(position, &raw[..2])?;
+				}
+				FatKind::Fat32 => {
+					// The top 4 bits are reserved and kept
+					self.read_bytes(position, &mut raw)?;
+					let entry = (get_u32(&raw, 0) & 0xf000_0000) | (value & 0x0fff_ffff);
+					put_u32(&mut raw, 0, entry);
+					self.write_bytes(position, &raw)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn check_cluster(&self, cluster: u32) -> Result<u32, Errno> {
+		match (2..self.cluster_count + 2).contains(&cluster) {
+			true => Ok(cluster),
+			false => {
+				log!(
+					LogLevel::Error,
+					"fat: cluster {} out of the filesystem",
+					cluster
+				);
+				Err(EIO)
+			}
+		}
+	}
+
+	// Cluster after `cluster` in its chain, None at the end
+	fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, Errno> {
+		match self.fat_entry(cluster)? {
+			next if next >= self.end_of_chain() - 7 => Ok(None),
+			next => self.check_cluster(next).map(Some),
+		}
+	}
+
+	// Clusters of the chain starting at `first`, which is 0 for no cluster at all
+	fn chain(&self, first: u32) -> Result<Vec<u32>, Errno> {
+		let mut clusters = Vec::new();
+		let mut cluster = match first {
+			0 => None,
+			first => Some(self.check_cluster(first)?),
+		};
+		while let Some(current) = cluster {
+			// A chain longer than the volume loops
+			if clusters.len() > self.cluster_count as usize {
+				log!(LogLevel::Error, "fat: cluster chain from {} loops", first);
+				return Err(EIO);
+			}
+			clusters.push(current);
+			cluster = self.next_cluster(current)?;
+		}
+		Ok(clusters)
+	}
+
+	fn cluster_position(&self, cluster: u32) -> u64 {
+		self.data_start + (cluster - 2) as u64 * self.cluster_size as u64
+	}
+
+	// Marks the free cluster count of FAT32 unknown, for fsck to recount it
+	fn invalidate_fsinfo(&self, allocator: &mut Allocator) -> Result<(), Errno> {
+		if let (Some(position), true) = (self.fsinfo, allocator.fsinfo_stale) {
+			self.write_bytes(
+				position + FSINFO_FREE_COUNT as u64,
+				&FSINFO_UNKNOWN.to_le_bytes(),
+			)?;
+			allocator.fsinfo_stale = false;
+		}
+		Ok(())
+	}
+
+	// Zeroed cluster added after `previous` in its chain
+	fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32, Errno> {
+		let mut allocator = self.allocator.lock();
+		for index in 0..self.cluster_count {
+			let cluster = 2 + (allocator.next - 2 + index) % self.cluster_count;
+			if self.fat_entry(cluster)? != 0 {
+				continue;
+			}
+			self.invalidate_fsinfo(&mut allocator)?;
+			self.set_fat_entry(cluster, self.end_of_chain())?;
+			if let Some(previous) = previous {
+				self.set_fat_entry(previous, cluster)?;
+			}
+			self.zero_bytes(self.cluster_position(cluster), self.cluster_size)?;
+			allocator.next = 2 + (cluster - 1) % self.cluster_count;
+			return Ok(cluster);
+		}
+		Err(ENOSPC)
+	}
+
+	fn free_chain(&self, first: u32) -> Result<(), Errno> {
+		let clusters = self.chain(first)?;
+		let mut allocator = self.allocator.lock();
+		self.invalidate_fsinfo(&mut allocator)?;
+		for cluster in clusters {
+			self.set_fat_entry(cluster, 0)?;
+		}
+		Ok(())
+	}
+
+	// Cluster of the chain from `first` holding the byte `offset`
+	fn cluster_at(&self, first: u32, offset: u64) -> Result<u32, Errno> {
+		let mut cluster = self.check_cluster(first)?;
+		for _ in 0..offset / self.cluster_size as u64 {
+			cluster = self.next_cluster(cluster)?.ok_or(EIO)?;
+		}
+		Ok(cluster)
+	}
+
+	// Calls `f` with the volume position, the offset in the range and the length of each piece
+	// of the `length` bytes from `offset` in the chain from `first`
+	fn for_each_extent<F>(
+		&self,
+		first: u32,
+		offset: u64,
+		length: usize,
+		mut f: F,
+	) -> Result<(), Errno>
+	where
+		F: FnMut(u64, usize, usize) -> Result<(), Errno>,
+	{
+		if length == 0 {
+			return Ok(());
+		}
+		let mut cluster = self.cluster_at(first, offset)?;
+		let mut done = 0;
+		while done < length {
+			let within = ((offset + done as u64) % self.cluster_size as u64) as usize;
+			if within == 0 && done > 0 {
+				cluster = self.next_cluster(cluster)?.ok_or(EIO)?;
+			}
+			let chunk = (self.cluster_size - within).min(length - done);
+			f(self.cluster_position(cluster) + within as u64, done, chunk)?;
+			done += chunk;
+		}
+		Ok(())
+	}
+
+	// Gives the chain of `node` enough clusters for `size` bytes, or as many as there is room
+	// for. Returns the bytes the chain holds.
+	fn reserve(&self, node: &mut Node, size: u64) -> Result<u64, Errno> {
+		let clusters = self.chain(node.cluster)?;
+		let mut count = clusters.len() as u64;
+		let mut last = clusters.last().copied();
+		while count * (self.cluster_size as u64) < size {
+			match self.allocate_cluster(last) {
+				Ok(cluster) => {
+					if last.is_none() {
+						node.cluster = cluster;
+					}
+					last = Some(cluster);
+					count += 1;
+				}
+				Err(ENOSPC) => break,
+				Err(errno) => return Err(errno),
+			}
+		}
+		Ok(count * self.cluster_size as u64)
+	}
+
+	// Frees the clusters of `node` past the first `size` bytes
+	fn shrink(&self, node: &mut Node, size: u64) -> Result<(), Errno> {
+		if node.cluster == 0 {
+			return Ok(());
+		}
+		if size == 0 {
+			self.free_chain(node.cluster)?;
+			node.cluster = 0;
+			return Ok(());
+		}
+		let last = self.cluster_at(node.cluster, size - 1)?;
+		if let Some(next) = self.next_cluster(last)? {
+			self.set_fat_entry(last, self.end_of_chain())?;
+			self.free_chain(next)?;
+		}
+		Ok(())
+	}
+
+	fn dir_slots(&self, node: &Node) -> Result<DirSlots, Errno> {
+		match node.cluster {
+			0 => Ok(DirSlots {
+				extents: alloc::vec![self.root_start],
+				extent_size: self.root_entries * ENTRY_SIZE,
+			}),
+			first => Ok(DirSlots {
+				extents: self
+					.chain(first)?
+					.into_iter()
+					.map(|cluster| self.cluster_position(cluster))
+					.collect(),
+				extent_size: self.cluster_size,
+			}),
+		}
+	}
+
+	fn read_slot(&self, slots: &DirSlots, slot: usize) -> Result<[u8; ENTRY_SIZE], Errno> {
+		let mut raw = [0; ENTRY_SIZE];
+		self.read_bytes(slots.position(slot), &mut raw)?;
+		Ok(raw)
+	}
+
+	// Entries of a directory with their long names, volume labels left out
+	fn entries(&self, slots: &DirSlots) -> Result<Vec<Entry>, Errno> {
+		let mut entries = Vec::new();
+		let mut long: Option<LongName> = None;
+		for slot in 0..slots.count() {
+			let raw = self.read_slot(slots, slot)?;
+			match raw[0] {
+				END_OF_DIRECTORY => break,
+				DELETED => {
+					long = None;
+					continue;
+				}
+				_ => {}
+			}
+
+			if raw[DE_ATTR] == ATTR_LONG_NAME {
+				let number = raw[0] & !LAST_LONG_ENTRY;
+				if raw[0] & LAST_LONG_ENTRY != 0 {
+					long = Some(LongName {
+						units: alloc::vec![0xffff; number as usize * LONG_NAME_UNITS],
+						checksum: raw[13],
+						first_slot: slot,
+						next: number,
+					});
+				}
+				// Parts out of order or of another name make the whole name invalid
+				long = long
+					.filter(|long| number != 0 && long.next == number && long.checksum == raw[13]);
+				if let Some(long) = long.as_mut() {
+					let start = (number as usize - 1) * LONG_NAME_UNITS;
+					for (index, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+						long.units[start + index] = get_u16(&raw, offset);
+					}
+					long.next -= 1;
+				}
+				continue;
+			}
+
+			let long_name = long.take();
+			if raw[DE_ATTR] & ATTR_VOLUME_ID != 0 {
+				continue;
+			}
+			let mut short = [0; 11];
+			short.copy_from_slice(&raw[..11]);
+			let (name, first_slot) = match long_name {
+				Some(long) if long.next == 0 && long.checksum == checksum(&short) => {
+					let length = long.units.iter().position(|&unit| unit == 0);
+					let units = &long.units[..length.unwrap_or(long.units.len())];
+					let name = char::decode_utf16(units.iter().copied())
+						.map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+						.collect();
+					(name, long.first_slot)
+				}
+				_ => (decode_short(&raw), slot),
+			};
+			entries.push(Entry {
+				name,
+				short,
+				first_slot,
+				slot,
+				position: slots.position(slot),
+				attr: raw[DE_ATTR],
+				cluster: (get_u16(&raw, DE_CLUSTER_HIGH) as u32) << 16
+					| get_u16(&raw, DE_CLUSTER_LOW) as u32,
+				size: get_u32(&raw, DE_SIZE),
+				date: get_u16(&raw, DE_WRITE_DATE),
+				time: get_u16(&raw, DE_WRITE_TIME),
+			});
+		}
+		Ok(entries)
+	}
+
+	fn write_short(
+		&self,
+		position: u64,
+		short: &[u8; 11],
+		ntres: u8,
+		attr: u8,
+		cluster: u32,
+	) -> Result<(), Errno> {
+		let (date, time) = now();
+		let mut raw = [0; ENTRY_SIZE];
+		raw[..11].copy_from_slice(short);
+		raw[DE_ATTR] = attr;
+		raw[DE_NTRES] = ntres;
+		put_u16(&mut raw, DE_CREATE_TIME, time);
+		put_u16(&mut raw, DE_CREATE_DATE, date);
+		put_u16(&mut raw, DE_ACCESS_DATE, date);
+		put_u16(&mut raw, DE_CLUSTER_HIGH, (cluster >> 16) as u16);
+		put_u16(&mut raw, DE_WRITE_TIME, time);
+		put_u16(&mut raw, DE_WRITE_DATE, date);
+		put_u16(&mut raw, DE_CLUSTER_LOW, cluster as u16);
+		self.write_bytes(position, &raw)
+	}
+
+	// Writes the first cluster, size and times of `node` back to its directory entry
+	fn update_entry(&self, node: &Node) -> Result<(), Errno> {
+		let Some(position) = node.entry else {
+			return Ok(());
+		};
+		let mut raw = [0; ENTRY_SIZE];
+		self.read_bytes(position, &mut raw)?;
+		put_u16(&mut raw, DE_CLUSTER_HIGH, (node.cluster >> 16) as u16);
+		put_u16(&mut raw, DE_CLUSTER_LOW, node.cluster as u16);
+		put_u32(&mut raw, DE_SIZE, node.size);
+		put_u16(&mut raw, DE_WRITE_TIME, node.time);
+		put_u16(&mut raw, DE_WRITE_DATE, node.date);
+		put_u16(&mut raw, DE_ACCESS_DATE, node.date);
+		raw[DE_ATTR] |= ATTR_ARCHIVE;
+		self.write_bytes(position, &raw)
+	}
+
+	fn inode(self: &Arc<Self>, parent: u64, entry: &Entry) -> Arc<FatInode> {
+		let ino = entry.position / ENTRY_SIZE as u64;
+		let mut inodes = self.inodes.lock();
+		if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+			return inode;
+		}
+		let inode = Arc::new(FatInode {
+			ino,
+			parent,
+			fs: self.clone(),
+			data: Mutex::new(Node {
+				directory: entry.attr & ATTR_DIRECTORY != 0,
+				read_only: entry.attr & ATTR_READ_ONLY != 0,
+				cluster: entry.cluster,
+				size: entry.size,
+				entry: Some(entry.position),
+				removed: false,
+				date: entry.date,
+				time: entry.time,
+			}),
+		});
+		inodes.insert(ino, Arc::downgrade(&inode));
+		inode
+	}
+}
+
+impl Drop for Shared {
+	fn drop(&mut self) {
+		if let Err(errno) = self.cache.sync() {
+			log!(
+				LogLevel::Error,
+				"fat: failed to write back the filesystem: error {}",
+				errno
+			);
+		}
+	}
+}
+
+struct Node {
+	directory: bool,
+	read_only: bool,
+	// First cluster, 0 for empty files and the fixed root directory
+	cluster: u32,
+	size: u32,
+	// Position of the directory entry, None for the root and removed files
+	entry: Option<u64>,
+	// Its clusters are freed with the inode
+	removed: bool,
+	date: u16,
+	time: u16,
+}
+
+struct FatInode {
+	ino: u64,
+	// Directory holding the entry, for `..`
+	parent: u64,
+	fs: Arc<Shared>,
+	data: Mutex<Node>,
+}
+
+impl FatInode {
+	fn check_dir(data: &Node) -> Result<(), Errno> {
+		match data.directory {
+			true => Ok(()),
+			false => Err(ENOTDIR),
+		}
+	}
+
+	fn check_regular(data: &Node) -> Result<(), Errno> {
+		match data.directory {
+			true => Err(EISDIR),
+			false => Ok(()),
+		}
+	}
+
+	fn find(&self, data: &Node, name: &str) -> Result<Entry, Errno> {
+		self.fs
+			.entries(&self.fs.dir_slots(data)?)?
+			.into_iter()
+			.find(|entry| entry.name.eq_ignore_ascii_case(name))
+			.ok_or(ENOENT)
+	}
+
+	// Cluster of this directory as stored in the `..` entries of its subdirectories
+	fn dotdot_cluster(&self, data: &Node) -> u32 {
+		match self.ino {
+			ROOT_INO => 0,
+			_ => data.cluster,
+		}
+	}
+
+	// Adds an entry for `name` with its long name entries when it needs some, growing the
+	// directory if there is no room. Returns the position of the short entry.
+	fn add_entry(&self, data: &mut Node, name: &str, attr: u8, cluster: u32) -> Result<u64, Errno> {
+		let fs = &self.fs;
+		let mut slots = fs.dir_slots(data)?;
+		let existing: Vec<[u8; 11]> = fs
+			.entries(&slots)?
+			.iter()
+			.map(|entry| entry.short)
+			.collect();
+		let (short, ntres, units) = match exact_short(name) {
+			Some((short, ntres)) if !existing.contains(&short) => (short, ntres, Vec::new()),
+			_ => {
+				let mut units: Vec<u16> = name.encode_utf16().collect();
+				// Names filling the last entry are not terminated
+				if units.len() % LONG_NAME_UNITS != 0 {
+					units.push(0);
+				}
+				units.resize(units.len().next_multiple_of(LONG_NAME_UNITS), 0xffff);
+				(short_alias(name, &existing)?, 0, units)
+			}
+		};
+		let long_entries = units.len() / LONG_NAME_UNITS;
+		let needed = long_entries + 1;
+
+		// First run of free slots long enough, in a new cluster if there is none
+		let mut run = 0;
+		let mut slot = 0;
+		loop {
+			if slot == slots.count() {
+				if data.cluster == 0 {
+					return Err(ENOSPC);
+				}
+				let last = fs.chain(data.cluster)?.last().copied();
+				fs.allocate_cluster(last)?;
+				slots = fs.dir_slots(data)?;
+			}
+			match fs.read_slot(&slots, slot)?[0] {
+				END_OF_DIRECTORY | DELETED => run += 1,
+				_ => run = 0,
+			}
+			slot += 1;
+			if run == needed {
+				break;
+			}
+		}
+
+		let first = slot - needed;
+		let sum = checksum(&short);
+		for index in 0..long_entries {
+			// The last part of the name comes first
+			let number = (long_entries - index) as u8;
+			let mut raw = [0; ENTRY_SIZE];
+			raw[0] = match index {
+				0 => number | LAST_LONG_ENTRY,
+				_ => number,
+			};
+			raw[DE_ATTR] = ATTR_LONG_NAME;
+			raw[13] = sum;
+			let start = (number as usize - 1) * LONG_NAME_UNITS;
+			for (unit, &offset) in units[start..start + LONG_NAME_UNITS]
+				.iter()
+				.zip(LONG_NAME_OFFSETS.iter())
+			{
+				put_u16(&mut raw, offset, *unit);
+			}
+			fs.write_bytes(slots.position(first + index), &raw)?;
+		}
+		let position = slots.position(first + long_entries);
+		fs.write_short(position, &short, ntres, attr, cluster)?;
+		(data.date, data.time) = now();
+		fs.update_entry(data)?;
+		Ok(position)
+	}
+
+	// Marks the entry and its long name entries deleted
+	fn remove_entry(&self, data: &mut Node, entry: &Entry) -> Result<(), Errno> {
+		let slots = self.fs.dir_slots(data)?;
+		for slot in entry.first_slot..=entry.slot {
+			self.fs.write_bytes(slots.position(slot), &[DELETED])?;
+		}
+		(data.date, data.time) = now();
+		self.fs.update_entry(data)
+	}
+
+	// Detaches the inode of a removed entry, whose clusters go with it
+	fn release(&self, inode: &Arc<FatInode>) {
+		let mut child = inode.data.lock();
+		child.entry = None;
+		child.removed = true;
+		drop(child);
+		let mut inodes = self.fs.inodes.lock();
+		if inodes
+			.get(&inode.ino)
+			.is_some_and(|weak| weak.as_ptr() == Arc::as_ptr(inode))
+		{
+			inodes.remove(&inode.ino);
+		}
+	}
+
+	fn check_name(name: &str) -> Result<(), Errno> {
+		if name.encode_utf16().count() > LONG_NAME_MAX {
+			return Err(ENAMETOOLONG);
+		}
+		match name
+			.chars()
+			.any(|c| c < ' ' || INVALID_CHARACTERS.contains(c))
+		{
+			true => Err(EINVAL),
+			false => Ok(()),
+		}
+	}
+}
+
+impl Inode for FatInode {
+	fn metadata(&self) -> Metadata {
+		let data = self.data.lock();
+		let (file_type, mode) = match (data.directory, data.read_only) {
+			(true, _) => (FileType::Directory, 0o755),
+			(false, false) => (FileType::Regular, 0o644),
+			(false, true) => (FileType::Regular, 0o444),
+		};
+		let mut metadata = Metadata::new(self.fs.dev, self.ino, file_type, mode);
+		let cluster_size = self.fs.cluster_size as u64;
+		let allocated = match data.directory {
+			true => self
+				.fs
+				.dir_slots(&data)
+				.map_or(0, |slots| slots.count() * ENTRY_SIZE) as u64,
+			false => (data.size as u64).next_multiple_of(cluster_size),
+		};
+		metadata.nlink = if data.directory { 2 } else { 1 };
+		metadata.size = if data.directory {
+			allocated
+		} else {
+			data.size as u64
+		};
+		metadata.block_size = cluster_size as u32;
+		metadata.blocks = allocated / 512;
+		let time = unix_time(data.date, data.time);
+		(metadata.atime, metadata.mtime, metadata.ctime) = (time, time, time);
+		metadata
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, Errno> {
+		let data = self.data.lock();
+		Self::check_dir(&data)?;
+		let entry = self.find(&data, name)?;
+		Ok(self.fs.inode(self.ino, &entry))
+	}
+
+	fn create(&self, name: &str, file_type: FileType, _mode: u32) -> Result<InodeRef, Errno> {
+		if !matches!(file_type, FileType::Regular | FileType::Directory) {
+			return Err(EINVAL);
+		}
+		Self::check_name(name)?;
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		if data.removed {
+			return Err(ENOENT);
+		}
+		match self.find(&data, name) {
+			Ok(_) => return Err(EEXIST),
+			Err(ENOENT) => {}
+			Err(errno) => return Err(errno),
+		}
+
+		let fs = &self.fs;
+		let position = match file_type {
+			FileType::Directory => {
+				let cluster = fs.allocate_cluster(None)?;
+				let start = fs.cluster_position(cluster);
+				let parent = self.dotdot_cluster(&data);
+				let result = fs
+					.write_short(start, b".          ", 0, ATTR_DIRECTORY, cluster)
+					.and_then(|_| {
+						fs.write_short(
+							start + ENTRY_SIZE as u64,
+							b"..         ",
+							0,
+							ATTR_DIRECTORY,
+							parent,
+						)
+					})
+					.and_then(|_| self.add_entry(&mut data, name, ATTR_DIRECTORY, cluster));
+				if result.is_err() {
+					fs.free_chain(cluster)?;
+				}
+				result?
+			}
+			_ => self.add_entry(&mut data, name, ATTR_ARCHIVE, 0)?,
+		};
+		let entry = fs
+			.entries(&fs.dir_slots(&data)?)?
+			.into_iter()
+			.find(|entry| entry.position == position)
+			.ok_or(EIO)?;
+		Ok(fs.inode(self.ino, &entry))
+	}
+
+	fn unlink(&self, name: &str) -> Result<(), Errno> {
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		let entry = self.find(&data, name)?;
+		if entry.attr & ATTR_DIRECTORY != 0 {
+			return Err(EISDIR);
+		}
+		let inode = self.fs.inode(self.ino, &entry);
+		self.remove_entry(&mut data, &entry)?;
+		self.release(&inode);
+		Ok(())
+	}
+
+	fn rmdir(&self, name: &str) -> Result<(), Errno> {
+		let mut data = self.data.lock();
+		Self::check_dir(&data)?;
+		let entry = self.find(&data, name)?;
+		if entry.attr & ATTR_DIRECTORY == 0 {
+			return Err(ENOTDIR);
+		}
+		let inode = self.fs.inode(self.ino, &entry);
+		let child = inode.data.lock();
+		let entries = self.fs.entries(&self.fs.dir_slots(&child)?)?;
+		if entries
+			.iter()
+			.any(|entry| entry.name != "." && entry.name != "..")
+		{
+			return Err(ENOTEMPTY);
+		}
+		drop(child);
+		self.remove_entry(&mut data, &entry)?;
+		self.release(&inode);
+		Ok(())
+	}
+
+	fn read_dir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+		let data = self.data.lock();
+		Self::check_dir(&data)?;
+		let directory = |name: &str, ino| DirEntry {
+			name: String::from(name),
+			ino,
+			file_type: FileType::Directory,
+		};
+		match index {
+			0 => return Ok(Some(directory(".", self.ino))),
+			1 => return Ok(Some(directory("..", self.parent))),
+			_ => {}
+		}
+		let entry = self
+			.fs
+			.entries(&self.fs.dir_slots(&data)?)?
+			.into_iter()
+			.filter(|entry| entry.name != "." && entry.name != "..")
+			.nth(index - 2);
+		Ok(entry.map(|entry| DirEntry {
+			name: entry.name,
+			ino: entry.position / ENTRY_SIZE as u64,
+			file_type: match entry.attr & ATTR_DIRECTORY {
+				0 => FileType::Regular,
+				_ => FileType::Directory,
+			},
+		}))
+	}
+
+	fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+		let data = self.data.lock();
+		Self::check_regular(&data)?;
+		if offset >= data.size as u64 {
+			return Ok(0);
+		}
+		let length = (data.size as u64 - offset).min(buffer.len() as u64) as usize;
+		self.fs
+			.for_each_extent(data.cluster, offset, length, |position, done, chunk| {
+				self.fs
+					.read_bytes(position, &mut buffer[done..done + chunk])
+			})?;
+		Ok(length)
+	}
+
+	// Writes as much as there is room for, ENOSPC if nothing fits
+	fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+		let end = offset
+			.checked_add(buffer.len() as u64)
+			.filter(|&end| end <= u32::MAX as u64)
+			.ok_or(EFBIG)?;
+		let mut data = self.data.lock();
+		Self::check_regular(&data)?;
+		if buffer.is_empty() {
+			return Ok(0);
+		}
+		let fs = &self.fs;
+		let capacity = fs.reserve(&mut data, end)?;
+		if capacity <= offset {
+			let size = data.size as u64;
+			fs.shrink(&mut data, size)?;
+			return Err(ENOSPC);
+		}
+		// Files have no holes, the gap up to the write reads as zeros
+		let size = data.size as u64;
+		if offset > size {
+			fs.for_each_extent(
+				data.cluster,
+				size,
+				(offset - size) as usize,
+				|position, _, chunk| fs.zero_bytes(position, chunk),
+			)?;
+		}
+		let length = (end.min(capacity) - offset) as usize;
+		fs.for_each_extent(data.cluster, offset, length, |position, done, chunk| {
+			fs.write_bytes(position, &buffer[done..done + chunk])
+		})?;
+		data.size = data.size.max((offset + length as u64) as u32);
+		(data.date, data.time) = now();
+		fs.update_entry(&data)?;
+		Ok(length)
+	}
+
+	fn truncate(&self, size: u64) -> Result<(), Errno> {
+		if size > u32::MAX as u64 {
+			return Err(EFBIG);
+		}
+		let mut data = self.data.lock();
+		Self::check_regular(&data)?;
+		let fs = &self.fs;
+		let old = data.size as u64;
+		if size < old {
+			fs.shrink(&mut data, size)?;
+		} else if size > old {
+			if fs.reserve(&mut data, size)? < size {
+				fs.shrink(&mut data, old)?;
+				return Err(ENOSPC);
+			}
+			fs.for_each_extent(
+				data.cluster,
+				old,
+				(size - old) as usize,
+				|position, _, chunk| fs.zero_bytes(position, chunk),
+			)?;
+		}
+		data.size = size as u32;
+		(data.date, data.time) = now();
+		fs.update_entry(&data)
+	}
+}
+
+impl Drop for FatInode {
+	// Frees the clusters of a removed file once nobody uses it
+	fn drop(&mut self) {
+		let data = self.data.get_mut();
+		if data.removed && data.cluster != 0 {
+			if let Err(errno) = self.fs.free_chain(data.cluster) {
+				log!(
+					LogLevel::Error,
+					"fat: failed to free inode {}: error {}",
+					self.ino,
+					errno
+				);
+			}
+		}
+		if data.entry.is_some() {
+			let mut inodes = self.fs.inodes.lock();
+			if inodes
+				.get(&self.ino)
+				.is_some_and(|inode| inode.strong_count() == 0)
+			{
+				inodes.remove(&self.ino);
+			}
+		}
+	}
+}
+
+pub struct FatFs {
+	shared: Arc<Shared>,
+	root: Arc<FatInode>,
+}
+
+impl FatFs {
+	// Reads the boot sector of the filesystem on `device`
+	pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<FatFs>, Errno> {
+		let mut boot = alloc::vec![0; device.sector_size()];
+		device.read_sectors(0, &mut boot)?;
+		if boot.len() < 512 {
+			return Err(EINVAL);
+		}
+		let sector_size = get_u16(&boot, BS_BYTES_PER_SECTOR) as usize;
+		let sectors_per_cluster = boot[BS_SECTORS_PER_CLUSTER] as u64;
+		let reserved = get_u16(&boot, BS_RESERVED_SECTORS) as u64;
+		let fat_count = boot[BS_FAT_COUNT] as u32;
+		let root_entries = get_u16(&boot, BS_ROOT_ENTRIES) as usize;
+		let total_sectors = match get_u16(&boot, BS_TOTAL_SECTORS_16) {
+			0 => get_u32(&boot, BS_TOTAL_SECTORS_32) as u64,
+			count => count as u64,
+		};
+		let fat_sectors = match get_u16(&boot, BS_FAT_SIZE_16) {
+			0 => get_u32(&boot, BS_FAT_SIZE_32) as u64,
+			count => count as u64,
+		};
+		if !(512..=4096).contains(&sector_size)
+			|| !sector_size.is_power_of_two()
+			|| !sectors_per_cluster.is_power_of_two()
+			|| reserved == 0
+			|| fat_count == 0
+			|| fat_sectors == 0
+		{
+			return Err(EINVAL);
+		}
+
+		let root_sectors = (root_entries * ENTRY_SIZE).div_ceil(sector_size) as u64;
+		let data_sector = reserved + fat_count as u64 * fat_sectors + root_sectors;
+		if total_sectors <= data_sector {
+			return Err(EINVAL);
+		}
+		let cluster_count = ((total_sectors - data_sector) / sectors_per_cluster) as u32;
+		let kind = match cluster_count {
+			0..=4084 => FatKind::Fat12,
+			4085..=65524 => FatKind::Fat16,
+			_ => FatKind::Fat32,
+		};
+		// Only FAT32 has its root directory in a cluster chain
+		if (kind == FatKind::Fat32) != (root_entries == 0) {
+			return Err(EINVAL);
+		}
+
+		let sector_bytes = sector_size as u64;
+		let cache = BufferCache::new(device, sector_size, CACHE_BLOCKS)?;
+		if cache.block_count() < total_sectors {
+			return Err(EINVAL);
+		}
+		let mut shared = Shared {
+			dev: anonymous_dev(),
+			cache,
+			kind,
+			sector_size,
+			cluster_size: sectors_per_cluster as usize * sector_size,
+			fat_start: reserved * sector_bytes,
+			fat_size: fat_sectors * sector_bytes,
+			fat_count,
+			root_start: (data_sector - root_sectors) * sector_bytes,
+			root_entries,
+			root_cluster: 0,
+			data_start: data_sector * sector_bytes,
+			cluster_count,
+			fsinfo: None,
+			allocator: Mutex::new(Allocator {
+				next: 2,
+				fsinfo_stale: true,
+			}),
+			inodes: Mutex::new(BTreeMap::new()),
+		};
+		if shared.fat_offset(cluster_count + 2) > shared.fat_size {
+			return Err(EINVAL);
+		}
+		if kind == FatKind::Fat32 {
+			shared.root_cluster = shared.check_cluster(get_u32(&boot, BS_ROOT_CLUSTER))?;
+			let fsinfo = get_u16(&boot, BS_FSINFO_SECTOR) as u64;
+			if fsinfo != 0 && fsinfo < reserved {
+				let mut sector = alloc::vec![0; sector_size];
+				shared.read_bytes(fsinfo * sector_bytes, &mut sector)?;
+				if get_u32(&sector, 0) == FSINFO_LEAD_SIGNATURE
+					&& get_u32(&sector, FSINFO_STRUCT) == FSINFO_STRUCT_SIGNATURE
+				{
+					shared.fsinfo = Some(fsinfo * sector_bytes);
+				}
+			}
+		}
+
+		let shared = Arc::new(shared);
+		let root = Arc::new(FatInode {
+			ino: ROOT_INO,
+			parent: ROOT_INO,
+			fs: shared.clone(),
+			data: Mutex::new(Node {
+				directory: true,
+				read_only: false,
+				cluster: shared.root_cluster,
+				size: 0,
+				entry: None,
+				removed: false,
+				date: 0,
+				time: 0,
+			}),
+		});
+		log!(
+			LogLevel::Info,
+			"fat: FAT{} with {} clusters of {} bytes",
+			match kind {
+				FatKind::Fat12 => 12,
+				FatKind::Fat16 => 16,
+				FatKind::Fat32 => 32,
+			},
+			cluster_count,
+			shared.cluster_size
+		);
+		Ok(Arc::new(FatFs { shared, root }))
+	}
+
+	// Clusters left, counted in the FAT
+	pub fn free_clusters(&self) -> Result<u32, Errno> {
+		let shared = &self.shared;
+		let mut free = 0;
+		for cluster in 2..shared.cluster_count + 2 {
+			if shared.fat_entry(cluster)? == 0 {
+				free += 1;
+			}
+		}
+		Ok(free)
+	}
+}
+
+impl SuperBlock for FatFs {
+	fn fs_type(&self) -> &'static str {
+		"vfat"
+	}
+
+	fn root(&self) -> InodeRef {
+		self.root.clone()
+	}
+
+	fn sync(&self) -> Result<(), Errno> {
+		self.shared.cache.sync()
+	}
+}
+
+pub fn fat_test() {
+	use super::dentry;
+	use super::file::{O_CREAT, O_RDONLY, O_RDWR, SEEK_SET};
+	use crate::drivers::block::RamDisk;
+
+	// Image like `mkfs.fat -s 1 -f 2 -r <root_entries>` on `sectors` sectors of 512 bytes
+	fn format(sectors: u32, fat_sectors: u16, root_entries: u16) -> Vec<u8> {
+		let mut image = alloc::vec![0; sectors as usize * 512];
+		image[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+		image[3..11].copy_from_slice(b"MSWIN4.1");
+		put_u16(&mut image, BS_BYTES_PER_SECTOR, 512);
+		image[BS_SECTORS_PER_CLUSTER] = 1;
+		put_u16(&mut image, BS_RESERVED_SECTORS, 1);
+		image[BS_FAT_COUNT] = 2;
+		put_u16(&mut image, BS_ROOT_ENTRIES, root_entries);
+		put_u16(&mut image, BS_TOTAL_SECTORS_16, sectors as u16);
+		// Media descriptor of fixed disks
+		image[21] = 0xf8;
+		put_u16(&mut image, BS_FAT_SIZE_16, fat_sectors);
+		image[510..512].copy_from_slice(&[0x55, 0xaa]);
+		// The first two entries hold the media descriptor and the end of chain mark
+		let fat12 = (sectors - 1 - 2 * fat_sectors as u32) < 4085;
+		let reserved: &[u8] = match fat12 {
+			true => &[0xf8, 0xff, 0xff],
+			false => &[0xf8, 0xff, 0xff, 0xff],
+		};
+		for copy in 0..2 {
+			let start = 512 + copy * fat_sectors as usize * 512;
+			image[start..start + reserved.len()].copy_from_slice(reserved);
+		}
+		image
+	}
+
+	fn names(path: &str) -> Vec<String> {
+		let directory = super::open(path, O_RDONLY, 0).unwrap();
+		let mut names = Vec::new();
+		directory
+			.read_dir(|entry, _| {
+				names.push(entry.name.clone());
+				Ok(true)
+			})
+			.unwrap();
+		names
+	}
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting FAT\n");
+
+	// 1.44MB floppy: 2847 clusters, root directory of 224 entries in sectors 19 to 32
+	let disk = RamDisk::new("fat-test", 2880);
+	disk.write_sectors(0, &format(2880, 9, 224)).unwrap();
+	assert!(FatFs::new(RamDisk::new("blank", 64)).err() == Some(EINVAL));
+
+	super::mkdir("/tmp/fat-test", 0o755).expect("mkdir failed");
+	let target = super::lookup("/tmp/fat-test").unwrap();
+	let fs = FatFs::new(disk.clone()).expect("Failed to mount FAT12");
+	assert!(fs.shared.kind == FatKind::Fat12);
+	let initial = fs.free_clusters().unwrap();
+	assert!(initial == 2847);
+	dentry::mount(&target, fs.clone()).expect("mount failed");
+	assert!(names("/tmp/fat-test") == [".", ".."]);
+
+	log!(
+		LogLevel::Info,
+		"Names should get long name entries only when they need some\n"
+	);
+	super::write_file("/tmp/fat-test/readme.txt", b"short", 0o644).unwrap();
+	super::write_file("/tmp/fat-test/A long file name.text", b"long", 0o644).unwrap();
+	super::write_file("/tmp/fat-test/a long file name.html", b"alias", 0o644).unwrap();
+	fs.sync().unwrap();
+	let mut root = [0; 512];
+	disk.read_sectors(19, &mut root).unwrap();
+	assert!(&root[..11] == b"README  TXT");
+	assert!(root[DE_NTRES] == NTRES_LOWER_BASE | NTRES_LOWER_EXT);
+	// 21 characters take two long name entries, the last part first
+	assert!(root[ENTRY_SIZE] == 2 | LAST_LONG_ENTRY && root[2 * ENTRY_SIZE] == 1);
+	assert!(&root[3 * ENTRY_SIZE..3 * ENTRY_SIZE + 11] == b"ALONGF~1TEX");
+	assert!(root[ENTRY_SIZE + 13] == checksum(b"ALONGF~1TEX"));
+	assert!(&root[6 * ENTRY_SIZE..6 * ENTRY_SIZE + 11] == b"ALONGF~1HTM");
+	assert!(
+		names("/tmp/fat-test")
+			== [
+				".",
+				"..",
+				"readme.txt",
+				"A long file name.text",
+				"a long file name.html"
+			]
+	);
+	assert!(super::read_file("/tmp/fat-test/README.TXT").unwrap() == b"short");
+	assert!(super::read_file("/tmp/fat-test/A LONG FILE NAME.TEXT").unwrap() == b"long");
+	assert!(super::mkdir("/tmp/fat-test/ReadMe.txt", 0o755) == Err(EEXIST));
+	assert!(super::write_file("/tmp/fat-test/a:b", b"", 0o644) == Err(EINVAL));
+
+	log!(
+		LogLevel::Info,
+		"Files should read back what was written, across clusters\n"
+	);
+	let file = super::open("/tmp/fat-test/big", O_CREAT | O_RDWR, 0o644).unwrap();
+	let mut pattern = alloc::vec![0; 100 * 1024];
+	for (index, byte) in pattern.iter_mut().enumerate() {
+		*byte = (index % 251) as u8;
+	}
+	assert!(file.write(&pattern) == Ok(pattern.len()));
+	assert!(fs.free_clusters().unwrap() == initial - 3 - 200);
+	let mut read = alloc::vec![0; pattern.len()];
+	file.seek(0, SEEK_SET).unwrap();
+	assert!(file.read(&mut read) == Ok(pattern.len()));
+	assert!(read == pattern);
+
+	log!(
+		LogLevel::Info,
+		"Truncating should free clusters and zero what comes back\n"
+	);
+	assert!(super::truncate("/tmp/fat-test/big", 1000) == Ok(()));
+	assert!(fs.free_clusters().unwrap() == initial - 3 - 2);
+	assert!(super::truncate("/tmp/fat-test/big", 3000) == Ok(()));
+	let mut buffer = [0; 1000];
+	file.seek(500, SEEK_SET).unwrap();
+	assert!(file.read(&mut buffer) == Ok(1000));
+	assert!(buffer[..500] == pattern[500..1000] && buffer[500..] == [0; 500]);
+	file.seek(5000, SEEK_SET).unwrap();
+	assert!(file.write(b"end") == Ok(3));
+	assert!(super::stat("/tmp/fat-test/big").unwrap().size == 5003);
+	file.seek(4000, SEEK_SET).unwrap();
+	assert!(file.read(&mut buffer) == Ok(1000) && buffer == [0; 1000]);
+	drop(file);
+
+	log!(
+		LogLevel::Info,
+		"Directories should grow and be removed only when empty\n"
+	);
+	assert!(super::mkdir("/tmp/fat-test/Sub Directory", 0o755) == Ok(()));
+	let mut expected = alloc::vec![String::from("."), String::from("..")];
+	for index in 0..10 {
+		// Three entries each, a cluster holds 16
+		let name = alloc::format!("File number {:02}.data", index);
+		super::write_file(
+			&alloc::format!("/tmp/fat-test/Sub Directory/{}", name),
+			name.as_bytes(),
+			0o644,
+		)
+		.unwrap();
+		expected.push(name);
+	}
+	assert!(super::stat("/tmp/fat-test/Sub Directory").unwrap().size == 1024);
+	assert!(names("/tmp/fat-test/sub directory") == expected);
+	assert!(
+		super::lookup("/tmp/fat-test/Sub Directory/..")
+			.unwrap()
+			.metadata()
+			.ino == ROOT_INO
+	);
+	assert!(super::rmdir("/tmp/fat-test/Sub Directory") == Err(ENOTEMPTY));
+	assert!(super::unlink("/tmp/fat-test/Sub Directory") == Err(EISDIR));
+
+	log!(
+		LogLevel::Info,
+		"Files should still be there after a remount\n"
+	);
+	dentry::umount(&super::lookup("/tmp/fat-test").unwrap()).expect("umount failed");
+	drop(fs);
+	let fs = FatFs::new(disk.clone()).expect("Failed to remount FAT12");
+	dentry::mount(&target, fs.clone()).expect("mount failed");
+	let path = alloc::format!("/tmp/fat-test/Sub Directory/{}", expected[7]);
+	assert!(super::read_file(&path).unwrap() == expected[7].as_bytes());
+	assert!(super::read_file("/tmp/fat-test/big").unwrap()[..1000] == pattern[..1000]);
+
+	log!(
+		LogLevel::Info,
+		"Removing everything should give every cluster back\n"
+	);
+	for name in &expected[2..] {
+		super::unlink(&alloc::format!("/tmp/fat-test/Sub Directory/{}", name)).unwrap();
+	}
+	assert!(names("/tmp/fat-test/Sub Directory") == [".", ".."]);
+	super::rmdir("/tmp/fat-test/Sub Directory").unwrap();
+	for name in [
+		"readme.txt",
+		"A long file name.text",
+		"a long file name.html",
+		"big",
+	] {
+		super::unlink(&alloc::format!("/tmp/fat-test/{}", name)).unwrap();
+	}
+	assert!(names("/tmp/fat-test") == [".", ".."]);
+	assert!(fs.free_clusters().unwrap() == initial);
+	dentry::umount(&super::lookup("/tmp/fat-test").unwrap()).expect("umount failed");
+	drop(fs);
+
+	log!(
+		LogLevel::Info,
+		"FAT16 should be told from the number of clusters\n"
+	);
+	let disk = RamDisk::new("fat16-test", 4267);
+	disk.write_sectors(0, &format(4267, 17, 512)).unwrap();
+	let fs = FatFs::new(disk.clone()).expect("Failed to mount FAT16");
+	assert!(fs.shared.kind == FatKind::Fat16 && fs.free_clusters().unwrap() == 4200);
+	dentry::mount(&target, fs.clone()).expect("mount failed");
+	super::write_file("/tmp/fat-test/data.bin", &pattern[..2000], 0o644).unwrap();
+	assert!(super::read_file("/tmp/fat-test/DATA.BIN").unwrap() == pattern[..2000]);
+	assert!(fs.free_clusters().unwrap() == 4196);
+	dentry::umount(&super::lookup("/tmp/fat-test").unwrap()).expect("umount failed");
+	drop(fs);
+	assert!(super::rmdir("/tmp/fat-test") == Ok(()));
+
+	log!(LogLevel::Info, "\t\tEnd of FAT test\n");
+}
@@ -0,0 +1,103 @@
+//! # File Descriptor Tables
+//!
+//! Every task maps descriptor numbers to open `File`s. fork copies the table, so parent and
+//! child share the files and their offsets, and exec keeps it but for the descriptors marked
+//! close-on-exec. New descriptors always take the lowest free number.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::console;
+use super::file::{File, O_RDWR};
+use crate::exceptions::errno::{Errno, EBADF, EMFILE};
+
+// Highest number of descriptors a task can hold, like RLIMIT_NOFILE
+pub const MAX_FDS: usize = 256;
+
+#[derive(Clone)]
+struct Descriptor {
+	file: Arc<File>,
+	close_on_exec: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct FdTable {
+	descriptors: Vec<Option<Descriptor>>,
+}
+
+impl FdTable {
+	pub const fn new() -> FdTable {
+		FdTable {
+			descriptors: Vec::new(),
+		}
+	}
+
+	// Table with stdin, stdout and stderr open on the console
+	pub fn console() -> FdTable {
+		let file = console::open(O_RDWR);
+		let descriptor = Descriptor {
+			file,
+			close_on_exec: false,
+		};
+		FdTable {
+			descriptors: alloc::vec![Some(descriptor); 3],
+		}
+	}
+
+	pub fn get(&self, fd: u32) -> Result<Arc<File>, Errno> {
+		match self.descriptors.get(fd as usize) {
+			Some(Some(descriptor)) => Ok(descriptor.file.clone()),
+			_ => Err(EBADF),
+		}
+	}
+
+	pub fn insert(&mut self, file: Arc<File>, close_on_exec: bool) -> Result<u32, Errno> {
+		let descriptor = Some(Descriptor {
+			file,
+			close_on_exec,
+		});
+		match self.descriptors.iter().position(Option::is_none) {
+			Some(fd) => {
+				self.descriptors[fd] = descriptor;
+				Ok(fd as u32)
+			}
+			None if self.descriptors.len() < MAX_FDS => {
+				self.descriptors.push(descriptor);
+				Ok(self.descriptors.len() as u32 - 1)
+			}
+			None => Err(EMFILE),
+		}
+	}
+
+	// Frees `fd` and returns its file, for the caller to drop outside of any lock
+	pub fn close(&mut self, fd: u32) -> Result<Arc<File>, Errno> {
+		let descriptor = self
+			.descriptors
+			.get_mut(fd as usize)
+			.and_then(Option::take)
+			.ok_or(EBADF)?;
+		while let Some(None) = self.descriptors.last() {
+			self.descriptors.pop();
+		}
+		Ok(descriptor.file)
+	}
+
+	// Closes the close-on-exec descriptors, returns their files like `close`
+	pub fn exec(&mut self) -> Vec<Arc<File>> {
+		let mut closed = Vec::new();
+		for slot in self.descriptors.iter_mut() {
+			if slot.as_ref().is_some_and(|descriptor| descriptor.close_on_exec) {
+				closed.extend(slot.take().map(|descriptor| descriptor.file));
+			}
+		}
+		while let Some(None) = self.descriptors.last() {
+			self.descriptors.pop();
+		}
+		closed
+	}
+
+	// Number of open descriptors
+	pub fn count(&self) -> usize {
+		self.descriptors.iter().flatten().count()
+	}
+}
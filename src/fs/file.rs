@@ -0,0 +1,167 @@
+//! # Open Files
+//!
+//! A `File` is an inode opened with a set of flags and an offset. Descriptors copied by fork
+//! share the same `File`, and with it the offset. Reads and writes go through the `FileOps`
+//! of the inode: devices bring their own, other inodes get `InodeOps`, which reads and writes
+//! their data at the offset of the file.
+
+use alloc::sync::Arc;
+use spin::{Mutex, MutexGuard};
+
+use super::inode::{DirEntry, InodeRef, Metadata};
+use crate::exceptions::errno::{Errno, EBADF, EINVAL, EISDIR, ENOTDIR, ENOTTY, ESPIPE};
+
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_RDONLY: u32 = 0o0;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_NOCTTY: u32 = 0o400;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+pub const O_DIRECTORY: u32 = 0o200000;
+pub const O_CLOEXEC: u32 = 0o2000000;
+
+// Flags that only matter while opening, a `File` does not keep them
+const OPEN_ONLY_FLAGS: u32 = O_CREAT | O_EXCL | O_NOCTTY | O_TRUNC | O_CLOEXEC;
+
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+pub trait FileOps: Send + Sync {
+	fn read(&self, file: &File, buffer: &mut [u8]) -> Result<usize, Errno>;
+
+	fn write(&self, file: &File, buffer: &[u8]) -> Result<usize, Errno>;
+
+	fn ioctl(&self, _file: &File, _request: u32, _argument: u32) -> Result<u32, Errno> {
+		Err(ENOTTY)
+	}
+
+	// Streams have no offset, lseek on them fails with ESPIPE
+	fn seekable(&self) -> bool {
+		false
+	}
+}
+
+struct InodeOps;
+
+impl FileOps for InodeOps {
+	fn read(&self, file: &File, buffer: &mut [u8]) -> Result<usize, Errno> {
+		let mut offset = file.offset.lock();
+		let read = file.inode.read_at(*offset, buffer)?;
+		*offset += read as u64;
+		Ok(read)
+	}
+
+	fn write(&self, file: &File, buffer: &[u8]) -> Result<usize, Errno> {
+		let mut offset = file.offset.lock();
+		if file.flags & O_APPEND != 0 {
+			*offset = file.inode.metadata().size;
+		}
+		let written = file.inode.write_at(*offset, buffer)?;
+		*offset += written as u64;
+		Ok(written)
+	}
+
+	fn seekable(&self) -> bool {
+		true
+	}
+}
+
+pub struct File {
+	pub inode: InodeRef,
+	pub flags: u32,
+	offset: Mutex<u64>,
+	ops: Arc<dyn FileOps>,
+}
+
+impl File {
+	pub fn new(inode: InodeRef, flags: u32) -> Arc<File> {
+		let ops = inode.file_ops().unwrap_or_else(|| Arc::new(InodeOps));
+		Arc::new(File {
+			inode,
+			flags: flags & !OPEN_ONLY_FLAGS,
+			offset: Mutex::new(0),
+			ops,
+		})
+	}
+
+	pub fn readable(&self) -> bool {
+		self.flags & O_ACCMODE != O_WRONLY
+	}
+
+	pub fn writable(&self) -> bool {
+		matches!(self.flags & O_ACCMODE, O_WRONLY | O_RDWR)
+	}
+
+	pub fn metadata(&self) -> Metadata {
+		self.inode.metadata()
+	}
+
+	pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno> {
+		if !self.readable() {
+			return Err(EBADF);
+		}
+		if self.metadata().is_dir() {
+			return Err(EISDIR);
+		}
+		self.ops.read(self, buffer)
+	}
+
+	pub fn write(&self, buffer: &[u8]) -> Result<usize, Errno> {
+		if !self.writable() {
+			return Err(EBADF);
+		}
+		self.ops.write(self, buffer)
+	}
+
+	// Offset of the file, for seekable devices whose `FileOps` move it themselves
+	pub fn offset(&self) -> MutexGuard<'_, u64> {
+		self.offset.lock()
+	}
+
+	pub fn ioctl(&self, request: u32, argument: u32) -> Result<u32, Errno> {
+		self.ops.ioctl(self, request, argument)
+	}
+
+	// Moves the offset as lseek does, returns the new one
+	pub fn seek(&self, offset: i64, whence: u32) -> Result<u64, Errno> {
+		if !self.ops.seekable() {
+			return Err(ESPIPE);
+		}
+		let mut position = self.offset.lock();
+		let base = match whence {
+			SEEK_SET => 0,
+			SEEK_CUR => *position as i64,
+			SEEK_END => self.metadata().size as i64,
+			_ => return Err(EINVAL),
+		};
+		let new = base
+			.checked_add(offset)
+			.filter(|&new| new >= 0)
+			.ok_or(EINVAL)?;
+		*position = new as u64;
+		Ok(new as u64)
+	}
+
+	// Hands the next entries of a directory to `emit` until it returns false, the entry it
+	// refused is returned again by the next call. The offset counts entries.
+	pub fn read_dir<F>(&self, mut emit: F) -> Result<(), Errno>
+	where
+		F: FnMut(&DirEntry, u64) -> Result<bool, Errno>,
+	{
+		if !self.metadata().is_dir() {
+			return Err(ENOTDIR);
+		}
+		let mut position = self.offset.lock();
+		while let Some(entry) = self.inode.read_dir(*position as usize)? {
+			if !emit(&entry, *position + 1)? {
+				break;
+			}
+			*position += 1;
+		}
+		Ok(())
+	}
+}
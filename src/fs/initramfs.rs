@@ -0,0 +1,363 @@
+//! # Initial Ramdisk
+//!
+//! Archives loaded as multiboot modules, from `module2` lines of grub.cfg, are unpacked into
+//! the root filesystem at boot. Both cpio archives in the newc format (`cpio -H newc`) and
+//! ustar archives (`tar --format=ustar`) are understood. Directories, regular files and hard
+//! links are created, other entries are skipped. The module memory goes back to the PMM once
+//! it is unpacked.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+
+use crate::exceptions::errno::{Errno, EEXIST, EINVAL, ENOENT};
+use crate::memory::kmem_managment::{HK_OFST, PMM};
+use crate::multiboot;
+use crate::tools::debug::LogLevel;
+
+const CPIO_MAGIC: &[u8] = b"070701";
+const CPIO_CRC_MAGIC: &[u8] = b"070702";
+const CPIO_HEADER: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+const TAR_BLOCK: usize = 512;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+// File type bits of the cpio mode
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+enum Kind<'a> {
+	Directory,
+	File(&'a [u8]),
+	// Hard link to an earlier entry of the archive
+	Link(&'a str),
+}
+
+// `name` of an archive, relative to `destination`
+fn join(destination: &str, name: &str) -> String {
+	format!("{}/{}", destination.trim_end_matches('/'), name)
+}
+
+// Archives do not always list the directories before what they hold
+fn make_parents(path: &str) -> Result<(), Errno> {
+	for (index, _) in path.match_indices('/').filter(|&(index, _)| index > 0) {
+		match super::mkdir(&path[..index], 0o755) {
+			Ok(()) | Err(EEXIST) => {}
+			Err(errno) => return Err(errno),
+		}
+	}
+	Ok(())
+}
+
+// Name of an archive entry without its leading `./` and slashes
+fn clean(name: &str) -> &str {
+	name.trim_start_matches("./").trim_matches('/')
+}
+
+// Creates the entry `name` of an archive under `destination`, false if it is skipped. Kinds
+// of files that cannot be created come as None.
+fn add_entry(destination: &str, name: &str, mode: u32, kind: Option<Kind>) -> Result<bool, Errno> {
+	let name = clean(name);
+	if name.is_empty() || name == "." {
+		return Ok(false);
+	}
+	if name.split('/').any(|component| component == "..") {
+		log!(LogLevel::Warning, "Skipping {}, it is outside of the archive", name);
+		return Ok(false);
+	}
+	let Some(kind) = kind else {
+		log!(LogLevel::Warning, "Skipping {}, its file type is not supported", name);
+		return Ok(false);
+	};
+
+	let path = join(destination, name);
+	make_parents(&path)?;
+	match kind {
+		Kind::Directory => match super::mkdir(&path, mode & 0o7777) {
+			Ok(()) | Err(EEXIST) => {}
+			Err(errno) => return Err(errno),
+		},
+		Kind::File(data) => super::write_file(&path, data, mode & 0o7777)?,
+		Kind::Link(target) => {
+			// Like files, links replace what an earlier unpack left
+			match super::unlink(&path) {
+				Ok(()) | Err(ENOENT) => {}
+				Err(errno) => return Err(errno),
+			}
+			super::link(&join(destination, clean(target)), &path)?;
+		}
+	}
+	Ok(true)
+}
+
+// Field `index` of a cpio header, 8 hexadecimal digits after the magic
+fn cpio_field(header: &[u8], index: usize) -> Result<u32, Errno> {
+	let start = CPIO_MAGIC.len() + index * 8;
+	let digits = core::str::from_utf8(&header[start..start + 8]).map_err(|_| EINVAL)?;
+	u32::from_str_radix(digits, 16).map_err(|_| EINVAL)
+}
+
+// Entries of a cpio newc archive up to its trailer, headers and data are 4 byte aligned
+fn unpack_cpio(archive: &[u8], destination: &str) -> Result<usize, Errno> {
+	let mut offset: usize = 0;
+	let mut count = 0;
+	// First name unpacked for each device and inode number with more than one link
+	let mut links: BTreeMap<(u32, u32, u32), &str> = BTreeMap::new();
+	loop {
+		let header_end = offset.checked_add(CPIO_HEADER).ok_or(EINVAL)?;
+		let header = archive.get(offset..header_end).ok_or(EINVAL)?;
+		if &header[..6] != CPIO_MAGIC && &header[..6] != CPIO_CRC_MAGIC {
+			return Err(EINVAL);
+		}
+		let inode = (cpio_field(header, 7)?, cpio_field(header, 8)?, cpio_field(header, 0)?);
+		let mode = cpio_field(header, 1)?;
+		let nlink = cpio_field(header, 4)?;
+		let size = cpio_field(header, 6)? as usize;
+		let name_size = cpio_field(header, 11)? as usize;
+
+		// The sizes come from the archive, they must not wrap the offsets
+		let name_start = header_end;
+		let name_end = name_start.checked_add(name_size).ok_or(EINVAL)?;
+		let name = archive.get(name_start..name_end).ok_or(EINVAL)?;
+		let name = core::str::from_utf8(name).map_err(|_| EINVAL)?.trim_end_matches('\0');
+		let data_start = name_end.checked_next_multiple_of(4).ok_or(EINVAL)?;
+		let data_end = data_start.checked_add(size).ok_or(EINVAL)?;
+		let data = archive.get(data_start..data_end).ok_or(EINVAL)?;
+		if name == CPIO_TRAILER {
+			return Ok(count);
+		}
+
+		// Hard links share an inode number and only the last of them carries the data, the
+		// later names are linked to the first one
+		let first = match mode & S_IFMT == S_IFREG && nlink > 1 {
+			true => links.get(&inode).copied(),
+			false => None,
+		};
+		let kind = match (mode & S_IFMT, first) {
+			(S_IFREG, Some(first)) => Some(Kind::Link(first)),
+			(S_IFDIR, _) => Some(Kind::Directory),
+			(S_IFREG, None) => Some(Kind::File(data)),
+			_ => None,
+		};
+		if add_entry(destination, name, mode, kind)? {
+			count += 1;
+			match first {
+				Some(first) if !data.is_empty() => {
+					super::write_file(&join(destination, clean(first)), data, mode & 0o7777)?
+				}
+				Some(_) => {}
+				None if mode & S_IFMT == S_IFREG && nlink > 1 => {
+					links.insert(inode, name);
+				}
+				None => {}
+			}
+		}
+		offset = data_end.checked_next_multiple_of(4).ok_or(EINVAL)?;
+	}
+}
+
+// NUL terminated string field of a tar header
+fn tar_string(field: &[u8]) -> Result<&str, Errno> {
+	let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+	core::str::from_utf8(&field[..end]).map_err(|_| EINVAL)
+}
+
+// Octal number field of a tar header, padded with spaces or NULs
+fn tar_field(field: &[u8]) -> Result<u64, Errno> {
+	let digits = tar_string(field)?.trim();
+	if digits.is_empty() {
+		return Ok(0);
+	}
+	u64::from_str_radix(digits, 8).map_err(|_| EINVAL)
+}
+
+// Entries of a ustar archive, 512 byte headers each followed by the data padded to a block.
+// The archive ends with a zero block.
+fn unpack_tar(archive: &[u8], destination: &str) -> Result<usize, Errno> {
+	let mut offset: usize = 0;
+	let mut count = 0;
+	while let Some(header) = archive.get(offset..offset.saturating_add(TAR_BLOCK)) {
+		if header.iter().all(|&byte| byte == 0) {
+			break;
+		}
+		if &header[257..262] != TAR_MAGIC {
+			return Err(EINVAL);
+		}
+		// The checksum is the sum of the header bytes, with its own field as spaces
+		let checksum: u64 = header
+			.iter()
+			.enumerate()
+			.map(|(index, &byte)| match index {
+				148..=155 => b' ' as u64,
+				_ => byte as u64,
+			})
+			.sum();
+		if checksum != tar_field(&header[148..156])? {
+			return Err(EINVAL);
+		}
+
+		let mode = tar_field(&header[100..108])? as u32;
+		let size = tar_field(&header[124..136])? as usize;
+		let data_start = offset + TAR_BLOCK;
+		let data_end = data_start.checked_add(size).ok_or(EINVAL)?;
+		let data = archive.get(data_start..data_end).ok_or(EINVAL)?;
+		let name = tar_string(&header[..100])?;
+		let prefix = tar_string(&header[345..500])?;
+		let name = match prefix.is_empty() {
+			true => String::from(name),
+			false => format!("{}/{}", prefix, name),
+		};
+
+		let kind = match header[156] {
+			b'0' | 0 => Some(Kind::File(data)),
+			b'1' => Some(Kind::Link(tar_string(&header[157..257])?)),
+			b'5' => Some(Kind::Directory),
+			_ => None,
+		};
+		if add_entry(destination, &name, mode, kind)? {
+			count += 1;
+		}
+		offset = data_end.checked_next_multiple_of(TAR_BLOCK).ok_or(EINVAL)?;
+	}
+	Ok(count)
+}
+
+// Unpacks a cpio or ustar `archive` under `destination`, returns the number of entries
+// created
+pub fn unpack(archive: &[u8], destination: &str) -> Result<usize, Errno> {
+	if archive.starts_with(CPIO_MAGIC) || archive.starts_with(CPIO_CRC_MAGIC) {
+		unpack_cpio(archive, destination)
+	} else if archive.get(257..262) == Some(TAR_MAGIC) {
+		unpack_tar(archive, destination)
+	} else {
+		Err(EINVAL)
+	}
+}
+
+// Unpacks the multiboot modules into the root filesystem and frees their memory
+pub fn load_modules() {
+	let memory_size = PMM.lock().memory_size;
+	for module in multiboot::modules() {
+		let name = module.cmdline();
+		if module.end < module.start || module.end > memory_size {
+			log!(LogLevel::Warning, "Module {} is out of the low memory, skipped", name);
+			continue;
+		}
+		let archive = unsafe {
+			core::slice::from_raw_parts(
+				(module.start + HK_OFST) as *const u8,
+				(module.end - module.start) as usize,
+			)
+		};
+		match unpack(archive, "/") {
+			Ok(count) => log!(LogLevel::Info, "Unpacked {} entries from module {}", count, name),
+			Err(errno) => log!(LogLevel::Error, "Failed to unpack module {}: error {}", name, errno),
+		}
+		PMM.lock().release_region(module.start, module.end - module.start);
+	}
+}
+
+pub fn initramfs_test() {
+	use alloc::vec::Vec;
+
+	fn cpio_entry(archive: &mut Vec<u8>, name: &str, mode: u32, links: (u32, u32), data: &[u8]) {
+		let (inode, nlink) = links;
+		let fields = [
+			inode, mode, 0, 0, nlink, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0,
+		];
+		archive.extend_from_slice(CPIO_MAGIC);
+		for field in fields {
+			archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+		}
+		archive.extend_from_slice(name.as_bytes());
+		archive.push(0);
+		archive.resize(archive.len().next_multiple_of(4), 0);
+		archive.extend_from_slice(data);
+		archive.resize(archive.len().next_multiple_of(4), 0);
+	}
+
+	fn tar_entry(archive: &mut Vec<u8>, name: &str, type_flag: u8, data: &[u8], link: &str) {
+		let mut header = [0; TAR_BLOCK];
+		header[..name.len()].copy_from_slice(name.as_bytes());
+		header[100..107].copy_from_slice(b"0000644");
+		header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+		header[156] = type_flag;
+		header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+		header[257..263].copy_from_slice(b"ustar\0");
+		header[263..265].copy_from_slice(b"00");
+		header[148..156].fill(b' ');
+		let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+		header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+		archive.extend_from_slice(&header);
+		archive.extend_from_slice(data);
+		archive.resize(archive.len().next_multiple_of(TAR_BLOCK), 0);
+	}
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the initial ramdisk\n");
+
+	log!(LogLevel::Info, "A cpio archive should be unpacked, odd entries skipped\n");
+	let mut cpio = Vec::new();
+	cpio_entry(&mut cpio, ".", S_IFDIR | 0o755, (0, 1), b"");
+	cpio_entry(&mut cpio, "etc", S_IFDIR | 0o700, (0, 1), b"");
+	cpio_entry(&mut cpio, "etc/motd", S_IFREG | 0o644, (0, 1), b"hello\n");
+	cpio_entry(&mut cpio, "bin/deep/tool", S_IFREG | 0o755, (0, 1), b"tool");
+	cpio_entry(&mut cpio, "dev/null", 0o020666, (0, 1), b"");
+	cpio_entry(&mut cpio, "../escape", S_IFREG | 0o644, (0, 1), b"no");
+	cpio_entry(&mut cpio, CPIO_TRAILER, 0, (0, 1), b"");
+	assert!(unpack(&cpio, "/tmp/initramfs-test") == Ok(3));
+	assert!(super::read_file("/tmp/initramfs-test/etc/motd").unwrap() == b"hello\n");
+	assert!(super::read_file("/tmp/initramfs-test/bin/deep/tool").unwrap() == b"tool");
+	assert!(super::stat("/tmp/initramfs-test/etc").unwrap().mode == 0o700);
+	assert!(super::stat("/tmp/initramfs-test/dev").err() == Some(ENOENT));
+	assert!(super::stat("/tmp/escape").err() == Some(ENOENT));
+
+	log!(LogLevel::Info, "Unpacking again should replace the files\n");
+	assert!(unpack(&cpio, "/tmp/initramfs-test") == Ok(3));
+	assert!(unpack(&cpio[..cpio.len() - 8], "/tmp/initramfs-test") == Err(EINVAL));
+
+	log!(LogLevel::Info, "cpio hard links should share the data of the last of them\n");
+	let mut links = Vec::new();
+	cpio_entry(&mut links, "links/first", S_IFREG | 0o644, (7, 2), b"");
+	cpio_entry(&mut links, "links/second", S_IFREG | 0o644, (7, 2), b"shared");
+	cpio_entry(&mut links, CPIO_TRAILER, 0, (0, 1), b"");
+	for _ in 0..2 {
+		assert!(unpack(&links, "/tmp/initramfs-test") == Ok(2));
+		assert!(super::read_file("/tmp/initramfs-test/links/first").unwrap() == b"shared");
+		assert!(super::stat("/tmp/initramfs-test/links/second").unwrap().nlink == 2);
+	}
+
+	log!(LogLevel::Info, "A ustar archive should be unpacked with its hard links\n");
+	let mut tar = Vec::new();
+	let config = [b'x'; 600];
+	tar_entry(&mut tar, "conf/", b'5', b"", "");
+	tar_entry(&mut tar, "conf/a.cfg", b'0', &config, "");
+	tar_entry(&mut tar, "conf/b.cfg", b'1', b"", "conf/a.cfg");
+	tar.resize(tar.len() + 2 * TAR_BLOCK, 0);
+	assert!(unpack(&tar, "/tmp/initramfs-test/tar") == Ok(3));
+	assert!(super::read_file("/tmp/initramfs-test/tar/conf/b.cfg").unwrap() == config);
+	assert!(super::stat("/tmp/initramfs-test/tar/conf/a.cfg").unwrap().nlink == 2);
+
+	log!(LogLevel::Info, "Corrupted archives should be refused\n");
+	tar[0] = b'k';
+	assert!(unpack(&tar, "/tmp/initramfs-test/tar") == Err(EINVAL));
+	assert!(unpack(b"not an archive", "/tmp/initramfs-test") == Err(EINVAL));
+
+	let files = [
+		"etc/motd",
+		"bin/deep/tool",
+		"links/first",
+		"links/second",
+		"tar/conf/a.cfg",
+		"tar/conf/b.cfg",
+	];
+	for file in files {
+		super::unlink(&join("/tmp/initramfs-test", file)).unwrap();
+	}
+	for directory in ["etc", "bin/deep", "bin", "links", "tar/conf", "tar", ""] {
+		super::rmdir(&join("/tmp/initramfs-test", directory)).unwrap();
+	}
+	log!(LogLevel::Info, "\t\tEnd of initial ramdisk test\n");
+}
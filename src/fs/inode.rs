@@ -0,0 +1,195 @@
+//! # Inodes and Superblocks
+//!
+//! A `SuperBlock` is a mounted instance of a filesystem and hands out the `Inode` of its
+//! root directory. Inodes are shared through `InodeRef`s and every operation has a default
+//! failing with the errno a file of the wrong type gets, so a filesystem only implements what
+//! its inodes support.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::file::FileOps;
+use crate::exceptions::errno::{Errno, EINVAL, EISDIR, ENOTDIR, EPERM};
+use crate::memory::page_directory::PAGE_SIZE;
+
+pub type InodeRef = Arc<dyn Inode>;
+
+// Anonymous filesystems get device numbers with major 0
+static NEXT_ANONYMOUS_MINOR: AtomicU32 = AtomicU32::new(1);
+
+// Device number as reported by stat
+pub const fn make_dev(major: u32, minor: u32) -> u32 {
+	(minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12)
+}
+
+// Device number for a filesystem that has no backing device
+pub fn anonymous_dev() -> u32 {
+	make_dev(0, NEXT_ANONYMOUS_MINOR.fetch_add(1, Ordering::Relaxed))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+	Regular,
+	Directory,
+	Symlink,
+	CharDevice,
+	BlockDevice,
+	Fifo,
+	Socket,
+}
+
+impl FileType {
+	// File type bits of st_mode
+	pub fn mode_bits(&self) -> u32 {
+		match self {
+			FileType::Fifo => 0o010000,
+			FileType::CharDevice => 0o020000,
+			FileType::Directory => 0o040000,
+			FileType::BlockDevice => 0o060000,
+			FileType::Regular => 0o100000,
+			FileType::Symlink => 0o120000,
+			FileType::Socket => 0o140000,
+		}
+	}
+
+	// d_type of the entries returned by getdents
+	pub fn dirent_type(&self) -> u8 {
+		match self {
+			FileType::Fifo => 1,
+			FileType::CharDevice => 2,
+			FileType::Directory => 4,
+			FileType::BlockDevice => 6,
+			FileType::Regular => 8,
+			FileType::Symlink => 10,
+			FileType::Socket => 12,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+	pub dev: u32,
+	pub ino: u64,
+	pub file_type: FileType,
+	// Permission bits, the file type is in `file_type`
+	pub mode: u32,
+	pub nlink: u32,
+	pub uid: u32,
+	pub gid: u32,
+	// Device number of character and block devices
+	pub rdev: u32,
+	pub size: u64,
+	pub block_size: u32,
+	// Allocated size in 512 byte units
+	pub blocks: u64,
+	pub atime: u32,
+	pub mtime: u32,
+	pub ctime: u32,
+}
+
+impl Metadata {
+	pub fn new(dev: u32, ino: u64, file_type: FileType, mode: u32) -> Metadata {
+		Metadata {
+			dev,
+			ino,
+			file_type,
+			mode: mode & 0o7777,
+			nlink: 1,
+			uid: 0,
+			gid: 0,
+			rdev: 0,
+			size: 0,
+			block_size: PAGE_SIZE as u32,
+			blocks: 0,
+			atime: 0,
+			mtime: 0,
+			ctime: 0,
+		}
+	}
+
+	pub fn is_dir(&self) -> bool {
+		self.file_type == FileType::Directory
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+	pub name: String,
+	pub ino: u64,
+	pub file_type: FileType,
+}
+
+pub trait Inode: Send + Sync {
+	fn metadata(&self) -> Metadata;
+
+	// Finds `name` in this directory. `.` and `..` never reach the filesystem, the VFS
+	// resolves them with the dentries.
+	fn lookup(&self, _name: &str) -> Result<InodeRef, Errno> {
+		Err(ENOTDIR)
+	}
+
+	// Creates a new entry `name` of `file_type` in this directory, EEXIST if it is taken
+	fn create(&self, _name: &str, _file_type: FileType, _mode: u32) -> Result<InodeRef, Errno> {
+		Err(ENOTDIR)
+	}
+
+	// Adds the entry `name` for `target`, an inode of the same filesystem that is not a
+	// directory
+	fn link(&self, _name: &str, _target: &InodeRef) -> Result<(), Errno> {
+		Err(EPERM)
+	}
+
+	// Removes the entry `name` of this directory, which is not a directory itself
+	fn unlink(&self, _name: &str) -> Result<(), Errno> {
+		Err(ENOTDIR)
+	}
+
+	// Removes the entry `name` of this directory, an empty directory
+	fn rmdir(&self, _name: &str) -> Result<(), Errno> {
+		Err(ENOTDIR)
+	}
+
+	// Entry number `index` of this directory, None past the last one. The listing starts
+	// with `.` and `..`.
+	fn read_dir(&self, _index: usize) -> Result<Option<DirEntry>, Errno> {
+		Err(ENOTDIR)
+	}
+
+	fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, Errno> {
+		Err(data_error(self.metadata().file_type))
+	}
+
+	fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, Errno> {
+		Err(data_error(self.metadata().file_type))
+	}
+
+	fn truncate(&self, _size: u64) -> Result<(), Errno> {
+		Err(data_error(self.metadata().file_type))
+	}
+
+	// Operations of the files opened on this inode, None to read and write the inode data
+	// with `read_at` and `write_at` at the offset of the file
+	fn file_ops(&self) -> Option<Arc<dyn FileOps>> {
+		None
+	}
+}
+
+fn data_error(file_type: FileType) -> Errno {
+	match file_type {
+		FileType::Directory => EISDIR,
+		_ => EINVAL,
+	}
+}
+
+pub trait SuperBlock: Send + Sync {
+	// Name of the filesystem type, such as "tmpfs"
+	fn fs_type(&self) -> &'static str;
+
+	fn root(&self) -> InodeRef;
+
+	// Writes whatever the filesystem caches back to its device
+	fn sync(&self) -> Result<(), Errno> {
+		Ok(())
+	}
+}
@@ -0,0 +1,319 @@
+//! # Virtual Filesystem
+//!
+//! Filesystems plug in behind the traits of `inode`: a `SuperBlock` per mounted instance, an
+//! `Inode` per file, and optionally the `FileOps` of the files opened on it. On top of them,
+//! paths are resolved into dentries from the root or from the working directory of the
+//! current task, crossing mount points on the way, and the files opened end up in the
+//! descriptor table of the task.
+
+pub mod buffer;
+pub mod console;
+pub mod dentry;
+pub mod devfs;
+pub mod ext2;
+pub mod fd;
+pub mod fat;
+pub mod file;
+pub mod initramfs;
+pub mod inode;
+pub mod procfs;
+pub mod tmpfs;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::drivers::block;
+use crate::exceptions::errno::{
+	Errno, EBUSY, EEXIST, EINVAL, EISDIR, ENAMETOOLONG, ENODEV, ENOENT, ENOMEM, ENOSPC, ENOTDIR,
+	ENOTEMPTY, EPERM, EXDEV,
+};
+use crate::memory::kmem_managment::PMM;
+use crate::memory::page_directory::PAGE_SIZE;
+use crate::task::scheduler;
+use crate::tools::debug::LogLevel;
+use dentry::Dentry;
+use devfs::DevFs;
+use ext2::Ext2Fs;
+use fat::FatFs;
+use file::{File, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_TRUNC, O_WRONLY};
+use inode::{FileType, Metadata, SuperBlock};
+use procfs::ProcFs;
+use tmpfs::TmpFs;
+
+pub const PATH_MAX: usize = 4096;
+pub const NAME_MAX: usize = 255;
+
+// Dentry lookups of relative paths start from
+fn working_directory() -> Result<Arc<Dentry>, Errno> {
+	match scheduler::with_current(|task| task.cwd.clone()) {
+		Some(cwd) => Ok(cwd),
+		None => dentry::root(),
+	}
+}
+
+pub fn lookup(path: &str) -> Result<Arc<Dentry>, Errno> {
+	if path.is_empty() {
+		return Err(ENOENT);
+	}
+	if path.len() >= PATH_MAX {
+		return Err(ENAMETOOLONG);
+	}
+	let mut dentry = match path.starts_with('/') {
+		true => dentry::root()?,
+		false => working_directory()?,
+	};
+	for name in path.split('/') {
+		if name.len() > NAME_MAX {
+			return Err(ENAMETOOLONG);
+		}
+		dentry = dentry.lookup(name)?;
+	}
+	Ok(dentry)
+}
+
+// Directory holding the last component of `path`, and that component. It is empty, `.` or
+// `..` when `path` names a directory that cannot be created or removed.
+fn lookup_parent(path: &str) -> Result<(Arc<Dentry>, &str), Errno> {
+	let trimmed = path.trim_end_matches('/');
+	let (directory, name) = match trimmed.rfind('/') {
+		Some(index) => (&path[..=index], &trimmed[index + 1..]),
+		None if trimmed.is_empty() => (path, ""),
+		None => (".", trimmed),
+	};
+	if name.len() > NAME_MAX {
+		return Err(ENAMETOOLONG);
+	}
+	let parent = lookup(directory)?;
+	if !parent.metadata().is_dir() {
+		return Err(ENOTDIR);
+	}
+	Ok((parent, name))
+}
+
+fn is_special(name: &str) -> bool {
+	matches!(name, "" | "." | "..")
+}
+
+pub fn open(path: &str, flags: u32, mode: u32) -> Result<Arc<File>, Errno> {
+	let dentry = if flags & O_CREAT != 0 {
+		let (parent, name) = lookup_parent(path)?;
+		match parent.lookup(name) {
+			Ok(_) if flags & O_EXCL != 0 => return Err(EEXIST),
+			Ok(dentry) => dentry,
+			Err(ENOENT) => {
+				let inode = parent.inode.create(name, FileType::Regular, mode)?;
+				parent.child(name, inode)
+			}
+			Err(errno) => return Err(errno),
+		}
+	} else {
+		lookup(path)?
+	};
+
+	let file = File::new(dentry.inode.clone(), flags);
+	let metadata = dentry.metadata();
+	if metadata.is_dir() && (file.writable() || flags & O_CREAT != 0) {
+		return Err(EISDIR);
+	}
+	if !metadata.is_dir() && flags & O_DIRECTORY != 0 {
+		return Err(ENOTDIR);
+	}
+	if flags & O_TRUNC != 0 && file.writable() && metadata.file_type == FileType::Regular {
+		dentry.inode.truncate(0)?;
+	}
+	Ok(file)
+}
+
+pub fn stat(path: &str) -> Result<Metadata, Errno> {
+	Ok(lookup(path)?.metadata())
+}
+
+pub fn mkdir(path: &str, mode: u32) -> Result<(), Errno> {
+	let (parent, name) = lookup_parent(path)?;
+	if is_special(name) {
+		return Err(EEXIST);
+	}
+	parent.inode.create(name, FileType::Directory, mode)?;
+	Ok(())
+}
+
+pub fn unlink(path: &str) -> Result<(), Errno> {
+	let (parent, name) = lookup_parent(path)?;
+	if is_special(name) || parent.lookup(name)?.metadata().is_dir() {
+		return Err(EISDIR);
+	}
+	parent.inode.unlink(name)
+}
+
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+	let (parent, name) = lookup_parent(path)?;
+	match name {
+		"" => return Err(EBUSY),
+		"." => return Err(EINVAL),
+		".." => return Err(ENOTEMPTY),
+		_ => {}
+	}
+	let dentry = parent.lookup(name)?;
+	if !dentry.metadata().is_dir() {
+		return Err(ENOTDIR);
+	}
+	if dentry::is_mount_root(&dentry) {
+		return Err(EBUSY);
+	}
+	parent.inode.rmdir(name)
+}
+
+// Makes `new_path` a hard link to the file at `old_path`
+pub fn link(old_path: &str, new_path: &str) -> Result<(), Errno> {
+	let target = lookup(old_path)?;
+	let metadata = target.metadata();
+	if metadata.is_dir() {
+		return Err(EPERM);
+	}
+	let (parent, name) = lookup_parent(new_path)?;
+	if is_special(name) {
+		return Err(EEXIST);
+	}
+	if parent.metadata().dev != metadata.dev {
+		return Err(EXDEV);
+	}
+	parent.inode.link(name, &target.inode)
+}
+
+pub fn truncate(path: &str, size: u64) -> Result<(), Errno> {
+	lookup(path)?.inode.truncate(size)
+}
+
+pub fn chdir(path: &str) -> Result<(), Errno> {
+	let dentry = lookup(path)?;
+	if !dentry.metadata().is_dir() {
+		return Err(ENOTDIR);
+	}
+	scheduler::with_current(|task| task.cwd = Some(dentry));
+	Ok(())
+}
+
+// Mounts the filesystem of type `fs_type` on the block device `source` over `target`
+pub fn mount(source: &str, target: &str, fs_type: &str) -> Result<(), Errno> {
+	let target = lookup(target)?;
+	if !target.metadata().is_dir() {
+		return Err(ENOTDIR);
+	}
+	let device = block::find(source).ok_or(ENODEV)?;
+	let superblock: Arc<dyn SuperBlock> = match fs_type {
+		"ext2" => Ext2Fs::new(device)?,
+		"vfat" => FatFs::new(device)?,
+		_ => return Err(ENODEV),
+	};
+	dentry::mount(&target, superblock)
+}
+
+pub fn umount(target: &str) -> Result<(), Errno> {
+	dentry::umount(&lookup(target)?)
+}
+
+pub fn sync() -> Result<(), Errno> {
+	dentry::sync()
+}
+
+// Whole content of the file at `path`, ENOMEM when it does not fit in the heap
+pub fn read_file(path: &str) -> Result<Vec<u8>, Errno> {
+	let file = open(path, O_RDONLY, 0)?;
+	let size = file.metadata().size as usize;
+	let mut data = Vec::new();
+	data.try_reserve_exact(size).map_err(|_| ENOMEM)?;
+	data.resize(size, 0);
+	let mut done = 0;
+	while done < data.len() {
+		match file.read(&mut data[done..])? {
+			0 => break,
+			read => done += read,
+		}
+	}
+	data.truncate(done);
+	Ok(data)
+}
+
+// Creates or replaces the file at `path` with `data`
+pub fn write_file(path: &str, data: &[u8], mode: u32) -> Result<(), Errno> {
+	let file = open(path, O_CREAT | O_WRONLY | O_TRUNC, mode)?;
+	let mut done = 0;
+	while done < data.len() {
+		match file.write(&data[done..])? {
+			0 => return Err(ENOSPC),
+			written => done += written,
+		}
+	}
+	Ok(())
+}
+
+// Mounts a tmpfs on `/`, limited to half of the memory like on Linux, with devfs on `/dev`
+// and procfs on `/proc`
+pub fn init() {
+	let pages = PMM.lock().total_frames() as usize / 2;
+	let root = TmpFs::new(pages * PAGE_SIZE, pages).expect("Failed to create the root tmpfs");
+	dentry::mount_root(root).expect("Failed to mount the root filesystem");
+	for directory in ["/bin", "/dev", "/mnt", "/proc", "/tmp"] {
+		mkdir(directory, 0o755).expect("Failed to create the base directories");
+	}
+	log!(LogLevel::Info, "Mounted tmpfs on / with room for {} pages", pages);
+	let dev = lookup("/dev").expect("Failed to find /dev");
+	dentry::mount(&dev, DevFs::new()).expect("Failed to mount devfs");
+	let proc = lookup("/proc").expect("Failed to find /proc");
+	dentry::mount(&proc, ProcFs::new()).expect("Failed to mount procfs");
+	initramfs::load_modules();
+}
+
+pub fn vfs_test() {
+	use crate::exceptions::errno::{EBADF, EMFILE, ESPIPE};
+	use fd::{FdTable, MAX_FDS};
+	use file::{O_RDONLY, O_RDWR, SEEK_SET};
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the virtual filesystem\n");
+
+	log!(LogLevel::Info, "Paths should be checked before any lookup\n");
+	assert!(lookup("").err() == Some(ENOENT));
+	let long_path = "a/".repeat(PATH_MAX / 2);
+	assert!(lookup(&long_path).err() == Some(ENAMETOOLONG));
+	let long_name = "a".repeat(NAME_MAX + 1);
+	assert!(mkdir(&long_name, 0o755) == Err(ENAMETOOLONG));
+
+	log!(LogLevel::Info, "The console should take plain writes and be a stream\n");
+	let console = console::open(O_RDWR);
+	assert!(console.write(b"console ok\n") == Ok(11));
+	assert!(console.read(&mut [0; 8]) == Ok(0));
+	assert!(console.seek(0, SEEK_SET) == Err(ESPIPE));
+	assert!(console.metadata().file_type == FileType::CharDevice);
+	assert!(console::open(O_RDONLY).write(b"no") == Err(EBADF));
+
+	log!(LogLevel::Info, "Descriptors should be allocated lowest first\n");
+	let mut table = FdTable::console();
+	assert!(table.count() == 3);
+	assert!(table.insert(console.clone(), false) == Ok(3));
+	assert!(table.close(1).is_ok());
+	assert!(table.close(1).err() == Some(EBADF));
+	assert!(table.get(1).err() == Some(EBADF));
+	assert!(table.insert(console.clone(), true) == Ok(1));
+	assert!(table.get(42).err() == Some(EBADF));
+
+	log!(LogLevel::Info, "Copies should share the files, exec closes close-on-exec ones\n");
+	let mut copy = table.clone();
+	assert!(Arc::ptr_eq(&copy.get(3).unwrap(), &table.get(3).unwrap()));
+	assert!(copy.exec().len() == 1);
+	assert!(copy.get(1).err() == Some(EBADF));
+	assert!(table.get(1).is_ok());
+
+	log!(LogLevel::Info, "The table should be limited to MAX_FDS descriptors\n");
+	while table.count() < MAX_FDS {
+		table.insert(console.clone(), false).unwrap();
+	}
+	assert!(table.insert(console.clone(), false) == Err(EMFILE));
+	// fd 0 and 2 hold another console file, `copy` and `console` hold one reference each
+	assert!(Arc::strong_count(&console) == MAX_FDS);
+	drop(table);
+	drop(copy);
+	assert!(Arc::strong_count(&console) == 1);
+
+	log!(LogLevel::Info, "\t\tEnd of virtual filesystem test\n");
+}
@@ -0,0 +1,447 @@
+//! # procfs
+//!
+//! Read-only filesystem mounted on `/proc` that shows the state of the kernel as text files.
+//! Nothing is stored: the content of a file is generated again on every read and on every
+//! stat, so its size is the length of what a read would return right now. Besides the
+//! global files, every task gets a directory named after its pid with its `status` and
+//! `maps`, which disappears with the task.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+
+use super::dentry;
+use super::inode::{anonymous_dev, DirEntry, FileType, Inode, InodeRef, Metadata, SuperBlock};
+use crate::exceptions::errno::{Errno, EISDIR, ENOENT, ENOTDIR};
+use crate::exceptions::interrupts::{self, InterruptIndex, TICKS};
+use crate::memory::allocator::heap_usage;
+use crate::memory::kmem_managment::PMM;
+use crate::memory::page_directory::PAGE_SIZE;
+use crate::memory::vma::{VmaBacking, VmaFlags};
+use crate::multiboot;
+use crate::task::scheduler::{self, IDLE_PID};
+use crate::task::task::Pid;
+use crate::tools::librs::{cpu_brand, cpu_vendor, get_cpuid};
+
+const ROOT_INO: u64 = 1;
+// Pid directories are numbered from here, leaving room for the files inside them
+const PID_INO_BASE: u64 = 0x1000;
+const PID_INO_STRIDE: u64 = 16;
+
+// Timer frequency of the PIT left at its default divisor, in hundredths of hertz
+const TIMER_CENTIHZ: u64 = 1821;
+
+// Names of the CPUID leaf 1 edx feature bits, empty for reserved ones
+const CPU_FLAGS: [&str; 32] = [
+	"fpu", "vme", "de", "pse", "tsc", "msr", "pae", "mce", "cx8", "apic", "", "sep", "mtrr", "pge",
+	"mca", "cmov", "pat", "pse36", "psn", "clflush", "", "ds", "acpi", "mmx", "fxsr", "sse",
+	"sse2", "ss", "ht", "tm", "ia64", "pbe",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Content {
+	MemInfo,
+	Interrupts,
+	Uptime,
+	CpuInfo,
+	Cmdline,
+	Mounts,
+	Status(Pid),
+	Maps(Pid),
+}
+
+const ROOT_FILES: [(&str, Content); 6] = [
+	("meminfo", Content::MemInfo),
+	("interrupts", Content::Interrupts),
+	("uptime", Content::Uptime),
+	("cpuinfo", Content::CpuInfo),
+	("cmdline", Content::Cmdline),
+	("mounts", Content::Mounts),
+];
+
+const PID_FILES: [&str; 2] = ["status", "maps"];
+
+impl Content {
+	fn ino(&self) -> u64 {
+		let root_file = |content| {
+			let index = ROOT_FILES.iter().position(|&(_, other)| other == content);
+			ROOT_INO + 1 + index.unwrap_or(0) as u64
+		};
+		match *self {
+			Content::Status(pid) => pid_ino(pid) + 1,
+			Content::Maps(pid) => pid_ino(pid) + 2,
+			content => root_file(content),
+		}
+	}
+
+	// Text of the file, ENOENT once the task it describes is gone
+	fn generate(&self) -> Result<String, Errno> {
+		let mut text = String::new();
+		match *self {
+			Content::MemInfo => meminfo(&mut text),
+			Content::Interrupts => interrupt_counts(&mut text),
+			Content::Uptime => uptime(&mut text),
+			Content::CpuInfo => cpuinfo(&mut text),
+			Content::Cmdline => {
+				text.push_str(&multiboot::cmdline());
+				text.push('\n');
+			}
+			Content::Mounts => {
+				for (path, fs_type) in dentry::mounts() {
+					let _ = writeln!(text, "{} {} {} rw 0 0", fs_type, path, fs_type);
+				}
+			}
+			Content::Status(pid) => status(pid, &mut text)?,
+			Content::Maps(pid) => maps(pid, &mut text)?,
+		}
+		Ok(text)
+	}
+}
+
+fn pid_ino(pid: Pid) -> u64 {
+	PID_INO_BASE + pid as u64 * PID_INO_STRIDE
+}
+
+fn task_exists(pid: Pid) -> bool {
+	scheduler::with_task(pid, |_| ()).is_some()
+}
+
+fn meminfo(text: &mut String) {
+	let (total, free, used) = {
+		let pmm = PMM.lock();
+		(pmm.total_frames(), pmm.free_frames(), pmm.used_frames())
+	};
+	let kb = |frames: u32| frames as usize * PAGE_SIZE / 1024;
+	let (kmalloc, vmalloc) = heap_usage();
+	let _ = writeln!(text, "MemTotal:      {:>8} kB", kb(total));
+	let _ = writeln!(text, "MemFree:       {:>8} kB", kb(free));
+	let _ = writeln!(text, "MemUsed:       {:>8} kB", kb(used));
+	let _ = writeln!(text, "KmallocMapped: {:>8} kB", kmalloc.mapped / 1024);
+	let _ = writeln!(text, "KmallocUsed:   {:>8} kB", kmalloc.used / 1024);
+	let _ = writeln!(text, "VmallocMapped: {:>8} kB", vmalloc.mapped / 1024);
+	let _ = writeln!(text, "VmallocUsed:   {:>8} kB", vmalloc.used / 1024);
+}
+
+fn vector_name(vector: u8) -> String {
+	// In the order of InterruptIndex
+	const IRQS: [&str; 15] = [
+		"timer",
+		"keyboard",
+		"cascade",
+		"com2",
+		"com1",
+		"lpt2",
+		"floppy",
+		"lpt1",
+		"rtc",
+		"free1",
+		"free2",
+		"free3",
+		"ps2 mouse",
+		"primary ata",
+		"secondary ata",
+	];
+	let first_irq = InterruptIndex::Timer.as_u8();
+	match vector {
+		interrupts::DIVIDE_ERROR => String::from("divide error"),
+		interrupts::INVALID_OPCODE => String::from("invalid opcode"),
+		interrupts::GENERAL_PROTECTION => String::from("general protection"),
+		interrupts::PAGE_FAULT => String::from("page fault"),
+		interrupts::SYSCALL => String::from("syscall"),
+		vector if (first_irq..first_irq + IRQS.len() as u8).contains(&vector) => {
+			let irq = vector - first_irq;
+			format!("irq {} {}", irq, IRQS[irq as usize])
+		}
+		_ => String::new(),
+	}
+}
+
+fn interrupt_counts(text: &mut String) {
+	for (vector, &count) in interrupts::counts().iter().enumerate() {
+		if count != 0 {
+			let _ = writeln!(
+				text,
+				"{:>3}: {:>10}  {}",
+				vector,
+				count,
+				vector_name(vector as u8)
+			);
+		}
+	}
+}
+
+// Seconds with two decimals for `ticks` timer ticks
+fn seconds(ticks: u32) -> String {
+	let hundredths = ticks as u64 * 10000 / TIMER_CENTIHZ;
+	format!("{}.{:02}", hundredths / 100, hundredths % 100)
+}
+
+fn uptime(text: &mut String) {
+	let idle = scheduler::with_task(IDLE_PID, |task| task.ticks).unwrap_or(0);
+	let _ = writeln!(
+		text,
+		"{} {}",
+		seconds(TICKS.load(Ordering::SeqCst)),
+		seconds(idle)
+	);
+}
+
+fn cpuinfo(text: &mut String) {
+	let (mut eax, mut ebx, mut ecx, mut edx) = (0, 0, 0, 0);
+	get_cpuid(1, &mut eax, &mut ebx, &mut ecx, &mut edx);
+	let vendor = cpu_vendor();
+	let brand = cpu_brand();
+	let brand = core::str::from_utf8(&brand).unwrap_or("Unknown");
+	let flags: alloc::vec::Vec<&str> = CPU_FLAGS
+		.iter()
+		.enumerate()
+		.filter(|&(bit, name)| !name.is_empty() && edx & (1 << bit) != 0)
+		.map(|(_, &name)| name)
+		.collect();
+
+	let _ = writeln!(text, "processor\t: 0");
+	let _ = writeln!(
+		text,
+		"vendor_id\t: {}",
+		core::str::from_utf8(&vendor).unwrap_or("Unknown")
+	);
+	let _ = writeln!(text, "cpu family\t: {}", (eax >> 8) & 0xf);
+	let _ = writeln!(text, "model\t\t: {}", (eax >> 4) & 0xf);
+	let _ = writeln!(text, "model name\t: {}", brand.trim_matches(['\0', ' ']));
+	let _ = writeln!(text, "stepping\t: {}", eax & 0xf);
+	let _ = writeln!(text, "flags\t\t: {}", flags.join(" "));
+}
+
+fn status(pid: Pid, text: &mut String) -> Result<(), Errno> {
+	scheduler::with_task(pid, |task| {
+		let vm_size: u32 = task
+			.address_space
+			.as_ref()
+			.map(|space| space.vmas().iter().map(|vma| vma.end - vma.start).sum())
+			.unwrap_or(0);
+		let _ = writeln!(text, "Name:\t{}", task.name);
+		let _ = writeln!(text, "State:\t{}", task.state.as_str());
+		let _ = writeln!(text, "Pid:\t{}", task.pid);
+		let _ = writeln!(text, "PPid:\t{}", task.ppid);
+		let _ = writeln!(text, "Ticks:\t{}", task.ticks);
+		let _ = writeln!(text, "VmSize:\t{} kB", vm_size / 1024);
+		let _ = writeln!(text, "SigPnd:\t{:016x}", task.signals.pending);
+		let _ = writeln!(text, "SigBlk:\t{:016x}", task.signals.blocked);
+		let _ = writeln!(text, "FDs:\t{}", task.files.count());
+	})
+	.ok_or(ENOENT)
+}
+
+fn maps(pid: Pid, text: &mut String) -> Result<(), Errno> {
+	scheduler::with_task(pid, |task| {
+		let Some(space) = task.address_space.as_ref() else {
+			return;
+		};
+		for vma in space.vmas() {
+			let flag = |flag, letter| {
+				if vma.flags.contains(flag) {
+					letter
+				} else {
+					'-'
+				}
+			};
+			let offset = match &vma.backing {
+				VmaBacking::File(file) => file.offset,
+				_ => 0,
+			};
+			let _ = writeln!(
+				text,
+				"{:08x}-{:08x} {}{}{}p {:08x} 00:00 0 {}",
+				vma.start,
+				vma.end,
+				flag(VmaFlags::READ, 'r'),
+				flag(VmaFlags::WRITE, 'w'),
+				flag(VmaFlags::EXEC, 'x'),
+				offset,
+				vma.name()
+			);
+		}
+	})
+	.ok_or(ENOENT)
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+	Root,
+	PidDir(Pid),
+	File(Content),
+}
+
+struct ProcInode {
+	dev: u32,
+	node: Node,
+}
+
+impl ProcInode {
+	fn new(dev: u32, node: Node) -> InodeRef {
+		Arc::new(ProcInode { dev, node })
+	}
+}
+
+impl Inode for ProcInode {
+	fn metadata(&self) -> Metadata {
+		let (ino, file_type, mode) = match self.node {
+			Node::Root => (ROOT_INO, FileType::Directory, 0o555),
+			Node::PidDir(pid) => (pid_ino(pid), FileType::Directory, 0o555),
+			Node::File(content) => (content.ino(), FileType::Regular, 0o444),
+		};
+		let mut metadata = Metadata::new(self.dev, ino, file_type, mode);
+		match self.node {
+			Node::File(content) => {
+				metadata.size = content.generate().map_or(0, |text| text.len() as u64);
+			}
+			_ => metadata.nlink = 2,
+		}
+		metadata
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, Errno> {
+		let node = match self.node {
+			Node::Root => match ROOT_FILES.iter().find(|&&(file, _)| file == name) {
+				Some(&(_, content)) => Node::File(content),
+				None => match name.parse::<Pid>() {
+					Ok(pid) if task_exists(pid) && name == pid.to_string() => Node::PidDir(pid),
+					_ => return Err(ENOENT),
+				},
+			},
+			Node::PidDir(pid) => match name {
+				"status" => Node::File(Content::Status(pid)),
+				"maps" => Node::File(Content::Maps(pid)),
+				_ => return Err(ENOENT),
+			},
+			Node::File(_) => return Err(ENOTDIR),
+		};
+		Ok(ProcInode::new(self.dev, node))
+	}
+
+	fn read_dir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+		let directory = |name: &str, ino| DirEntry {
+			name: String::from(name),
+			ino,
+			file_type: FileType::Directory,
+		};
+		let file = |name: &str, content: Content| DirEntry {
+			name: String::from(name),
+			ino: content.ino(),
+			file_type: FileType::Regular,
+		};
+		let (ino, parent) = match self.node {
+			Node::Root => (ROOT_INO, ROOT_INO),
+			Node::PidDir(pid) => (pid_ino(pid), ROOT_INO),
+			Node::File(_) => return Err(ENOTDIR),
+		};
+		Ok(match index {
+			0 => Some(directory(".", ino)),
+			1 => Some(directory("..", parent)),
+			_ => match self.node {
+				Node::Root => match ROOT_FILES.get(index - 2) {
+					Some(&(name, content)) => Some(file(name, content)),
+					None => scheduler::task_list()
+						.get(index - 2 - ROOT_FILES.len())
+						.map(|task| directory(&task.pid.to_string(), pid_ino(task.pid))),
+				},
+				Node::PidDir(pid) => PID_FILES.get(index - 2).map(|&name| match name {
+					"status" => file(name, Content::Status(pid)),
+					_ => file(name, Content::Maps(pid)),
+				}),
+				Node::File(_) => None,
+			},
+		})
+	}
+
+	fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+		let Node::File(content) = self.node else {
+			return Err(EISDIR);
+		};
+		let text = content.generate()?;
+		let start = (offset as usize).min(text.len());
+		let length = buffer.len().min(text.len() - start);
+		buffer[..length].copy_from_slice(&text.as_bytes()[start..start + length]);
+		Ok(length)
+	}
+}
+
+pub struct ProcFs {
+	root: InodeRef,
+}
+
+impl ProcFs {
+	pub fn new() -> Arc<ProcFs> {
+		Arc::new(ProcFs {
+			root: ProcInode::new(anonymous_dev(), Node::Root),
+		})
+	}
+}
+
+impl SuperBlock for ProcFs {
+	fn fs_type(&self) -> &'static str {
+		"proc"
+	}
+
+	fn root(&self) -> InodeRef {
+		self.root.clone()
+	}
+}
+
+pub fn procfs_test() {
+	use crate::tools::debug::LogLevel;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting procfs\n");
+
+	let read = |path: &str| String::from_utf8(super::read_file(path).expect(path)).unwrap();
+
+	log!(LogLevel::Info, "Global files should describe the kernel\n");
+	let meminfo = read("/proc/meminfo");
+	assert!(meminfo.starts_with("MemTotal:") && meminfo.contains("KmallocUsed:"));
+	let uptime = read("/proc/uptime");
+	assert!(uptime.ends_with('\n') && uptime.split(' ').count() == 2);
+	let timer = format!("{:>3}:", InterruptIndex::Timer.as_usize());
+	let timer_line = read("/proc/interrupts")
+		.lines()
+		.find(|line| line.starts_with(&timer))
+		.map(String::from)
+		.expect("no timer line");
+	let count: u32 = timer_line[4..]
+		.trim()
+		.split(' ')
+		.next()
+		.unwrap()
+		.parse()
+		.unwrap();
+	assert!(count > 0);
+	assert!(read("/proc/mounts").contains("proc /proc proc"));
+	assert!(read("/proc/cpuinfo").contains("vendor_id"));
+
+	log!(
+		LogLevel::Info,
+		"Tasks should have a directory while they exist\n"
+	);
+	let pid = scheduler::current_pid();
+	let status = read(&format!("/proc/{}/status", pid));
+	assert!(status.lines().any(|line| line == format!("Pid:\t{}", pid)));
+	assert!(super::read_file(&format!("/proc/{}/maps", pid)).is_ok());
+	assert!(super::stat("/proc/99999").err() == Some(ENOENT));
+	assert!(super::stat(&format!("/proc/0{}", pid)).err() == Some(ENOENT));
+
+	log!(
+		LogLevel::Info,
+		"The listing should hold the files and the tasks\n"
+	);
+	let directory = super::lookup("/proc").unwrap();
+	let names: alloc::vec::Vec<String> = (0..)
+		.map_while(|index| directory.inode.read_dir(index).unwrap())
+		.map(|entry| entry.name)
+		.collect();
+	assert!(names.iter().any(|name| name == "meminfo"));
+	assert!(names.iter().any(|name| *name == pid.to_string()));
+	assert!(super::write_file("/proc/meminfo", b"no", 0o644).is_err());
+	log!(LogLevel::Info, "\t\tEnd of procfs test\n");
+}
@@ -0,0 +1,506 @@
+//! # tmpfs
+//!
+//! Filesystem kept entirely in memory, mounted on `/` at boot so files can be created
+//! without any disk. The data of a regular file lives in PMM frames indexed by page number.
+//! A page only gets a frame the first time it is written, so holes left by writing past the
+//! end or by truncating up read as zeros and cost nothing.
+//!
+//! Directory entries own their inodes: a hard link is one more entry for the same inode, and
+//! an inode lives on while it is linked somewhere or open. Its frames go back to the PMM
+//! with the last reference. Each instance has a budget of pages and inodes, going over
+//! either fails with ENOSPC.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+use super::inode::{anonymous_dev, DirEntry, FileType, Inode, InodeRef, Metadata, SuperBlock};
+use crate::exceptions::errno::{
+	Errno, EEXIST, EFBIG, EINVAL, EISDIR, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY, EPERM, EXDEV,
+};
+use crate::memory::kmem_managment::{HK_OFST, PMM};
+use crate::memory::page_directory::PAGE_SIZE;
+use crate::tools::debug::LogLevel;
+
+// Page numbers are 32 bits, which is more than the memory there is to fill anyway
+const MAX_FILE_SIZE: u64 = 1 << 32;
+const ROOT_INO: u64 = 1;
+
+struct State {
+	pages: usize,
+	next_ino: u64,
+	// Every live inode by number, for link to find the inode behind an `InodeRef`
+	inodes: BTreeMap<u64, Weak<TmpInode>>,
+}
+
+// Part of the filesystem every inode refers to
+struct Shared {
+	dev: u32,
+	max_pages: usize,
+	max_inodes: usize,
+	state: Mutex<State>,
+}
+
+impl Shared {
+	fn new_inode(
+		self: &Arc<Self>,
+		file_type: FileType,
+		mode: u32,
+		parent: u64,
+	) -> Result<Arc<TmpInode>, Errno> {
+		let mut state = self.state.lock();
+		if state.inodes.len() >= self.max_inodes {
+			return Err(ENOSPC);
+		}
+		let ino = state.next_ino;
+		state.next_ino += 1;
+		let inode = Arc::new(TmpInode {
+			ino,
+			file_type,
+			fs: self.clone(),
+			data: Mutex::new(InodeData {
+				mode: mode & 0o7777,
+				nlink: if file_type == FileType::Directory { 2 } else { 1 },
+				size: 0,
+				pages: BTreeMap::new(),
+				entries: BTreeMap::new(),
+				parent: if ino == ROOT_INO { ino } else { parent },
+			}),
+		});
+		state.inodes.insert(ino, Arc::downgrade(&inode));
+		Ok(inode)
+	}
+
+	// Zeroed frame for a file page
+	fn allocate_page(&self) -> Result<u32, Errno> {
+		let mut state = self.state.lock();
+		if state.pages >= self.max_pages {
+			return Err(ENOSPC);
+		}
+		let frame = PMM.lock().allocate_frame().map_err(|_| ENOSPC)?;
+		page_bytes(frame).fill(0);
+		state.pages += 1;
+		Ok(frame)
+	}
+
+	fn free_page(&self, frame: u32) {
+		PMM.lock().deallocate_frame(frame);
+		self.state.lock().pages -= 1;
+	}
+}
+
+fn page_bytes(frame: u32) -> &'static mut [u8] {
+	unsafe { core::slice::from_raw_parts_mut((frame + HK_OFST) as *mut u8, PAGE_SIZE) }
+}
+
+struct InodeData {
+	mode: u32,
+	nlink: u32,
+	size: u64,
+	// Frames of a regular file by page number, missing pages are holes
+	pages: BTreeMap<u32, u32>,
+	// Entries of a directory, without `.` and `..`
+	entries: BTreeMap<String, Arc<TmpInode>>,
+	// Inode number of the `..` of a directory
+	parent: u64,
+}
+
+struct TmpInode {
+	ino: u64,
+	file_type: FileType,
+	fs: Arc<Shared>,
+	data: Mutex<InodeData>,
+}
+
+impl TmpInode {
+	fn check_dir(&self) -> Result<(), Errno> {
+		match self.file_type {
+			FileType::Directory => Ok(()),
+			_ => Err(ENOTDIR),
+		}
+	}
+
+	fn check_regular(&self) -> Result<(), Errno> {
+		match self.file_type {
+			FileType::Regular => Ok(()),
+			FileType::Directory => Err(EISDIR),
+			_ => Err(EINVAL),
+		}
+	}
+}
+
+impl Inode for TmpInode {
+	fn metadata(&self) -> Metadata {
+		let data = self.data.lock();
+		let mut metadata = Metadata::new(self.fs.dev, self.ino, self.file_type, data.mode);
+		metadata.nlink = data.nlink;
+		metadata.size = data.size;
+		metadata.blocks = (data.pages.len() * PAGE_SIZE / 512) as u64;
+		metadata
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, Errno> {
+		self.check_dir()?;
+		match self.data.lock().entries.get(name) {
+			Some(inode) => Ok(inode.clone()),
+			None => Err(ENOENT),
+		}
+	}
+
+	fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<InodeRef, Errno> {
+		self.check_dir()?;
+		if !matches!(file_type, FileType::Regular | FileType::Directory) {
+			return Err(EINVAL);
+		}
+		let mut data = self.data.lock();
+		// A removed directory still open somewhere takes no new entries
+		if data.nlink == 0 {
+			return Err(ENOENT);
+		}
+		if data.entries.contains_key(name) {
+			return Err(EEXIST);
+		}
+		let inode = self.fs.new_inode(file_type, mode, self.ino)?;
+		if file_type == FileType::Directory {
+			data.nlink += 1;
+		}
+		data.entries.insert(String::from(name), inode.clone());
+		Ok(inode)
+	}
+
+	fn link(&self, name: &str, target: &InodeRef) -> Result<(), Errno> {
+		self.check_dir()?;
+		let metadata = target.metadata();
+		if metadata.dev != self.fs.dev {
+			return Err(EXDEV);
+		}
+		if metadata.is_dir() {
+			return Err(EPERM);
+		}
+		let inode = self
+			.fs
+			.state
+			.lock()
+			.inodes
+			.get(&metadata.ino)
+			.and_then(Weak::upgrade)
+			.ok_or(ENOENT)?;
+
+		let mut data = self.data.lock();
+		if data.nlink == 0 {
+			return Err(ENOENT);
+		}
+		if data.entries.contains_key(name) {
+			return Err(EEXIST);
+		}
+		{
+			let mut target = inode.data.lock();
+			if target.nlink == 0 {
+				return Err(ENOENT);
+			}
+			target.nlink += 1;
+		}
+		data.entries.insert(String::from(name), inode);
+		Ok(())
+	}
+
+	fn unlink(&self, name: &str) -> Result<(), Errno> {
+		self.check_dir()?;
+		let mut data = self.data.lock();
+		match data.entries.get(name) {
+			None => return Err(ENOENT),
+			Some(inode) if inode.file_type == FileType::Directory => return Err(EISDIR),
+			Some(_) => {}
+		}
+		let inode = data.entries.remove(name);
+		drop(data);
+		if let Some(inode) = inode {
+			inode.data.lock().nlink -= 1;
+		}
+		Ok(())
+	}
+
+	fn rmdir(&self, name: &str) -> Result<(), Errno> {
+		self.check_dir()?;
+		let mut data = self.data.lock();
+		let inode = data.entries.get(name).ok_or(ENOENT)?.clone();
+		inode.check_dir()?;
+		{
+			let mut child = inode.data.lock();
+			if !child.entries.is_empty() {
+				return Err(ENOTEMPTY);
+			}
+			child.nlink = 0;
+		}
+		data.entries.remove(name);
+		data.nlink -= 1;
+		drop(data);
+		Ok(())
+	}
+
+	fn read_dir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+		self.check_dir()?;
+		let data = self.data.lock();
+		let entry = |name: &str, inode: &TmpInode| DirEntry {
+			name: String::from(name),
+			ino: inode.ino,
+			file_type: inode.file_type,
+		};
+		Ok(match index {
+			0 => Some(entry(".", self)),
+			1 => Some(DirEntry {
+				name: String::from(".."),
+				ino: data.parent,
+				file_type: FileType::Directory,
+			}),
+			_ => data
+				.entries
+				.iter()
+				.nth(index - 2)
+				.map(|(name, inode)| entry(name, inode)),
+		})
+	}
+
+	fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Errno> {
+		self.check_regular()?;
+		let data = self.data.lock();
+		if offset >= data.size {
+			return Ok(0);
+		}
+		let len = (data.size - offset).min(buffer.len() as u64) as usize;
+		let mut done = 0;
+		while done < len {
+			let position = offset + done as u64;
+			let page = (position / PAGE_SIZE as u64) as u32;
+			let page_offset = (position % PAGE_SIZE as u64) as usize;
+			let chunk = (PAGE_SIZE - page_offset).min(len - done);
+			let destination = &mut buffer[done..done + chunk];
+			match data.pages.get(&page) {
+				Some(&frame) => {
+					destination.copy_from_slice(&page_bytes(frame)[page_offset..page_offset + chunk])
+				}
+				None => destination.fill(0),
+			}
+			done += chunk;
+		}
+		Ok(len)
+	}
+
+	// Stops at the first page that cannot be allocated, ENOSPC if nothing was written
+	fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, Errno> {
+		self.check_regular()?;
+		offset
+			.checked_add(buffer.len() as u64)
+			.filter(|&end| end <= MAX_FILE_SIZE)
+			.ok_or(EFBIG)?;
+		let mut data = self.data.lock();
+		let mut done = 0;
+		while done < buffer.len() {
+			let position = offset + done as u64;
+			let page = (position / PAGE_SIZE as u64) as u32;
+			let page_offset = (position % PAGE_SIZE as u64) as usize;
+			let chunk = (PAGE_SIZE - page_offset).min(buffer.len() - done);
+			let frame = match data.pages.get(&page) {
+				Some(&frame) => frame,
+				None => match self.fs.allocate_page() {
+					Ok(frame) => {
+						data.pages.insert(page, frame);
+						frame
+					}
+					Err(errno) if done == 0 => return Err(errno),
+					Err(_) => break,
+				},
+			};
+			page_bytes(frame)[page_offset..page_offset + chunk]
+				.copy_from_slice(&buffer[done..done + chunk]);
+			done += chunk;
+		}
+		data.size = data.size.max(offset + done as u64);
+		Ok(done)
+	}
+
+	fn truncate(&self, size: u64) -> Result<(), Errno> {
+		self.check_regular()?;
+		if size > MAX_FILE_SIZE {
+			return Err(EFBIG);
+		}
+		let mut data = self.data.lock();
+		if size < data.size {
+			let first_freed = size.div_ceil(PAGE_SIZE as u64) as u32;
+			let freed = data.pages.split_off(&first_freed);
+			for &frame in freed.values() {
+				self.fs.free_page(frame);
+			}
+			// The cut off end of the last page must read as zeros if the file grows again
+			let tail = (size % PAGE_SIZE as u64) as usize;
+			if let Some(&frame) = data.pages.get(&((size / PAGE_SIZE as u64) as u32)) {
+				page_bytes(frame)[tail..].fill(0);
+			}
+		}
+		data.size = size;
+		Ok(())
+	}
+}
+
+impl Drop for TmpInode {
+	fn drop(&mut self) {
+		let data = self.data.get_mut();
+		for &frame in data.pages.values() {
+			self.fs.free_page(frame);
+		}
+		self.fs.state.lock().inodes.remove(&self.ino);
+	}
+}
+
+pub struct TmpFs {
+	shared: Arc<Shared>,
+	root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+	// Instance holding at most `max_size` bytes of file data in `max_inodes` inodes
+	pub fn new(max_size: usize, max_inodes: usize) -> Result<Arc<TmpFs>, Errno> {
+		let shared = Arc::new(Shared {
+			dev: anonymous_dev(),
+			max_pages: max_size / PAGE_SIZE,
+			max_inodes,
+			state: Mutex::new(State {
+				pages: 0,
+				next_ino: ROOT_INO,
+				inodes: BTreeMap::new(),
+			}),
+		});
+		let root = shared.new_inode(FileType::Directory, 0o1777, ROOT_INO)?;
+		Ok(Arc::new(TmpFs { shared, root }))
+	}
+
+	// Pages and inodes in use
+	pub fn usage(&self) -> (usize, usize) {
+		let state = self.shared.state.lock();
+		(state.pages, state.inodes.len())
+	}
+}
+
+impl SuperBlock for TmpFs {
+	fn fs_type(&self) -> &'static str {
+		"tmpfs"
+	}
+
+	fn root(&self) -> InodeRef {
+		self.root.clone()
+	}
+}
+
+pub fn tmpfs_test() {
+	use super::dentry;
+	use super::file::{O_CREAT, O_RDONLY, O_RDWR, SEEK_SET};
+	use crate::exceptions::errno::EBUSY;
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting tmpfs\n");
+
+	super::mkdir("/tmp/tmpfs-test", 0o755).expect("mkdir failed");
+	let target = super::lookup("/tmp/tmpfs-test").unwrap();
+	let fs = TmpFs::new(4 * PAGE_SIZE, 6).expect("Failed to create tmpfs");
+	dentry::mount(&target, fs.clone()).expect("mount failed");
+
+	log!(LogLevel::Info, "Paths should cross the mount point both ways\n");
+	let root = super::lookup("/tmp/tmpfs-test").unwrap();
+	assert!(root.metadata().dev != target.metadata().dev);
+	assert!(root.path() == "/tmp/tmpfs-test");
+	assert!(super::lookup("/tmp/tmpfs-test/..").unwrap().path() == "/tmp");
+	assert!(super::lookup("/tmp/tmpfs-test/./../tmpfs-test//").unwrap().metadata().ino == ROOT_INO);
+
+	log!(LogLevel::Info, "Files should read back what was written\n");
+	let file = super::open("/tmp/tmpfs-test/a", O_CREAT | O_RDWR, 0o644).unwrap();
+	assert!(file.write(b"hello tmpfs") == Ok(11));
+	let mut buffer = [0; 16];
+	assert!(file.seek(0, SEEK_SET) == Ok(0));
+	assert!(file.read(&mut buffer) == Ok(11));
+	assert!(&buffer[..11] == b"hello tmpfs");
+	assert!(super::stat("/tmp/tmpfs-test/a").unwrap().size == 11);
+
+	log!(LogLevel::Info, "Holes should read as zeros and take no pages\n");
+	let far = 3 * PAGE_SIZE as i64 + 10;
+	file.seek(far, SEEK_SET).unwrap();
+	assert!(file.write(b"x") == Ok(1));
+	assert!(file.metadata().size == far as u64 + 1);
+	assert!(fs.usage().0 == 2);
+	file.seek(PAGE_SIZE as i64 - 4, SEEK_SET).unwrap();
+	assert!(file.read(&mut buffer) == Ok(16));
+	assert!(buffer == [0; 16]);
+
+	log!(LogLevel::Info, "Truncating should free pages and zero the cut off bytes\n");
+	assert!(super::truncate("/tmp/tmpfs-test/a", 5) == Ok(()));
+	assert!(fs.usage().0 == 1);
+	assert!(super::truncate("/tmp/tmpfs-test/a", 100) == Ok(()));
+	file.seek(0, SEEK_SET).unwrap();
+	assert!(file.read(&mut buffer) == Ok(16));
+	assert!(&buffer[..5] == b"hello" && buffer[5..] == [0; 11]);
+
+	log!(LogLevel::Info, "Hard links should share the inode\n");
+	assert!(super::link("/tmp/tmpfs-test/a", "/tmp/tmpfs-test/b") == Ok(()));
+	assert!(super::stat("/tmp/tmpfs-test/a").unwrap().nlink == 2);
+	assert!(super::link("/tmp/tmpfs-test/a", "/tmp/tmpfs-test/b") == Err(EEXIST));
+	assert!(super::link("/tmp/tmpfs-test/a", "/tmp/a") == Err(EXDEV));
+	assert!(super::unlink("/tmp/tmpfs-test/a") == Ok(()));
+	let link = super::open("/tmp/tmpfs-test/b", O_RDONLY, 0).unwrap();
+	assert!(link.metadata().nlink == 1 && link.metadata().ino == file.metadata().ino);
+	assert!(super::unlink("/tmp/tmpfs-test/b") == Ok(()));
+	assert!(super::lookup("/tmp/tmpfs-test/b").err() == Some(ENOENT));
+	assert!(fs.usage() == (1, 2));
+	drop(link);
+	drop(file);
+	assert!(fs.usage() == (0, 1));
+
+	log!(LogLevel::Info, "Directories should be listed and removed only when empty\n");
+	assert!(super::mkdir("/tmp/tmpfs-test/d", 0o755) == Ok(()));
+	assert!(super::mkdir("/tmp/tmpfs-test/d", 0o755) == Err(EEXIST));
+	super::open("/tmp/tmpfs-test/d/f", O_CREAT | O_RDWR, 0o644).unwrap();
+	assert!(super::stat("/tmp/tmpfs-test").unwrap().nlink == 3);
+	let directory = super::open("/tmp/tmpfs-test/d", O_RDONLY, 0).unwrap();
+	let mut names = alloc::vec::Vec::new();
+	directory
+		.read_dir(|entry, _| {
+			names.push(entry.name.clone());
+			Ok(true)
+		})
+		.unwrap();
+	assert!(names == [".", "..", "f"]);
+	assert!(super::rmdir("/tmp/tmpfs-test/d") == Err(ENOTEMPTY));
+	assert!(super::unlink("/tmp/tmpfs-test/d") == Err(EISDIR));
+	assert!(super::unlink("/tmp/tmpfs-test/d/f") == Ok(()));
+	assert!(super::rmdir("/tmp/tmpfs-test/d") == Ok(()));
+	assert!(super::open("/tmp/tmpfs-test/d/g", O_CREAT | O_RDWR, 0o644).err() == Some(ENOENT));
+	drop(directory);
+	assert!(super::stat("/tmp/tmpfs-test").unwrap().nlink == 2);
+
+	log!(LogLevel::Info, "Going over the limits should fail with ENOSPC\n");
+	let file = super::open("/tmp/tmpfs-test/big", O_CREAT | O_RDWR, 0o644).unwrap();
+	let page = [0x5a; PAGE_SIZE];
+	for _ in 0..4 {
+		assert!(file.write(&page) == Ok(PAGE_SIZE));
+	}
+	assert!(file.write(&page) == Err(ENOSPC));
+	for index in 0..5 {
+		let path = alloc::format!("/tmp/tmpfs-test/{}", index);
+		let result = super::open(&path, O_CREAT | O_RDWR, 0o644);
+		assert!(result.is_ok() == (index < 4));
+	}
+	drop(file);
+	for name in ["big", "0", "1", "2", "3"] {
+		super::unlink(&alloc::format!("/tmp/tmpfs-test/{}", name)).unwrap();
+	}
+	assert!(fs.usage() == (0, 1));
+
+	log!(LogLevel::Info, "The mount point should stay busy until unmounted\n");
+	assert!(super::rmdir("/tmp/tmpfs-test") == Err(EBUSY));
+	dentry::umount(&root).expect("umount failed");
+	assert!(super::lookup("/tmp/tmpfs-test").unwrap().metadata().dev == target.metadata().dev);
+	assert!(super::rmdir("/tmp/tmpfs-test") == Ok(()));
+	drop(root);
+	assert!(Arc::strong_count(&fs) == 1);
+
+	log!(LogLevel::Info, "\t\tEnd of tmpfs test\n");
+}
@@ -1,10 +1,27 @@
+pub mod tss;
+
 use crate::shell::accessflags::{
 	KERNEL_CODE, KERNEL_DATA, KERNEL_STACK, MAX_SEGMENT_SIZE, NO_OFF,
-	NULL_SEGMENT, SEGMENT_FLAGS, USER_CODE, USER_DATA, USER_STACK,
+	NULL_SEGMENT, SEGMENT_FLAGS, TLS_FLAGS, TLS_PAGE_FLAGS, TSS_SEGMENT, USER_CODE, USER_DATA,
+	USER_STACK,
 };
 use crate::tools::debug::LogLevel;
 use core::arch::asm;
 
+pub const GDT_ENTRIES: usize = 9;
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const KERNEL_STACK_SELECTOR: u16 = 0x18;
+// User selectors carry a requested privilege level of 3
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+pub const USER_DATA_SELECTOR: u16 = 0x28 | 3;
+pub const USER_STACK_SELECTOR: u16 = 0x30 | 3;
+pub const TSS_SELECTOR: u16 = 0x38;
+// Thread local storage segment of the running task, set with set_thread_area
+pub const TLS_ENTRY: usize = 8;
+pub const TLS_SELECTOR: u16 = (TLS_ENTRY << 3) as u16 | 3;
+
 #[repr(C, packed)]
 pub struct GdtEntry {
 	limit_low: u16,
@@ -28,8 +45,9 @@ macro_rules! gdt_entry {
 	};
 }
 
+// Written by init and set_tls, and by the CPU when it marks the TSS busy
 #[link_section = ".gdt"]
-static LOW_GDT: [GdtEntry; 7] = [
+static mut LOW_GDT: [GdtEntry; GDT_ENTRIES] = [
 	gdt_entry!(0, 0, NULL_SEGMENT, 0, "NULL segment"),
 	gdt_entry!(
 		MAX_SEGMENT_SIZE,
@@ -73,19 +91,50 @@ static LOW_GDT: [GdtEntry; 7] = [
 		SEGMENT_FLAGS,
 		"User stack segment"
 	),
+	// Filled by init once the address of the TSS is known
+	gdt_entry!(0, 0, NULL_SEGMENT, 0, "Task state segment"),
+	gdt_entry!(0, 0, NULL_SEGMENT, 0, "Thread local storage segment"),
 ];
 
-pub static mut GDT: *mut [GdtEntry; 7] = core::ptr::null_mut();
+pub static mut GDT: *mut [GdtEntry; GDT_ENTRIES] = core::ptr::null_mut();
 #[repr(C, packed)]
 pub struct GdtRegister {
 	size: u16, 
 	off: u32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsSegment {
+	pub base: u32,
+	pub limit: u32,
+	pub limit_in_pages: bool,
+}
+
+// Loads the TLS descriptor of the task about to run, None leaves the entry not present
+pub fn set_tls(segment: Option<TlsSegment>) {
+	let entry = match segment {
+		Some(segment) => gdt_entry!(
+			segment.limit,
+			segment.base,
+			USER_DATA,
+			if segment.limit_in_pages { TLS_PAGE_FLAGS } else { TLS_FLAGS },
+			"Thread local storage segment"
+		),
+		None => gdt_entry!(0, 0, NULL_SEGMENT, 0, "Thread local storage segment"),
+	};
+	unsafe {
+		set_entry(TLS_ENTRY, entry);
+	}
+}
+
+unsafe fn set_entry(index: usize, entry: GdtEntry) {
+	core::ptr::addr_of_mut!((*GDT)[index]).write(entry);
+}
+
 fn load_gdt() {
 	unsafe {
 		let gdt_register = GdtRegister {
-			size: (core::mem::size_of::<[GdtEntry; 7]>() - 1) as u16,
+			size: (core::mem::size_of::<[GdtEntry; GDT_ENTRIES]>() - 1) as u16,
 			off: GDT as u32,
 		};
 
@@ -118,6 +167,12 @@ fn load_stack_segment() {
 	}
 }
 
+fn load_task_register() {
+	unsafe {
+		asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+	}
+}
+
 fn load_code_segment() {
 	unsafe {
 		asm!(
@@ -133,7 +188,13 @@ fn load_code_segment() {
 
 pub fn init() {
 	unsafe {
-		GDT = (&LOW_GDT as *const _ as usize + 0xC0000000) as *mut [GdtEntry; 7];
+		GDT = (core::ptr::addr_of_mut!(LOW_GDT) as usize + 0xC0000000)
+			as *mut [GdtEntry; GDT_ENTRIES];
+		let (base, limit) = tss::init();
+		set_entry(
+			TSS_SELECTOR as usize / 8,
+			gdt_entry!(limit, base, TSS_SEGMENT, 0, "Task state segment"),
+		);
 	}
 	load_gdt();
 	log!(
@@ -148,4 +209,6 @@ pub fn init() {
 		LogLevel::Info,
 		"Kernel data, stack and code segments successfully loaded"
 	);
+	load_task_register();
+	log!(LogLevel::Info, "TSS successfully loaded");
 }
\ No newline at end of file
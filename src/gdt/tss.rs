@@ -0,0 +1,132 @@
+//! # Task State Segment
+//!
+//! The kernel does not use hardware task switching, the TSS only exists so the CPU knows which
+//! stack to switch to when an interrupt or a syscall arrives while running in ring 3. `esp0`
+//! must point to the top of the kernel stack of the running task and is updated by the
+//! scheduler on every switch.
+
+use core::arch::asm;
+use core::mem::size_of;
+
+use super::{
+	KERNEL_STACK_SELECTOR, USER_CODE_SELECTOR, USER_DATA_SELECTOR, USER_STACK_SELECTOR,
+};
+
+extern "C" {
+	static stack_top: u8;
+}
+
+#[derive(Debug)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+	prev_tss: u32,
+	esp0: u32,
+	ss0: u32,
+	esp1: u32,
+	ss1: u32,
+	esp2: u32,
+	ss2: u32,
+	cr3: u32,
+	eip: u32,
+	eflags: u32,
+	eax: u32,
+	ecx: u32,
+	edx: u32,
+	ebx: u32,
+	esp: u32,
+	ebp: u32,
+	esi: u32,
+	edi: u32,
+	es: u32,
+	cs: u32,
+	ss: u32,
+	ds: u32,
+	fs: u32,
+	gs: u32,
+	ldt: u32,
+	trap: u16,
+	iomap_base: u16,
+}
+
+static mut TSS: TaskStateSegment = TaskStateSegment {
+	prev_tss: 0,
+	esp0: 0,
+	ss0: KERNEL_STACK_SELECTOR as u32,
+	esp1: 0,
+	ss1: 0,
+	esp2: 0,
+	ss2: 0,
+	cr3: 0,
+	eip: 0,
+	eflags: 0,
+	eax: 0,
+	ecx: 0,
+	edx: 0,
+	ebx: 0,
+	esp: 0,
+	ebp: 0,
+	esi: 0,
+	edi: 0,
+	es: 0,
+	cs: 0,
+	ss: 0,
+	ds: 0,
+	fs: 0,
+	gs: 0,
+	ldt: 0,
+	trap: 0,
+	// No I/O permission bitmap, every port access from ring 3 faults
+	iomap_base: size_of::<TaskStateSegment>() as u16,
+};
+
+// Returns the base and limit of the TSS descriptor, the boot stack is used until a task runs
+pub fn init() -> (usize, usize) {
+	unsafe {
+		TSS.esp0 = boot_stack_top();
+		(
+			core::ptr::addr_of!(TSS) as usize,
+			size_of::<TaskStateSegment>() - 1,
+		)
+	}
+}
+
+pub fn boot_stack_top() -> u32 {
+	unsafe { &stack_top as *const u8 as u32 }
+}
+
+// Stack loaded by the CPU on the next transition from ring 3 to ring 0
+pub fn set_kernel_stack(esp0: u32) {
+	unsafe {
+		core::ptr::addr_of_mut!(TSS.esp0).write_unaligned(esp0);
+	}
+}
+
+// Drops to ring 3 at `entry` with `user_stack`, interrupts enabled and cleared registers
+pub unsafe fn enter_user_mode(entry: u32, user_stack: u32) -> ! {
+	asm!(
+		"mov ds, {data:x}",
+		"mov es, {data:x}",
+		"mov fs, {data:x}",
+		"mov gs, {data:x}",
+		"push {stack_segment}",
+		"push {user_stack}",
+		"pushfd",
+		"or dword ptr [esp], 0x200",
+		"push {code_segment}",
+		"push {entry}",
+		"xor eax, eax",
+		"xor ebx, ebx",
+		"xor ecx, ecx",
+		"xor edx, edx",
+		"xor esi, esi",
+		"xor edi, edi",
+		"xor ebp, ebp",
+		"iretd",
+		data = in(reg) USER_DATA_SELECTOR as u32,
+		user_stack = in(reg) user_stack,
+		entry = in(reg) entry,
+		stack_segment = const USER_STACK_SELECTOR as u32,
+		code_segment = const USER_CODE_SELECTOR as u32,
+		options(noreturn)
+	);
+}
@@ -1,134 +1,82 @@
-
 .text
-.extern exception_handler
-.extern irq_handler
-
-
-.macro isr_err_stub n
-.global isr_stub_\n
-
-isr_stub_\n:
-    cli
-    push $\n
-    jmp isr_common_stub
-.endm
-
-
-.macro isr_no_err_stub n
-.global isr_stub_\n
-
-isr_stub_\n:
-    cli
-    push $0 # Pushing a dummy error code
-    push $\n
-    jmp isr_common_stub
-.endm
-
-
-.macro IRQ  n
-.global irq_\n
-
-irq_\n:
-    cli
-    push $0
-    push $\n
-    jmp irq_common_stub
-
-.endm
+.extern syscall_handler
+.extern return_from_trap
 
 
+# Builds a TrapFrame on top of the frame pushed by the CPU and the error code
 .macro SAVE_REGS
-    pusha
+    pushal
     push %ds
     push %es
     push %fs
     push %gs
-    mov $0x10, %ax   # Load the Data Segment descriptor!
+    mov $0x10, %ax   # Load the kernel data segment
     mov %ax, %ds
-    mov %ax, %ss
-    mov $0x0, %ax
     mov %ax, %es
-    mov %ax, %fs 
-    mov %ax, %gs
 .endm
 
 .macro RESTORE_REGS
-    #pop %eax
     pop %gs
     pop %fs
     pop %es
     pop %ds
-    popa
+    popal
 .endm
 
 
-isr_no_err_stub 0
-isr_no_err_stub 1
-isr_no_err_stub 2
-isr_no_err_stub 3
-isr_no_err_stub 4
-isr_no_err_stub 5
-isr_no_err_stub 6
-isr_no_err_stub 7
-isr_err_stub    8
-isr_no_err_stub 9
-isr_err_stub    10
-isr_err_stub    11
-isr_err_stub    12
-isr_err_stub    13
-isr_err_stub    14
-isr_no_err_stub 15
-isr_no_err_stub 16
-isr_err_stub    17
-isr_no_err_stub 18
-isr_no_err_stub 19
-isr_no_err_stub 20
-isr_no_err_stub 21
-isr_no_err_stub 22
-isr_no_err_stub 23
-isr_no_err_stub 24
-isr_no_err_stub 25
-isr_no_err_stub 26
-isr_no_err_stub 27
-isr_no_err_stub 28
-isr_no_err_stub 29
-isr_err_stub    30
-isr_no_err_stub 31
-
-
-IRQ 0
-IRQ 1
-IRQ 2
-IRQ 3
-IRQ 4
-IRQ 5
-IRQ 6
-IRQ 7
-IRQ 8
-IRQ 9
-IRQ 10
-IRQ 11
-IRQ 12
-IRQ 13
-IRQ 14
-IRQ 15
-
-irq_common_stub:
+# Exceptions and interrupts that can come from user mode, `handler` gets the TrapFrame.
+# The CPU pushes an error code for some exceptions only, the others get a 0 in its place.
+.macro TRAP name, handler, error_code=0
+.global \name
+
+\name:
+.if \error_code == 0
+    push $0
+.endif
 SAVE_REGS
-    mov $irq_handler, %eax
-    call *%eax
-RESTORE_REGS
-    add $8, %esp
-    iret
+    push %esp
+    call \handler
+    add $4, %esp
+    jmp trap_return
+.endm
+
+TRAP divide_by_zero_stub, divide_by_zero
+TRAP invalid_opcode_stub, invalid_opcode
+TRAP general_protection_stub, general_protection_fault, 1
+TRAP page_fault_stub, page_fault, 1
+TRAP timer_stub, timer_intp
+TRAP com1_stub, com1_intp
+TRAP primary_ata_stub, primary_ata_intp
+TRAP secondary_ata_stub, secondary_ata_intp
 
-isr_common_stub:
+
+# int 0x80, the syscall number is in eax and the arguments in ebx, ecx, edx, esi, edi
+# and ebp. The handler writes the return value to the saved eax, which popal restores.
+
+.global syscall_stub
+
+syscall_stub:
+    push %eax        # The syscall number takes the place of the error code
 SAVE_REGS
-    mov $exception_handler, %eax
-    call *%eax
+    sti
+    push %esp
+    call syscall_handler
+    add $4, %esp
+
+# Pending signals are delivered here before going back to user mode. A forked task also
+# starts here, with its copy of the parent's TrapFrame on top of its stack.
+.global trap_return
+
+trap_return:
+    cli
+    push %esp
+    call return_from_trap
+    add $4, %esp
 RESTORE_REGS
-    add $8, %esp
+    add $4, %esp     # Drop the error code
     iret
 
+
 .global load_idt
 
 load_idt:
@@ -1,14 +1,26 @@
-use crate::exceptions::interrupts::InterruptIndex;
+use crate::exceptions::interrupts::{InterruptIndex, SYSCALL};
 use crate::exceptions::interrupts::{
 	alignment_check, bound_range_exceeded, breakpoint, coprocessor_not_available,
-	coprocessor_segment_overrun, debug, divide_by_zero, double_fault, general_protection_fault,
-	invalid_opcode, invalid_task_state_segment, keybrd_intp, machine_check, math_fault,
-	non_maskable_intp, overflow, page_fault, reserved, segment_not_present,
-	simd_float_exception, stack_fault, syscall_intp, timer_intp,
-	virtualization_exception,
+	coprocessor_segment_overrun, debug, double_fault, invalid_task_state_segment, keybrd_intp,
+	machine_check, math_fault, non_maskable_intp, overflow, reserved, segment_not_present,
+	simd_float_exception, stack_fault, virtualization_exception,
 };
 use crate::tools::debug::LogLevel;
-use core::arch::asm;
+use core::arch::{asm, global_asm};
+
+global_asm!(include_str!("idt.s"), options(att_syntax));
+
+extern "C" {
+	fn divide_by_zero_stub();
+	fn invalid_opcode_stub();
+	fn general_protection_stub();
+	fn page_fault_stub();
+	fn timer_stub();
+	fn com1_stub();
+	fn primary_ata_stub();
+	fn secondary_ata_stub();
+	fn syscall_stub();
+}
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
@@ -32,8 +44,6 @@ macro_rules! idt_entry {
 	};
 }
 
-static DIVIDE_BY_ZERO: extern "C" fn() = handler!(divide_by_zero);
-
 static DEBUGG: extern "C" fn() = handler!(debug);
 
 static NON_MASKABLE_INTP: extern "C" fn() = handler!(non_maskable_intp);
@@ -44,8 +54,6 @@ static OVERFLOW: extern "C" fn() = handler!(overflow);
 
 static BOUND_RANGE_EXCEEDED: extern "C" fn() = handler!(bound_range_exceeded);
 
-static INVALID_OPCODE: extern "C" fn() = handler!(invalid_opcode);
-
 static COPROCESSOR_NOT_AVAILABLE: extern "C" fn() = handler!(coprocessor_not_available);
 
 static DOUBLE_FAULT: extern "C" fn() = handler!(double_fault);
@@ -59,10 +67,6 @@ static SEGMENT_NOT_PRESENT: extern "C" fn() = handler!(segment_not_present);
 
 static STACK_FAULT: extern "C" fn() = handler!(stack_fault);
 
-static GENERAL_PROTECTION_FAULT: extern "C" fn() = handler!(general_protection_fault);
-
-static PAGE_FAULT: extern "C" fn() = handler_with_error_code!(page_fault);
-
 static RESERVED: extern "C" fn() = handler!(reserved);
 
 static MATH_FAULT: extern "C" fn() = handler!(math_fault);
@@ -75,12 +79,8 @@ static SIMD_FLOAT_EXCEPTION: extern "C" fn() = handler!(simd_float_exception);
 
 static VIRTUALIZATION_EXCEPTION: extern "C" fn() = handler!(virtualization_exception);
 
-static TIMER_INTP: extern "C" fn() = handler!(timer_intp);
-
 static KEYBRD_INTP: extern "C" fn() = handler!(keybrd_intp);
 
-static SYSCALL: extern "C" fn() = handler!(syscall_intp);
-
 #[link_section = ".idt"]
 static LOW_IDT: [IdtDesc; 256] = {
 	let idt = [idt_entry!(0, 0, 0); 256];
@@ -100,31 +100,36 @@ unsafe fn fill_idt() {
     }
 	let idt = unsafe { &mut *IDT };
 
-	idt[0] = idt_entry!(DIVIDE_BY_ZERO as u32, 0x08, 0x8e);
+	idt[0] = idt_entry!(divide_by_zero_stub as u32, 0x08, 0x8e);
 	idt[1] = idt_entry!(DEBUGG as u32, 0x08, 0x8e);
 	idt[2] = idt_entry!(NON_MASKABLE_INTP as u32, 0x08, 0x8e);
 	idt[3] = idt_entry!(BREAKPOINT as u32, 0x08, 0x8e);
 	idt[4] = idt_entry!(OVERFLOW as u32, 0x08, 0x8e);
 	idt[5] = idt_entry!(BOUND_RANGE_EXCEEDED as u32, 0x08, 0x8e);
-	idt[6] = idt_entry!(INVALID_OPCODE as u32, 0x08, 0x8e);
+	idt[6] = idt_entry!(invalid_opcode_stub as u32, 0x08, 0x8e);
 	idt[7] = idt_entry!(COPROCESSOR_NOT_AVAILABLE as u32, 0x08, 0x8e);
 	idt[8] = idt_entry!(DOUBLE_FAULT as u32, 0x08, 0x8e);
 	idt[9] = idt_entry!(COPROCESSOR_SEGMENT_OVERRUN as u32, 0x08, 0x8e);
 	idt[10] = idt_entry!(INVALID_TASK_STATE_SEGMENT as u32, 0x08, 0x8e);
 	idt[11] = idt_entry!(SEGMENT_NOT_PRESENT as u32, 0x08, 0x8e);
 	idt[12] = idt_entry!(STACK_FAULT as u32, 0x08, 0x8e);
-	idt[13] = idt_entry!(GENERAL_PROTECTION_FAULT as u32, 0x08, 0x8e);
-	idt[14] = idt_entry!(PAGE_FAULT as u32, 0x08, 0x8e);
+	idt[13] = idt_entry!(general_protection_stub as u32, 0x08, 0x8e);
+	idt[14] = idt_entry!(page_fault_stub as u32, 0x08, 0x8e);
 	idt[15] = idt_entry!(RESERVED as u32, 0x08, 0x8e);
 	idt[16] = idt_entry!(MATH_FAULT as u32, 0x08, 0x8e);
 	idt[17] = idt_entry!(ALIGNMENT_CHECK as u32, 0x08, 0x8e);
 	idt[18] = idt_entry!(MACHINE_CHECK as u32, 0x08, 0x8e);
 	idt[19] = idt_entry!(SIMD_FLOAT_EXCEPTION as u32, 0x08, 0x8e);
 	idt[20] = idt_entry!(VIRTUALIZATION_EXCEPTION as u32, 0x08, 0x8e);
-	idt[InterruptIndex::Timer.as_usize()] = idt_entry!(TIMER_INTP as u32, 0x08, 0x8e);
+	idt[InterruptIndex::Timer.as_usize()] = idt_entry!(timer_stub as u32, 0x08, 0x8e);
 	idt[InterruptIndex::Keyboard.as_usize()] =
 		idt_entry!(KEYBRD_INTP as u32, 0x08, 0x8e);
-	idt[0x80] = idt_entry!(SYSCALL as u32, 0x08, 0xee);
+	idt[InterruptIndex::Com1.as_usize()] = idt_entry!(com1_stub as u32, 0x08, 0x8e);
+	idt[InterruptIndex::PrimaryAtaHardDisk.as_usize()] =
+		idt_entry!(primary_ata_stub as u32, 0x08, 0x8e);
+	idt[InterruptIndex::SecondaryAtaHardDisk.as_usize()] =
+		idt_entry!(secondary_ata_stub as u32, 0x08, 0x8e);
+	idt[SYSCALL as usize] = idt_entry!(syscall_stub as u32, 0x08, 0xee);
 }
 
 pub fn init() {
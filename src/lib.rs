@@ -2,6 +2,9 @@
 #![no_main]
 #![feature(panic_info_message)]
 #![feature(naked_functions)]
+#![feature(alloc_error_handler)]
+extern crate alloc;
+
 #[macro_use]
 mod macros;
 mod tools;
@@ -11,10 +14,14 @@ mod idt;
 mod memory;
 mod exceptions;
 mod multiboot;
+mod task;
+mod fs;
+mod drivers;
 
 use crate::shell::prints;
 use crate::tools::debug;
 use crate::tools::librs::hlt;
+use core::alloc::Layout;
 use core::panic::PanicInfo;
 use exceptions::{interrupts, keyboard::process_keyboard_input, panic::handle_panic};
 use crate::memory::kmem_managment::HK_OFST;
@@ -27,11 +34,20 @@ pub extern "C" fn _start(multiboot_magic: u32, multiboot_addr: u32) -> ! {
 
 #[no_mangle]
 pub extern "C" fn main() -> ! {
+    task::scheduler::spawn("shell", shell).expect("Failed to start the shell");
     loop {
-        process_keyboard_input();
+        task::scheduler::reap();
         hlt();
     }
+}
 
+fn shell() {
+    shell::serial::init();
+    loop {
+        process_keyboard_input();
+        shell::serial::process_serial_input();
+        hlt();
+    }
 }
 
 fn init(multiboot_magic: u32, multiboot_addr: u32) {
@@ -45,12 +61,46 @@ fn init(multiboot_magic: u32, multiboot_addr: u32) {
     memory::kmem_managment::kmem_manager_init();
     unsafe { memory::page_directory::init_page_directory() };
     memory::page_directory::enable_paging();
+    unsafe { memory::kmalloc::kheap_init() };
+    unsafe { memory::vmalloc::vheap_init() };
     prints::print_welcome_message();
 	memory::vmalloc::vmalloc_test();
 	memory::kmalloc::kmalloc_test();
+	memory::buddy::buddy_test();
+	memory::slab::slab_test();
+	memory::address_space::address_space_test();
+	task::scheduler::init();
+	drivers::ata::init();
+	drivers::serial::init();
+	drivers::mem::init();
+	drivers::tty::init();
+	fs::init();
+	task::scheduler::scheduler_test();
+	task::elf::elf_test();
+	memory::uaccess::uaccess_test();
+	exceptions::syscalls::syscall_test();
+	exceptions::syscalls::process::process_test();
+	task::signal::signal_test();
+	fs::vfs_test();
+	fs::tmpfs::tmpfs_test();
+	fs::initramfs::initramfs_test();
+	fs::buffer::buffer_test();
+	fs::ext2::ext2_test();
+	fs::fat::fat_test();
+	fs::procfs::procfs_test();
+	fs::devfs::devfs_test();
+	drivers::ata::ata_test();
+	drivers::serial::serial_test();
+	drivers::tty::tty_test();
+	tools::vga::vga_test();
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
 	handle_panic(info, None);
 }
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+	handle_panic(&format_args!("Kernel heap exhausted: {:?}", layout), None);
+}
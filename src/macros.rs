@@ -1,5 +1,7 @@
 use crate::debug::DEBUG;
+use crate::drivers::serial;
 use crate::exceptions::interrupts;
+use crate::tools::debug::LogLevel;
 use crate::tools::vga::{WriteMode, WRITER};
 use core::fmt;
 
@@ -30,9 +32,13 @@ macro_rules! println_srl {
 #[macro_export]
 macro_rules! log {
     ($level:expr, $($arg:tt)*) => {{
-        let level_str = $level.as_str();
-        $crate::macros::print_srl(format_args!("{}", level_str));
-        $crate::macros::print_srl(format_args!(": {}\n", format_args!($($arg)*)));
+        let level = $level;
+        $crate::macros::log(&level, format_args!(
+            "\x1b[{}m{}\x1b[0m: {}\n",
+            level.color(),
+            level.as_str(),
+            format_args!($($arg)*)
+        ));
     }};
 }
 
@@ -93,8 +99,8 @@ macro_rules! handler_with_error_code {
 					"pop eax",
 					"pop edx",
 					"popad",
-                    "add esp, 4",
                     "pop ebp",
+                    "add esp, 4",
                     "iretd",
                     sym $name,
                     options(noreturn)
@@ -107,24 +113,37 @@ macro_rules! handler_with_error_code {
 
 pub fn print(args: fmt::Arguments) {
 	use core::fmt::Write;
-	interrupts::disable();
-	let mut writer = WRITER.lock();
-	writer.set_mode(WriteMode::Normal);
-	writer.write_fmt(args).unwrap();
-	interrupts::enable();
+	interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		writer.set_mode(WriteMode::Normal);
+		writer.write_fmt(args).unwrap();
+		if serial::is_console() {
+			serial::ConsoleWriter.write_fmt(args).unwrap();
+		}
+	});
 }
 
 pub fn print_srl(args: fmt::Arguments) {
+	write_log(args, !serial::is_console());
+}
+
+// Errors still reach COM1 when it runs the console, the rest of the log would land in the
+// middle of the session and only goes to the log display
+pub fn log(level: &LogLevel, args: fmt::Arguments) {
+	write_log(args, !serial::is_console() || level.is_error());
+}
+
+fn write_log(args: fmt::Arguments, to_port: bool) {
 	use core::fmt::Write;
-	interrupts::disable();
-	let mut debug = DEBUG.lock();
-	let mut writer = WRITER.lock();
-
-	debug.write_fmt(args).expect("Printing to srl failed");
-	writer.set_mode(WriteMode::Srl);
-	writer
-		.write_fmt(args)
-		.expect("Writing to srl screen failed");
-	writer.set_mode(WriteMode::Normal);
-	interrupts::enable();
+	interrupts::without_interrupts(|| {
+		if to_port {
+			DEBUG.lock().write_fmt(args).expect("Printing to srl failed");
+		}
+		let mut writer = WRITER.lock();
+		writer.set_mode(WriteMode::Srl);
+		writer
+			.write_fmt(args)
+			.expect("Writing to srl screen failed");
+		writer.set_mode(WriteMode::Normal);
+	});
 }
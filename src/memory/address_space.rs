@@ -0,0 +1,641 @@
+//! # Address Spaces
+//!
+//! An `AddressSpace` is a page directory of its own: the user half (entries 0..768) is private
+//! and filled on demand, the kernel half (entries 768..1024) is copied from the kernel page
+//! directory. Every kernel page table is preallocated by `init_page_directory`, so kernel
+//! mappings added later are seen by every address space without any synchronisation.
+//!
+//! Page directories and user page tables come from the `page_table` slab cache and are reached
+//! through the low memory mapping. Dropping an address space frees every user frame it maps.
+//!
+//! The user half is described by a list of VMAs; `handle_page_fault` only populates pages
+//! that fall inside one of them, with the permissions of that VMA.
+
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use lazy_static::lazy_static;
+
+use super::kmem_managment::{HK_OFST, PMM};
+use super::page_directory::{
+	current_directory, flush_tlb_entry, kernel_directory, load_directory, PageDirectory,
+	ENTRY_COUNT, PAGE_DIRECTORY, PAGE_SIZE,
+};
+use super::page_directory_entry::PageDirectoryFlags;
+use super::page_table::PageTable;
+use super::page_table_entry::{FlagTablePages, PageTableEntry};
+use super::slab::KmemCache;
+use super::vma::{Vma, VmaBacking, VmaFlags, MAX_STACK_SIZE};
+use crate::tools::debug::LogLevel;
+
+pub const USER_SPACE_END: u32 = HK_OFST;
+const KERNEL_FIRST_ENTRY: usize = (HK_OFST >> 22) as usize;
+
+lazy_static! {
+	pub static ref PAGE_TABLE_CACHE: &'static KmemCache =
+		KmemCache::new("page_table", PAGE_SIZE, PAGE_SIZE);
+}
+
+// Address space whose directory is in CR3, null while the kernel directory is loaded
+static ACTIVE: AtomicPtr<AddressSpace> = AtomicPtr::new(null_mut());
+
+bitflags! {
+	pub struct PageFaultError: u32 {
+		const PRESENT = 0b1;
+		const WRITE = 0b10;
+		const USER = 0b100;
+		const RESERVED = 0b1000;
+		const INSTRUCTION_FETCH = 0b1_0000;
+	}
+}
+
+pub struct AddressSpace {
+	directory: *mut PageDirectory,
+	directory_address: u32,
+	vmas: Vec<Vma>,
+	// The heap VMA spans brk_start up to brk rounded to a page
+	brk_start: u32,
+	brk: u32,
+}
+
+unsafe impl Send for AddressSpace {}
+
+fn alloc_table_page() -> Result<*mut u8, &'static str> {
+	let page = PAGE_TABLE_CACHE
+		.alloc()
+		.ok_or("Failed to allocate a page table")?;
+	unsafe { page.write_bytes(0, PAGE_SIZE) };
+	Ok(page)
+}
+
+impl AddressSpace {
+	pub fn new() -> Result<AddressSpace, &'static str> {
+		let directory = alloc_table_page()? as *mut PageDirectory;
+		let kernel_directory = unsafe { &*PAGE_DIRECTORY.load(Ordering::Relaxed) };
+		let entries = unsafe { &mut (*directory).entries };
+
+		entries[KERNEL_FIRST_ENTRY..ENTRY_COUNT]
+			.copy_from_slice(&kernel_directory.entries[KERNEL_FIRST_ENTRY..ENTRY_COUNT]);
+
+		Ok(AddressSpace {
+			directory,
+			directory_address: directory as u32 - HK_OFST,
+			vmas: Vec::new(),
+			brk_start: 0,
+			brk: 0,
+		})
+	}
+
+	pub fn directory_address(&self) -> u32 {
+		self.directory_address
+	}
+
+	// The address space must not move while it is active, owners keep it boxed
+	pub fn activate(&self) {
+		load_directory(self.directory_address);
+		ACTIVE.store(self as *const AddressSpace as *mut AddressSpace, Ordering::SeqCst);
+	}
+
+	pub fn is_active(&self) -> bool {
+		current_directory() == self.directory_address
+	}
+
+	fn directory(&self) -> &mut PageDirectory {
+		unsafe { &mut *self.directory }
+	}
+
+	// Page table entry of a user address, if its page table exists
+	pub fn entry(&self, virtual_address: u32) -> Option<&mut PageTableEntry> {
+		if virtual_address >= USER_SPACE_END {
+			return None;
+		}
+		let directory_entry = &self.directory().entries[(virtual_address >> 22) as usize];
+		if !directory_entry.present() {
+			return None;
+		}
+		Some(
+			directory_entry
+				.get_page_table()
+				.get_page_table_entry(virtual_address),
+		)
+	}
+
+	fn entry_or_create(&mut self, virtual_address: u32) -> Result<&mut PageTableEntry, &'static str> {
+		if virtual_address >= USER_SPACE_END {
+			return Err("Address outside of user space");
+		}
+		let directory_entry = &mut self.directory().entries[(virtual_address >> 22) as usize];
+		if !directory_entry.present() {
+			let table = alloc_table_page()?;
+			directory_entry.set(
+				table as u32,
+				PageDirectoryFlags::PRESENT | PageDirectoryFlags::WRITABLE | PageDirectoryFlags::USER,
+			);
+		}
+		Ok(directory_entry
+			.get_page_table()
+			.get_page_table_entry(virtual_address))
+	}
+
+	pub fn flush(&self, virtual_address: u32) {
+		if self.is_active() {
+			flush_tlb_entry(virtual_address);
+		}
+	}
+
+	pub fn map_page(
+		&mut self,
+		virtual_address: u32,
+		frame: u32,
+		flags: FlagTablePages,
+	) -> Result<(), &'static str> {
+		let entry = self.entry_or_create(virtual_address)?;
+		entry.set_frame_address(
+			frame & FlagTablePages::FRAME.bits(),
+			(flags | FlagTablePages::PRESENT) - FlagTablePages::FRAME,
+		);
+		self.flush(virtual_address);
+		Ok(())
+	}
+
+	// Maps a fresh zeroed frame at `virtual_address` and returns its physical address
+	pub fn map_new_page(
+		&mut self,
+		virtual_address: u32,
+		flags: FlagTablePages,
+	) -> Result<u32, &'static str> {
+		let frame = PMM.lock().allocate_frame()?;
+		unsafe { ((frame + HK_OFST) as *mut u8).write_bytes(0, PAGE_SIZE) };
+		if let Err(e) = self.map_page(virtual_address, frame, flags) {
+			PMM.lock().deallocate_frame(frame);
+			return Err(e);
+		}
+		Ok(frame)
+	}
+
+	// Removes the mapping and returns the frame, the caller decides what to do with it
+	pub fn unmap_page(&mut self, virtual_address: u32) -> Option<u32> {
+		let entry = self.entry(virtual_address)?;
+		if !entry.present() {
+			return None;
+		}
+		let frame = entry.frame();
+		entry.clear();
+		self.flush(virtual_address);
+		Some(frame)
+	}
+
+	pub fn translate(&self, virtual_address: u32) -> Option<u32> {
+		let entry = self.entry(virtual_address)?;
+		if !entry.present() {
+			return None;
+		}
+		Some(entry.frame() | (virtual_address & (PAGE_SIZE as u32 - 1)))
+	}
+
+	pub fn vmas(&self) -> &[Vma] {
+		&self.vmas
+	}
+
+	pub fn find_vma(&self, address: u32) -> Option<&Vma> {
+		self.vmas.iter().find(|vma| vma.contains(address))
+	}
+
+	pub fn add_vma(&mut self, vma: Vma) -> Result<(), &'static str> {
+		if vma.start >= vma.end || vma.end > USER_SPACE_END {
+			return Err("Invalid VMA range");
+		}
+		if self.vmas.iter().any(|other| other.overlaps(vma.start, vma.end)) {
+			return Err("VMA overlaps an existing mapping");
+		}
+		let index = self
+			.vmas
+			.iter()
+			.position(|other| other.start > vma.start)
+			.unwrap_or(self.vmas.len());
+		self.vmas.insert(index, vma);
+		Ok(())
+	}
+
+	// Removes the VMA starting at `start` and frees the pages it populated
+	pub fn remove_vma(&mut self, start: u32) -> Option<Vma> {
+		let index = self.vmas.iter().position(|vma| vma.start == start)?;
+		let vma = self.vmas.remove(index);
+		let mut address = vma.start;
+		while address < vma.end {
+			if let Some(frame) = self.unmap_page(address) {
+				PMM.lock().deallocate_frame(frame);
+			}
+			address += PAGE_SIZE as u32;
+		}
+		Some(vma)
+	}
+
+	// Splits the VMA containing `address` so that one of its parts starts at `address`
+	fn split_vma_at(&mut self, address: u32) {
+		if let Some(index) = self
+			.vmas
+			.iter()
+			.position(|vma| vma.contains(address) && vma.start != address)
+		{
+			let upper = self.vmas[index].split_off(address);
+			self.vmas.insert(index + 1, upper);
+		}
+	}
+
+	// Removes every mapping in `start..end`, VMAs crossing the bounds are split
+	pub fn unmap_range(&mut self, start: u32, end: u32) {
+		let start = start & !(PAGE_SIZE as u32 - 1);
+		let end = end.next_multiple_of(PAGE_SIZE as u32).min(USER_SPACE_END);
+		if start >= end {
+			return;
+		}
+		self.split_vma_at(start);
+		self.split_vma_at(end);
+
+		let mut index = 0;
+		while index < self.vmas.len() {
+			if self.vmas[index].start >= start && self.vmas[index].end <= end {
+				let vma = self.vmas[index].clone();
+				self.remove_vma(vma.start);
+			} else {
+				index += 1;
+			}
+		}
+	}
+
+	// Highest page aligned free range of `size` bytes ending at or below `top`
+	pub fn find_free_range(&self, size: u32, top: u32) -> Option<u32> {
+		let size = size.next_multiple_of(PAGE_SIZE as u32);
+		let mut end = top & !(PAGE_SIZE as u32 - 1);
+		for vma in self.vmas.iter().rev() {
+			if vma.end <= end && end - vma.end >= size {
+				return Some(end - size);
+			}
+			end = end.min(vma.start);
+		}
+		// Page 0 stays unmapped so null pointers always fault
+		(end >= size + PAGE_SIZE as u32).then(|| end - size)
+	}
+
+	// Sets where the heap starts, usually the end of the highest ELF segment
+	pub fn init_brk(&mut self, start: u32) {
+		self.brk_start = start;
+		self.brk = start;
+	}
+
+	pub fn current_brk(&self) -> u32 {
+		self.brk
+	}
+
+	// Moves the program break, returns the new break or the current one if it cannot move
+	pub fn brk(&mut self, new_brk: u32) -> u32 {
+		if self.brk_start == 0 || new_brk < self.brk_start {
+			return self.brk;
+		}
+		let old_end = self.brk.next_multiple_of(PAGE_SIZE as u32);
+		let new_end = match new_brk.checked_next_multiple_of(PAGE_SIZE as u32) {
+			Some(end) if end <= USER_SPACE_END => end,
+			_ => return self.brk,
+		};
+
+		if new_end > old_end {
+			if self.vmas.iter().any(|vma| vma.overlaps(old_end, new_end)) {
+				return self.brk;
+			}
+			match self.vmas.iter_mut().find(|vma| vma.start == self.brk_start) {
+				Some(heap) => heap.end = new_end,
+				None => {
+					let heap = Vma::new(
+						self.brk_start,
+						new_end,
+						VmaFlags::READ | VmaFlags::WRITE,
+						VmaBacking::Anonymous,
+					);
+					if self.add_vma(heap).is_err() {
+						return self.brk;
+					}
+				}
+			}
+		} else if new_end < old_end {
+			self.unmap_range(new_end, old_end);
+		}
+		self.brk = new_brk;
+		self.brk
+	}
+
+	// Index of the stack VMA that may grow down to `address`
+	fn growable_stack(&self, address: u32) -> Option<usize> {
+		let index = self
+			.vmas
+			.iter()
+			.position(|vma| vma.is_stack() && address < vma.start)?;
+		let vma = &self.vmas[index];
+		let previous_end = if index > 0 { self.vmas[index - 1].end } else { 0 };
+		if address < vma.end.saturating_sub(MAX_STACK_SIZE) || address < previous_end {
+			return None;
+		}
+		Some(index)
+	}
+
+	// Populates the page behind a fault, returns false when the access is not allowed
+	pub fn handle_page_fault(&mut self, address: u32, error: PageFaultError) -> bool {
+		if address >= USER_SPACE_END || error.contains(PageFaultError::RESERVED) {
+			return false;
+		}
+
+		let index = match self.vmas.iter().position(|vma| vma.contains(address)) {
+			Some(index) => index,
+			None => match self.growable_stack(address) {
+				Some(index) => {
+					self.vmas[index].start = address & !(PAGE_SIZE as u32 - 1);
+					index
+				}
+				None => return false,
+			},
+		};
+
+		let vma = &self.vmas[index];
+		if error.contains(PageFaultError::WRITE) && !vma.flags.contains(VmaFlags::WRITE) {
+			return false;
+		}
+
+		let page = address & !(PAGE_SIZE as u32 - 1);
+		if error.contains(PageFaultError::PRESENT) {
+			return error.contains(PageFaultError::WRITE) && self.handle_cow_fault(page);
+		}
+
+		let frame = match PMM.lock().allocate_frame() {
+			Ok(frame) => frame,
+			Err(e) => {
+				log!(LogLevel::Error, "Failed to allocate frame for page {:#x}: {}", page, e);
+				return false;
+			}
+		};
+		vma.fill_page(page, frame);
+		let flags = vma.page_flags();
+		if let Err(e) = self.map_page(page, frame, flags) {
+			log!(LogLevel::Error, "Failed to map page {:#x}: {}", page, e);
+			PMM.lock().deallocate_frame(frame);
+			return false;
+		}
+		true
+	}
+
+	// Maps the page behind `address` the way a user access would, returns its frame
+	fn populate(&mut self, address: u32, write: bool) -> Option<u32> {
+		let mut error = PageFaultError::USER;
+		if write {
+			error |= PageFaultError::WRITE;
+		}
+		if let Some(entry) = self.entry(address) {
+			if entry.present() {
+				if !write || entry.flags().contains(FlagTablePages::WRITABLE) {
+					return Some(entry.frame());
+				}
+				error |= PageFaultError::PRESENT;
+			}
+		}
+		if !self.handle_page_fault(address, error) {
+			return None;
+		}
+		self.entry(address).map(|entry| entry.frame())
+	}
+
+	// Calls `f` with the kernel address, buffer offset and length of every page chunk
+	fn for_each_chunk<F: FnMut(*mut u8, usize, usize)>(
+		&mut self,
+		address: u32,
+		len: usize,
+		write: bool,
+		mut f: F,
+	) -> Result<(), &'static str> {
+		let mut done = 0;
+		while done < len {
+			let current = address
+				.checked_add(done as u32)
+				.ok_or("Invalid user address")?;
+			let offset = current as usize & (PAGE_SIZE - 1);
+			let chunk = (PAGE_SIZE - offset).min(len - done);
+			let frame = self.populate(current, write).ok_or("Invalid user address")?;
+			f(((frame + HK_OFST) as usize + offset) as *mut u8, done, chunk);
+			done += chunk;
+		}
+		Ok(())
+	}
+
+	// Copies `data` to user memory, the address space does not need to be active
+	pub fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), &'static str> {
+		self.for_each_chunk(address, data.len(), true, |page, offset, len| unsafe {
+			core::ptr::copy_nonoverlapping(data.as_ptr().add(offset), page, len);
+		})
+	}
+
+	pub fn read_bytes(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+		let destination = buffer.as_mut_ptr();
+		self.for_each_chunk(address, buffer.len(), false, |page, offset, len| unsafe {
+			core::ptr::copy_nonoverlapping(page, destination.add(offset), len);
+		})
+	}
+
+	// Gives a private copy of a copy-on-write page, or the page itself to its last user
+	fn handle_cow_fault(&mut self, page: u32) -> bool {
+		let entry = match self.entry(page) {
+			Some(entry) if entry.present() => entry,
+			_ => return false,
+		};
+		let flags = entry.flags();
+		if !flags.contains(FlagTablePages::COPY_ON_WRITE) {
+			return false;
+		}
+
+		let frame = entry.frame();
+		let writable_flags = (flags - FlagTablePages::COPY_ON_WRITE) | FlagTablePages::WRITABLE;
+		let mut pmm = PMM.lock();
+		if pmm.frame_refcount(frame) == 1 {
+			entry.set_frame_address(frame, writable_flags);
+		} else {
+			let copy = match pmm.allocate_frame() {
+				Ok(copy) => copy,
+				Err(e) => {
+					log!(LogLevel::Error, "Failed to copy page {:#x}: {}", page, e);
+					return false;
+				}
+			};
+			unsafe {
+				core::ptr::copy_nonoverlapping(
+					(frame + HK_OFST) as *const u8,
+					(copy + HK_OFST) as *mut u8,
+					PAGE_SIZE,
+				);
+			}
+			entry.set_frame_address(copy, writable_flags);
+			pmm.deallocate_frame(frame);
+		}
+		drop(pmm);
+		self.flush(page);
+		true
+	}
+
+	// Duplicates the address space, writable pages end up shared copy-on-write by both sides
+	pub fn fork(&self) -> Result<AddressSpace, &'static str> {
+		let mut child = AddressSpace::new()?;
+		child.vmas = self.vmas.clone();
+		child.brk_start = self.brk_start;
+		child.brk = self.brk;
+
+		let mut result = Ok(());
+		self.for_each_page(|address, entry| {
+			if result.is_err() {
+				return;
+			}
+			let mut flags = entry.flags();
+			if flags.contains(FlagTablePages::WRITABLE) {
+				flags = (flags - FlagTablePages::WRITABLE) | FlagTablePages::COPY_ON_WRITE;
+				entry.set_flags(flags);
+			}
+			PMM.lock().get_frame(entry.frame());
+			result = child.map_page(address, entry.frame(), flags);
+			if result.is_err() {
+				PMM.lock().deallocate_frame(entry.frame());
+			}
+		});
+
+		if self.is_active() {
+			load_directory(self.directory_address);
+		}
+		result.map(|_| child)
+	}
+
+	// Calls `f` with the address and entry of every present user page
+	pub fn for_each_page<F: FnMut(u32, &mut PageTableEntry)>(&self, mut f: F) {
+		for (directory_index, directory_entry) in self.directory().entries[..KERNEL_FIRST_ENTRY]
+			.iter()
+			.enumerate()
+		{
+			if !directory_entry.present() {
+				continue;
+			}
+			let table: &mut PageTable = directory_entry.get_page_table();
+			for (table_index, entry) in table.entries.iter_mut().enumerate() {
+				if entry.present() {
+					f(((directory_index << 22) | (table_index << 12)) as u32, entry);
+				}
+			}
+		}
+	}
+}
+
+// Loads the kernel page directory, no user address space is active afterwards
+pub fn switch_to_kernel() {
+	load_directory(kernel_directory());
+	ACTIVE.store(null_mut(), Ordering::SeqCst);
+}
+
+pub fn current() -> Option<&'static mut AddressSpace> {
+	unsafe { ACTIVE.load(Ordering::SeqCst).as_mut() }
+}
+
+impl Drop for AddressSpace {
+	fn drop(&mut self) {
+		if self.is_active() || ACTIVE.load(Ordering::SeqCst) == self as *mut AddressSpace {
+			switch_to_kernel();
+		}
+
+		let mut frames = 0;
+		for directory_entry in self.directory().entries[..KERNEL_FIRST_ENTRY].iter_mut() {
+			if !directory_entry.present() {
+				continue;
+			}
+			let table: &mut PageTable = directory_entry.get_page_table();
+			for entry in table.entries.iter_mut().filter(|entry| entry.present()) {
+				PMM.lock().deallocate_frame(entry.frame());
+				frames += 1;
+			}
+			PAGE_TABLE_CACHE.free(table as *mut PageTable as *mut u8);
+			directory_entry.clear();
+		}
+		PAGE_TABLE_CACHE.free(self.directory as *mut u8);
+		log!(
+			LogLevel::Debug,
+			"Address space {:#x} released, {} user frames freed",
+			self.directory_address,
+			frames
+		);
+	}
+}
+
+pub fn address_space_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting address spaces\n");
+
+	lazy_static::initialize(&PAGE_TABLE_CACHE);
+	let free_before = PMM.lock().free_frames();
+
+	let mut first = AddressSpace::new().expect("Failed to create address space");
+	let mut second = AddressSpace::new().expect("Failed to create address space");
+	let flags = FlagTablePages::USER | FlagTablePages::WRITABLE;
+
+	log!(LogLevel::Info, "Mapping the same user address in two address spaces\n");
+	first.map_new_page(0x400000, flags).expect("Failed to map page");
+	second.map_new_page(0x400000, flags).expect("Failed to map page");
+	second.map_new_page(0xBFFFF000, flags).expect("Failed to map page");
+	assert!(first.translate(0x400000) != second.translate(0x400000));
+	assert!(first.translate(0xBFFFF000).is_none());
+
+	log!(LogLevel::Info, "Writing through each address space\n");
+	first.activate();
+	unsafe { (0x400000 as *mut u32).write_volatile(0x11111111) };
+	second.activate();
+	unsafe { (0x400000 as *mut u32).write_volatile(0x22222222) };
+	first.activate();
+	assert!(unsafe { (0x400000 as *const u32).read_volatile() } == 0x11111111);
+	switch_to_kernel();
+
+	log!(LogLevel::Info, "Forking an address space and writing to the shared page\n");
+	// Copy on write faults are resolved through the VMA of the page
+	first
+		.add_vma(Vma::new(0x400000, 0x401000, VmaFlags::READ | VmaFlags::WRITE, VmaBacking::Anonymous))
+		.expect("Failed to add VMA");
+	let third = first.fork().expect("Failed to fork address space");
+	let shared_frame = first.translate(0x400000).unwrap();
+	assert!(third.translate(0x400000) == Some(shared_frame));
+	assert!(PMM.lock().frame_refcount(shared_frame) == 2);
+	third.activate();
+	unsafe { (0x400000 as *mut u32).write_volatile(0x55555555) };
+	assert!(third.translate(0x400000) != Some(shared_frame));
+	assert!(PMM.lock().frame_refcount(shared_frame) == 1);
+	first.activate();
+	assert!(unsafe { (0x400000 as *const u32).read_volatile() } == 0x11111111);
+	unsafe { (0x400000 as *mut u32).write_volatile(0x66666666) };
+	assert!(first.translate(0x400000) == Some(shared_frame));
+	switch_to_kernel();
+	drop(third);
+
+	log!(LogLevel::Info, "Touching pages of an anonymous VMA and a stack VMA\n");
+	first
+		.add_vma(Vma::new(0x800000, 0x802000, VmaFlags::READ | VmaFlags::WRITE, VmaBacking::Anonymous))
+		.expect("Failed to add VMA");
+	first
+		.add_vma(Vma::new(0xBFFFE000, 0xC0000000, VmaFlags::READ | VmaFlags::WRITE, VmaBacking::Stack))
+		.expect("Failed to add VMA");
+	first.activate();
+	unsafe {
+		(0x801ffc as *mut u32).write_volatile(0x33333333);
+		(0xBFFF0000 as *mut u32).write_volatile(0x44444444);
+	}
+	assert!(first.translate(0x801000).is_some());
+	assert!(first.translate(0x800000).is_none());
+	assert!(first.find_vma(0xBFFF0000).is_some_and(|vma| vma.start == 0xBFFF0000));
+	assert!(!first.handle_page_fault(0x900000, PageFaultError::WRITE));
+	switch_to_kernel();
+
+	log!(LogLevel::Info, "Dropping both address spaces, every frame should be back\n");
+	drop(first);
+	drop(second);
+	PAGE_TABLE_CACHE.shrink();
+	assert!(PMM.lock().free_frames() == free_before);
+
+	log!(LogLevel::Info, "\t\tEnd of address spaces test\n");
+}
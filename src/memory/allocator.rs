@@ -0,0 +1,129 @@
+//! # Kernel Global Allocator
+//!
+//! Plugs the kernel heaps into the `alloc` crate so `Box`, `Vec`, `String` and `BTreeMap`
+//! can be used anywhere after `kheap_init()`. Small layouts are served by `kmalloc`, layouts
+//! that do not fit in a kmalloc block fall back to `vmalloc`.
+//!
+//! Both heaps return 16 byte aligned blocks and know nothing about `Layout::align`, so
+//! stricter alignments are handled here by over-allocating and storing the address returned
+//! by the heap just below the aligned pointer.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+use spin::Mutex;
+
+use super::kmalloc::{
+	kbrk, kextend, kfree, kmalloc, kmalloc_usage, KMALLOC_ALIGN, KMALLOC_MAX_SIZE,
+};
+use super::vmalloc::{kfree as vfree, vmalloc, vmalloc_usage};
+use crate::exceptions::interrupts;
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+static HEAP_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct KernelAllocator;
+
+// Bytes mapped for a heap and bytes handed out from it
+#[derive(Clone, Copy)]
+pub struct HeapUsage {
+	pub mapped: usize,
+	pub used: usize,
+}
+
+// Usage of the kmalloc and vmalloc heaps
+pub fn heap_usage() -> (HeapUsage, HeapUsage) {
+	interrupts::without_interrupts(|| {
+		let _guard = HEAP_LOCK.lock();
+		let usage = |(mapped, used)| HeapUsage { mapped, used };
+		unsafe { (usage(kmalloc_usage()), usage(vmalloc_usage())) }
+	})
+}
+
+impl KernelAllocator {
+	// Size actually requested from the heaps, including the room needed to realign
+	fn padded_size(layout: &Layout) -> usize {
+		if layout.align() <= KMALLOC_ALIGN {
+			layout.size()
+		} else {
+			layout.size() + layout.align()
+		}
+	}
+
+	unsafe fn alloc_raw(size: usize) -> *mut u8 {
+		if size > KMALLOC_MAX_SIZE {
+			return vmalloc(size).unwrap_or(null_mut());
+		}
+
+		if let Some(ptr) = kmalloc(size) {
+			return ptr;
+		}
+		kbrk(size as isize);
+		kmalloc(size).unwrap_or(null_mut())
+	}
+
+	unsafe fn free_raw(ptr: *mut u8, size: usize) {
+		if size > KMALLOC_MAX_SIZE {
+			vfree(ptr);
+		} else {
+			kfree(ptr);
+		}
+	}
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		// A task preempted while holding the heap lock would deadlock the scheduler
+		interrupts::without_interrupts(|| {
+			let _guard = HEAP_LOCK.lock();
+			let size = Self::padded_size(&layout);
+			let raw = Self::alloc_raw(size);
+
+			if raw.is_null() || layout.align() <= KMALLOC_ALIGN {
+				return raw;
+			}
+
+			// The heap pointer is at least 16 byte aligned, so there is always room
+			// for it between the raw block and the next aligned address
+			let aligned = (raw as usize + 1).next_multiple_of(layout.align()) as *mut u8;
+			(aligned as *mut usize).offset(-1).write(raw as usize);
+			aligned
+		})
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		interrupts::without_interrupts(|| {
+			let _guard = HEAP_LOCK.lock();
+			let raw = if layout.align() <= KMALLOC_ALIGN {
+				ptr
+			} else {
+				(ptr as *mut usize).offset(-1).read() as *mut u8
+			};
+			Self::free_raw(raw, Self::padded_size(&layout));
+		})
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		if layout.align() <= KMALLOC_ALIGN
+			&& layout.size() <= KMALLOC_MAX_SIZE
+			&& new_size <= KMALLOC_MAX_SIZE
+		{
+			let extended = interrupts::without_interrupts(|| {
+				let _guard = HEAP_LOCK.lock();
+				new_size <= layout.size() || kextend(ptr, new_size)
+			});
+			if extended {
+				return ptr;
+			}
+		}
+
+		let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+		let new_ptr = self.alloc(new_layout);
+		if !new_ptr.is_null() {
+			core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+			self.dealloc(ptr, layout);
+		}
+		new_ptr
+	}
+}
@@ -0,0 +1,196 @@
+//! # Buddy Frame Allocator
+//!
+//! Serves power of two runs of physical frames, from order 0 (one 4 KiB frame) up to
+//! `MAX_ORDER` (1024 frames, 4 MiB). Every order has a doubly linked free list threaded
+//! through a per-frame link array, so allocating and freeing never scan the memory map.
+//! Freed blocks are merged with their buddy as long as it is free and of the same order.
+//!
+//! The metadata lives right after the PMM bitmap and costs 9 bytes per frame.
+
+use core::mem::size_of;
+
+use super::kmem_managment::PMM;
+use crate::tools::debug::LogLevel;
+
+pub const MAX_ORDER: usize = 10;
+
+const NO_FRAME: u32 = u32::MAX;
+const NOT_FREE: u8 = 0xff;
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct FrameLink {
+	next: u32,
+	prev: u32,
+}
+
+#[derive(Debug)]
+pub struct BuddyAllocator {
+	links: &'static mut [FrameLink],
+	orders: &'static mut [u8],
+	free_lists: [u32; MAX_ORDER + 1],
+	free_counts: [u32; MAX_ORDER + 1],
+	frames: u32,
+}
+
+impl BuddyAllocator {
+	pub fn new() -> BuddyAllocator {
+		BuddyAllocator {
+			links: unsafe { core::slice::from_raw_parts_mut(0 as *mut FrameLink, 0) },
+			orders: unsafe { core::slice::from_raw_parts_mut(0 as *mut u8, 0) },
+			free_lists: [NO_FRAME; MAX_ORDER + 1],
+			free_counts: [0; MAX_ORDER + 1],
+			frames: 0,
+		}
+	}
+
+	pub fn metadata_size(frames: u32) -> u32 {
+		frames * (size_of::<FrameLink>() + size_of::<u8>()) as u32
+	}
+
+	// Places the metadata at `address`, every frame starts as allocated
+	pub unsafe fn init(&mut self, address: u32, frames: u32) {
+		self.links = core::slice::from_raw_parts_mut(address as *mut FrameLink, frames as usize);
+		self.orders = core::slice::from_raw_parts_mut(
+			(address as usize + frames as usize * size_of::<FrameLink>()) as *mut u8,
+			frames as usize,
+		);
+		self.orders.fill(NOT_FREE);
+		self.free_lists = [NO_FRAME; MAX_ORDER + 1];
+		self.free_counts = [0; MAX_ORDER + 1];
+		self.frames = frames;
+	}
+
+	// Hands a run of free frames to the allocator, split in the largest aligned blocks
+	pub fn add_region(&mut self, mut frame: u32, count: u32) {
+		let end = frame + count;
+		while frame < end {
+			let mut order = MAX_ORDER;
+			while order > 0 && (frame % (1 << order) != 0 || frame + (1 << order) > end) {
+				order -= 1;
+			}
+			self.free(frame, order);
+			frame += 1 << order;
+		}
+	}
+
+	pub fn alloc(&mut self, order: usize) -> Option<u32> {
+		if order > MAX_ORDER {
+			return None;
+		}
+
+		let mut current_order = order;
+		while current_order <= MAX_ORDER && self.free_lists[current_order] == NO_FRAME {
+			current_order += 1;
+		}
+		if current_order > MAX_ORDER {
+			return None;
+		}
+
+		let frame = self.free_lists[current_order];
+		self.remove(frame, current_order);
+
+		while current_order > order {
+			current_order -= 1;
+			self.push(frame + (1 << current_order), current_order);
+		}
+		Some(frame)
+	}
+
+	pub fn free(&mut self, mut frame: u32, mut order: usize) {
+		while order < MAX_ORDER {
+			let buddy = frame ^ (1 << order);
+			if buddy >= self.frames || self.orders[buddy as usize] != order as u8 {
+				break;
+			}
+			self.remove(buddy, order);
+			frame = frame.min(buddy);
+			order += 1;
+		}
+		self.push(frame, order);
+	}
+
+	pub fn free_frames(&self) -> u32 {
+		self.free_counts
+			.iter()
+			.enumerate()
+			.map(|(order, count)| count << order)
+			.sum()
+	}
+
+	pub fn free_blocks(&self, order: usize) -> u32 {
+		self.free_counts[order]
+	}
+
+	fn push(&mut self, frame: u32, order: usize) {
+		let head = self.free_lists[order];
+		self.links[frame as usize] = FrameLink {
+			next: head,
+			prev: NO_FRAME,
+		};
+		if head != NO_FRAME {
+			self.links[head as usize].prev = frame;
+		}
+		self.free_lists[order] = frame;
+		self.orders[frame as usize] = order as u8;
+		self.free_counts[order] += 1;
+	}
+
+	fn remove(&mut self, frame: u32, order: usize) {
+		let link = self.links[frame as usize];
+		if link.prev != NO_FRAME {
+			self.links[link.prev as usize].next = link.next;
+		} else {
+			self.free_lists[order] = link.next;
+		}
+		if link.next != NO_FRAME {
+			self.links[link.next as usize].prev = link.prev;
+		}
+		self.orders[frame as usize] = NOT_FREE;
+		self.free_counts[order] -= 1;
+	}
+
+	pub fn print_free_lists(&self) {
+		println_srl!("Buddy free lists:");
+		for order in 0..=MAX_ORDER {
+			println_srl!(
+				"  Order {:2} ({:5} KiB): {} free blocks",
+				order,
+				4 << order,
+				self.free_counts[order]
+			);
+		}
+		println_srl!("  Free frames: {:#x}", self.free_frames());
+	}
+}
+
+pub fn buddy_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the buddy frame allocator\n");
+
+	let free_before = PMM.lock().free_frames();
+
+	log!(LogLevel::Info, "Allocating blocks of order 0, 3 and 10\n");
+	let single = PMM.lock().allocate_frame().expect("Failed to allocate frame");
+	let eight = PMM.lock().allocate_frames(3).expect("Failed to allocate 8 frames");
+	let table = PMM
+		.lock()
+		.allocate_frames(MAX_ORDER)
+		.expect("Failed to allocate 1024 frames");
+	println_srl!("\tOrder 0: {:#x}, order 3: {:#x}, order 10: {:#x}", single, eight, table);
+	assert!(eight % (8 * 4096) == 0);
+	assert!(table % (1024 * 4096) == 0);
+	assert!(PMM.lock().free_frames() == free_before - 1 - 8 - 1024);
+
+	log!(LogLevel::Info, "Allocating an order above MAX_ORDER should fail\n");
+	assert!(PMM.lock().allocate_frames(MAX_ORDER + 1).is_err());
+
+	log!(LogLevel::Info, "Freeing every block, buddies should coalesce back\n");
+	PMM.lock().deallocate_frames(table, MAX_ORDER);
+	PMM.lock().deallocate_frames(eight, 3);
+	PMM.lock().deallocate_frame(single);
+	assert!(PMM.lock().free_frames() == free_before);
+	PMM.lock().print_buddy_info();
+
+	log!(LogLevel::Info, "\t\tEnd of buddy frame allocator test\n");
+}
@@ -17,6 +17,9 @@ const MIN_ALLOCATION_SIZE: usize = 32;
 pub static mut KMALLOC_BREAK: *mut u8 = core::ptr::null_mut();
 const MAX_ALLOCATION_SIZE: usize = PAGE_SIZE;
 
+pub const KMALLOC_ALIGN: usize = KMALLOC_HEADER_SIZE;
+pub const KMALLOC_MAX_SIZE: usize = MAX_ALLOCATION_SIZE - KMALLOC_HEADER_SIZE;
+
 #[repr(C, packed)]
 pub struct KmallocHeader {
 	prev: *mut KmallocHeader,
@@ -83,7 +86,6 @@ impl KmallocHeader {
 }
 
 pub unsafe fn kmalloc(mut size: usize) -> Option<*mut u8> {
-	log!(LogLevel::Info, "kmalloc() allocating {} bytes", size);
 	size += KMALLOC_HEADER_SIZE;
 	let remaining = size % MIN_ALLOCATION_SIZE;
 	if remaining != 0 {
@@ -140,11 +142,6 @@ pub unsafe fn kmalloc(mut size: usize) -> Option<*mut u8> {
 }
 
 pub unsafe fn kfree(kmalloc_address: *mut u8) {
-	log!(
-		LogLevel::Info,
-		"kfree() freeing address: {:p}",
-		kmalloc_address
-	);
 	let header = (kmalloc_address as *mut KmallocHeader)
 		.offset(-1)
 		.as_mut()
@@ -216,6 +213,70 @@ pub unsafe fn ksize(kmalloc_address: *mut u8) -> usize {
 	header.size()
 }
 
+// Grows the block in place by taking over the next block when it is free,
+// returns false when the caller has to move the data elsewhere
+pub unsafe fn kextend(kmalloc_address: *mut u8, mut size: usize) -> bool {
+	size += KMALLOC_HEADER_SIZE;
+	let remaining = size % MIN_ALLOCATION_SIZE;
+	if remaining != 0 {
+		size += MIN_ALLOCATION_SIZE - remaining;
+	}
+
+	if size > MAX_ALLOCATION_SIZE {
+		return false;
+	}
+
+	let current_header = (kmalloc_address as *mut KmallocHeader).offset(-1);
+	let header = current_header.as_mut().unwrap();
+
+	if header.magic() != KMALLOC_MAGIC || header.used() != USED {
+		log!(
+			LogLevel::Warning,
+			"Invalid address passed to kextend(): {:p}",
+			kmalloc_address
+		);
+		return false;
+	}
+
+	if header.size() >= size {
+		return true;
+	}
+
+	let next_header = match header.next().as_mut() {
+		Some(next_header) => next_header,
+		None => return false,
+	};
+
+	if next_header.used() != FREE
+		|| header.next() != current_header.add(header.size() / 16)
+		|| header.size() + next_header.size() < size
+	{
+		return false;
+	}
+
+	let total_size = header.size() + next_header.size();
+	let next_next_header = next_header.next();
+	next_header.reset();
+
+	if total_size - size >= MIN_ALLOCATION_SIZE {
+		let split_header = current_header.add(size / 16);
+		(*split_header).new_header(current_header, next_next_header, total_size - size, FREE);
+		if let Some(next_next_header) = next_next_header.as_mut() {
+			next_next_header.prev = split_header;
+		}
+		header.next = split_header;
+		header.set_size(size);
+	} else {
+		if let Some(next_next_header) = next_next_header.as_mut() {
+			next_next_header.prev = current_header;
+		}
+		header.next = next_next_header;
+		header.set_size(total_size);
+	}
+
+	true
+}
+
 pub unsafe fn kbrk(increment: isize) {
 	let frame_number: isize;
 
@@ -225,7 +286,6 @@ pub unsafe fn kbrk(increment: isize) {
 			if KMALLOC_BREAK == KMALLOC_END {
 				return;
 			}
-			println_srl!("Mapping address {:p}...", KMALLOC_BREAK);
 			map_address(KMALLOC_BREAK);
 			KMALLOC_BREAK = KMALLOC_BREAK.offset(PAGE_SIZE as isize);
 		}
@@ -239,7 +299,6 @@ pub unsafe fn kbrk(increment: isize) {
 			if KMALLOC_BREAK == KMALLOC_START {
 				return;
 			}
-			println_srl!("Unmapping address {:p}...", KMALLOC_BREAK);
 			unmap_address(KMALLOC_BREAK);
 			KMALLOC_BREAK = KMALLOC_BREAK.offset(-(PAGE_SIZE as isize));
 		}
@@ -267,6 +326,21 @@ pub unsafe fn kheap_init() {
 	);
 }
 
+// Bytes mapped for the heap and bytes of the blocks in use, headers included. The caller
+// keeps the heap from changing meanwhile.
+pub unsafe fn kmalloc_usage() -> (usize, usize) {
+	let mut used = 0;
+	let mut current_header = KMALLOC_START as *mut KmallocHeader;
+	while !current_header.is_null() && current_header < KMALLOC_BREAK as *mut KmallocHeader {
+		let header = &*current_header;
+		if header.used() == USED {
+			used += header.size();
+		}
+		current_header = header.next();
+	}
+	(KMALLOC_BREAK as usize - KMALLOC_START as usize, used)
+}
+
 fn print_kmalloc_info() {
 	unsafe {
 		let mut current_header = KMALLOC_START as *mut KmallocHeader;
@@ -294,8 +368,6 @@ const MAX_PTRS: usize = 20;
 
 pub fn kmalloc_test() {
 	unsafe {
-		kheap_init();
-
 		println_srl!("\n");
 		log!(LogLevel::Info, "\t\tTesting kmalloc() and kfree()\n");
 
@@ -1,9 +1,10 @@
 use core::{mem::size_of, ptr::addr_of};
 use crate::print_srl;
+use super::buddy::{BuddyAllocator, MAX_ORDER};
 use super::page_directory::{PAGE_DIRECTORY_ADDR, PAGE_TABLES_ADDR, PAGE_TABLE_SIZE};
 use lazy_static::lazy_static;
-use spin::Mutex;
-use crate::multiboot::MltbtMME;
+use crate::tools::irqlock::IrqMutex;
+use crate::multiboot::{self, MltbtMME};
 use crate::multiboot::MltbtMMT;
 
 const MAX_REGIONS: usize = 10;
@@ -14,12 +15,18 @@ const USED_BLOCK: u32 = 0xFFFFFFFF;
 pub const HK_OFST: u32 = 0xC0000000;
 pub const KERNEL_HEAP_START: u32 = 0xD0000000;
 pub const KERNEL_HEAP_END: u32 = 0xEFFFFFFF;
+// Physical memory below this address is mapped at HK_OFST and is the only memory the PMM hands out
+pub const LOWMEM_END: u32 = KERNEL_HEAP_START - HK_OFST;
 
 const PS_START: u32 = 0;
 const PS_E: u32 = HK_OFST - 1;
 const KS_S: u32 = HK_OFST;
 const KS_END: u32 = 0xFFFFFFFF;
+// Physical memory mapped by boot.asm, where the PMM metadata has to fit
+const BOOT_MAPPING_END: u32 = 0x1000000;
 
+pub static mut BUDDY_ADDRESS: u32 = 0;
+pub static mut FRAME_REFS_ADDRESS: u32 = 0;
 pub static mut PMM_ADDRESS: u32 = 0;
 pub static mut PAGE_TABLE_END: u32 = 0;
 
@@ -38,6 +45,8 @@ pub struct KmemManager {
 	used_blocks: u32,
 	max_blocks: u32,
 	memory_map_size: u32,
+	buddy: BuddyAllocator,
+	frame_refs: &'static mut [u16],
 	pub usable_regions: [MemoryRegion; MAX_REGIONS],
 	pub memory_size: u32,
 	pub memory_map_tag: Option<&'static MltbtMMT>,
@@ -45,11 +54,13 @@ pub struct KmemManager {
 }
 
 lazy_static! {
-	pub static ref PMM: Mutex<KmemManager> = Mutex::new(KmemManager {
+	pub static ref PMM: IrqMutex<KmemManager> = IrqMutex::new(KmemManager {
 		memory_map: unsafe { core::slice::from_raw_parts_mut(0 as *mut u32, 0) },
 		used_blocks: 0,
 		max_blocks: 0,
 		memory_map_size: 0,
+		buddy: BuddyAllocator::new(),
+		frame_refs: unsafe { core::slice::from_raw_parts_mut(0 as *mut u16, 0) },
 		usable_regions: [MemoryRegion {
 			start_address: 0,
 			size: 0,
@@ -68,20 +79,23 @@ extern "C" {
 impl KmemManager {
 	pub fn init(&mut self) {
 		let max_blocks = self.memory_size / PMMNGR_BLOCK_SIZE;
-		let memory_map_size = max_blocks / PMMNGR_BLOCKS_PER_INDEX;
+		let memory_map_size = max_blocks.div_ceil(PMMNGR_BLOCKS_PER_INDEX);
 
 		println_srl!("Initializing Physical Memory Manager");
 		unsafe {
-			MEMORY_MAP = addr_of!(_kernel_end) as *const u8 as u32;
-			PMM_ADDRESS = align_up(MEMORY_MAP + memory_map_size);
-			PAGE_DIRECTORY_ADDR = align_up(PMM_ADDRESS + size_of::<KmemManager>() as u32);
-			PAGE_TABLES_ADDR = PAGE_DIRECTORY_ADDR + 0x1000;
-			PAGE_TABLE_END = PAGE_TABLES_ADDR + PAGE_TABLE_SIZE as u32 + 0x1000;	
-			
+			place_metadata(addr_of!(_kernel_end) as *const u8 as u32, max_blocks, memory_map_size);
+			let base = metadata_base(MEMORY_MAP, PAGE_TABLE_END - MEMORY_MAP);
+			place_metadata(base, max_blocks, memory_map_size);
+			if PAGE_TABLE_END - HK_OFST > BOOT_MAPPING_END {
+				panic!("PMM metadata ends at {:#x}, past the boot mapping", PAGE_TABLE_END);
+			}
+
 			println_srl!("User space start:         {:#x}", PS_START);
 			println_srl!("User space end:           {:#x}", PS_E);
 			println_srl!("Kernel space start:       {:#x}", KS_S);
 			println_srl!("Memory map address:       {:#x}", MEMORY_MAP);
+			println_srl!("Buddy metadata address:   {:#x}", BUDDY_ADDRESS);
+			println_srl!("Frame refcounts address:  {:#x}", FRAME_REFS_ADDRESS);
 			println_srl!("PMM address:              {:#x}", PMM_ADDRESS);
 			println_srl!("Page directory address:   {:#x}", PAGE_DIRECTORY_ADDR);
 			println_srl!("Page tables address:      {:#x}", PAGE_TABLES_ADDR);
@@ -104,10 +118,7 @@ impl KmemManager {
 		);
 		
 		self.memory_map = unsafe {
-			core::slice::from_raw_parts_mut(
-				addr_of!(_kernel_end) as *const u8 as *mut u32,
-				self.memory_map_size as usize,
-			)
+			core::slice::from_raw_parts_mut(MEMORY_MAP as *mut u32, self.memory_map_size as usize)
 		};
 		
 		for i in 0..self.memory_map_size as usize {
@@ -125,10 +136,45 @@ impl KmemManager {
 		self.set_region_as_unavailable(KS_S - HK_OFST, unsafe {
 			PAGE_TABLE_END as u32 - KS_S - 1
 		});
+		for module in multiboot::modules() {
+			let start = module.start & !(PMMNGR_BLOCK_SIZE - 1);
+			self.set_region_as_unavailable(start, module.end.saturating_sub(start));
+		}
+
+		self.frame_refs = unsafe {
+			core::slice::from_raw_parts_mut(FRAME_REFS_ADDRESS as *mut u16, self.max_blocks as usize)
+		};
+		self.frame_refs.fill(0);
+
+		unsafe { self.buddy.init(BUDDY_ADDRESS, self.max_blocks) };
+		let mut block = 1;
+		while block < self.max_blocks {
+			if self.mmap_test(block) {
+				block += 1;
+				continue;
+			}
+			let start = block;
+			while block < self.max_blocks && !self.mmap_test(block) {
+				block += 1;
+			}
+			self.buddy.add_region(start, block - start);
+		}
 		println_srl!("PMM memory size: {:#x}", self.memory_size);
+		println_srl!(
+			"PMM free frames: {:#x}, used frames: {:#x}",
+			self.buddy.free_frames(),
+			self.used_blocks
+		);
 	}
-	
+
+	fn mmap_test(&self, bit: u32) -> bool {
+		self.memory_map[(bit / 32) as usize] & (1 << (bit % 32)) != 0
+	}
+
 	fn mmap_set(&mut self, bit: u32) {
+		if bit >= self.max_blocks || self.mmap_test(bit) {
+			return;
+		}
 		let index = bit / 32;
 		let off = bit % 32;
 		self.memory_map[index as usize] |= 1 << off;
@@ -136,6 +182,9 @@ impl KmemManager {
 	}
 
 	fn mmap_unset(&mut self, bit: u32) {
+		if bit >= self.max_blocks || !self.mmap_test(bit) {
+			return;
+		}
 		let index = bit / 32;
 		let off = bit % 32;
 		self.memory_map[index as usize] &= !(1 << off);
@@ -165,48 +214,100 @@ impl KmemManager {
 
 		for block in start_block..start_block + blocks {
 			self.mmap_set(block);
-			self.used_blocks += 1;
+		}
+	}
+
+	// Hands the whole frames of a region reserved at boot, such as a module, to the allocator
+	pub fn release_region(&mut self, address: u32, size: u32) {
+		let start = address.div_ceil(PMMNGR_BLOCK_SIZE);
+		let end = (address.saturating_add(size) / PMMNGR_BLOCK_SIZE).min(self.max_blocks);
+		for block in start.max(1)..end {
+			if self.mmap_test(block) && self.frame_refs[block as usize] == 0 {
+				self.mmap_unset(block);
+				self.buddy.add_region(block, 1);
+			}
 		}
 	}
 
 	pub fn allocate_frame(&mut self) -> Result<u32, &'static str> {
-		println_srl!(
-			"Used blocks: {:#x}, Max blocks: {:#x}",
-			self.used_blocks,
-			self.max_blocks
-		);
+		self.allocate_frames(0)
+	}
 
-		if self.used_blocks >= self.max_blocks {
-			return Err("Out of memory");
+	// Allocates 2^order physically contiguous frames, aligned on their size
+	pub fn allocate_frames(&mut self, order: usize) -> Result<u32, &'static str> {
+		if order > MAX_ORDER {
+			return Err("Allocation order too big");
 		}
 
-		let mut frame = 0;
-		'outer: for i in 0..self.max_blocks / 32 {
-			if self.memory_map[i as usize] != 0xffffffff {
-				for j in 0..32 {
-					let bit: u32 = 1 << j;
-					if (self.memory_map[i as usize] & bit) == 0 {
-						frame = i * 32 + j;
-						break 'outer;
-					}
-				}
+		let frame = self.buddy.alloc(order).ok_or("Out of memory")?;
+		for block in frame..frame + (1 << order) {
+			self.mmap_set(block);
+			self.frame_refs[block as usize] = 1;
+		}
+		Ok(frame * PMMNGR_BLOCK_SIZE)
+	}
+
+	// Drops one reference to the frame, it goes back to the allocator with the last one
+	pub fn deallocate_frame(&mut self, address: u32) {
+		let frame = address / PMMNGR_BLOCK_SIZE;
+		if frame < self.max_blocks && self.frame_refs[frame as usize] > 1 {
+			// A saturated count no longer knows its references, the frame is kept for good
+			if self.frame_refs[frame as usize] != u16::MAX {
+				self.frame_refs[frame as usize] -= 1;
 			}
+			return;
 		}
-		println_srl!("Frame: {:#x}", frame);
-		if frame != 0 {
-			self.mmap_set(frame);
-			Ok(frame * PMMNGR_BLOCK_SIZE)
-		} else {
-			Err("Out of memory")
+		self.deallocate_frames(address, 0);
+	}
+
+	pub fn deallocate_frames(&mut self, address: u32, order: usize) {
+		let frame = address / PMMNGR_BLOCK_SIZE;
+		if frame == 0 || !self.is_address_usable(address) || frame >= self.max_blocks {
+			return;
+		}
+		if self.frame_refs[frame as usize] == 0 {
+			println_srl!("Invalid free of frame {:#x}", address);
+			return;
+		}
+
+		for block in frame..frame + (1 << order) {
+			self.mmap_unset(block);
+			self.frame_refs[block as usize] = 0;
 		}
+		self.buddy.free(frame, order);
 	}
 
-	pub fn deallocate_frame(&mut self, address: u32) {
-		if self.is_address_usable(address) {
-			self.mmap_unset(address / PMMNGR_BLOCK_SIZE);
+	// Adds a reference to an allocated frame, used to share it between address spaces. The
+	// count saturates instead of wrapping to 0, which would free a frame still mapped.
+	pub fn get_frame(&mut self, address: u32) {
+		let frame = address / PMMNGR_BLOCK_SIZE;
+		if frame < self.max_blocks && self.frame_refs[frame as usize] > 0 {
+			let refs = &mut self.frame_refs[frame as usize];
+			*refs = refs.saturating_add(1);
+		}
+	}
+
+	pub fn frame_refcount(&self, address: u32) -> u16 {
+		let frame = address / PMMNGR_BLOCK_SIZE;
+		if frame < self.max_blocks {
+			self.frame_refs[frame as usize]
+		} else {
+			0
 		}
 	}
 
+	pub fn free_frames(&self) -> u32 {
+		self.buddy.free_frames()
+	}
+
+	pub fn used_frames(&self) -> u32 {
+		self.used_blocks
+	}
+
+	pub fn total_frames(&self) -> u32 {
+		self.max_blocks
+	}
+
 	fn process_memory_map(&mut self) {
 		let memory_map_entries: &[MltbtMME] = self.memory_map_entries.unwrap();
 
@@ -236,8 +337,8 @@ impl KmemManager {
 			}
 		}
 
-		self.memory_size = memory_map_entries.last().unwrap().address as u32
-			+ memory_map_entries.last().unwrap().len as u32;
+		let last_entry = memory_map_entries.last().unwrap();
+		self.memory_size = (last_entry.address + last_entry.len).min(LOWMEM_END as u64) as u32;
 	}
 
 	fn is_address_usable(&self, address: u32) -> bool {
@@ -271,7 +372,35 @@ impl KmemManager {
 			}
 			println_srl!();
 		}
+		self.print_buddy_info();
+	}
+
+	pub fn print_buddy_info(&self) {
+		self.buddy.print_free_lists();
+	}
+}
+
+// Lays out the bitmap, the buddy metadata, the frame refcounts, the PMM and the page tables
+// from `base`
+unsafe fn place_metadata(base: u32, max_blocks: u32, memory_map_size: u32) {
+	MEMORY_MAP = base;
+	BUDDY_ADDRESS = align_up(MEMORY_MAP + memory_map_size * size_of::<u32>() as u32);
+	FRAME_REFS_ADDRESS = align_up(BUDDY_ADDRESS + BuddyAllocator::metadata_size(max_blocks));
+	PMM_ADDRESS = align_up(FRAME_REFS_ADDRESS + max_blocks * size_of::<u16>() as u32);
+	PAGE_DIRECTORY_ADDR = align_up(PMM_ADDRESS + size_of::<KmemManager>() as u32);
+	PAGE_TABLES_ADDR = PAGE_DIRECTORY_ADDR + 0x1000;
+	PAGE_TABLE_END = PAGE_TABLES_ADDR + PAGE_TABLE_SIZE as u32 + 0x1000;
+}
+
+// GRUB loads the modules right after the kernel, the metadata is moved past the ones it
+// would overwrite
+fn metadata_base(mut base: u32, size: u32) -> u32 {
+	while let Some(module) = multiboot::modules()
+		.find(|module| module.start < base - HK_OFST + size && base - HK_OFST < module.end)
+	{
+		base = align_up(module.end + HK_OFST);
 	}
+	base
 }
 
 pub fn kmem_manager_init() {
@@ -13,3 +13,15 @@ pub mod page_table_entry;
 pub mod kmalloc;
 
 pub mod vmalloc;
+
+pub mod allocator;
+
+pub mod buddy;
+
+pub mod slab;
+
+pub mod address_space;
+
+pub mod vma;
+
+pub mod uaccess;
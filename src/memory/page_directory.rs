@@ -47,10 +47,41 @@ pub fn unmap_address(virtual_address: *mut u8) {
 	let page_directory: &mut PageDirectory =
 		unsafe { &mut *PAGE_DIRECTORY.load(Ordering::Relaxed) };
 	let page_table: &mut PageTable = page_directory.get_page_table(virtual_address as u32);
-	let page_table_entry: &PageTableEntry =
+	let page_table_entry: &mut PageTableEntry =
 		page_table.get_page_table_entry(virtual_address as u32);
 
-	PMM.lock().deallocate_frame(page_table_entry.frame());
+	if !page_table_entry.present() {
+		return;
+	}
+	let frame = page_table_entry.frame();
+	page_table_entry.clear();
+	flush_tlb_entry(virtual_address as u32);
+	PMM.lock().deallocate_frame(frame);
+}
+
+pub fn flush_tlb_entry(virtual_address: u32) {
+	unsafe {
+		asm!("invlpg [{}]", in(reg) virtual_address, options(nostack, preserves_flags));
+	}
+}
+
+pub fn current_directory() -> u32 {
+	let cr3: u32;
+	unsafe {
+		asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+	}
+	cr3
+}
+
+pub fn load_directory(physical_address: u32) {
+	unsafe {
+		asm!("mov cr3, {}", in(reg) physical_address, options(nostack, preserves_flags));
+	}
+}
+
+// Physical address of the directory built by init_page_directory, the kernel address space
+pub fn kernel_directory() -> u32 {
+	unsafe { PAGE_DIRECTORY_ADDR - HK_OFST }
 }
 
 pub fn enable_paging() {
@@ -102,16 +133,17 @@ pub unsafe fn init_page_directory() {
 		current_page_table += PAGE_SIZE as u32;
 	}
 
-	page_directory
-		.get_page_table(HK_OFST)
-		.kernel_mapping(
-			0x00000000,
-			FlagTablePages::PRESENT | FlagTablePages::WRITABLE,
-		);
-	page_directory
-		.get_page_table(HK_OFST + PAGE_TABLE_SIZE as u32)
-		.kernel_mapping(
-			0x00000000 + PAGE_TABLE_SIZE as u32,
-			FlagTablePages::PRESENT | FlagTablePages::WRITABLE,
-		);
+	// Map the whole low memory at HK_OFST so frames from the PMM can be reached
+	// at `physical + HK_OFST`, the first 16MB hold the kernel and its page tables
+	let lowmem_end = PMM.lock().memory_size.max(4 * PAGE_TABLE_SIZE as u32);
+	let mut physical_address = 0x00000000;
+	while physical_address < lowmem_end {
+		page_directory
+			.get_page_table(HK_OFST + physical_address)
+			.kernel_mapping(
+				physical_address,
+				FlagTablePages::PRESENT | FlagTablePages::WRITABLE,
+			);
+		physical_address += PAGE_TABLE_SIZE as u32;
+	}
 }
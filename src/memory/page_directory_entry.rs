@@ -34,4 +34,12 @@ impl PageDirectoryEntry {
 		let table_address = (self.value & PageDirectoryFlags::PAGE_TABLE.bits()) + HK_OFST;
 		unsafe { &mut *(table_address as *mut PageTable) }
 	}
+
+	pub fn present(&self) -> bool {
+		self.value & PageDirectoryFlags::PRESENT.bits() != 0
+	}
+
+	pub fn clear(&mut self) {
+		self.value = 0;
+	}
 }
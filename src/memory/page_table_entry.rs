@@ -14,6 +14,7 @@ bitflags! {
 		const PAT = 0b1000_0000;
 		const CPU_GLOBAL = 0b1_0000_0000;
 		const AVAILABLE = 0b1110_0000_0000;
+		const COPY_ON_WRITE = 0b10_0000_0000;
 		const FRAME = 0xFFFFF000;
 	}
 }
@@ -52,6 +53,18 @@ impl PageTableEntry {
 		print_srl!("Frame allocated at {:?}\n", frame.unwrap());
 	}
 
+	pub fn flags(&self) -> FlagTablePages {
+		FlagTablePages::from_bits_truncate(self.value & !FlagTablePages::FRAME.bits())
+	}
+
+	pub fn present(&self) -> bool {
+		self.value & FlagTablePages::PRESENT.bits() != 0
+	}
+
+	pub fn clear(&mut self) {
+		self.value = 0;
+	}
+
 	pub fn frame(&self) -> u32 {
 		self.value & FlagTablePages::FRAME.bits()
 	}
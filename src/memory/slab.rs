@@ -0,0 +1,334 @@
+//! # Slab Object Caches
+//!
+//! A `KmemCache` hands out objects of one fixed size and alignment in O(1). Objects are carved
+//! out of slabs, runs of 2^order frames taken from the PMM and reached through the low memory
+//! mapping. Each slab starts with a `Slab` header and keeps its free objects in a singly linked
+//! list stored inside the free objects themselves.
+//!
+//! Slabs are aligned on their size, so the header of the slab owning an object is found by
+//! masking the object address. Every cache keeps its slabs in three lists (partial, full and
+//! empty) and at most one empty slab, the others are given back to the PMM.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use spin::Mutex;
+
+use super::kmem_managment::{HK_OFST, PMM};
+use super::page_directory::PAGE_SIZE;
+use crate::tools::irqlock::IrqMutex;
+use crate::tools::debug::LogLevel;
+
+const SLAB_MAX_ORDER: usize = 4;
+const SLAB_MIN_OBJECTS: usize = 8;
+
+pub static CACHES: Mutex<Vec<&'static KmemCache>> = Mutex::new(Vec::new());
+
+#[repr(C)]
+struct Slab {
+	cache: *const KmemCache,
+	prev: *mut Slab,
+	next: *mut Slab,
+	free: *mut FreeObject,
+	in_use: usize,
+}
+
+struct FreeObject {
+	next: *mut FreeObject,
+}
+
+struct SlabLists {
+	partial: *mut Slab,
+	full: *mut Slab,
+	empty: *mut Slab,
+	slabs: usize,
+	active_objects: usize,
+}
+
+unsafe impl Send for SlabLists {}
+
+pub struct KmemCache {
+	name: &'static str,
+	object_size: usize,
+	align: usize,
+	order: usize,
+	first_object: usize,
+	objects_per_slab: usize,
+	lists: IrqMutex<SlabLists>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+	pub name: &'static str,
+	pub object_size: usize,
+	pub objects_per_slab: usize,
+	pub pages_per_slab: usize,
+	pub slabs: usize,
+	pub active_objects: usize,
+	pub total_objects: usize,
+}
+
+unsafe fn list_push(head: &mut *mut Slab, slab: *mut Slab) {
+	(*slab).prev = null_mut();
+	(*slab).next = *head;
+	if let Some(next) = (*head).as_mut() {
+		next.prev = slab;
+	}
+	*head = slab;
+}
+
+unsafe fn list_remove(head: &mut *mut Slab, slab: *mut Slab) {
+	if let Some(prev) = (*slab).prev.as_mut() {
+		prev.next = (*slab).next;
+	} else {
+		*head = (*slab).next;
+	}
+	if let Some(next) = (*slab).next.as_mut() {
+		next.prev = (*slab).prev;
+	}
+	(*slab).prev = null_mut();
+	(*slab).next = null_mut();
+}
+
+impl KmemCache {
+	// Creates and registers a cache, caches live for the whole kernel lifetime
+	pub fn new(name: &'static str, size: usize, align: usize) -> &'static KmemCache {
+		let align = align.max(size_of::<usize>()).next_power_of_two();
+		let object_size = size.max(size_of::<FreeObject>()).next_multiple_of(align);
+		let first_object = size_of::<Slab>().next_multiple_of(align);
+
+		let mut order = 0;
+		while order < SLAB_MAX_ORDER
+			&& (PAGE_SIZE << order).saturating_sub(first_object) / object_size < SLAB_MIN_OBJECTS
+		{
+			order += 1;
+		}
+		let objects_per_slab = (PAGE_SIZE << order).saturating_sub(first_object) / object_size;
+		assert!(objects_per_slab > 0, "Slab object too big for cache {}", name);
+
+		let cache: &'static KmemCache = Box::leak(Box::new(KmemCache {
+			name,
+			object_size,
+			align,
+			order,
+			first_object,
+			objects_per_slab,
+			lists: IrqMutex::new(SlabLists {
+				partial: null_mut(),
+				full: null_mut(),
+				empty: null_mut(),
+				slabs: 0,
+				active_objects: 0,
+			}),
+		}));
+		CACHES.lock().push(cache);
+		log!(
+			LogLevel::Info,
+			"Slab cache {} created: {} bytes objects, {} per slab of {} pages",
+			name,
+			object_size,
+			objects_per_slab,
+			1 << order
+		);
+		cache
+	}
+
+	pub fn alloc(&self) -> Option<*mut u8> {
+		let mut lists = self.lists.lock();
+		unsafe {
+			let slab = if !lists.partial.is_null() {
+				lists.partial
+			} else if !lists.empty.is_null() {
+				let slab = lists.empty;
+				list_remove(&mut lists.empty, slab);
+				list_push(&mut lists.partial, slab);
+				slab
+			} else {
+				let slab = self.grow()?;
+				lists.slabs += 1;
+				list_push(&mut lists.partial, slab);
+				slab
+			};
+
+			let object = (*slab).free;
+			(*slab).free = (*object).next;
+			(*slab).in_use += 1;
+			lists.active_objects += 1;
+
+			if (*slab).in_use == self.objects_per_slab {
+				list_remove(&mut lists.partial, slab);
+				list_push(&mut lists.full, slab);
+			}
+			Some(object as *mut u8)
+		}
+	}
+
+	pub fn free(&self, object: *mut u8) {
+		let slab = (object as usize & !((PAGE_SIZE << self.order) - 1)) as *mut Slab;
+		let mut lists = self.lists.lock();
+		unsafe {
+			let offset = (object as usize - slab as usize).wrapping_sub(self.first_object);
+			if (*slab).cache != self as *const KmemCache
+				|| offset % self.object_size != 0
+				|| offset >= self.objects_per_slab * self.object_size
+			{
+				log!(
+					LogLevel::Warning,
+					"Invalid free of address {:p} in slab cache {}",
+					object,
+					self.name
+				);
+				return;
+			}
+
+			if (*slab).in_use == self.objects_per_slab {
+				list_remove(&mut lists.full, slab);
+				list_push(&mut lists.partial, slab);
+			}
+
+			let free_object = object as *mut FreeObject;
+			(*free_object).next = (*slab).free;
+			(*slab).free = free_object;
+			(*slab).in_use -= 1;
+			lists.active_objects -= 1;
+
+			if (*slab).in_use == 0 {
+				list_remove(&mut lists.partial, slab);
+				if lists.empty.is_null() {
+					list_push(&mut lists.empty, slab);
+				} else {
+					lists.slabs -= 1;
+					self.release(slab);
+				}
+			}
+		}
+	}
+
+	// Gives every empty slab back to the PMM
+	pub fn shrink(&self) {
+		let mut lists = self.lists.lock();
+		while !lists.empty.is_null() {
+			let slab = lists.empty;
+			unsafe {
+				list_remove(&mut lists.empty, slab);
+				self.release(slab);
+			}
+			lists.slabs -= 1;
+		}
+	}
+
+	pub fn stats(&self) -> CacheStats {
+		let lists = self.lists.lock();
+		CacheStats {
+			name: self.name,
+			object_size: self.object_size,
+			objects_per_slab: self.objects_per_slab,
+			pages_per_slab: 1 << self.order,
+			slabs: lists.slabs,
+			active_objects: lists.active_objects,
+			total_objects: lists.slabs * self.objects_per_slab,
+		}
+	}
+
+	pub fn object_size(&self) -> usize {
+		self.object_size
+	}
+
+	pub fn align(&self) -> usize {
+		self.align
+	}
+
+	unsafe fn grow(&self) -> Option<*mut Slab> {
+		let frame = match PMM.lock().allocate_frames(self.order) {
+			Ok(frame) => frame,
+			Err(e) => {
+				log!(LogLevel::Warning, "Slab cache {} cannot grow: {}", self.name, e);
+				return None;
+			}
+		};
+
+		let slab = (frame + HK_OFST) as *mut Slab;
+		let mut free: *mut FreeObject = null_mut();
+		for index in (0..self.objects_per_slab).rev() {
+			let object =
+				(slab as usize + self.first_object + index * self.object_size) as *mut FreeObject;
+			(*object).next = free;
+			free = object;
+		}
+		slab.write(Slab {
+			cache: self,
+			prev: null_mut(),
+			next: null_mut(),
+			free,
+			in_use: 0,
+		});
+		Some(slab)
+	}
+
+	unsafe fn release(&self, slab: *mut Slab) {
+		(*slab).cache = core::ptr::null();
+		PMM.lock()
+			.deallocate_frames(slab as u32 - HK_OFST, self.order);
+	}
+}
+
+pub fn print_slab_info() {
+	println!(
+		"{:16} {:>8} {:>8} {:>8} {:>6} {:>6}",
+		"name", "active", "total", "objsize", "perslb", "pages"
+	);
+	for cache in CACHES.lock().iter() {
+		let stats = cache.stats();
+		println!(
+			"{:16} {:>8} {:>8} {:>8} {:>6} {:>6}",
+			stats.name,
+			stats.active_objects,
+			stats.total_objects,
+			stats.object_size,
+			stats.objects_per_slab,
+			stats.pages_per_slab
+		);
+	}
+}
+
+const MAX_OBJECTS: usize = 40;
+
+pub fn slab_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting slab caches\n");
+
+	let cache = KmemCache::new("slab-test", 100, 64);
+	let mut objects: [*mut u8; MAX_OBJECTS] = [null_mut(); MAX_OBJECTS];
+
+	log!(LogLevel::Info, "Allocating {} objects of 100 bytes aligned on 64\n", MAX_OBJECTS);
+	for object in objects.iter_mut() {
+		*object = cache.alloc().expect("Failed to allocate object");
+		assert!(*object as usize % 64 == 0);
+	}
+	let stats = cache.stats();
+	println_srl!("\t{:?}", stats);
+	assert!(stats.active_objects == MAX_OBJECTS);
+	assert!(stats.slabs == MAX_OBJECTS.div_ceil(stats.objects_per_slab));
+
+	log!(LogLevel::Info, "Freeing every other object and allocating them again\n");
+	for object in objects.iter().step_by(2) {
+		cache.free(*object);
+	}
+	for object in objects.iter_mut().step_by(2) {
+		*object = cache.alloc().expect("Failed to allocate object");
+	}
+	assert!(cache.stats().slabs == stats.slabs);
+
+	log!(LogLevel::Info, "Freeing all objects, one empty slab should be kept\n");
+	for object in objects.iter() {
+		cache.free(*object);
+	}
+	let stats = cache.stats();
+	println_srl!("\t{:?}", stats);
+	assert!(stats.active_objects == 0 && stats.slabs == 1);
+	cache.shrink();
+	assert!(cache.stats().slabs == 0);
+
+	log!(LogLevel::Info, "\t\tEnd of slab caches test\n");
+}
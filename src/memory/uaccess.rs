@@ -0,0 +1,181 @@
+//! # User Memory Access
+//!
+//! Syscalls never dereference user pointers directly. The range is first checked to lie
+//! below `USER_SPACE_END` and inside the VMAs of the current address space, then the copy is
+//! done by the routines of uaccess.s. Those are listed in the `__ex_table` section: when one
+//! of them faults on a page that cannot be populated, the page fault handler resumes at its
+//! fixup address and the copy fails with `EFAULT` instead of bringing the kernel down.
+
+use core::arch::global_asm;
+use core::mem::{size_of, MaybeUninit};
+
+use super::address_space::{self, AddressSpace, USER_SPACE_END};
+use super::vma::VmaFlags;
+use crate::exceptions::errno::{Errno, EFAULT};
+use crate::tools::debug::LogLevel;
+
+global_asm!(include_str!("uaccess.s"), options(att_syntax));
+
+extern "C" {
+	fn __copy_user(to: *mut u8, from: *const u8, n: usize) -> usize;
+	fn __strncpy_user(to: *mut u8, from: *const u8, n: usize) -> isize;
+	static __start_ex_table: ExceptionTableEntry;
+	static __stop_ex_table: ExceptionTableEntry;
+}
+
+#[repr(C)]
+struct ExceptionTableEntry {
+	instruction: u32,
+	fixup: u32,
+}
+
+// Address to resume at when the instruction at `eip` faults, if it may touch user memory
+pub fn search_exception_table(eip: u32) -> Option<u32> {
+	let table = unsafe {
+		let start = &__start_ex_table as *const ExceptionTableEntry;
+		let end = &__stop_ex_table as *const ExceptionTableEntry;
+		core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+	};
+	table
+		.iter()
+		.find(|entry| entry.instruction == eip)
+		.map(|entry| entry.fixup)
+}
+
+// True when `address..address + len` is user memory covered by VMAs with `flags`
+fn range_ok(space: &AddressSpace, address: u32, len: usize, flags: VmaFlags) -> bool {
+	let end = address as u64 + len as u64;
+	if end > USER_SPACE_END as u64 {
+		return false;
+	}
+
+	let mut current = address as u64;
+	while current < end {
+		match space.find_vma(current as u32) {
+			Some(vma) if vma.flags.contains(flags) => current = vma.end as u64,
+			_ => return false,
+		}
+	}
+	true
+}
+
+pub fn access_ok(address: u32, len: usize, write: bool) -> bool {
+	let flags = if write { VmaFlags::WRITE } else { VmaFlags::READ };
+	match address_space::current() {
+		Some(space) => range_ok(space, address, len, flags),
+		None => false,
+	}
+}
+
+pub fn copy_from_user(destination: &mut [u8], source: u32) -> Result<(), Errno> {
+	if !access_ok(source, destination.len(), false) {
+		return Err(EFAULT);
+	}
+	let left = unsafe {
+		__copy_user(destination.as_mut_ptr(), source as *const u8, destination.len())
+	};
+	if left != 0 {
+		return Err(EFAULT);
+	}
+	Ok(())
+}
+
+pub fn copy_to_user(destination: u32, source: &[u8]) -> Result<(), Errno> {
+	if !access_ok(destination, source.len(), true) {
+		return Err(EFAULT);
+	}
+	let left = unsafe { __copy_user(destination as *mut u8, source.as_ptr(), source.len()) };
+	if left != 0 {
+		return Err(EFAULT);
+	}
+	Ok(())
+}
+
+// Copies a NUL terminated string, returns its length or `destination.len()` if it did not
+// fit. The string may end before the end of its VMA, so only the first byte is checked
+// up front and the fixup catches the rest.
+pub fn strncpy_from_user(destination: &mut [u8], source: u32) -> Result<usize, Errno> {
+	if destination.is_empty() {
+		return Ok(0);
+	}
+	if !access_ok(source, 1, false) {
+		return Err(EFAULT);
+	}
+	let available = (USER_SPACE_END - source) as usize;
+	let len = destination.len().min(available);
+	let copied = unsafe { __strncpy_user(destination.as_mut_ptr(), source as *const u8, len) };
+	if copied < 0 {
+		return Err(EFAULT);
+	}
+	Ok(copied as usize)
+}
+
+pub fn read_user<T: Copy>(source: u32) -> Result<T, Errno> {
+	let mut value = MaybeUninit::<T>::uninit();
+	let bytes =
+		unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+	copy_from_user(bytes, source)?;
+	Ok(unsafe { value.assume_init() })
+}
+
+pub fn write_user<T: Copy>(destination: u32, value: &T) -> Result<(), Errno> {
+	let bytes =
+		unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+	copy_to_user(destination, bytes)
+}
+
+pub fn uaccess_test() {
+	use super::vma::{Vma, VmaBacking};
+
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting user memory access\n");
+
+	let mut space =
+		alloc::boxed::Box::new(AddressSpace::new().expect("Failed to create address space"));
+	space
+		.add_vma(Vma::new(
+			0x1000_0000,
+			0x1000_2000,
+			VmaFlags::READ | VmaFlags::WRITE,
+			VmaBacking::Anonymous,
+		))
+		.unwrap();
+	space
+		.add_vma(Vma::new(0x1000_2000, 0x1000_3000, VmaFlags::READ, VmaBacking::Anonymous))
+		.unwrap();
+	space.activate();
+
+	log!(LogLevel::Info, "Copying across a page boundary and back\n");
+	let message = b"hello from user space";
+	copy_to_user(0x1000_1ff8, message).expect("copy_to_user failed");
+	let mut buffer = [0; 21];
+	copy_from_user(&mut buffer, 0x1000_1ff8).expect("copy_from_user failed");
+	assert!(&buffer == message);
+	assert!(read_user::<u32>(0x1000_1ff8) == Ok(u32::from_le_bytes(*b"hell")));
+
+	log!(LogLevel::Info, "Copying strings, truncated or not\n");
+	let mut string = [0; 32];
+	assert!(strncpy_from_user(&mut string, 0x1000_2000 - 4) == Ok(0));
+	copy_to_user(0x1000_0000, b"lenrek\0").unwrap();
+	assert!(strncpy_from_user(&mut string, 0x1000_0000) == Ok(6));
+	assert!(&string[..7] == b"lenrek\0");
+	assert!(strncpy_from_user(&mut string[..3], 0x1000_0000) == Ok(3));
+
+	log!(LogLevel::Info, "Bad pointers should fail with EFAULT\n");
+	assert!(copy_from_user(&mut buffer, 0x2000_0000) == Err(EFAULT));
+	assert!(copy_from_user(&mut buffer, 0x1000_2ff0) == Err(EFAULT));
+	assert!(copy_to_user(0x1000_2000, message) == Err(EFAULT));
+	assert!(copy_to_user(0xc010_0000, message) == Err(EFAULT));
+	assert!(strncpy_from_user(&mut string, 0x0) == Err(EFAULT));
+
+	log!(LogLevel::Info, "A fault inside the copy should be fixed up\n");
+	let left = unsafe { __copy_user(buffer.as_mut_ptr(), 0x2000_0000 as *const u8, buffer.len()) };
+	assert!(left == buffer.len());
+	let copied = unsafe { __strncpy_user(string.as_mut_ptr(), 0x2000_0000 as *const u8, 8) };
+	assert!(copied == -(EFAULT as isize));
+
+	address_space::switch_to_kernel();
+	drop(space);
+
+	log!(LogLevel::Info, "\t\tEnd of user memory access test\n");
+}
@@ -0,0 +1,55 @@
+.text
+
+# Every instruction that touches user memory has an entry in __ex_table, the page fault
+# handler resumes at the fixup address when such an instruction faults on a bad address.
+
+# __copy_user(to, from, n) returns the number of bytes left uncopied
+
+.global __copy_user
+
+__copy_user:
+    push %esi
+    push %edi
+    mov 12(%esp), %edi
+    mov 16(%esp), %esi
+    mov 20(%esp), %ecx
+1:  rep movsb
+2:  mov %ecx, %eax
+    pop %edi
+    pop %esi
+    ret
+
+# __strncpy_user(to, from, n) returns the length of the string copied, n if no NUL byte
+# was found in the first n bytes, or -EFAULT
+
+.global __strncpy_user
+
+__strncpy_user:
+    push %esi
+    push %edi
+    mov 12(%esp), %edi
+    mov 16(%esp), %esi
+    mov 20(%esp), %ecx
+    mov %ecx, %edx
+    test %ecx, %ecx
+    jz 4f
+3:  lodsb
+    stosb
+    test %al, %al
+    jz 4f
+    dec %ecx
+    jnz 3b
+4:  mov %edx, %eax
+    sub %ecx, %eax
+    pop %edi
+    pop %esi
+    ret
+5:  mov $-14, %eax
+    pop %edi
+    pop %esi
+    ret
+
+.section __ex_table, "a"
+    .long 1b, 2b
+    .long 3b, 5b
+.previous
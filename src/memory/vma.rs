@@ -0,0 +1,127 @@
+//! # Virtual Memory Areas
+//!
+//! A `Vma` describes a page aligned range of user addresses, what may be done with it and
+//! where its content comes from. Nothing is mapped when a VMA is created: pages are populated
+//! by the page fault handler the first time they are touched, with the permissions of the VMA.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
+use super::kmem_managment::HK_OFST;
+use super::page_directory::PAGE_SIZE;
+use super::page_table_entry::FlagTablePages;
+
+// Stack VMAs grow down on faults as long as they stay under this size
+pub const MAX_STACK_SIZE: u32 = 8 * 1024 * 1024;
+
+bitflags! {
+	pub struct VmaFlags: u32 {
+		const READ = 0b1;
+		const WRITE = 0b10;
+		const EXEC = 0b100;
+	}
+}
+
+// Bytes `offset..offset + size` of `data` are mapped at the start of the VMA, the rest is zero
+#[derive(Clone)]
+pub struct FileBacking {
+	pub data: Arc<Vec<u8>>,
+	pub offset: usize,
+	pub size: usize,
+}
+
+#[derive(Clone)]
+pub enum VmaBacking {
+	Anonymous,
+	File(FileBacking),
+	Stack,
+}
+
+#[derive(Clone)]
+pub struct Vma {
+	pub start: u32,
+	pub end: u32,
+	pub flags: VmaFlags,
+	pub backing: VmaBacking,
+}
+
+impl Vma {
+	pub fn new(start: u32, end: u32, flags: VmaFlags, backing: VmaBacking) -> Vma {
+		Vma {
+			start: start & !(PAGE_SIZE as u32 - 1),
+			end: (end + PAGE_SIZE as u32 - 1) & !(PAGE_SIZE as u32 - 1),
+			flags,
+			backing,
+		}
+	}
+
+	pub fn contains(&self, address: u32) -> bool {
+		self.start <= address && address < self.end
+	}
+
+	pub fn overlaps(&self, start: u32, end: u32) -> bool {
+		self.start < end && start < self.end
+	}
+
+	pub fn is_stack(&self) -> bool {
+		matches!(self.backing, VmaBacking::Stack)
+	}
+
+	pub fn page_flags(&self) -> FlagTablePages {
+		if self.flags.contains(VmaFlags::WRITE) {
+			FlagTablePages::USER | FlagTablePages::WRITABLE
+		} else {
+			FlagTablePages::USER
+		}
+	}
+
+	// Fills a freshly allocated frame with the content of the page at `address`
+	pub fn fill_page(&self, address: u32, frame: u32) {
+		let page = unsafe {
+			core::slice::from_raw_parts_mut((frame + HK_OFST) as *mut u8, PAGE_SIZE)
+		};
+		page.fill(0);
+
+		if let VmaBacking::File(file) = &self.backing {
+			let page_offset = (address & !(PAGE_SIZE as u32 - 1)) - self.start;
+			let start = page_offset as usize;
+			if start < file.size {
+				let len = (file.size - start).min(PAGE_SIZE);
+				let source = file.offset + start;
+				page[..len].copy_from_slice(&file.data[source..source + len]);
+			}
+		}
+	}
+
+	// Cuts the VMA at `address`, it keeps the lower part and returns the upper one
+	pub fn split_off(&mut self, address: u32) -> Vma {
+		let backing = match &self.backing {
+			VmaBacking::File(file) => {
+				let skip = (address - self.start) as usize;
+				VmaBacking::File(FileBacking {
+					data: file.data.clone(),
+					offset: file.offset + skip,
+					size: file.size.saturating_sub(skip),
+				})
+			}
+			backing => backing.clone(),
+		};
+		let upper = Vma {
+			start: address,
+			end: self.end,
+			flags: self.flags,
+			backing,
+		};
+		self.end = address;
+		upper
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self.backing {
+			VmaBacking::Anonymous => "[anon]",
+			VmaBacking::File(_) => "[file]",
+			VmaBacking::Stack => "[stack]",
+		}
+	}
+}
@@ -79,7 +79,6 @@ impl VmallocHeader {
 }
 
 pub unsafe fn vmalloc(mut size: usize) -> Option<*mut u8> {
-	log!(LogLevel::Info, "vmalloc() allocating {} bytes", size);
 	size += VMALLOC_HEADER_SIZE;
 	let remaining = size % MIN_ALLOCATION_SIZE;
 	if remaining != 0 {
@@ -120,7 +119,6 @@ pub unsafe fn vmalloc(mut size: usize) -> Option<*mut u8> {
 }
 
 pub unsafe fn kfree(vmalloc_address: *mut u8) {
-	log!(LogLevel::Info, "kfree() freeing address: {:p}", vmalloc_address);
 	let header = (vmalloc_address as *mut VmallocHeader)
 		.offset(-1)
 		.as_mut()
@@ -199,7 +197,6 @@ pub unsafe fn vbrk(increment: isize) {
 			if VMALLOC_BREAK == VMALLOC_END {
 				return;
 			}
-			println_srl!("Mapping address {:p}...", VMALLOC_BREAK);
 			map_address(VMALLOC_BREAK);
 			VMALLOC_BREAK = VMALLOC_BREAK.offset(PAGE_SIZE as isize);
 		}
@@ -209,7 +206,6 @@ pub unsafe fn vbrk(increment: isize) {
 			if VMALLOC_BREAK == VMALLOC_START {
 				return;
 			}
-			println_srl!("Unmapping address {:p}...", VMALLOC_BREAK);
 			unmap_address(VMALLOC_BREAK);
 			VMALLOC_BREAK = VMALLOC_BREAK.offset(-(PAGE_SIZE as isize));
 		}
@@ -238,6 +234,21 @@ pub unsafe fn vheap_init() {
 	);
 }
 
+// Bytes mapped for the heap and bytes of the blocks in use, headers included. The caller
+// keeps the heap from changing meanwhile.
+pub unsafe fn vmalloc_usage() -> (usize, usize) {
+	let mut used = 0;
+	let mut current_header = VMALLOC_START as *mut VmallocHeader;
+	while !current_header.is_null() && current_header < VMALLOC_BREAK as *mut VmallocHeader {
+		let header = &*current_header;
+		if header.used() == USED {
+			used += header.size();
+		}
+		current_header = header.next();
+	}
+	(VMALLOC_BREAK as usize - VMALLOC_START as usize, used)
+}
+
 fn print_vmalloc_info() {
 	unsafe {
 		let mut current_header = VMALLOC_START as *mut VmallocHeader;
@@ -264,8 +275,6 @@ const MAX_PTRS: usize = 10;
 
 pub fn vmalloc_test() {
 	unsafe {
-		vheap_init();
-
 		println_srl!("\n");
 		log!(LogLevel::Info, "\t\tTesting vmalloc() and kfree()\n");
 
@@ -331,6 +340,10 @@ pub fn vmalloc_test() {
 		let ptr = vmalloc(1024 * 1024).expect("Failed to allocate memory");
 		ptrs[0] = ptr;
 		print_vmalloc_info();
+
+		log!(LogLevel::Info, "Freeing the 1MB block\n");
+		kfree(ptrs[0]);
+		print_vmalloc_info();
 	}
 	log!(LogLevel::Info, "\t\tEnd of vmalloc() and kfree() test\n");
 }
@@ -11,6 +11,7 @@ dd - (0xE85250D6 + 0)
 
 section .bootstrap_stack
 align 16
+global stack_top
 stack_bottom:
 times 32768 db 0
 stack_top:
@@ -20,7 +21,7 @@ align 4096
 boot_page_directory:
     resb 4096
 boot_page_table:
-    resb 4096 * 2
+    resb 4096 * 4
 
 section .boot
 global start
@@ -28,12 +29,12 @@ start:
     cli
     mov edi, boot_page_table - 0xC0000000
     mov esi, 0
-    mov ecx, 2048
+    mov ecx, 4096
 
 .loop_start:
     cmp esi, 0
     jl .skip_mapping
-    cmp esi, 0xC0800000 - 0xC0000000
+    cmp esi, 0xC1000000 - 0xC0000000
     jge .end_mapping
     mov edx, esi
     or edx, 0x003
@@ -47,8 +48,12 @@ start:
 .end_mapping:
     mov dword [boot_page_directory - 0xC0000000], boot_page_table - 0xC0000000 + 0x003
     mov dword [boot_page_directory - 0xC0000000 + 4], boot_page_table - 0xC0000000 + 0x1003
+    mov dword [boot_page_directory - 0xC0000000 + 8], boot_page_table - 0xC0000000 + 0x2003
+    mov dword [boot_page_directory - 0xC0000000 + 12], boot_page_table - 0xC0000000 + 0x3003
     mov dword [boot_page_directory - 0xC0000000 + 768 * 4], boot_page_table - 0xC0000000 + 0x003
     mov dword [boot_page_directory - 0xC0000000 + 769 * 4], boot_page_table - 0xC0000000 + 0x1003
+    mov dword [boot_page_directory - 0xC0000000 + 770 * 4], boot_page_table - 0xC0000000 + 0x2003
+    mov dword [boot_page_directory - 0xC0000000 + 771 * 4], boot_page_table - 0xC0000000 + 0x3003
     mov ecx, boot_page_directory - 0xC0000000
     mov cr3, ecx
     mov ecx, cr0
@@ -1,3 +1,6 @@
+use alloc::string::String;
+use spin::Mutex;
+
 use crate::{memory::kmem_managment::PMM, tools::debug::LogLevel};
 
 const MULTIBOOT_HEADER_MAGIC: u32 = 0xe85250d6;
@@ -51,6 +54,15 @@ pub struct MultibootTagString {
 	string: u8,
 }
 
+#[repr(C)]
+pub struct MultibootTagModule {
+	tag_type: u32,
+	size: u32,
+	mod_start: u32,
+	mod_end: u32,
+	cmdline: u8,
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct MultibootTagBasicMemInfo {
@@ -92,10 +104,75 @@ const MULTIBOOT_TAG_TYPE_END: u32 = 0;
 
 const MULTIBOOT_TAG_TYPE_CMDLINE: u32 = 1;
 const MULTIBOOT_TAG_TYPE_BOOT_LOADER_NAME: u32 = 2;
+const MULTIBOOT_TAG_TYPE_MODULE: u32 = 3;
 const MULTIBOOT_TAG_TYPE_BASIC_MEMINFO: u32 = 4;
 const MULTIBOOT_TAG_TYPE_BOOTDEV: u32 = 5;
 const MULTIBOOT_TAG_TYPE_MMAP: u32 = 6;
 
+const MAX_MODULES: usize = 8;
+const MODULE_CMDLINE_MAX: usize = 64;
+const CMDLINE_MAX: usize = 256;
+
+// Kernel command line from grub.cfg, copied for the same reason as the module ones
+static CMDLINE: Mutex<([u8; CMDLINE_MAX], usize)> = Mutex::new(([0; CMDLINE_MAX], 0));
+
+pub fn cmdline() -> String {
+	let (bytes, len) = *CMDLINE.lock();
+	String::from(core::str::from_utf8(&bytes[..len]).unwrap_or(""))
+}
+
+// Copies as much of `string` as fits in `buffer` without splitting a character
+fn copy_str(string: &str, buffer: &mut [u8]) -> usize {
+	let mut len = string.len().min(buffer.len());
+	while !string.is_char_boundary(len) {
+		len -= 1;
+	}
+	buffer[..len].copy_from_slice(&string.as_bytes()[..len]);
+	len
+}
+
+// Module loaded by the bootloader, from a `module2` line of grub.cfg. The addresses are
+// physical and the command line is copied since the boot information gets overwritten.
+#[derive(Clone, Copy)]
+pub struct BootModule {
+	pub start: u32,
+	pub end: u32,
+	cmdline: [u8; MODULE_CMDLINE_MAX],
+	cmdline_len: usize,
+}
+
+impl BootModule {
+	pub fn cmdline(&self) -> &str {
+		core::str::from_utf8(&self.cmdline[..self.cmdline_len]).unwrap_or("")
+	}
+}
+
+static MODULES: Mutex<[Option<BootModule>; MAX_MODULES]> = Mutex::new([None; MAX_MODULES]);
+
+pub fn modules() -> impl Iterator<Item = BootModule> {
+	let modules = *MODULES.lock();
+	modules.into_iter().flatten()
+}
+
+fn add_module(tag: &MultibootTagModule) {
+	let mut cmdline = [0; MODULE_CMDLINE_MAX];
+	let string = u8_to_str(&tag.cmdline);
+	let len = copy_str(string, &mut cmdline);
+
+	let mut modules = MODULES.lock();
+	match modules.iter_mut().find(|module| module.is_none()) {
+		Some(slot) => {
+			*slot = Some(BootModule {
+				start: tag.mod_start,
+				end: tag.mod_end,
+				cmdline,
+				cmdline_len: len,
+			})
+		}
+		None => println_srl!("      Too many modules, ignoring {}", string),
+	}
+}
+
 pub fn strlen(s: *const u8) -> usize {
 	let mut len = 0;
 	while unsafe { *s.add(len) } != 0 {
@@ -140,7 +217,10 @@ pub fn read_multiboot_info(address: u32) {
 			MULTIBOOT_TAG_TYPE_CMDLINE => {
 				let cmdline = unsafe { &*(current_tag as *const MultibootTagString) };
 				if cmdline.string != 0 {
-					println_srl!("      Command line: {}", u8_to_str(&cmdline.string));
+					let string = u8_to_str(&cmdline.string);
+					println_srl!("      Command line: {}", string);
+					let (bytes, len) = &mut *CMDLINE.lock();
+					*len = copy_str(string, bytes);
 				}
 			}
 			MULTIBOOT_TAG_TYPE_BOOT_LOADER_NAME => {
@@ -150,6 +230,16 @@ pub fn read_multiboot_info(address: u32) {
 					u8_to_str(&bootloader_name.string)
 				);
 			}
+			MULTIBOOT_TAG_TYPE_MODULE => {
+				let module = unsafe { &*(current_tag as *const MultibootTagModule) };
+				println_srl!(
+					"      Module: {:#x}-{:#x} {}",
+					module.mod_start,
+					module.mod_end,
+					u8_to_str(&module.cmdline)
+				);
+				add_module(module);
+			}
 			MULTIBOOT_TAG_TYPE_BASIC_MEMINFO => {
 				_meminfo = Some(unsafe { &*(current_tag as *const MultibootTagBasicMemInfo) });
 				println_srl!(
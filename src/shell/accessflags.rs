@@ -8,7 +8,10 @@ pub const KERNEL_STACK: u8 = PRESENT | TYPE | READABLE_WRITABLE | DIRECTION_CONF
 pub const USER_CODE: u8 = PRESENT | DPL | TYPE | READABLE_WRITABLE | EXECUTABLE;
 pub const USER_DATA: u8 = PRESENT | DPL | TYPE | READABLE_WRITABLE;
 pub const USER_STACK: u8 = PRESENT | DPL | TYPE | READABLE_WRITABLE | DIRECTION_CONFORMING;
+pub const TSS_SEGMENT: u8 = PRESENT | EXECUTABLE | ACCESSED;
 pub const SEGMENT_FLAGS: u8 = GRANULARITY | DB_SIZE | SEGMENT_SIZE_HIGH;
+pub const TLS_FLAGS: u8 = DB_SIZE;
+pub const TLS_PAGE_FLAGS: u8 = GRANULARITY | DB_SIZE;
 const GRANULARITY: u8 = 1 << 7;
 const DB_SIZE: u8 = 1 << 6;
 
@@ -24,6 +27,4 @@ const TYPE: u8 = 1 << 4;
 const EXECUTABLE: u8 = 1 << 3;
 const DIRECTION_CONFORMING: u8 = 1 << 2;
 const READABLE_WRITABLE: u8 = 1 << 1;
-
-#[allow(dead_code)]
 const ACCESSED: u8 = 1 << 0;
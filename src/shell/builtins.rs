@@ -1,10 +1,13 @@
 use crate::exceptions::interrupts::{self, TICKS};
+use crate::memory::slab::print_slab_info;
+use crate::shell::files;
 use crate::shell::history::HISTORY;
 use crate::shell::prints::PrintSM;
 use crate::shell::prints::{help, print_stack, print_unknown_command};
+use crate::task::scheduler;
 use crate::tools::debug::LogLevel;
 use crate::tools::io::{outb, outw};
-use crate::tools::librs::hlt;
+use crate::tools::librs::{cpu_brand, cpu_vendor, hlt};
 use crate::tools::librs::{get_rtc_date, get_rtc_time};
 use crate::tools::vga::WRITER;
 use core::sync::atomic::Ordering;
@@ -22,7 +25,9 @@ pub fn clear() {
 
 fn echo(line: &str) {
 	let message: &str = &line["echo".len()..];
-	if message.starts_with(" ") && message.len() > 1 {
+	if let Some(index) = message.find('>') {
+		files::echo_to_file(&message[..index], &message[index..]);
+	} else if message.starts_with(" ") && message.len() > 1 {
 		println!("{}", message[1..].trim());
 	} else {
 		println!("echo: missing argument");
@@ -116,26 +121,8 @@ fn describe_cr0(cr0: usize) {
 }
 
 fn cpu_info() {
-	let mut cpu_vendor = [0u8; 12];
-	let mut cpu_brand = [0u8; 48];
-	let mut eax: usize = 0;
-	let mut ebx: usize = 0;
-	let mut ecx: usize = 0;
-	let mut edx: usize = 0;
-
-	get_cpuid(0, &mut eax, &mut ebx, &mut ecx, &mut edx);
-	cpu_vendor[0..4].copy_from_slice(&ebx.to_ne_bytes());
-	cpu_vendor[4..8].copy_from_slice(&edx.to_ne_bytes());
-	cpu_vendor[8..12].copy_from_slice(&ecx.to_ne_bytes());
-
-	for i in 0x80000002..=0x80000004 {
-		get_cpuid(i, &mut eax, &mut ebx, &mut ecx, &mut edx);
-		let off = (i - 0x80000002) * 16;
-		cpu_brand[off..off + 4].copy_from_slice(&eax.to_ne_bytes());
-		cpu_brand[off + 4..off + 8].copy_from_slice(&ebx.to_ne_bytes());
-		cpu_brand[off + 8..off + 12].copy_from_slice(&ecx.to_ne_bytes());
-		cpu_brand[off + 12..off + 16].copy_from_slice(&edx.to_ne_bytes());
-	}
+	let cpu_vendor = cpu_vendor();
+	let cpu_brand = cpu_brand();
 
 	let cpu_vendor_str = core::str::from_utf8(&cpu_vendor).unwrap_or("Unknown");
 	let cpu_brand_str = core::str::from_utf8(&cpu_brand).unwrap_or("Unknown");
@@ -144,20 +131,6 @@ fn cpu_info() {
 	println!("CPU Brand: {}", cpu_brand_str);
 }
 
-fn get_cpuid(info_type: usize, eax: &mut usize, ebx: &mut usize, ecx: &mut usize, edx: &mut usize) {
-	unsafe {
-		core::arch::asm!(
-			"cpuid",
-			in("eax") info_type,
-			lateout("eax") *eax,
-			lateout("ebx") *ebx,
-			lateout("ecx") *ecx,
-			lateout("edx") *edx,
-			options(nostack, nomem, preserves_flags)
-		);
-	}
-}
-
 fn show_uptime() {
 	let uptime_seconds = TICKS.load(Ordering::SeqCst) / 18;
 
@@ -171,19 +144,23 @@ fn show_uptime() {
 	);
 }
 
-pub fn trigger_syscall(syscall_number: u32, arg1: u32, arg2: u32, arg3: u32) {
-	use crate::exceptions::syscalls::GeneralRegs;
-	let mut regs = GeneralRegs {
-		eax: syscall_number,
-		ebx: arg1,
-		ecx: arg2,
-		edx: arg3,
-		esi: 0,
-		edi: 0,
-		ebp: 0,
-	};
+fn ps() {
+	println!("{:>5} {:>5} {:<8} {:>8} NAME", "PID", "PPID", "STATE", "TICKS");
+	for task in scheduler::task_list() {
+		println!(
+			"{:>5} {:>5} {:<8} {:>8} {}",
+			task.pid,
+			task.ppid,
+			task.state.as_str(),
+			task.ticks,
+			task.name
+		);
+	}
+}
 
-	crate::exceptions::syscalls::syscall(&mut regs);
+pub fn trigger_syscall(syscall_number: u32, arg1: u32, arg2: u32, arg3: u32) {
+	let result = crate::exceptions::syscalls::kernel_syscall(syscall_number, arg1, arg2, arg3);
+	println!("Syscall {} returned {}", syscall_number, result as i32);
 }
 
 fn test_syscall(line: &str) {
@@ -231,6 +208,8 @@ pub fn readline(raw_line: &str) {
 		"uptime" => show_uptime(),
 		"cpu" => cpu_info(),
 		"mode" => cmd_mode(),
+		"slabinfo" => print_slab_info(),
+		"ps" => ps(),
 		_ => handle_special_commands(line),
 	}
 }
@@ -244,7 +223,7 @@ fn handle_special_commands(line: &str) {
 		print_stack(line, PrintSM::Srl);
 	} else if line.starts_with("test_syscall") {
 		test_syscall(line);
-	} else {
+	} else if !files::run(line) {
 		print_unknown_command(line);
 	}
 }
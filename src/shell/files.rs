@@ -0,0 +1,112 @@
+//! # File Commands
+//!
+//! Builtins working on the filesystem. Relative paths start from the working directory of
+//! the shell task, which `cd` changes.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::exceptions::errno::Errno;
+use crate::fs;
+use crate::fs::file::{O_APPEND, O_CREAT, O_DIRECTORY, O_RDONLY, O_TRUNC, O_WRONLY};
+use crate::task::scheduler;
+
+fn report(command: &str, path: &str, result: Result<(), Errno>) {
+	if let Err(errno) = result {
+		println!("{}: {}: error {}", command, path, errno);
+	}
+}
+
+fn ls(path: &str) -> Result<(), Errno> {
+	let directory = fs::open(path, O_RDONLY | O_DIRECTORY, 0)?;
+	let mut names = Vec::new();
+	directory.read_dir(|entry, _| {
+		let mut name = entry.name.clone();
+		if entry.file_type == fs::inode::FileType::Directory {
+			name.push('/');
+		}
+		names.push(name);
+		Ok(true)
+	})?;
+	println!("{}", names.join("  "));
+	Ok(())
+}
+
+fn cat(path: &str) -> Result<(), Errno> {
+	for byte in fs::read_file(path)? {
+		print!("{}", byte as char);
+	}
+	Ok(())
+}
+
+fn touch(path: &str) -> Result<(), Errno> {
+	fs::open(path, O_CREAT | O_WRONLY, 0o644).map(drop)
+}
+
+fn pwd() {
+	let cwd = scheduler::with_current(|task| task.cwd.clone());
+	println!("{}", cwd.map_or_else(|| String::from("/"), |cwd| cwd.path()));
+}
+
+// Without arguments lists the mounts, `mount hda /mnt ext2` mounts a disk
+fn mount(source: Option<&str>, target: Option<&str>, fs_type: Option<&str>) {
+	match (source, target, fs_type) {
+		(None, _, _) => {
+			for (path, fs_type) in fs::dentry::mounts() {
+				println!("{} on {}", fs_type, path);
+			}
+		}
+		(Some(source), Some(target), Some(fs_type)) => {
+			report("mount", target, fs::mount(source, target, fs_type));
+		}
+		_ => println!("mount: usage: mount device directory type"),
+	}
+}
+
+// `echo text > file` replaces the file, `echo text >> file` appends to it
+pub fn echo_to_file(text: &str, redirection: &str) {
+	let append = redirection.starts_with(">>");
+	let path = redirection.trim_start_matches('>').trim();
+	if path.is_empty() {
+		println!("echo: missing file name");
+		return;
+	}
+	let mode = if append { O_APPEND } else { O_TRUNC };
+	let result = fs::open(path, O_CREAT | O_WRONLY | mode, 0o644).and_then(|file| {
+		let mut line = String::from(text.trim());
+		line.push('\n');
+		file.write(line.as_bytes()).map(drop)
+	});
+	report("echo", path, result);
+}
+
+// Runs `line` if it is one of the file commands, returns false otherwise
+pub fn run(line: &str) -> bool {
+	let mut words = line.split_whitespace();
+	let command = words.next().unwrap_or("");
+	let argument = words.next();
+	let path = argument.unwrap_or(".");
+
+	match command {
+		"ls" => report(command, path, ls(path)),
+		"pwd" => pwd(),
+		"cd" => {
+			let path = argument.unwrap_or("/");
+			report(command, path, fs::chdir(path));
+		}
+		"mount" => mount(argument, words.next(), words.next()),
+		"umount" if argument.is_none() => println!("umount: missing operand"),
+		"umount" => report(command, path, fs::umount(path)),
+		"sync" => report(command, "/", fs::sync()),
+		"cat" | "touch" | "mkdir" | "rmdir" | "rm" if argument.is_none() => {
+			println!("{}: missing operand", command);
+		}
+		"cat" => report(command, path, cat(path)),
+		"touch" => report(command, path, touch(path)),
+		"mkdir" => report(command, path, fs::mkdir(path, 0o755)),
+		"rmdir" => report(command, path, fs::rmdir(path)),
+		"rm" => report(command, path, fs::unlink(path)),
+		_ => return false,
+	}
+	true
+}
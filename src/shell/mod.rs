@@ -1,4 +1,6 @@
 pub mod history;
 pub mod builtins;
+pub mod files;
 pub mod prints;
+pub mod serial;
 pub mod accessflags;
\ No newline at end of file
@@ -125,6 +125,8 @@ pub fn help() {
 	print_help_line("cpu", "display the CPU information");
 	print_help_line("mode", "display the current system mode");
 	print_help_line("uname", "print system information");
+	print_help_line("ps | slabinfo", "list the running tasks | the slab caches");
+	print_help_line("ls cat cd mkdir rm", "edit files, echo text > file, mount | umount | sync");
 	print_help_line("halt", "halt the system");
 	print_help_line("shutdown | reboot", "shutdown | reboot the system");
 	printraw("---------------------------------------------------------------------------------");
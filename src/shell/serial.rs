@@ -0,0 +1,133 @@
+//! # Serial Console
+//!
+//! Runs the shell over COM1 when the kernel is booted with `console=ttyS0`, to drive it from
+//! `qemu -serial stdio` or a test script. Lines are edited here and echoed to the terminal
+//! on the other end, then run like the lines typed on the keyboard. What the shell prints
+//! goes to the screen and to the serial console alike. While a program reads `/dev/ttyS0`,
+//! or when COM1 is not the console, the bytes go to the terminal instead.
+
+use spin::Mutex;
+
+use crate::drivers::serial;
+use crate::drivers::tty::{self, SERIAL_TTY};
+use crate::shell::builtins::{self, readline, MAX_LINE_LENGTH};
+use crate::task::signal::{self, SIGINT};
+use crate::tools::prompt::PROMPT_STRING;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+	None,
+	// After ESC
+	Started,
+	// Inside a CSI sequence, such as the ones of the arrow keys, which are ignored
+	Sequence,
+}
+
+struct SerialLine {
+	buffer: [u8; MAX_LINE_LENGTH],
+	length: usize,
+	escape: Escape,
+	// Terminals end lines with CR, pipes with LF, and some with both
+	after_cr: bool,
+}
+
+static LINE: Mutex<SerialLine> = Mutex::new(SerialLine {
+	buffer: [0; MAX_LINE_LENGTH],
+	length: 0,
+	escape: Escape::None,
+	after_cr: false,
+});
+
+fn prompt() {
+	serial::write(PROMPT_STRING.as_bytes());
+}
+
+pub fn init() {
+	if serial::is_console() {
+		prompt();
+	}
+}
+
+// Runs the lines received since the last call
+pub fn process_serial_input() {
+	let mut input = [0; 64];
+	loop {
+		let read = serial::read(&mut input);
+		if read == 0 {
+			break;
+		}
+		if !serial::is_console() || tty::wants_input(SERIAL_TTY) {
+			tty::input(SERIAL_TTY, &input[..read]);
+			continue;
+		}
+		for &byte in &input[..read] {
+			handle_byte(byte);
+		}
+	}
+}
+
+fn handle_byte(byte: u8) {
+	let mut line = LINE.lock();
+	let after_cr = core::mem::replace(&mut line.after_cr, byte == b'\r');
+	match line.escape {
+		Escape::Started => {
+			line.escape = match byte {
+				b'[' => Escape::Sequence,
+				_ => Escape::None,
+			};
+			return;
+		}
+		Escape::Sequence => {
+			if (0x40..=0x7e).contains(&byte) {
+				line.escape = Escape::None;
+			}
+			return;
+		}
+		Escape::None => {}
+	}
+
+	match byte {
+		0x1b => line.escape = Escape::Started,
+		b'\n' if after_cr => {}
+		b'\r' | b'\n' => {
+			serial::write(b"\r\n");
+			let (buffer, length) = (line.buffer, line.length);
+			line.length = 0;
+			drop(line);
+			readline(core::str::from_utf8(&buffer[..length]).unwrap_or(""));
+			prompt();
+		}
+		// Backspace and DEL
+		0x08 | 0x7f => {
+			if line.length > 0 {
+				line.length -= 1;
+				serial::write(b"\x08 \x08");
+			}
+		}
+		// Ctrl-C
+		0x03 => {
+			line.length = 0;
+			drop(line);
+			serial::write(b"^C\r\n");
+			if !signal::signal_foreground(SIGINT) {
+				prompt();
+			}
+		}
+		// Ctrl-L
+		0x0c => {
+			let (buffer, length) = (line.buffer, line.length);
+			drop(line);
+			builtins::clear();
+			serial::write(b"\x1b[2J\x1b[H");
+			prompt();
+			serial::write(&buffer[..length]);
+		}
+		0x20..=0x7e if line.length < MAX_LINE_LENGTH => {
+			let length = line.length;
+			line.buffer[length] = byte;
+			line.length += 1;
+			serial::write(&[byte]);
+		}
+		_ => {}
+	}
+}
@@ -0,0 +1,463 @@
+//! # ELF Loader
+//!
+//! Loads statically linked ELF32 i386 executables (`ET_EXEC`) into a user address space.
+//! Every `PT_LOAD` segment becomes a file backed VMA with the permissions of its program
+//! header: pages are copied from the image the first time they are touched and everything
+//! past `p_filesz` reads as zero, which takes care of `.bss`.
+//!
+//! The initial stack follows the i386 System V ABI: argc, argv, envp and the auxiliary vector
+//! from the stack pointer up, the strings they point to at the top of the stack.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use super::scheduler;
+use super::task::Pid;
+use crate::memory::address_space::{AddressSpace, USER_SPACE_END};
+use crate::memory::page_directory::PAGE_SIZE;
+use crate::memory::vma::{FileBacking, Vma, VmaBacking, VmaFlags};
+use crate::tools::debug::LogLevel;
+use crate::tools::librs::rdtsc;
+
+pub const USER_STACK_TOP: u32 = USER_SPACE_END;
+// Initial size of the stack VMA, it grows on faults up to MAX_STACK_SIZE
+const USER_STACK_SIZE: u32 = 64 * 1024;
+// Room left on the initial stack for the strings of argv and envp
+pub const MAX_ARGS_SIZE: usize = 32 * 1024;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u32 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+
+const PF_X: u32 = 0b1;
+const PF_W: u32 = 0b10;
+const PF_R: u32 = 0b100;
+
+const AT_NULL: u32 = 0;
+const AT_PHDR: u32 = 3;
+const AT_PHENT: u32 = 4;
+const AT_PHNUM: u32 = 5;
+const AT_PAGESZ: u32 = 6;
+const AT_ENTRY: u32 = 9;
+const AT_UID: u32 = 11;
+const AT_EUID: u32 = 12;
+const AT_GID: u32 = 13;
+const AT_EGID: u32 = 14;
+const AT_RANDOM: u32 = 25;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ElfHeader {
+	pub ident: [u8; 16],
+	pub elf_type: u16,
+	pub machine: u16,
+	pub version: u32,
+	pub entry: u32,
+	pub phoff: u32,
+	pub shoff: u32,
+	pub flags: u32,
+	pub ehsize: u16,
+	pub phentsize: u16,
+	pub phnum: u16,
+	pub shentsize: u16,
+	pub shnum: u16,
+	pub shstrndx: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ProgramHeader {
+	pub p_type: u32,
+	pub offset: u32,
+	pub vaddr: u32,
+	pub paddr: u32,
+	pub filesz: u32,
+	pub memsz: u32,
+	pub flags: u32,
+	pub align: u32,
+}
+
+// What is left to know about an image once its segments are mapped
+#[derive(Debug, Clone, Copy)]
+pub struct ElfImage {
+	pub entry: u32,
+	pub phdr: u32,
+	pub phnum: u32,
+	// First page after the highest segment, where the heap starts
+	pub brk: u32,
+}
+
+fn read<T: Copy>(data: &[u8], offset: usize) -> Result<T, &'static str> {
+	match offset.checked_add(size_of::<T>()) {
+		Some(end) if end <= data.len() => {
+			Ok(unsafe { (data.as_ptr().add(offset) as *const T).read_unaligned() })
+		}
+		_ => Err("Truncated ELF image"),
+	}
+}
+
+pub fn parse_header(data: &[u8]) -> Result<ElfHeader, &'static str> {
+	let header: ElfHeader = read(data, 0)?;
+	if header.ident[..4] != ELF_MAGIC {
+		return Err("Not an ELF image");
+	}
+	if header.ident[4] != ELFCLASS32 || header.ident[5] != ELFDATA2LSB {
+		return Err("Not a 32-bit little endian ELF image");
+	}
+	if header.version != EV_CURRENT {
+		return Err("Unsupported ELF version");
+	}
+	if header.elf_type != ET_EXEC {
+		return Err("Not an ELF executable");
+	}
+	if header.machine != EM_386 {
+		return Err("Not an i386 ELF image");
+	}
+	if header.phentsize as usize != size_of::<ProgramHeader>() {
+		return Err("Invalid ELF program header size");
+	}
+	Ok(header)
+}
+
+pub fn program_headers(data: &[u8], header: &ElfHeader) -> Result<Vec<ProgramHeader>, &'static str> {
+	(0..header.phnum as usize)
+		.map(|index| {
+			match (header.phoff as usize).checked_add(index * size_of::<ProgramHeader>()) {
+				Some(offset) => read(data, offset),
+				None => Err("Truncated ELF image"),
+			}
+		})
+		.collect()
+}
+
+fn segment_flags(header: &ProgramHeader) -> VmaFlags {
+	let mut flags = VmaFlags::empty();
+	if header.flags & PF_R != 0 {
+		flags |= VmaFlags::READ;
+	}
+	if header.flags & PF_W != 0 {
+		flags |= VmaFlags::WRITE;
+	}
+	if header.flags & PF_X != 0 {
+		flags |= VmaFlags::EXEC;
+	}
+	flags
+}
+
+// Maps every loadable segment of `data` in `space`
+pub fn load(space: &mut AddressSpace, data: Arc<Vec<u8>>) -> Result<ElfImage, &'static str> {
+	let header = parse_header(&data)?;
+	let headers = program_headers(&data, &header)?;
+	let headers_end = header
+		.phoff
+		.checked_add(header.phnum as u32 * size_of::<ProgramHeader>() as u32)
+		.ok_or("Truncated ELF image")?;
+
+	let mut image = ElfImage {
+		entry: header.entry,
+		phdr: 0,
+		phnum: header.phnum as u32,
+		brk: 0,
+	};
+	for segment in headers.iter() {
+		match segment.p_type {
+			PT_LOAD => {}
+			PT_INTERP => return Err("Dynamically linked executables are not supported"),
+			_ => continue,
+		}
+
+		let end = match segment.vaddr.checked_add(segment.memsz) {
+			Some(end) if end <= USER_SPACE_END => end,
+			_ => return Err("ELF segment outside of user space"),
+		};
+		// usize is 32 bits wide here, a huge offset would wrap past the bounds check
+		let file_end = match segment.offset.checked_add(segment.filesz) {
+			Some(end) if segment.filesz <= segment.memsz && end as usize <= data.len() => end,
+			_ => return Err("Invalid ELF segment size"),
+		};
+		let page_offset = segment.vaddr & (PAGE_SIZE as u32 - 1);
+		if segment.offset & (PAGE_SIZE as u32 - 1) != page_offset {
+			return Err("Misaligned ELF segment");
+		}
+		let backing_size = segment
+			.filesz
+			.checked_add(page_offset)
+			.ok_or("Invalid ELF segment size")?;
+		if segment.memsz == 0 {
+			continue;
+		}
+
+		// The VMA starts on a page boundary, so the backing starts that many bytes earlier
+		let backing = VmaBacking::File(FileBacking {
+			data: data.clone(),
+			offset: (segment.offset - page_offset) as usize,
+			size: backing_size as usize,
+		});
+		space.add_vma(Vma::new(segment.vaddr, end, segment_flags(segment), backing))?;
+
+		if segment.offset <= header.phoff && headers_end <= file_end {
+			image.phdr = segment.vaddr + header.phoff - segment.offset;
+		}
+		image.brk = image.brk.max(end.next_multiple_of(PAGE_SIZE as u32));
+	}
+
+	match space.find_vma(image.entry) {
+		Some(vma) if vma.flags.contains(VmaFlags::EXEC) => Ok(image),
+		_ => Err("ELF entry point outside of the executable segments"),
+	}
+}
+
+// Seed for AT_RANDOM, good enough until the kernel has an entropy source
+fn random_bytes() -> [u8; 16] {
+	let mut state = rdtsc() ^ 0x9e37_79b9_7f4a_7c15;
+	let mut bytes = [0; 16];
+	for chunk in bytes.chunks_mut(8) {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		chunk.copy_from_slice(&state.to_le_bytes());
+	}
+	bytes
+}
+
+fn push_bytes(space: &mut AddressSpace, sp: &mut u32, bytes: &[u8]) -> Result<u32, &'static str> {
+	*sp -= bytes.len() as u32;
+	space.write_bytes(*sp, bytes)?;
+	Ok(*sp)
+}
+
+fn push_string(space: &mut AddressSpace, sp: &mut u32, string: &str) -> Result<u32, &'static str> {
+	*sp -= 1;
+	space.write_bytes(*sp, &[0])?;
+	push_bytes(space, sp, string.as_bytes())
+}
+
+// Creates the stack VMA and the initial stack, returns the stack pointer to start with
+pub fn setup_stack(
+	space: &mut AddressSpace,
+	image: &ElfImage,
+	argv: &[&str],
+	envp: &[&str],
+) -> Result<u32, &'static str> {
+	let strings_size: usize = argv.iter().chain(envp.iter()).map(|s| s.len() + 1).sum();
+	if strings_size > MAX_ARGS_SIZE {
+		return Err("Argument list too long");
+	}
+	space.add_vma(Vma::new(
+		USER_STACK_TOP - USER_STACK_SIZE,
+		USER_STACK_TOP,
+		VmaFlags::READ | VmaFlags::WRITE,
+		VmaBacking::Stack,
+	))?;
+
+	let mut sp = USER_STACK_TOP;
+	let mut env_pointers = Vec::with_capacity(envp.len());
+	for string in envp.iter() {
+		env_pointers.push(push_string(space, &mut sp, string)?);
+	}
+	let mut arg_pointers = Vec::with_capacity(argv.len());
+	for string in argv.iter() {
+		arg_pointers.push(push_string(space, &mut sp, string)?);
+	}
+	let random = push_bytes(space, &mut sp, &random_bytes())?;
+
+	let mut words = Vec::new();
+	words.push(argv.len() as u32);
+	words.extend_from_slice(&arg_pointers);
+	words.push(0);
+	words.extend_from_slice(&env_pointers);
+	words.push(0);
+	words.extend_from_slice(&[
+		AT_PHDR,
+		image.phdr,
+		AT_PHENT,
+		size_of::<ProgramHeader>() as u32,
+		AT_PHNUM,
+		image.phnum,
+		AT_PAGESZ,
+		PAGE_SIZE as u32,
+		AT_ENTRY,
+		image.entry,
+		AT_UID,
+		0,
+		AT_EUID,
+		0,
+		AT_GID,
+		0,
+		AT_EGID,
+		0,
+		AT_RANDOM,
+		random,
+		AT_NULL,
+		0,
+	]);
+
+	// argc must sit on a 16 byte boundary
+	sp = (sp - (words.len() * size_of::<u32>()) as u32) & !0xf;
+	let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+	space.write_bytes(sp, &bytes)?;
+	Ok(sp)
+}
+
+// Builds a fresh address space running `data`, returns it with the entry point and the
+// initial stack pointer
+pub fn load_program(
+	data: Arc<Vec<u8>>,
+	argv: &[&str],
+	envp: &[&str],
+) -> Result<(AddressSpace, u32, u32), &'static str> {
+	let mut space = AddressSpace::new()?;
+	let image = load(&mut space, data)?;
+	let stack = setup_stack(&mut space, &image, argv, envp)?;
+	space.init_brk(image.brk);
+	Ok((space, image.entry, stack))
+}
+
+// Loads `data` in a fresh address space and starts it as a new user task
+pub fn spawn(name: &str, data: Arc<Vec<u8>>, argv: &[&str], envp: &[&str]) -> Result<Pid, &'static str> {
+	let (space, entry, stack) = load_program(data, argv, envp)?;
+	log!(
+		LogLevel::Info,
+		"Starting {} at {:#x} with stack {:#x}",
+		name,
+		entry,
+		stack
+	);
+	scheduler::spawn_user(name, space, entry, stack)
+}
+
+const TEST_TEXT: u32 = 0x0804_8000;
+const TEST_DATA: u32 = 0x0804_a000;
+const TEST_DATA_OFFSET: usize = 0x1000;
+const TEST_DATA_SIZE: usize = 0x10;
+const TEST_BSS_SIZE: u32 = 0x2000;
+// mov eax, 1; mov ebx, 0; int 0x80; jmp $
+const TEST_CODE: [u8; 14] = [
+	0xb8, 0x01, 0x00, 0x00, 0x00, 0xbb, 0x00, 0x00, 0x00, 0x00, 0xcd, 0x80, 0xeb, 0xfe,
+];
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+	unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+// A text segment holding the headers and `code`, and a data segment followed by .bss
+pub fn test_image(code: &[u8]) -> Vec<u8> {
+	let code_offset = size_of::<ElfHeader>() + 2 * size_of::<ProgramHeader>();
+	let mut image = alloc::vec![0u8; TEST_DATA_OFFSET + TEST_DATA_SIZE];
+
+	let mut ident = [0; 16];
+	ident[..4].copy_from_slice(&ELF_MAGIC);
+	ident[4] = ELFCLASS32;
+	ident[5] = ELFDATA2LSB;
+	ident[6] = EV_CURRENT as u8;
+	let header = ElfHeader {
+		ident,
+		elf_type: ET_EXEC,
+		machine: EM_386,
+		version: EV_CURRENT,
+		entry: TEST_TEXT + code_offset as u32,
+		phoff: size_of::<ElfHeader>() as u32,
+		shoff: 0,
+		flags: 0,
+		ehsize: size_of::<ElfHeader>() as u16,
+		phentsize: size_of::<ProgramHeader>() as u16,
+		phnum: 2,
+		shentsize: 0,
+		shnum: 0,
+		shstrndx: 0,
+	};
+	let text = ProgramHeader {
+		p_type: PT_LOAD,
+		offset: 0,
+		vaddr: TEST_TEXT,
+		paddr: TEST_TEXT,
+		filesz: (code_offset + code.len()) as u32,
+		memsz: (code_offset + code.len()) as u32,
+		flags: PF_R | PF_X,
+		align: PAGE_SIZE as u32,
+	};
+	let data = ProgramHeader {
+		p_type: PT_LOAD,
+		offset: TEST_DATA_OFFSET as u32,
+		vaddr: TEST_DATA,
+		paddr: TEST_DATA,
+		filesz: TEST_DATA_SIZE as u32,
+		memsz: TEST_DATA_SIZE as u32 + TEST_BSS_SIZE,
+		flags: PF_R | PF_W,
+		align: PAGE_SIZE as u32,
+	};
+
+	let mut offset = 0;
+	for bytes in [as_bytes(&header), as_bytes(&text), as_bytes(&data), code] {
+		image[offset..offset + bytes.len()].copy_from_slice(bytes);
+		offset += bytes.len();
+	}
+	image[TEST_DATA_OFFSET..].fill(0xab);
+	image
+}
+
+pub fn elf_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the ELF loader\n");
+
+	let bytes = test_image(&TEST_CODE);
+	let mut space = AddressSpace::new().expect("Failed to create address space");
+
+	log!(LogLevel::Info, "Rejecting images that are not i386 executables\n");
+	let mut broken = bytes.clone();
+	broken[0] = 0;
+	assert!(load(&mut space, Arc::new(broken)).is_err());
+	let mut broken = bytes.clone();
+	broken[18] = 62;
+	assert!(load(&mut space, Arc::new(broken)).is_err());
+	assert!(space.vmas().is_empty());
+
+	log!(LogLevel::Info, "Rejecting a segment whose offset wraps past the image\n");
+	let mut broken = bytes.clone();
+	let data_header = size_of::<ElfHeader>() + size_of::<ProgramHeader>();
+	broken[data_header + 4..data_header + 8].copy_from_slice(&0xffff_f000u32.to_le_bytes());
+	broken[data_header + 16..data_header + 20].copy_from_slice(&0x2000u32.to_le_bytes());
+	let mut scratch = AddressSpace::new().expect("Failed to create address space");
+	assert!(load(&mut scratch, Arc::new(broken)).is_err());
+	drop(scratch);
+
+	log!(LogLevel::Info, "Loading a two segment image\n");
+	let image = load(&mut space, Arc::new(bytes.clone())).expect("Failed to load image");
+	println_srl!("\t{:?}", image);
+	assert!(space.vmas().len() == 2);
+	assert!(image.phdr == TEST_TEXT + size_of::<ElfHeader>() as u32);
+	assert!(image.brk == TEST_DATA + 3 * PAGE_SIZE as u32);
+
+	let mut code = [0; TEST_CODE.len()];
+	space.read_bytes(image.entry, &mut code).unwrap();
+	assert!(code == TEST_CODE);
+	let mut data = [0; 2 * TEST_DATA_SIZE];
+	space.read_bytes(TEST_DATA, &mut data).unwrap();
+	assert!(data[..TEST_DATA_SIZE].iter().all(|&byte| byte == 0xab));
+	assert!(data[TEST_DATA_SIZE..].iter().all(|&byte| byte == 0));
+	assert!(space.write_bytes(image.entry, &[0]).is_err());
+
+	log!(LogLevel::Info, "Building the initial stack\n");
+	let sp = setup_stack(&mut space, &image, &["test", "arg"], &["HOME=/"])
+		.expect("Failed to set up the stack");
+	let mut words = [0u32; 4];
+	space
+		.read_bytes(sp, unsafe {
+			core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, 16)
+		})
+		.unwrap();
+	println_srl!("\tStack pointer {:#x}: {:x?}", sp, words);
+	assert!(sp % 16 == 0 && words[0] == 2 && words[3] == 0);
+	let mut name = [0; 5];
+	space.read_bytes(words[1], &mut name).unwrap();
+	assert!(&name == b"test\0");
+
+	log!(LogLevel::Info, "\t\tEnd of ELF loader test\n");
+}
@@ -0,0 +1,4 @@
+pub mod elf;
+pub mod scheduler;
+pub mod signal;
+pub mod task;
@@ -0,0 +1,620 @@
+//! # Scheduler
+//!
+//! Preemptive round-robin over a single run queue. The timer interrupt calls `tick()` and the
+//! running task is switched out once it has used up its time slice. The boot context becomes
+//! the idle task (pid 0), it never sits in the run queue and only runs when nothing else is
+//! ready.
+//!
+//! Switching happens in `switch_context`, which swaps kernel stacks. A task that exits
+//! becomes a zombie until its parent collects it with `wait`, its children are handed over
+//! to the init task (pid 1) which collects them in a loop. The idle task never waits, so its
+//! children go straight to `Dead` and `reap()` frees them from another task, since a task
+//! cannot give back the stack it is running on.
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::task::{stopped_status, Pid, Task, TaskState, KERNEL_STACK_ORDER};
+use super::signal::{sigmask, SIGCHLD, SIGCONT};
+use crate::exceptions::errno::{Errno, ECHILD, EINTR, EPERM, ESRCH};
+use crate::exceptions::interrupts;
+use crate::exceptions::interrupts::TrapFrame;
+use crate::fs::fd::FdTable;
+use crate::gdt::set_tls;
+use crate::gdt::tss::{enter_user_mode, set_kernel_stack};
+use crate::memory::address_space::{self, switch_to_kernel, AddressSpace};
+use crate::memory::kmem_managment::PMM;
+use crate::tools::debug::LogLevel;
+use crate::tools::irqlock::IrqMutex;
+
+global_asm!(include_str!("switch.s"), options(att_syntax));
+
+extern "C" {
+	fn switch_context(old_esp: *mut u32, new_esp: u32);
+}
+
+pub const IDLE_PID: Pid = 0;
+pub const INIT_PID: Pid = 1;
+
+// In timer ticks, the PIT runs at its default 18.2 Hz
+pub const DEFAULT_TIME_SLICE: u32 = 2;
+
+static TIME_SLICE: AtomicU32 = AtomicU32::new(DEFAULT_TIME_SLICE);
+
+static SCHEDULER: IrqMutex<Scheduler> = IrqMutex::new(Scheduler::new());
+
+// Children a wait is after, as selected by the pid argument of waitpid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitTarget {
+	Any,
+	Pid(Pid),
+	Group(Pid),
+}
+
+impl WaitTarget {
+	fn matches(&self, task: &Task) -> bool {
+		match *self {
+			WaitTarget::Any => true,
+			WaitTarget::Pid(pid) => task.pid == pid,
+			WaitTarget::Group(pgid) => task.pgid == pgid,
+		}
+	}
+}
+
+struct Scheduler {
+	tasks: BTreeMap<Pid, Box<Task>>,
+	run_queue: VecDeque<Pid>,
+	current: Pid,
+	next_pid: Pid,
+	slice_left: u32,
+	running: bool,
+}
+
+impl Scheduler {
+	const fn new() -> Scheduler {
+		Scheduler {
+			tasks: BTreeMap::new(),
+			run_queue: VecDeque::new(),
+			current: IDLE_PID,
+			next_pid: IDLE_PID + 1,
+			slice_left: DEFAULT_TIME_SLICE,
+			running: false,
+		}
+	}
+
+	fn current_task(&mut self) -> &mut Task {
+		self.tasks
+			.get_mut(&self.current)
+			.expect("Current task missing from the task list")
+	}
+
+	// Removes the zombie children of the current task matching `target`, at most `limit`
+	fn take_zombies(&mut self, target: WaitTarget, limit: usize) -> Vec<Box<Task>> {
+		let parent = self.current;
+		let pids: Vec<Pid> = self
+			.tasks
+			.values()
+			.filter(|task| task.ppid == parent && task.state == TaskState::Zombie)
+			.filter(|task| target.matches(task))
+			.map(|task| task.pid)
+			.take(limit)
+			.collect();
+		pids.iter()
+			.filter_map(|pid| self.tasks.remove(pid))
+			.collect()
+	}
+
+	fn has_child(&self, target: WaitTarget) -> bool {
+		let parent = self.current;
+		self.tasks
+			.values()
+			.any(|task| task.ppid == parent && task.pid != parent && target.matches(task))
+	}
+
+	// Takes the stop of a child matching `target` that no wait has reported yet
+	fn take_stopped(&mut self, target: WaitTarget) -> Option<(Pid, u32)> {
+		let parent = self.current;
+		let task = self.tasks.values_mut().find(|task| {
+			task.ppid == parent
+				&& task.state == TaskState::Stopped
+				&& task.stop_signal != 0
+				&& target.matches(task)
+		})?;
+		let signal = core::mem::take(&mut task.stop_signal);
+		Some((task.pid, stopped_status(signal)))
+	}
+
+	// Hands the children of the current task over to init and marks it as exited
+	fn exit_current(&mut self, status: u32) {
+		let current = self.current;
+		let mut orphaned_zombie = false;
+		for task in self.tasks.values_mut() {
+			if task.ppid == current && task.pid != current {
+				task.ppid = INIT_PID;
+				orphaned_zombie |= task.state == TaskState::Zombie;
+			}
+		}
+
+		let task = self.current_task();
+		task.exit_status = status;
+		task.tls = None;
+		let parent = task.ppid;
+		if parent == IDLE_PID || parent == current {
+			task.state = TaskState::Dead;
+		} else {
+			task.state = TaskState::Zombie;
+			if let Some(parent_task) = self.tasks.get_mut(&parent) {
+				parent_task.signals.queue(SIGCHLD);
+			}
+			self.wake(parent);
+		}
+		if orphaned_zombie {
+			self.wake(INIT_PID);
+		}
+	}
+
+	fn wake(&mut self, pid: Pid) {
+		if let Some(task) = self.tasks.get_mut(&pid) {
+			if task.state == TaskState::Blocked {
+				task.state = TaskState::Ready;
+				self.run_queue.push_back(pid);
+			}
+		}
+	}
+
+	fn is_ready(&self, pid: Pid) -> bool {
+		self.tasks
+			.get(&pid)
+			.is_some_and(|task| task.state == TaskState::Ready)
+	}
+
+	// Picks the next task and loads its kernel stack and address space, returns where to
+	// save the current stack pointer and the stack pointer to resume
+	fn switch_next(&mut self) -> Option<(*mut u32, u32)> {
+		let previous = self.current;
+		let task = self.current_task();
+		if task.state == TaskState::Running {
+			task.state = TaskState::Ready;
+			if previous != IDLE_PID {
+				self.run_queue.push_back(previous);
+			}
+		}
+
+		let next = loop {
+			match self.run_queue.pop_front() {
+				Some(pid) if self.is_ready(pid) => break pid,
+				Some(_) => continue,
+				None => break IDLE_PID,
+			}
+		};
+		self.slice_left = TIME_SLICE.load(Ordering::Relaxed);
+		self.current = next;
+
+		let next_task = self.current_task();
+		next_task.state = TaskState::Running;
+		if next == previous {
+			return None;
+		}
+
+		set_kernel_stack(next_task.kernel_stack_top());
+		set_tls(next_task.tls);
+		match &next_task.address_space {
+			Some(space) if !space.is_active() => space.activate(),
+			Some(_) => {}
+			None if address_space::current().is_some() => switch_to_kernel(),
+			None => {}
+		}
+		let new_esp = next_task.esp;
+		let old_esp = &mut self.tasks.get_mut(&previous)?.esp as *mut u32;
+		Some((old_esp, new_esp))
+	}
+}
+
+// First code run by a spawned task, the previous task switched out with interrupts disabled
+extern "C" fn task_entry() -> ! {
+	let entry = SCHEDULER.lock().current_task().entry.take();
+	interrupts::enable();
+	if let Some(entry) = entry {
+		entry();
+	}
+	exit(0);
+}
+
+// Collects the zombies handed over to init, sleeps when there are none
+fn init_thread() {
+	loop {
+		let zombies = {
+			let mut scheduler = SCHEDULER.lock();
+			let zombies = scheduler.take_zombies(WaitTarget::Any, usize::MAX);
+			if zombies.is_empty() {
+				scheduler.current_task().state = TaskState::Blocked;
+			}
+			zombies
+		};
+		if zombies.is_empty() {
+			schedule();
+		}
+		drop(zombies);
+	}
+}
+
+// First code run by a user task, its address space is already loaded
+extern "C" fn user_task_entry() -> ! {
+	let start = SCHEDULER.lock().current_task().user_start.take();
+	let (entry, user_stack) = start.expect("User task started without an entry point");
+	unsafe { enter_user_mode(entry, user_stack) }
+}
+
+// Turns the boot context into the idle task and starts preempting
+pub fn init() {
+	let mut scheduler = SCHEDULER.lock();
+	scheduler
+		.tasks
+		.insert(IDLE_PID, Box::new(Task::boot(IDLE_PID, "idle")));
+	scheduler.current = IDLE_PID;
+	scheduler.running = true;
+	drop(scheduler);
+	let init = spawn("init", init_thread).expect("Failed to spawn init");
+	assert!(init == INIT_PID, "init did not get pid {}", INIT_PID);
+	log!(
+		LogLevel::Info,
+		"Scheduler started with a time slice of {} ticks",
+		TIME_SLICE.load(Ordering::Relaxed)
+	);
+}
+
+pub fn set_time_slice(ticks: u32) {
+	TIME_SLICE.store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub fn time_slice() -> u32 {
+	TIME_SLICE.load(Ordering::Relaxed)
+}
+
+// Queues the task built by `create` as a child of the current task
+fn add_task<F: FnOnce(Pid) -> Result<Task, &'static str>>(create: F) -> Result<Pid, &'static str> {
+	reap();
+	let (pid, parent) = {
+		let mut scheduler = SCHEDULER.lock();
+		let pid = scheduler.next_pid;
+		scheduler.next_pid += 1;
+		(pid, scheduler.current)
+	};
+
+	let mut task = Box::new(create(pid)?);
+	task.ppid = parent;
+
+	let mut scheduler = SCHEDULER.lock();
+	scheduler.tasks.insert(pid, task);
+	scheduler.run_queue.push_back(pid);
+	Ok(pid)
+}
+
+// Starts a kernel thread running `entry`, the thread exits when `entry` returns
+pub fn spawn(name: &str, entry: fn()) -> Result<Pid, &'static str> {
+	add_task(|pid| {
+		let mut task = Task::new(pid, name, task_entry)?;
+		task.entry = Some(entry);
+		Ok(task)
+	})
+}
+
+// Starts a task that enters ring 3 in `address_space` at `entry` with `user_stack`, its
+// standard streams open on the console
+pub fn spawn_user(
+	name: &str,
+	address_space: AddressSpace,
+	entry: u32,
+	user_stack: u32,
+) -> Result<Pid, &'static str> {
+	add_task(|pid| {
+		let mut task = Task::new(pid, name, user_task_entry)?;
+		task.address_space = Some(address_space);
+		task.user_start = Some((entry, user_stack));
+		task.files = FdTable::console();
+		Ok(task)
+	})
+}
+
+// Duplicates the current user task, the child returns from the syscall of `frame` with 0
+pub fn fork(frame: &TrapFrame) -> Result<Pid, &'static str> {
+	let address_space = address_space::current()
+		.ok_or("Kernel threads cannot fork")?
+		.fork()?;
+	let (name, tls, signals, files, cwd, group) = with_current(|task| {
+		let signals = task.signals.fork();
+		let group = (task.pgid, task.sid);
+		(task.name.clone(), task.tls, signals, task.files.clone(), task.cwd.clone(), group)
+	});
+	add_task(|pid| {
+		let mut task = Task::fork(pid, &name, frame)?;
+		(task.pgid, task.sid) = group;
+		task.address_space = Some(address_space);
+		task.tls = tls;
+		task.signals = signals;
+		task.files = files;
+		task.cwd = cwd;
+		Ok(task)
+	})
+}
+
+// Switches to the next ready task, returns once the current task is scheduled again
+pub fn schedule() {
+	let enabled = interrupts::are_enabled();
+	interrupts::disable();
+	let switch = SCHEDULER.lock().switch_next();
+	if let Some((old_esp, new_esp)) = switch {
+		unsafe {
+			switch_context(old_esp, new_esp);
+			// Reload GS so its cached descriptor matches the TLS entry of this task
+			asm!("mov {0:x}, gs", "mov gs, {0:x}", out(reg) _, options(nostack, preserves_flags));
+		}
+	}
+	if enabled {
+		interrupts::enable();
+	}
+}
+
+pub fn yield_now() {
+	schedule();
+}
+
+// Called on every timer interrupt, preempts the current task at the end of its time slice
+pub fn tick() {
+	let preempt = {
+		let mut scheduler = SCHEDULER.lock();
+		if !scheduler.running {
+			return;
+		}
+		scheduler.current_task().ticks += 1;
+		scheduler.slice_left = scheduler.slice_left.saturating_sub(1);
+		scheduler.slice_left == 0
+	};
+	if preempt {
+		schedule();
+	}
+}
+
+// Puts the current task to sleep until `wake` is called with its pid
+pub fn block() {
+	SCHEDULER.lock().current_task().state = TaskState::Blocked;
+	schedule();
+}
+
+pub fn wake(pid: Pid) {
+	SCHEDULER.lock().wake(pid);
+}
+
+// Stops the current task for `signal` until it gets SIGCONT, unless one is already pending.
+// The parent is woken up to report the stop if it waits with WUNTRACED.
+pub fn stop(signal: u32) {
+	{
+		let mut scheduler = SCHEDULER.lock();
+		let task = scheduler.current_task();
+		if task.signals.pending & sigmask(SIGCONT) != 0 {
+			return;
+		}
+		task.state = TaskState::Stopped;
+		task.stop_signal = signal;
+		let parent = task.ppid;
+		if let Some(parent_task) = scheduler.tasks.get_mut(&parent) {
+			parent_task.signals.queue(SIGCHLD);
+		}
+		scheduler.wake(parent);
+	}
+	schedule();
+}
+
+pub fn resume(pid: Pid) {
+	let mut scheduler = SCHEDULER.lock();
+	if let Some(task) = scheduler.tasks.get_mut(&pid) {
+		if task.state == TaskState::Stopped {
+			task.state = TaskState::Ready;
+			task.stop_signal = 0;
+			scheduler.run_queue.push_back(pid);
+		}
+	}
+}
+
+// Releases the address space and files of the current task and leaves `status` to its
+// parent, see `task::exit_status` for the encoding
+pub fn exit(status: u32) -> ! {
+	let (address_space, files, cwd) = {
+		let mut scheduler = SCHEDULER.lock();
+		let current = scheduler.current;
+		assert!(current != IDLE_PID && current != INIT_PID, "Task {} cannot exit", current);
+		let task = scheduler.current_task();
+		(task.address_space.take(), core::mem::take(&mut task.files), task.cwd.take())
+	};
+	if address_space.is_some() {
+		switch_to_kernel();
+	}
+	drop(address_space);
+	drop(files);
+	drop(cwd);
+
+	SCHEDULER.lock().exit_current(status);
+	schedule();
+	unreachable!("Exited task scheduled again");
+}
+
+// Collects a zombie child of the current task matching `target`, or with `untraced` reports
+// one that stopped. Returns its pid and wait status, or None when `nohang` is set and no
+// child has exited yet.
+pub fn wait(
+	target: WaitTarget,
+	nohang: bool,
+	untraced: bool,
+) -> Result<Option<(Pid, u32)>, Errno> {
+	loop {
+		{
+			let mut scheduler = SCHEDULER.lock();
+			let zombie = scheduler.take_zombies(target, 1).pop();
+			if let Some(zombie) = zombie {
+				drop(scheduler);
+				return Ok(Some((zombie.pid, zombie.exit_status)));
+			}
+			if untraced {
+				if let Some(stopped) = scheduler.take_stopped(target) {
+					return Ok(Some(stopped));
+				}
+			}
+			if !scheduler.has_child(target) {
+				return Err(ECHILD);
+			}
+			if nohang {
+				return Ok(None);
+			}
+			if scheduler.current_task().signals.has_deliverable() {
+				return Err(EINTR);
+			}
+			scheduler.current_task().state = TaskState::Blocked;
+		}
+		schedule();
+	}
+}
+
+pub fn current_pid() -> Pid {
+	SCHEDULER.lock().current
+}
+
+pub struct TaskInfo {
+	pub pid: Pid,
+	pub ppid: Pid,
+	pub pgid: Pid,
+	pub sid: Pid,
+	pub state: TaskState,
+	pub ticks: u32,
+	pub name: String,
+}
+
+// Snapshot of every task, sorted by pid
+pub fn task_list() -> Vec<TaskInfo> {
+	let scheduler = SCHEDULER.lock();
+	scheduler
+		.tasks
+		.values()
+		.map(|task| TaskInfo {
+			pid: task.pid,
+			ppid: task.ppid,
+			pgid: task.pgid,
+			sid: task.sid,
+			state: task.state,
+			ticks: task.ticks,
+			name: task.name.clone(),
+		})
+		.collect()
+}
+
+// Runs `f` on the current task with the scheduler locked, `f` must not block
+pub fn with_current<F: FnOnce(&mut Task) -> R, R>(f: F) -> R {
+	f(SCHEDULER.lock().current_task())
+}
+
+// Same as `with_current` for any task, None if `pid` does not exist
+pub fn with_task<F: FnOnce(&mut Task) -> R, R>(pid: Pid, f: F) -> Option<R> {
+	SCHEDULER.lock().tasks.get_mut(&pid).map(|task| f(task))
+}
+
+// Moves `pid`, the caller when 0, to the process group `pgid`, a group of its own when 0.
+// Only the caller and its children in the same session can be moved, into a new group or
+// one that already exists in that session. Session leaders stay where they are.
+pub fn setpgid(pid: Pid, pgid: Pid) -> Result<(), Errno> {
+	let mut scheduler = SCHEDULER.lock();
+	let current = scheduler.current;
+	let session = scheduler.current_task().sid;
+	let pid = if pid == 0 { current } else { pid };
+	let pgid = if pgid == 0 { pid } else { pgid };
+	let target = scheduler
+		.tasks
+		.get(&pid)
+		.filter(|task| task.pid == current || task.ppid == current)
+		.ok_or(ESRCH)?;
+	if target.sid != session || target.pid == target.sid {
+		return Err(EPERM);
+	}
+	let group_exists = scheduler
+		.tasks
+		.values()
+		.any(|task| task.pgid == pgid && task.sid == session);
+	if pgid != pid && !group_exists {
+		return Err(EPERM);
+	}
+	if let Some(task) = scheduler.tasks.get_mut(&pid) {
+		task.pgid = pgid;
+	}
+	Ok(())
+}
+
+// Makes the caller the leader of a new session and process group, returns the session id.
+// A process group leader cannot, the group would end up split across two sessions.
+pub fn setsid() -> Result<Pid, Errno> {
+	let mut scheduler = SCHEDULER.lock();
+	let current = scheduler.current;
+	if scheduler.tasks.values().any(|task| task.pgid == current) {
+		return Err(EPERM);
+	}
+	let task = scheduler.current_task();
+	task.pgid = current;
+	task.sid = current;
+	Ok(current)
+}
+
+// Frees the tasks that exited, the current task is never freed here
+pub fn reap() {
+	let dead: Vec<Box<Task>> = {
+		let mut scheduler = SCHEDULER.lock();
+		let current = scheduler.current;
+		let pids: Vec<Pid> = scheduler
+			.tasks
+			.values()
+			.filter(|task| task.state == TaskState::Dead && task.pid != current)
+			.map(|task| task.pid)
+			.collect();
+		pids.iter()
+			.filter_map(|pid| scheduler.tasks.remove(pid))
+			.collect()
+	};
+	drop(dead);
+}
+
+static TEST_COUNTER: AtomicU32 = AtomicU32::new(0);
+const TEST_ROUNDS: u32 = 3;
+
+fn test_thread() {
+	for _ in 0..TEST_ROUNDS {
+		TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
+		yield_now();
+	}
+}
+
+pub fn scheduler_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting the scheduler\n");
+
+	log!(LogLevel::Info, "Spawning two kernel threads yielding {} times\n", TEST_ROUNDS);
+	let task_count = SCHEDULER.lock().tasks.len();
+	let first = spawn("test-a", test_thread).expect("Failed to spawn thread");
+	let second = spawn("test-b", test_thread).expect("Failed to spawn thread");
+	let free_after_spawn = PMM.lock().free_frames();
+	println_srl!("\tSpawned pids {} and {}", first, second);
+	assert!(first != second && current_pid() == IDLE_PID);
+
+	while TEST_COUNTER.load(Ordering::SeqCst) < 2 * TEST_ROUNDS {
+		yield_now();
+	}
+	// Let both threads reach their exit
+	yield_now();
+	yield_now();
+
+	log!(LogLevel::Info, "Reaping the dead threads, their stacks should be freed\n");
+	reap();
+	assert!(SCHEDULER.lock().tasks.len() == task_count);
+	assert!(PMM.lock().free_frames() == free_after_spawn + (2 << KERNEL_STACK_ORDER));
+
+	log!(LogLevel::Info, "\t\tEnd of scheduler test\n");
+}
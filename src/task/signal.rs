@@ -0,0 +1,700 @@
+//! # Signals
+//!
+//! Signals follow the Linux i386 numbering and ABI. Every task keeps its pending and blocked
+//! sets and its actions in a `SignalState`. A signal is only acted upon when its task goes
+//! back to user mode: `handle_pending` runs on the way out of every trap and either applies
+//! the default action or builds a signal frame on the user stack and returns into the
+//! handler. The handler comes back through sigreturn or rt_sigreturn, which restore the
+//! registers and the signal mask saved in the frame.
+
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::scheduler::{self, WaitTarget};
+use super::task::{signaled_status, Pid, TaskState};
+use crate::exceptions::errno::{Errno, EINVAL, EPERM, ESRCH};
+use crate::exceptions::interrupts::{GeneralRegs, TrapFrame};
+use crate::gdt::{TLS_SELECTOR, USER_DATA_SELECTOR, USER_STACK_SELECTOR};
+use crate::memory::uaccess;
+use crate::tools::debug::LogLevel;
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGURG: u32 = 23;
+pub const SIGXCPU: u32 = 24;
+pub const SIGXFSZ: u32 = 25;
+pub const SIGWINCH: u32 = 28;
+pub const SIGSYS: u32 = 31;
+
+// Signals 32 to 64 are the real-time signals, they are not queued more than once
+pub const NSIG: usize = 64;
+
+pub type SigSet = u64;
+
+pub const fn sigmask(signal: u32) -> SigSet {
+	1 << (signal - 1)
+}
+
+const UNBLOCKABLE: SigSet = sigmask(SIGKILL) | sigmask(SIGSTOP);
+const STOP_SIGNALS: SigSet =
+	sigmask(SIGSTOP) | sigmask(SIGTSTP) | sigmask(SIGTTIN) | sigmask(SIGTTOU);
+
+pub const SIG_DFL: u32 = 0;
+pub const SIG_IGN: u32 = 1;
+
+pub const SA_SIGINFO: u32 = 0x4;
+pub const SA_RESTORER: u32 = 0x0400_0000;
+pub const SA_NODEFER: u32 = 0x4000_0000;
+pub const SA_RESETHAND: u32 = 0x8000_0000;
+
+// si_code of signals sent by kill and of the ones raised by the kernel
+const SI_USER: i32 = 0;
+const SI_KERNEL: i32 = 0x80;
+
+// Flags user code may change through sigreturn: CF, PF, AF, ZF, SF, TF, DF, OF, RF and AC
+const USER_EFLAGS: u32 = 0x50dd5;
+const EFLAGS_TF: u32 = 1 << 8;
+const EFLAGS_DF: u32 = 1 << 10;
+
+// popl %eax; movl $119, %eax; int $0x80
+const SIGRETURN_CODE: [u8; 8] = [0x58, 0xb8, 0x77, 0x00, 0x00, 0x00, 0xcd, 0x80];
+// movl $173, %eax; int $0x80
+const RT_SIGRETURN_CODE: [u8; 8] = [0xb8, 0xad, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x00];
+
+// struct sigaction as passed to rt_sigaction
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct SigAction {
+	pub handler: u32,
+	pub flags: u32,
+	pub restorer: u32,
+	pub mask: SigSet,
+}
+
+impl SigAction {
+	const DEFAULT: SigAction = SigAction {
+		handler: SIG_DFL,
+		flags: 0,
+		restorer: 0,
+		mask: 0,
+	};
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefaultAction {
+	Terminate,
+	Core,
+	Stop,
+	Continue,
+	Ignore,
+}
+
+fn default_action(signal: u32) -> DefaultAction {
+	match signal {
+		SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+		SIGCONT => DefaultAction::Continue,
+		SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+		SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+		| SIGSYS => DefaultAction::Core,
+		_ => DefaultAction::Terminate,
+	}
+}
+
+fn valid_signal(signal: u32) -> bool {
+	(1..=NSIG as u32).contains(&signal)
+}
+
+#[derive(Clone)]
+pub struct SignalState {
+	pub pending: SigSet,
+	pub blocked: SigSet,
+	actions: [SigAction; NSIG],
+	// Address reported in the siginfo of the last fault
+	fault_address: u32,
+}
+
+impl SignalState {
+	pub const fn new() -> SignalState {
+		SignalState {
+			pending: 0,
+			blocked: 0,
+			actions: [SigAction::DEFAULT; NSIG],
+			fault_address: 0,
+		}
+	}
+
+	// The child of fork keeps the actions and the mask but none of the pending signals
+	pub fn fork(&self) -> SignalState {
+		SignalState {
+			pending: 0,
+			..self.clone()
+		}
+	}
+
+	// execve resets the caught signals to their default action, ignored ones stay ignored
+	pub fn exec(&mut self) {
+		for action in self.actions.iter_mut() {
+			if action.handler != SIG_IGN {
+				*action = SigAction::DEFAULT;
+			}
+		}
+	}
+
+	pub fn action(&self, signal: u32) -> SigAction {
+		self.actions[signal as usize - 1]
+	}
+
+	pub fn set_action(&mut self, signal: u32, action: SigAction) -> Result<(), Errno> {
+		if !valid_signal(signal) || UNBLOCKABLE & sigmask(signal) != 0 {
+			return Err(EINVAL);
+		}
+		self.actions[signal as usize - 1] = SigAction {
+			mask: action.mask & !UNBLOCKABLE,
+			..action
+		};
+		if self.ignores(signal) {
+			self.pending &= !sigmask(signal);
+		}
+		Ok(())
+	}
+
+	pub fn set_blocked(&mut self, mask: SigSet) {
+		self.blocked = mask & !UNBLOCKABLE;
+	}
+
+	// True when a pending signal would interrupt the task
+	pub fn has_deliverable(&self) -> bool {
+		self.pending & !self.blocked != 0
+	}
+
+	// True when `signal` would not reach the task, so sending it cannot stop the task
+	pub fn blocks_or_ignores(&self, signal: u32) -> bool {
+		self.blocked & sigmask(signal) != 0 || self.ignores(signal)
+	}
+
+	fn ignores(&self, signal: u32) -> bool {
+		match self.action(signal).handler {
+			SIG_IGN => true,
+			SIG_DFL => default_action(signal) == DefaultAction::Ignore,
+			_ => false,
+		}
+	}
+
+	// Marks `signal` pending, returns false when it is discarded right away
+	pub fn queue(&mut self, signal: u32) -> bool {
+		let bit = sigmask(signal);
+		if signal == SIGCONT {
+			self.pending &= !STOP_SIGNALS;
+		} else if STOP_SIGNALS & bit != 0 {
+			self.pending &= !sigmask(SIGCONT);
+		}
+		if self.ignores(signal) && self.blocked & bit == 0 {
+			return false;
+		}
+		self.pending |= bit;
+		true
+	}
+
+	// Takes the lowest unblocked pending signal, SIGKILL and SIGSTOP go first
+	fn dequeue(&mut self) -> Option<(u32, SigAction)> {
+		let deliverable = self.pending & !self.blocked;
+		if deliverable == 0 {
+			return None;
+		}
+		let urgent = deliverable & UNBLOCKABLE;
+		let set = if urgent != 0 { urgent } else { deliverable };
+		let signal = set.trailing_zeros() + 1;
+		self.pending &= !sigmask(signal);
+		Some((signal, self.action(signal)))
+	}
+
+	// Mask and action for the duration of the handler of `signal`
+	fn enter_handler(&mut self, signal: u32, action: &SigAction) {
+		let mut mask = action.mask;
+		if action.flags & SA_NODEFER == 0 {
+			mask |= sigmask(signal);
+		}
+		self.set_blocked(self.blocked | mask);
+		if action.flags & SA_RESETHAND != 0 {
+			self.actions[signal as usize - 1] = SigAction::DEFAULT;
+		}
+	}
+}
+
+// struct sigcontext
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct SigContext {
+	gs: u32,
+	fs: u32,
+	es: u32,
+	ds: u32,
+	edi: u32,
+	esi: u32,
+	ebp: u32,
+	esp: u32,
+	ebx: u32,
+	edx: u32,
+	ecx: u32,
+	eax: u32,
+	trapno: u32,
+	err: u32,
+	eip: u32,
+	cs: u32,
+	eflags: u32,
+	esp_at_signal: u32,
+	ss: u32,
+	fpstate: u32,
+	oldmask: u32,
+	cr2: u32,
+}
+
+impl SigContext {
+	fn save(frame: &TrapFrame, mask: SigSet, fault_address: u32) -> SigContext {
+		let regs = &frame.regs;
+		SigContext {
+			gs: frame.gs,
+			fs: frame.fs,
+			es: frame.es,
+			ds: frame.ds,
+			edi: regs.edi,
+			esi: regs.esi,
+			ebp: regs.ebp,
+			esp: frame.user_esp,
+			ebx: regs.ebx,
+			edx: regs.edx,
+			ecx: regs.ecx,
+			eax: regs.eax,
+			trapno: 0,
+			err: frame.error_code,
+			eip: frame.eip,
+			cs: frame.cs,
+			eflags: frame.eflags,
+			esp_at_signal: frame.user_esp,
+			ss: frame.user_ss,
+			fpstate: 0,
+			oldmask: mask as u32,
+			cr2: fault_address,
+		}
+	}
+
+	// Puts the saved registers back in `frame`. Selectors that could fault on the way out
+	// of the kernel are refused, code and stack segments cannot be changed at all.
+	fn restore(&self, frame: &mut TrapFrame, has_tls: bool) -> Result<(), Errno> {
+		let data_selector = |selector: u32| match selector as u16 {
+			0 | USER_DATA_SELECTOR | USER_STACK_SELECTOR => Ok(selector),
+			TLS_SELECTOR if has_tls => Ok(selector),
+			_ => Err(EINVAL),
+		};
+		let (gs, fs) = (data_selector(self.gs)?, data_selector(self.fs)?);
+		let (es, ds) = (data_selector(self.es)?, data_selector(self.ds)?);
+		frame.gs = gs;
+		frame.fs = fs;
+		frame.es = es;
+		frame.ds = ds;
+		frame.regs = GeneralRegs {
+			edi: self.edi,
+			esi: self.esi,
+			ebp: self.ebp,
+			esp: frame.regs.esp,
+			ebx: self.ebx,
+			edx: self.edx,
+			ecx: self.ecx,
+			eax: self.eax,
+		};
+		frame.eip = self.eip;
+		frame.user_esp = self.esp;
+		frame.eflags = (frame.eflags & !USER_EFLAGS) | (self.eflags & USER_EFLAGS);
+		Ok(())
+	}
+}
+
+// struct sigframe, built for handlers registered without SA_SIGINFO
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SigFrame {
+	pretcode: u32,
+	signal: u32,
+	context: SigContext,
+	extramask: u32,
+	retcode: [u8; 8],
+}
+
+// siginfo_t, only the fields shared by every signal and the fault address are filled
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SigInfo {
+	signo: i32,
+	errno: i32,
+	code: i32,
+	fields: [u32; 29],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UContext {
+	flags: u32,
+	link: u32,
+	stack: [u32; 3],
+	context: SigContext,
+	sigmask: SigSet,
+}
+
+// struct rt_sigframe, built for SA_SIGINFO handlers
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+	pretcode: u32,
+	signal: u32,
+	info_address: u32,
+	context_address: u32,
+	info: SigInfo,
+	context: UContext,
+	retcode: [u8; 8],
+}
+
+// Address of a frame of `size` bytes below the user stack pointer, placed so the handler
+// finds its stack aligned as right after a call
+fn frame_address(frame: &TrapFrame, size: usize) -> u32 {
+	let address = frame.user_esp.wrapping_sub(size as u32);
+	(address.wrapping_add(4) & !15).wrapping_sub(4)
+}
+
+// Builds the frame of `action` on the user stack and points `frame` at the handler
+fn setup_frame(
+	frame: &mut TrapFrame,
+	signal: u32,
+	action: &SigAction,
+	mask: SigSet,
+	fault_address: u32,
+) -> Result<(), Errno> {
+	let context = SigContext::save(frame, mask, fault_address);
+	let pretcode = |address: u32, retcode_offset: usize| {
+		if action.flags & SA_RESTORER != 0 {
+			action.restorer
+		} else {
+			address + retcode_offset as u32
+		}
+	};
+
+	if action.flags & SA_SIGINFO != 0 {
+		let address = frame_address(frame, size_of::<RtSigFrame>());
+		let code = if fault_address != 0 { SI_KERNEL } else { SI_USER };
+		let mut fields = [0; 29];
+		fields[0] = fault_address;
+		let rt_frame = RtSigFrame {
+			pretcode: pretcode(address, offset_of!(RtSigFrame, retcode)),
+			signal,
+			info_address: address + offset_of!(RtSigFrame, info) as u32,
+			context_address: address + offset_of!(RtSigFrame, context) as u32,
+			info: SigInfo {
+				signo: signal as i32,
+				errno: 0,
+				code,
+				fields,
+			},
+			context: UContext {
+				flags: 0,
+				link: 0,
+				stack: [0; 3],
+				context,
+				sigmask: mask,
+			},
+			retcode: RT_SIGRETURN_CODE,
+		};
+		uaccess::write_user(address, &rt_frame)?;
+		frame.user_esp = address;
+		frame.regs.edx = rt_frame.info_address;
+		frame.regs.ecx = rt_frame.context_address;
+	} else {
+		let address = frame_address(frame, size_of::<SigFrame>());
+		let sig_frame = SigFrame {
+			pretcode: pretcode(address, offset_of!(SigFrame, retcode)),
+			signal,
+			context,
+			extramask: (mask >> 32) as u32,
+			retcode: SIGRETURN_CODE,
+		};
+		uaccess::write_user(address, &sig_frame)?;
+		frame.user_esp = address;
+		frame.regs.edx = 0;
+		frame.regs.ecx = 0;
+	}
+
+	frame.regs.eax = signal;
+	frame.eip = action.handler;
+	frame.ds = USER_DATA_SELECTOR as u32;
+	frame.es = USER_DATA_SELECTOR as u32;
+	frame.eflags &= !(EFLAGS_TF | EFLAGS_DF);
+	Ok(())
+}
+
+// Runs the pending signals of the current task before it goes back to user mode through
+// `frame`. Returns once a handler has been set up or nothing is left to deliver.
+pub fn handle_pending(frame: &mut TrapFrame) {
+	loop {
+		let next = scheduler::with_current(|task| {
+			let signals = &mut task.signals;
+			let (signal, action) = signals.dequeue()?;
+			let fault_address = core::mem::take(&mut signals.fault_address);
+			Some((signal, action, signals.blocked, fault_address))
+		});
+		let Some((signal, action, mask, fault_address)) = next else {
+			return;
+		};
+
+		match action.handler {
+			SIG_IGN => continue,
+			SIG_DFL => match default_action(signal) {
+				DefaultAction::Ignore | DefaultAction::Continue => continue,
+				DefaultAction::Stop => scheduler::stop(signal),
+				DefaultAction::Terminate => {
+					log!(
+						LogLevel::Info,
+						"Task {} killed by signal {}",
+						scheduler::current_pid(),
+						signal
+					);
+					scheduler::exit(signaled_status(signal, false));
+				}
+				// The register dump on the serial port stands in for a core file
+				DefaultAction::Core => {
+					log!(
+						LogLevel::Warning,
+						"Task {} killed by signal {} at {:#x} (core dumped)\n{:#x?}",
+						scheduler::current_pid(),
+						signal,
+						frame.eip,
+						frame
+					);
+					scheduler::exit(signaled_status(signal, true));
+				}
+			},
+			_ => {
+				if setup_frame(frame, signal, &action, mask, fault_address).is_err() {
+					// No room for the frame on the user stack
+					if signal == SIGSEGV {
+						scheduler::exit(signaled_status(SIGSEGV, true));
+					}
+					force(SIGSEGV, frame.user_esp);
+					continue;
+				}
+				scheduler::with_current(|task| task.signals.enter_handler(signal, &action));
+				return;
+			}
+		}
+	}
+}
+
+fn restore_frame(frame: &mut TrapFrame, rt: bool) -> Result<(), Errno> {
+	// The handler returned into the retcode or the restorer, which popped the signal
+	// number unless this is an rt frame
+	let (context, mask) = if rt {
+		let address = frame.user_esp.wrapping_sub(4);
+		let rt_frame: RtSigFrame = uaccess::read_user(address)?;
+		(rt_frame.context.context, rt_frame.context.sigmask)
+	} else {
+		let address = frame.user_esp.wrapping_sub(8);
+		let sig_frame: SigFrame = uaccess::read_user(address)?;
+		let mask = sig_frame.context.oldmask as SigSet | (sig_frame.extramask as SigSet) << 32;
+		(sig_frame.context, mask)
+	};
+
+	let has_tls = scheduler::with_current(|task| task.tls.is_some());
+	context.restore(frame, has_tls)?;
+	scheduler::with_current(|task| task.signals.set_blocked(mask));
+	Ok(())
+}
+
+// Puts back the state saved when the handler was entered, returns the value to leave in
+// eax. A broken frame kills the task with SIGSEGV.
+pub fn sigreturn(frame: &mut TrapFrame, rt: bool) -> u32 {
+	if restore_frame(frame, rt).is_err() {
+		log!(
+			LogLevel::Debug,
+			"Task {} returned from a signal handler with a broken frame",
+			scheduler::current_pid()
+		);
+		force(SIGSEGV, 0);
+	}
+	frame.regs.eax
+}
+
+// Sends `signal` to `pid`, returns whether the task is still alive. Signal 0 only checks
+// that the task exists.
+fn queue(pid: Pid, signal: u32) -> Result<bool, Errno> {
+	if signal != 0 && !valid_signal(signal) {
+		return Err(EINVAL);
+	}
+	let (alive, queued) = scheduler::with_task(pid, |task| {
+		if matches!(task.state, TaskState::Zombie | TaskState::Dead) {
+			return Ok((false, false));
+		}
+		if task.is_kernel_thread() {
+			return Err(EPERM);
+		}
+		Ok((true, signal != 0 && task.signals.queue(signal)))
+	})
+	.ok_or(ESRCH)??;
+
+	if alive && (signal == SIGCONT || signal == SIGKILL) {
+		scheduler::resume(pid);
+	}
+	if queued {
+		scheduler::wake(pid);
+	}
+	Ok(alive)
+}
+
+pub fn send(pid: Pid, signal: u32) -> Result<(), Errno> {
+	queue(pid, signal).map(|_| ())
+}
+
+// Sends `signal` to every live task of the process group `pgid`. Fails with ESRCH when the
+// group is empty and EPERM when it only holds kernel threads.
+pub fn send_group(pgid: Pid, signal: u32) -> Result<(), Errno> {
+	let mut result = Err(ESRCH);
+	for task in scheduler::task_list() {
+		if task.pgid != pgid || matches!(task.state, TaskState::Zombie | TaskState::Dead) {
+			continue;
+		}
+		match send(task.pid, signal) {
+			Ok(()) => result = Ok(()),
+			Err(EPERM) if result.is_err() => result = Err(EPERM),
+			Err(EPERM) => {}
+			Err(errno) => return Err(errno),
+		}
+	}
+	result
+}
+
+// Raises a signal for a fault of the current task. It cannot be blocked or ignored: the
+// task either handles it or dies.
+pub fn force(signal: u32, fault_address: u32) {
+	scheduler::with_current(|task| {
+		let signals = &mut task.signals;
+		let bit = sigmask(signal);
+		if signals.blocked & bit != 0 || signals.action(signal).handler == SIG_IGN {
+			signals.blocked &= !bit;
+			signals.actions[signal as usize - 1] = SigAction::DEFAULT;
+		}
+		signals.pending |= bit;
+		signals.fault_address = fault_address;
+	});
+}
+
+// Process that gets the signals typed on the keyboard, 0 when there is none
+static FOREGROUND: AtomicU32 = AtomicU32::new(0);
+
+pub fn set_foreground(pid: Option<Pid>) {
+	FOREGROUND.store(pid.unwrap_or(0), Ordering::SeqCst);
+}
+
+// Sends `signal` to the foreground process, returns false when there is none
+pub fn signal_foreground(signal: u32) -> bool {
+	let pid = FOREGROUND.load(Ordering::SeqCst);
+	if pid == 0 {
+		return false;
+	}
+	match queue(pid, signal) {
+		Ok(true) => true,
+		_ => {
+			let _ = FOREGROUND.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+			false
+		}
+	}
+}
+
+// Installs a SIGUSR1 handler that sets the first word of the data segment and clobbers ebp,
+// sends itself SIGUSR1 and checks the handler ran and ebp survived. Then divides by zero.
+const SIGNAL_CODE: [u8; 120] = [
+	0xe8, 0x00, 0x00, 0x00, 0x00, 0x5f, 0x8d, 0x87, 0x66, 0x00, 0x00, 0x00, 0x6a, 0x00, 0x6a,
+	0x00, 0x6a, 0x00, 0x6a, 0x00, 0x50, 0xb8, 0xae, 0x00, 0x00, 0x00, 0xbb, 0x0a, 0x00, 0x00,
+	0x00, 0x89, 0xe1, 0x31, 0xd2, 0xbe, 0x08, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x85, 0xc0, 0x75,
+	0x31, 0xb8, 0x14, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x89, 0xc3, 0xb9, 0x0a, 0x00, 0x00, 0x00,
+	0xbd, 0x51, 0x51, 0x00, 0x00, 0xb8, 0x25, 0x00, 0x00, 0x00, 0xcd, 0x80, 0x81, 0xfd, 0x51,
+	0x51, 0x00, 0x00, 0x75, 0x0f, 0x83, 0x3d, 0x00, 0xa0, 0x04, 0x08, 0x01, 0x75, 0x06, 0x31,
+	0xd2, 0x31, 0xc9, 0xf7, 0xf1, 0xb8, 0x01, 0x00, 0x00, 0x00, 0xbb, 0x01, 0x00, 0x00, 0x00,
+	0xcd, 0x80, 0xc7, 0x05, 0x00, 0xa0, 0x04, 0x08, 0x01, 0x00, 0x00, 0x00, 0x31, 0xed, 0xc3,
+];
+// jmp $
+const LOOP_CODE: [u8; 2] = [0xeb, 0xfe];
+
+static TEST_DONE: AtomicU32 = AtomicU32::new(0);
+
+fn state_of(pid: Pid) -> Option<TaskState> {
+	scheduler::with_task(pid, |task| task.state)
+}
+
+fn wait_for(pid: Pid) -> u32 {
+	let (child, status) = scheduler::wait(WaitTarget::Pid(pid), false, false)
+		.expect("wait failed")
+		.expect("wait returned without a child");
+	assert!(child == pid);
+	status
+}
+
+// Parent of the user programs, a kernel thread since the idle task never waits
+fn signal_test_thread() {
+	use super::elf;
+
+	log!(LogLevel::Info, "A handler should run and return, a fault should kill\n");
+	let image = elf::test_image(&SIGNAL_CODE);
+	let pid = elf::spawn("signal-test", image.into(), &["signal-test"], &[])
+		.expect("Failed to spawn the signal test");
+	let status = wait_for(pid);
+	println_srl!("\tsignal-test exited with status {:#x}", status);
+	assert!(status == signaled_status(SIGFPE, true));
+
+	log!(LogLevel::Info, "Stopping, continuing and interrupting a busy task\n");
+	let image = elf::test_image(&LOOP_CODE);
+	let pid = elf::spawn("loop", image.into(), &["loop"], &[]).expect("Failed to spawn loop");
+	assert!(send(pid, SIGSTOP) == Ok(()));
+	while state_of(pid) != Some(TaskState::Stopped) {
+		scheduler::yield_now();
+	}
+	assert!(send(pid, SIGCONT) == Ok(()));
+	assert!(state_of(pid) != Some(TaskState::Stopped));
+	set_foreground(Some(pid));
+	assert!(signal_foreground(SIGINT));
+	assert!(wait_for(pid) == signaled_status(SIGINT, false));
+	assert!(!signal_foreground(SIGINT));
+
+	log!(LogLevel::Info, "Kernel threads and bad signals should be refused\n");
+	assert!(send(scheduler::INIT_PID, SIGTERM) == Err(EPERM));
+	assert!(send(pid, SIGTERM) == Err(ESRCH));
+	assert!(send(scheduler::current_pid(), 65) == Err(EINVAL));
+
+	TEST_DONE.store(1, Ordering::SeqCst);
+}
+
+pub fn signal_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting signals\n");
+
+	scheduler::spawn("signal-test", signal_test_thread).expect("Failed to spawn thread");
+	while TEST_DONE.load(Ordering::SeqCst) == 0 {
+		scheduler::yield_now();
+	}
+
+	log!(LogLevel::Info, "\t\tEnd of signal test\n");
+}
@@ -0,0 +1,20 @@
+.global switch_context
+.text
+
+# switch_context(old_esp: *mut u32, new_esp: u32)
+# Saves the callee-saved registers on the current stack, stores the stack pointer
+# in *old_esp and resumes the task whose saved stack pointer is new_esp
+
+switch_context:
+    push %ebp
+    push %ebx
+    push %esi
+    push %edi
+    mov 20(%esp), %eax
+    mov %esp, (%eax)
+    mov 24(%esp), %esp
+    pop %edi
+    pop %esi
+    pop %ebx
+    pop %ebp
+    ret
@@ -0,0 +1,212 @@
+//! # Tasks
+//!
+//! A `Task` is a schedulable thread of execution with its own kernel stack. Kernel threads
+//! only run kernel code and borrow whatever address space is loaded, user tasks own an
+//! `AddressSpace` that is loaded every time they are scheduled.
+//!
+//! While a task is not running, its callee-saved registers and return address sit on top of
+//! its kernel stack in a `Context` and `esp` points to them.
+//!
+//! A task that exits stays around as a `Zombie` holding its wait status until its parent
+//! collects it with `waitpid`.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+use super::signal::SignalState;
+use crate::exceptions::interrupts::{GeneralRegs, TrapFrame};
+use crate::fs::dentry::Dentry;
+use crate::fs::fd::FdTable;
+use crate::gdt::TlsSegment;
+use crate::memory::address_space::AddressSpace;
+use crate::memory::kmem_managment::{HK_OFST, PMM};
+use crate::memory::page_directory::PAGE_SIZE;
+
+pub type Pid = u32;
+
+pub const KERNEL_STACK_ORDER: usize = 2;
+pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE << KERNEL_STACK_ORDER;
+
+extern "C" {
+	fn trap_return();
+}
+
+// Wait status of a task that called exit with `code`
+pub fn exit_status(code: u32) -> u32 {
+	(code & 0xff) << 8
+}
+
+// Wait status of a task killed by `signal`
+pub fn signaled_status(signal: u32, core_dumped: bool) -> u32 {
+	(signal & 0x7f) | if core_dumped { 0x80 } else { 0 }
+}
+
+// Wait status reported by waitpid with WUNTRACED for a task stopped by `signal`
+pub fn stopped_status(signal: u32) -> u32 {
+	((signal & 0xff) << 8) | 0x7f
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+	Ready,
+	Running,
+	Blocked,
+	Stopped,
+	Zombie,
+	Dead,
+}
+
+impl TaskState {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			TaskState::Ready => "ready",
+			TaskState::Running => "running",
+			TaskState::Blocked => "blocked",
+			TaskState::Stopped => "stopped",
+			TaskState::Zombie => "zombie",
+			TaskState::Dead => "dead",
+		}
+	}
+}
+
+// Layout of the registers saved by `switch_context`
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+	pub edi: u32,
+	pub esi: u32,
+	pub ebx: u32,
+	pub ebp: u32,
+	pub eip: u32,
+}
+
+pub struct Task {
+	pub pid: Pid,
+	pub ppid: Pid,
+	// Process group and session, a new task leads both until it calls setpgid or setsid
+	pub pgid: Pid,
+	pub sid: Pid,
+	pub name: String,
+	pub state: TaskState,
+	pub esp: u32,
+	pub ticks: u32,
+	pub address_space: Option<AddressSpace>,
+	pub entry: Option<fn()>,
+	// Entry point and stack pointer of a user task that has not reached ring 3 yet
+	pub user_start: Option<(u32, u32)>,
+	pub tls: Option<TlsSegment>,
+	pub exit_status: u32,
+	// Signal that stopped the task, 0 once a wait with WUNTRACED has reported it
+	pub stop_signal: u32,
+	pub signals: SignalState,
+	pub files: FdTable,
+	// Working directory, None until the task changes it, which means the root
+	pub cwd: Option<Arc<Dentry>>,
+	kernel_stack: Option<u32>,
+}
+
+impl Task {
+	// Wraps the code currently running on the boot stack
+	pub fn boot(pid: Pid, name: &str) -> Task {
+		Task {
+			pid,
+			ppid: pid,
+			pgid: pid,
+			sid: pid,
+			name: String::from(name),
+			state: TaskState::Running,
+			esp: 0,
+			ticks: 0,
+			address_space: None,
+			entry: None,
+			user_start: None,
+			tls: None,
+			exit_status: 0,
+			stop_signal: 0,
+			signals: SignalState::new(),
+			files: FdTable::new(),
+			cwd: None,
+			kernel_stack: None,
+		}
+	}
+
+	fn allocate(pid: Pid, name: &str) -> Result<Task, &'static str> {
+		let stack = PMM.lock().allocate_frames(KERNEL_STACK_ORDER)? + HK_OFST;
+		let mut task = Task {
+			pid,
+			ppid: pid,
+			pgid: pid,
+			sid: pid,
+			name: String::from(name),
+			state: TaskState::Ready,
+			esp: 0,
+			ticks: 0,
+			address_space: None,
+			entry: None,
+			user_start: None,
+			tls: None,
+			exit_status: 0,
+			stop_signal: 0,
+			signals: SignalState::new(),
+			files: FdTable::new(),
+			cwd: None,
+			kernel_stack: Some(stack),
+		};
+		task.esp = task.kernel_stack_top();
+		Ok(task)
+	}
+
+	fn push_context(&mut self, eip: u32) {
+		self.esp -= size_of::<Context>() as u32;
+		unsafe {
+			(self.esp as *mut Context).write(Context {
+				eip,
+				..Context::default()
+			});
+		}
+	}
+
+	// Creates a task whose first switch returns into `start`
+	pub fn new(pid: Pid, name: &str, start: extern "C" fn() -> !) -> Result<Task, &'static str> {
+		let mut task = Task::allocate(pid, name)?;
+		task.push_context(start as u32);
+		Ok(task)
+	}
+
+	// Creates a task whose first switch returns from the syscall that produced `frame`, with
+	// 0 in eax. The caller gives it its copy of the address space.
+	pub fn fork(pid: Pid, name: &str, frame: &TrapFrame) -> Result<Task, &'static str> {
+		let mut task = Task::allocate(pid, name)?;
+		task.esp -= size_of::<TrapFrame>() as u32;
+		unsafe {
+			(task.esp as *mut TrapFrame).write(TrapFrame {
+				regs: GeneralRegs { eax: 0, ..frame.regs },
+				..*frame
+			});
+		}
+		task.push_context(trap_return as u32);
+		Ok(task)
+	}
+
+	// Value for the TSS esp0, the boot task keeps the stack set up by the GDT
+	pub fn kernel_stack_top(&self) -> u32 {
+		match self.kernel_stack {
+			Some(stack) => stack + KERNEL_STACK_SIZE as u32,
+			None => crate::gdt::tss::boot_stack_top(),
+		}
+	}
+
+	pub fn is_kernel_thread(&self) -> bool {
+		self.address_space.is_none()
+	}
+}
+
+impl Drop for Task {
+	fn drop(&mut self) {
+		if let Some(stack) = self.kernel_stack {
+			PMM.lock()
+				.deallocate_frames(stack - HK_OFST, KERNEL_STACK_ORDER);
+		}
+	}
+}
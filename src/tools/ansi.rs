@@ -0,0 +1,180 @@
+//! # ANSI Escape Sequences
+//!
+//! Parser for the escape sequences of ECMA-48, as VT100 terminals and their successors
+//! understand them. It is fed the bytes written to a terminal one at a time and reports what
+//! they stand for once a sequence is complete: a character to print, a control character, an
+//! `ESC` sequence or a control sequence (`CSI`) with its parameters. Operating system
+//! commands and the other strings, such as the ones setting the window title, are skipped.
+
+// Parameters past this many are dropped
+const MAX_PARAMETERS: usize = 8;
+// Parameters are clamped to what VT terminals accept
+const MAX_PARAMETER: u16 = 9999;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+// CAN and SUB abort a sequence
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	Ground,
+	Escape,
+	Csi,
+	// Inside a string ended by BEL or ESC \, which is ignored
+	String,
+}
+
+// A complete control sequence: CSI, the parameters separated by `;`, then the final byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlSequence {
+	parameters: [u16; MAX_PARAMETERS],
+	count: usize,
+	// Set by a `?` or another private marker before the parameters
+	pub private: bool,
+	pub final_byte: u8,
+}
+
+impl ControlSequence {
+	const fn new() -> ControlSequence {
+		ControlSequence {
+			parameters: [0; MAX_PARAMETERS],
+			count: 0,
+			private: false,
+			final_byte: 0,
+		}
+	}
+
+	pub fn parameters(&self) -> &[u16] {
+		&self.parameters[..self.count]
+	}
+
+	// Parameter `index`, `default` when it is missing or 0
+	pub fn parameter(&self, index: usize, default: u16) -> u16 {
+		match self.parameters().get(index) {
+			Some(&parameter) if parameter != 0 => parameter,
+			_ => default,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	Print(u8),
+	Control(u8),
+	// ESC followed by this byte
+	Escape(u8),
+	Csi(ControlSequence),
+}
+
+pub struct Parser {
+	state: State,
+	// An ESC sequence with intermediate bytes, such as the charset selections, is ignored
+	intermediate: bool,
+	sequence: ControlSequence,
+}
+
+fn is_control(byte: u8) -> bool {
+	byte < 0x20 || byte == 0x7f
+}
+
+impl Parser {
+	pub const fn new() -> Parser {
+		Parser {
+			state: State::Ground,
+			intermediate: false,
+			sequence: ControlSequence::new(),
+		}
+	}
+
+	// Takes the next byte, returns what it completes. Control characters inside a sequence
+	// take effect right away, as on a VT100.
+	pub fn advance(&mut self, byte: u8) -> Option<Action> {
+		match byte {
+			ESC => {
+				self.state = State::Escape;
+				self.intermediate = false;
+				return None;
+			}
+			CAN | SUB if self.state != State::Ground => {
+				self.state = State::Ground;
+				return None;
+			}
+			_ => {}
+		}
+
+		match self.state {
+			State::Ground if is_control(byte) => Some(Action::Control(byte)),
+			State::Ground => Some(Action::Print(byte)),
+			State::Escape => match byte {
+				b'[' => {
+					self.state = State::Csi;
+					self.sequence = ControlSequence::new();
+					None
+				}
+				b']' | b'P' | b'X' | b'^' | b'_' => {
+					self.state = State::String;
+					None
+				}
+				0x20..=0x2f => {
+					self.intermediate = true;
+					None
+				}
+				_ if is_control(byte) => Some(Action::Control(byte)),
+				_ => {
+					self.state = State::Ground;
+					match self.intermediate {
+						true => None,
+						false => Some(Action::Escape(byte)),
+					}
+				}
+			},
+			State::Csi => self.advance_csi(byte),
+			State::String => {
+				if byte == BEL {
+					self.state = State::Ground;
+				}
+				None
+			}
+		}
+	}
+
+	fn advance_csi(&mut self, byte: u8) -> Option<Action> {
+		let sequence = &mut self.sequence;
+		match byte {
+			b'0'..=b'9' => {
+				if sequence.count == 0 {
+					sequence.count = 1;
+				}
+				let parameter = &mut sequence.parameters[sequence.count - 1];
+				*parameter = parameter
+					.saturating_mul(10)
+					.saturating_add((byte - b'0') as u16)
+					.min(MAX_PARAMETER);
+				None
+			}
+			b';' => {
+				// An empty parameter before the separator still counts
+				if sequence.count == 0 {
+					sequence.count = 1;
+				}
+				if sequence.count < MAX_PARAMETERS {
+					sequence.count += 1;
+				}
+				None
+			}
+			b'<'..=b'?' => {
+				sequence.private = true;
+				None
+			}
+			0x40..=0x7e => {
+				self.state = State::Ground;
+				sequence.final_byte = byte;
+				Some(Action::Csi(*sequence))
+			}
+			_ if is_control(byte) => Some(Action::Control(byte)),
+			_ => None,
+		}
+	}
+}
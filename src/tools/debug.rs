@@ -7,8 +7,8 @@
 
 use crate::tools::io::{inb, outb};
 use core::fmt;
+use crate::tools::irqlock::IrqMutex;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use crate::log;
 
 const SERIAL_PORT: u16 = 0x3f8;
@@ -27,7 +27,7 @@ pub enum LogLevel {
 }
 
 impl LogLevel {
-	pub fn as_str(self) -> &'static str {
+	pub fn as_str(&self) -> &'static str {
 		match self {
 			LogLevel::Panic => "PANIC",
 			LogLevel::Emergency => "EMERGENCY",
@@ -40,10 +40,33 @@ impl LogLevel {
 			LogLevel::Debug => "DEBUG",
 		}
 	}
+
+	pub fn is_error(&self) -> bool {
+		matches!(
+			self,
+			LogLevel::Panic
+				| LogLevel::Emergency
+				| LogLevel::Alert
+				| LogLevel::Critical
+				| LogLevel::Error
+		)
+	}
+
+	// SGR color the level is printed in, on the serial port and the log display alike
+	pub fn color(&self) -> u8 {
+		match self {
+			LogLevel::Panic | LogLevel::Emergency | LogLevel::Alert | LogLevel::Critical => 91,
+			LogLevel::Error => 31,
+			LogLevel::Warning => 33,
+			LogLevel::Notice => 36,
+			LogLevel::Info => 32,
+			LogLevel::Debug => 90,
+		}
+	}
 }
 
 lazy_static! {
-	pub static ref DEBUG: Mutex<Debug> = Mutex::new(Debug {});
+	pub static ref DEBUG: IrqMutex<Debug> = IrqMutex::new(Debug {});
 }
 
 pub struct Debug;
@@ -61,7 +84,11 @@ impl Debug {
 	}
 
 	pub fn write_string_srl(&self, s: &str) {
-		for byte in s.bytes() {
+		self.write_bytes_srl(s.as_bytes());
+	}
+
+	pub fn write_bytes_srl(&self, bytes: &[u8]) {
+		for &byte in bytes {
 			self.write_byte_srl(byte);
 			if byte == b'\n' {
 				self.write_byte_srl(b'\r');
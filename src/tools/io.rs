@@ -13,3 +13,9 @@ pub unsafe fn outb(port: u16, value: u8) {
 pub unsafe fn outw(port: u16, value: u16) {
 	asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack));
 }
+
+pub unsafe fn inw(port: u16) -> u16 {
+	let value: u16;
+	asm!("in ax, dx", out("ax") value, in("dx") port, options(nomem, nostack));
+	value
+}
@@ -0,0 +1,60 @@
+//! # Interrupt Safe Mutex
+//!
+//! A spin lock that keeps interrupts disabled while it is held. Locks taken from interrupt
+//! handlers or by the scheduler must be `IrqMutex`es: with a plain `Mutex`, a task preempted
+//! while holding the lock would leave the interrupt handler spinning forever.
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+
+use crate::exceptions::interrupts;
+
+pub struct IrqMutex<T> {
+	inner: Mutex<T>,
+}
+
+pub struct IrqMutexGuard<'a, T> {
+	guard: ManuallyDrop<MutexGuard<'a, T>>,
+	interrupts_enabled: bool,
+}
+
+impl<T> IrqMutex<T> {
+	pub const fn new(value: T) -> IrqMutex<T> {
+		IrqMutex {
+			inner: Mutex::new(value),
+		}
+	}
+
+	pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+		let interrupts_enabled = interrupts::are_enabled();
+		interrupts::disable();
+		IrqMutexGuard {
+			guard: ManuallyDrop::new(self.inner.lock()),
+			interrupts_enabled,
+		}
+	}
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.guard
+	}
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.guard
+	}
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+	fn drop(&mut self) {
+		unsafe { ManuallyDrop::drop(&mut self.guard) };
+		if self.interrupts_enabled {
+			interrupts::enable();
+		}
+	}
+}
@@ -51,6 +51,57 @@ pub fn get_rtc_date() -> (u8, u8, u8) {
 	(year, month, day)
 }
 
+pub fn get_cpuid(info_type: usize, eax: &mut usize, ebx: &mut usize, ecx: &mut usize, edx: &mut usize) {
+	unsafe {
+		core::arch::asm!(
+			"cpuid",
+			in("eax") info_type,
+			lateout("eax") *eax,
+			lateout("ebx") *ebx,
+			lateout("ecx") *ecx,
+			lateout("edx") *edx,
+			options(nostack, nomem, preserves_flags)
+		);
+	}
+}
+
+// Vendor string of CPUID leaf 0, such as "GenuineIntel"
+pub fn cpu_vendor() -> [u8; 12] {
+	let mut cpu_vendor = [0u8; 12];
+	let (mut eax, mut ebx, mut ecx, mut edx) = (0, 0, 0, 0);
+
+	get_cpuid(0, &mut eax, &mut ebx, &mut ecx, &mut edx);
+	cpu_vendor[0..4].copy_from_slice(&ebx.to_ne_bytes());
+	cpu_vendor[4..8].copy_from_slice(&edx.to_ne_bytes());
+	cpu_vendor[8..12].copy_from_slice(&ecx.to_ne_bytes());
+	cpu_vendor
+}
+
+// Brand string of the extended leaves, padded with zeros
+pub fn cpu_brand() -> [u8; 48] {
+	let mut cpu_brand = [0u8; 48];
+	let (mut eax, mut ebx, mut ecx, mut edx) = (0, 0, 0, 0);
+
+	for i in 0x80000002..=0x80000004 {
+		get_cpuid(i, &mut eax, &mut ebx, &mut ecx, &mut edx);
+		let off = (i - 0x80000002) * 16;
+		cpu_brand[off..off + 4].copy_from_slice(&eax.to_ne_bytes());
+		cpu_brand[off + 4..off + 8].copy_from_slice(&ebx.to_ne_bytes());
+		cpu_brand[off + 8..off + 12].copy_from_slice(&ecx.to_ne_bytes());
+		cpu_brand[off + 12..off + 16].copy_from_slice(&edx.to_ne_bytes());
+	}
+	cpu_brand
+}
+
+// Cycles counted since the CPU was reset
+pub fn rdtsc() -> u64 {
+	let (low, high): (u32, u32);
+	unsafe {
+		asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack));
+	}
+	(high as u64) << 32 | low as u64
+}
+
 #[inline]
 pub fn hlt() {
 	unsafe {
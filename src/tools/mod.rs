@@ -1,5 +1,8 @@
+pub mod ansi;
 pub mod debug;
 pub mod io;
 pub mod librs;
 pub mod vga;
 pub mod prompt;
+pub mod irqlock;
+pub mod ring_buffer;
@@ -0,0 +1,53 @@
+//! # Ring Buffer
+//!
+//! Fixed size byte queue for the data drivers exchange with their interrupt handlers. It
+//! never allocates, so it can live in a static and be filled from an interrupt handler.
+
+pub struct RingBuffer<const N: usize> {
+	data: [u8; N],
+	head: usize,
+	len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+	pub const fn new() -> RingBuffer<N> {
+		RingBuffer {
+			data: [0; N],
+			head: 0,
+			len: 0,
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.len == N
+	}
+
+	// Appends `byte`, false if the buffer is full
+	pub fn push(&mut self, byte: u8) -> bool {
+		if self.is_full() {
+			return false;
+		}
+		self.data[(self.head + self.len) % N] = byte;
+		self.len += 1;
+		true
+	}
+
+	pub fn pop(&mut self) -> Option<u8> {
+		if self.is_empty() {
+			return None;
+		}
+		let byte = self.data[self.head];
+		self.head = (self.head + 1) % N;
+		self.len -= 1;
+		Some(byte)
+	}
+
+	pub fn clear(&mut self) {
+		self.head = 0;
+		self.len = 0;
+	}
+}
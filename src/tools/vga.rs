@@ -1,60 +1,68 @@
+//! # VGA Text Mode
+//!
+//! Writer for the 80x25 text buffer, shared by five displays that F1 to F5 switch between.
+//! Each display is a small terminal: the bytes written to it go through the escape sequence
+//! parser of [`ansi`](crate::tools::ansi), so programs and the kernel logger can move the
+//! cursor, erase, scroll a region and pick colors with the same sequences they send to the
+//! serial terminal. The hidden displays keep their cells, cursor and colors in memory until
+//! they are shown again.
+
+use crate::debug::LogLevel;
+use crate::drivers::tty;
 use crate::exceptions::interrupts;
+use crate::tools::ansi::{Action, ControlSequence, Parser};
 use crate::tools::io::outb;
+use crate::tools::irqlock::IrqMutex;
 use crate::tools::prompt;
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
 
-const NUM_SCREENS: usize = 5;
+pub const NUM_SCREENS: usize = 5;
 const SERIAL_SCREEN: usize = 4;
-const VGA_BUFFER_SIZE: usize = VGA_COLUMNS * VGA_ROWS;
 
 const VGA_BUFF_ADDRR: usize = 0xc00b8000;
 pub const VGA_COLUMNS: usize = 80;
-const VGA_ROWS: usize = 25;
+pub const VGA_ROWS: usize = 25;
 pub const VGA_LAST_LINE: usize = VGA_ROWS - 1;
 
 const VGA_CTRL_REGISTER: u16 = 0x3d4;
 const VGA_DATA_REGISTER: u16 = 0x3d5;
 
+// ANSI colors in SGR order, the bright ones follow 8 entries later in VGA order
+const ANSI_COLORS: [ColorCode; 8] = [
+	ColorCode::Black,
+	ColorCode::Red,
+	ColorCode::Green,
+	ColorCode::Brown,
+	ColorCode::Blue,
+	ColorCode::Magenta,
+	ColorCode::Cyan,
+	ColorCode::LightGray,
+];
+const BRIGHT: u8 = 0x8;
+
+// The saved displays take 20KB, too much to be built on the boot stack by lazy_static
+static mut SCREENS: [ScreenState; NUM_SCREENS] = [
+	ScreenState::new(Color::new(ColorCode::Red, ColorCode::Yellow), VGA_LAST_LINE),
+	ScreenState::new(
+		Color::new(ColorCode::Yellow, ColorCode::Brown),
+		VGA_LAST_LINE,
+	),
+	ScreenState::new(
+		Color::new(ColorCode::Black, ColorCode::LightCyan),
+		VGA_LAST_LINE,
+	),
+	ScreenState::new(Color::new(ColorCode::Yellow, ColorCode::Red), VGA_LAST_LINE),
+	ScreenState::new(Color::new(ColorCode::LightGray, ColorCode::Black), 0),
+];
+
 lazy_static! {
-	pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+	pub static ref WRITER: IrqMutex<Writer> = IrqMutex::new(Writer {
 		column_position: 0,
-		row_position: 0,
+		row_position: VGA_LAST_LINE,
 		color: Color::new(ColorCode::Red, ColorCode::Yellow),
 		buffer: unsafe { &mut *(VGA_BUFF_ADDRR as *mut VgaBuffer) },
-		screen: [
-			ScreenState {
-				column_position: 0,
-				row_position: 0,
-				color: Color::new(ColorCode::Green, ColorCode::Black),
-				buffer: [0; VGA_BUFFER_SIZE],
-			},
-			ScreenState {
-				column_position: 0,
-				row_position: 0,
-				color: Color::new(ColorCode::Yellow, ColorCode::Brown),
-				buffer: [0; VGA_BUFFER_SIZE],
-			},
-			ScreenState {
-				column_position: 0,
-				row_position: 0,
-				color: Color::new(ColorCode::Black, ColorCode::LightCyan),
-				buffer: [0; VGA_BUFFER_SIZE],
-			},
-			ScreenState {
-				column_position: 0,
-				row_position: 0,
-				color: Color::new(ColorCode::Yellow, ColorCode::Red),
-				buffer: [0; VGA_BUFFER_SIZE],
-			},
-			ScreenState {
-				column_position: 0,
-				row_position: 0,
-				color: Color::new(ColorCode::LightGray, ColorCode::Black),
-				buffer: [0; VGA_BUFFER_SIZE],
-			},
-		],
+		screen: unsafe { &mut *core::ptr::addr_of_mut!(SCREENS) },
 		current_display: 0,
 		mode: WriteMode::Normal,
 	});
@@ -87,10 +95,30 @@ pub enum ColorCode {
 struct Color(u8);
 
 impl Color {
-	fn new(foreground: ColorCode, background: ColorCode) -> Color {
+	const fn new(foreground: ColorCode, background: ColorCode) -> Color {
 		Color(((background as u8) << 4) | (foreground as u8))
 	}
 
+	fn foreground(&self) -> u8 {
+		self.0 & 0x0f
+	}
+
+	fn background(&self) -> u8 {
+		self.0 >> 4
+	}
+
+	fn set_foreground(&mut self, foreground: u8) {
+		self.0 = (self.0 & 0xf0) | (foreground & 0x0f);
+	}
+
+	fn set_background(&mut self, background: u8) {
+		self.0 = (self.0 & 0x0f) | (background << 4);
+	}
+
+	fn swap(&mut self) {
+		self.0 = self.0.rotate_left(4);
+	}
+
 	fn increase_foreground(&mut self) {
 		let foreground = self.0 & 0x0f;
 		self.0 = ((foreground + 0x01) % 0x0f) + (self.0 & 0xf0);
@@ -109,18 +137,47 @@ struct ScreenChar {
 	color: Color,
 }
 
+impl ScreenChar {
+	const fn blank(color: Color) -> ScreenChar {
+		ScreenChar {
+			ascii_character: b' ',
+			color,
+		}
+	}
+}
+
+type Cells = [[ScreenChar; VGA_COLUMNS]; VGA_ROWS];
+
 #[repr(transparent)]
 struct VgaBuffer {
-	chars: [[ScreenChar; VGA_COLUMNS]; VGA_ROWS],
+	chars: Cells,
 }
 
-impl VgaBuffer {
-	fn read(&self, row: usize, column: usize) -> ScreenChar {
-		self.chars[row][column]
-	}
+// What escape sequences set on a display besides its cursor and color
+struct Terminal {
+	parser: Parser,
+	// Color SGR 0 and 39/49 go back to
+	default_color: Color,
+	bold: bool,
+	reverse: bool,
+	// Row, column and color of ESC 7 and CSI s
+	saved_cursor: (usize, usize, Color),
+	// Rows scrolled by line feeds, set by DECSTBM
+	scroll_top: usize,
+	scroll_bottom: usize,
+}
 
-	fn write(&mut self, character: ScreenChar, row: usize, column: usize) {
-		self.chars[row][column] = character;
+impl Terminal {
+	const fn new(color: Color, row: usize) -> Terminal {
+		Terminal {
+			parser: Parser::new(),
+			default_color: color,
+			bold: false,
+			reverse: false,
+			saved_cursor: (row, 0, color),
+			scroll_top: 0,
+			scroll_bottom: VGA_LAST_LINE,
+		}
 	}
 }
 
@@ -128,138 +185,389 @@ struct ScreenState {
 	column_position: usize,
 	row_position: usize,
 	color: Color,
-	buffer: [u8; VGA_BUFFER_SIZE],
+	cells: Cells,
+	terminal: Terminal,
 }
 
-pub struct Writer {
-	pub column_position: usize,
-	pub row_position: usize,
-	color: Color,
-	buffer: &'static mut VgaBuffer,
-	screen: [ScreenState; NUM_SCREENS],
-	pub current_display: usize,
-	mode: WriteMode,
+impl ScreenState {
+	const fn new(color: Color, row: usize) -> ScreenState {
+		ScreenState {
+			column_position: 0,
+			row_position: row,
+			color,
+			cells: [[ScreenChar::blank(color); VGA_COLUMNS]; VGA_ROWS],
+			terminal: Terminal::new(color, row),
+		}
+	}
 }
 
-pub enum WriteMode {
-	Normal,
-	Srl,
+// A display as the bytes written to it see it: the VGA buffer and the cursor of the writer
+// for the display shown, the saved state for the others
+struct Surface<'a> {
+	cells: &'a mut Cells,
+	row: &'a mut usize,
+	column: &'a mut usize,
+	color: &'a mut Color,
+	terminal: &'a mut Terminal,
 }
 
-impl Writer {
-	pub fn write_byte(&mut self, byte: u8) {
-		match self.mode {
-			WriteMode::Normal => self.write_byte_normal(byte),
-			WriteMode::Srl => self.write_byte_srl(byte),
+impl Surface<'_> {
+	fn write(&mut self, byte: u8) {
+		match self.terminal.parser.advance(byte) {
+			Some(Action::Print(byte)) => self.put(convert_to_cp437(byte)),
+			Some(Action::Control(byte)) => self.control(byte),
+			Some(Action::Escape(byte)) => self.escape(byte),
+			Some(Action::Csi(sequence)) => self.control_sequence(&sequence),
+			None => {}
 		}
 	}
 
-	fn write_byte_normal(&mut self, byte: u8) {
-		if self.column_position == VGA_COLUMNS {
+	// Writes a glyph at the cursor, wrapping the line the glyph before filled
+	fn put(&mut self, glyph: u8) {
+		if *self.column >= VGA_COLUMNS {
 			self.new_line();
 		}
+		self.cells[*self.row][*self.column] = ScreenChar {
+			ascii_character: glyph,
+			color: *self.color,
+		};
+		*self.column += 1;
+	}
+
+	fn control(&mut self, byte: u8) {
 		match byte {
 			b'\n' => self.new_line(),
-			byte => {
-				self.buffer.write(
-					ScreenChar {
-						ascii_character: byte,
-						color: self.color,
-					},
-					VGA_LAST_LINE,
-					self.column_position,
-				);
-
-				self.column_position += 1;
+			b'\r' => *self.column = 0,
+			0x08 => *self.column = (*self.column).min(VGA_COLUMNS - 1).saturating_sub(1),
+			b'\t' => *self.column = ((*self.column / 8 + 1) * 8).min(VGA_COLUMNS - 1),
+			// NUL and BEL
+			0x00 | 0x07 => {}
+			// The keyboard layouts send the other ones for accented letters
+			byte => self.put(convert_to_cp437(byte)),
+		}
+	}
+
+	fn escape(&mut self, byte: u8) {
+		match byte {
+			b'7' => self.save_cursor(),
+			b'8' => self.restore_cursor(),
+			// Index, next line and reverse index
+			b'D' => self.line_feed(),
+			b'E' => self.new_line(),
+			b'M' => {
+				if *self.row == self.terminal.scroll_top {
+					self.scroll(
+						self.terminal.scroll_top,
+						self.terminal.scroll_bottom,
+						1,
+						false,
+					);
+				} else {
+					*self.row = self.row.saturating_sub(1);
+				}
 			}
+			b'c' => self.reset(),
+			_ => {}
 		}
 	}
 
-	fn write_byte_srl(&mut self, byte: u8) {
-		if self.screen[SERIAL_SCREEN].column_position >= VGA_COLUMNS {
-			self.new_line_srl();
+	fn control_sequence(&mut self, sequence: &ControlSequence) {
+		if sequence.private {
+			return;
 		}
+		let count = sequence.parameter(0, 1) as usize;
+		let column = (*self.column).min(VGA_COLUMNS - 1);
+		match sequence.final_byte {
+			b'A' => *self.row = self.row.saturating_sub(count),
+			b'B' => *self.row = (*self.row + count).min(VGA_LAST_LINE),
+			b'C' => *self.column = (column + count).min(VGA_COLUMNS - 1),
+			b'D' => *self.column = column.saturating_sub(count),
+			b'E' => (*self.row, *self.column) = ((*self.row + count).min(VGA_LAST_LINE), 0),
+			b'F' => (*self.row, *self.column) = (self.row.saturating_sub(count), 0),
+			b'G' | b'`' => *self.column = (count - 1).min(VGA_COLUMNS - 1),
+			b'H' | b'f' => {
+				*self.row = (count - 1).min(VGA_LAST_LINE);
+				*self.column = (sequence.parameter(1, 1) as usize - 1).min(VGA_COLUMNS - 1);
+			}
+			b'd' => *self.row = (count - 1).min(VGA_LAST_LINE),
+			b'J' => match sequence.parameter(0, 0) {
+				0 => {
+					self.erase(*self.row, column, VGA_COLUMNS);
+					self.erase_rows(*self.row + 1, VGA_ROWS);
+				}
+				1 => {
+					self.erase_rows(0, *self.row);
+					self.erase(*self.row, 0, column + 1);
+				}
+				_ => self.erase_rows(0, VGA_ROWS),
+			},
+			b'K' => match sequence.parameter(0, 0) {
+				0 => self.erase(*self.row, column, VGA_COLUMNS),
+				1 => self.erase(*self.row, 0, column + 1),
+				_ => self.erase(*self.row, 0, VGA_COLUMNS),
+			},
+			b'X' => self.erase(*self.row, column, (column + count).min(VGA_COLUMNS)),
+			b'@' | b'P' => {
+				let line = &mut self.cells[*self.row];
+				let count = count.min(VGA_COLUMNS - column);
+				if sequence.final_byte == b'@' {
+					line.copy_within(column..VGA_COLUMNS - count, column + count);
+					line[column..column + count].fill(ScreenChar::blank(*self.color));
+				} else {
+					line.copy_within(column + count..VGA_COLUMNS, column);
+					line[VGA_COLUMNS - count..].fill(ScreenChar::blank(*self.color));
+				}
+			}
+			b'L' | b'M' => {
+				let (top, bottom) = (self.terminal.scroll_top, self.terminal.scroll_bottom);
+				if (top..=bottom).contains(&*self.row) {
+					self.scroll(*self.row, bottom, count, sequence.final_byte == b'M');
+					*self.column = 0;
+				}
+			}
+			b'S' => self.scroll(
+				self.terminal.scroll_top,
+				self.terminal.scroll_bottom,
+				count,
+				true,
+			),
+			b'T' => self.scroll(
+				self.terminal.scroll_top,
+				self.terminal.scroll_bottom,
+				count,
+				false,
+			),
+			b'm' => self.select_graphic_rendition(sequence.parameters()),
+			b'r' => {
+				let top = sequence.parameter(0, 1) as usize - 1;
+				let bottom = sequence.parameter(1, VGA_ROWS as u16) as usize - 1;
+				if top < bottom && bottom <= VGA_LAST_LINE {
+					self.terminal.scroll_top = top;
+					self.terminal.scroll_bottom = bottom;
+					(*self.row, *self.column) = (0, 0);
+				}
+			}
+			b's' => self.save_cursor(),
+			b'u' => self.restore_cursor(),
+			_ => {}
+		}
+	}
 
-		match byte {
-			b'\n' => self.new_line_srl(),
-			byte => {
-				self.screen[SERIAL_SCREEN].buffer[self.screen[SERIAL_SCREEN].row_position
-					* VGA_COLUMNS + self.screen[SERIAL_SCREEN]
-					.column_position] = byte;
-				self.screen[SERIAL_SCREEN].column_position += 1;
+	fn select_graphic_rendition(&mut self, parameters: &[u16]) {
+		// CSI m is a reset
+		let parameters = if parameters.is_empty() {
+			&[0][..]
+		} else {
+			parameters
+		};
+		let default = self.terminal.default_color;
+		for &parameter in parameters {
+			match parameter {
+				0 => {
+					*self.color = default;
+					self.terminal.bold = false;
+					self.terminal.reverse = false;
+				}
+				1 => {
+					self.terminal.bold = true;
+					self.set_foreground(self.foreground() | BRIGHT);
+				}
+				22 => {
+					self.terminal.bold = false;
+					self.set_foreground(self.foreground() & !BRIGHT);
+				}
+				7 | 27 => {
+					if self.terminal.reverse != (parameter == 7) {
+						self.terminal.reverse = parameter == 7;
+						self.color.swap();
+					}
+				}
+				30..=37 => self.set_foreground(ANSI_COLORS[parameter as usize - 30] as u8),
+				39 => self.set_foreground(default.foreground()),
+				40..=47 => self.set_background(ANSI_COLORS[parameter as usize - 40] as u8),
+				49 => self.set_background(default.background()),
+				90..=97 => self.set_foreground(ANSI_COLORS[parameter as usize - 90] as u8 | BRIGHT),
+				100..=107 => {
+					self.set_background(ANSI_COLORS[parameter as usize - 100] as u8 | BRIGHT)
+				}
+				_ => {}
 			}
 		}
 	}
 
-	fn new_line_srl(&mut self) {
-		let srl_screen = &mut self.screen[SERIAL_SCREEN];
-		srl_screen.column_position = 0;
-		if srl_screen.row_position < VGA_ROWS - 1 {
-			srl_screen.row_position += 1;
+	// The colors as SGR sees them, which reverse video keeps swapped in the cells
+	fn foreground(&self) -> u8 {
+		match self.terminal.reverse {
+			true => self.color.background(),
+			false => self.color.foreground(),
+		}
+	}
+
+	// Bold shows the foreground bright
+	fn set_foreground(&mut self, mut foreground: u8) {
+		if self.terminal.bold {
+			foreground |= BRIGHT;
+		}
+		match self.terminal.reverse {
+			true => self.color.set_background(foreground),
+			false => self.color.set_foreground(foreground),
+		}
+	}
+
+	fn set_background(&mut self, background: u8) {
+		match self.terminal.reverse {
+			true => self.color.set_foreground(background),
+			false => self.color.set_background(background),
+		}
+	}
+
+	fn save_cursor(&mut self) {
+		self.terminal.saved_cursor = (*self.row, *self.column, *self.color);
+	}
+
+	fn restore_cursor(&mut self) {
+		(*self.row, *self.column, *self.color) = self.terminal.saved_cursor;
+	}
+
+	fn new_line(&mut self) {
+		*self.column = 0;
+		self.line_feed();
+	}
+
+	// Moves down a row, scrolling the region when the cursor is on its last row
+	fn line_feed(&mut self) {
+		if *self.row == self.terminal.scroll_bottom {
+			self.scroll(
+				self.terminal.scroll_top,
+				self.terminal.scroll_bottom,
+				1,
+				true,
+			);
+		} else if *self.row < VGA_LAST_LINE {
+			*self.row += 1;
+		}
+	}
+
+	// Moves rows `top` to `bottom` by `count` rows and blanks the rows left behind
+	fn scroll(&mut self, top: usize, bottom: usize, count: usize, up: bool) {
+		let count = count.min(bottom + 1 - top);
+		if up {
+			self.cells.copy_within(top + count..bottom + 1, top);
+			self.erase_rows(bottom + 1 - count, bottom + 1);
 		} else {
-			self.scroll_screen_srl();
+			self.cells.copy_within(top..bottom + 1 - count, top + count);
+			self.erase_rows(top, top + count);
+		}
+	}
+
+	// Erasing fills with the current background, as xterm does
+	fn erase(&mut self, row: usize, start: usize, end: usize) {
+		self.cells[row][start..end].fill(ScreenChar::blank(*self.color));
+	}
+
+	fn erase_rows(&mut self, start: usize, end: usize) {
+		for row in start..end {
+			self.erase(row, 0, VGA_COLUMNS);
 		}
 	}
 
-	fn scroll_screen_srl(&mut self) {
-		let srl_screen = &mut self.screen[SERIAL_SCREEN];
+	fn reset(&mut self) {
+		let default = self.terminal.default_color;
+		*self.terminal = Terminal::new(default, 0);
+		*self.color = default;
+		(*self.row, *self.column) = (0, 0);
+		self.erase_rows(0, VGA_ROWS);
+	}
+}
+
+pub struct Writer {
+	pub column_position: usize,
+	pub row_position: usize,
+	color: Color,
+	buffer: &'static mut VgaBuffer,
+	screen: &'static mut [ScreenState; NUM_SCREENS],
+	pub current_display: usize,
+	mode: WriteMode,
+}
+
+pub enum WriteMode {
+	Normal,
+	Srl,
+}
 
-		for row in 1..VGA_ROWS {
-			for col in 0..VGA_COLUMNS {
-				let character = srl_screen.buffer[row * VGA_COLUMNS + col];
-				srl_screen.buffer[(row - 1) * VGA_COLUMNS + col] = character;
+impl Writer {
+	fn surface(&mut self, display: usize) -> Surface<'_> {
+		if display == self.current_display {
+			Surface {
+				cells: &mut self.buffer.chars,
+				row: &mut self.row_position,
+				column: &mut self.column_position,
+				color: &mut self.color,
+				terminal: &mut self.screen[display].terminal,
+			}
+		} else {
+			let screen = &mut self.screen[display];
+			Surface {
+				cells: &mut screen.cells,
+				row: &mut screen.row_position,
+				column: &mut screen.column_position,
+				color: &mut screen.color,
+				terminal: &mut screen.terminal,
 			}
 		}
+	}
 
-		self.clear_line_srl(VGA_LAST_LINE);
+	pub fn write_byte(&mut self, byte: u8) {
+		let display = match self.mode {
+			WriteMode::Normal => self.current_display,
+			WriteMode::Srl => SERIAL_SCREEN,
+		};
+		self.surface(display).write(byte);
 	}
 
-	fn clear_line_srl(&mut self, row: usize) {
-		for col in 0..VGA_COLUMNS {
-			self.screen[SERIAL_SCREEN].buffer[row * VGA_COLUMNS + col] = b' ';
+	// Writes `bytes` on `display`, into its saved cells when another display is shown
+	pub fn write_display(&mut self, display: usize, bytes: &[u8]) {
+		let mut surface = self.surface(display);
+		for &byte in bytes {
+			surface.write(byte);
+		}
+		if display == self.current_display {
+			self.update_cursor(self.row_position, self.column_position);
 		}
 	}
 
 	pub fn write_string(&mut self, s: &str) {
 		for byte in s.bytes() {
-			self.write_byte(convert_to_cp437(byte));
+			self.write_byte(byte);
 		}
-		self.update_cursor(VGA_LAST_LINE, self.column_position);
+		self.update_cursor(self.row_position, self.column_position);
 	}
 
 	pub fn write_string_raw(&mut self, s: &str) {
 		let shift: u8 = 96;
+		let mut surface = self.surface(self.current_display);
 		for byte in s.bytes() {
-			self.write_byte(byte + shift);
+			surface.put(byte + shift);
 		}
-		self.update_cursor(VGA_LAST_LINE, self.column_position);
+		self.update_cursor(self.row_position, self.column_position);
 	}
 
+	// Redraws the prompt on the last line. What was typed is shown as is, without
+	// interpreting escape sequences.
 	pub fn update_line(&mut self, s: &str) {
 		let cursor = self.column_position;
+		self.row_position = VGA_LAST_LINE;
 		self.clear_row(VGA_LAST_LINE);
-		self.write_string(s);
-		self.column_position = cursor;
-	}
-
-	fn new_line(&mut self) {
-		for row in 1..VGA_ROWS {
-			for column in 0..VGA_COLUMNS {
-				let character = self.buffer.read(row, column);
-				self.buffer.write(character, row - 1, column);
-			}
+		let mut surface = self.surface(self.current_display);
+		for byte in s.bytes() {
+			surface.put(convert_to_cp437(byte));
 		}
-		self.clear_row(VGA_LAST_LINE);
+		self.update_cursor(self.row_position, self.column_position);
+		self.column_position = cursor;
 	}
 
 	fn clear_row(&mut self, row: usize) {
-		let blank = ScreenChar {
-			ascii_character: b' ',
-			color: self.color,
-		};
-		for column in 0..VGA_COLUMNS {
-			self.buffer.write(blank, row, column);
-		}
+		self.buffer.chars[row].fill(ScreenChar::blank(self.color));
 		self.column_position = 0;
 	}
 
@@ -267,7 +575,8 @@ impl Writer {
 		for row in 0..VGA_ROWS {
 			self.clear_row(row);
 		}
-		self.update_cursor(VGA_LAST_LINE, self.column_position);
+		self.row_position = VGA_LAST_LINE;
+		self.update_cursor(self.row_position, self.column_position);
 	}
 
 	pub fn hide_cursor(&self) {
@@ -289,7 +598,7 @@ impl Writer {
 			return;
 		}
 
-		let position: u16 = (row * VGA_COLUMNS + column) as u16;
+		let position: u16 = (row * VGA_COLUMNS + column.min(VGA_COLUMNS - 1)) as u16;
 
 		unsafe {
 			outb(VGA_CTRL_REGISTER, 0x0f);
@@ -305,50 +614,33 @@ impl Writer {
 		} else if i > 0 {
 			self.column_position += i as usize;
 		}
-		self.update_cursor(VGA_LAST_LINE, self.column_position);
+		self.update_cursor(self.row_position, self.column_position);
 	}
 
 	fn backup_display(&mut self) {
-		self.screen[self.current_display].column_position = self.column_position;
-		self.screen[self.current_display].color = self.color;
-		for row in 0..VGA_ROWS - 1 {
-			for column in 0..VGA_COLUMNS {
-				self.screen[self.current_display].buffer[row * VGA_COLUMNS + column] =
-					self.buffer.read(row, column).ascii_character;
-			}
-		}
+		let screen = &mut self.screen[self.current_display];
+		screen.column_position = self.column_position;
+		screen.row_position = self.row_position;
+		screen.color = self.color;
+		screen.cells = self.buffer.chars;
 	}
 
 	fn restore_display(&mut self, display: usize) {
-		self.column_position = self.screen[display].column_position;
-		self.color = self.screen[display].color;
-		for row in 0..VGA_ROWS - 1 {
-			for column in 0..VGA_COLUMNS {
-				self.buffer.write(
-					ScreenChar {
-						ascii_character: self.screen[display].buffer[row * VGA_COLUMNS + column],
-						color: self.color,
-					},
-					row,
-					column,
-				);
-			}
-		}
+		let screen = &self.screen[display];
+		self.column_position = screen.column_position;
+		self.row_position = screen.row_position;
+		self.color = screen.color;
+		self.buffer.chars = screen.cells;
 	}
 
 	fn update_display(&mut self) {
-		for row in 0..VGA_ROWS {
-			for column in 0..VGA_COLUMNS {
-				self.buffer.write(
-					ScreenChar {
-						ascii_character: self.buffer.read(row, column).ascii_character,
-						color: self.color,
-					},
-					row,
-					column,
-				);
+		for row in self.buffer.chars.iter_mut() {
+			for character in row.iter_mut() {
+				character.color = self.color;
 			}
 		}
+		// The color picked becomes the one escape sequences reset to
+		self.screen[self.current_display].terminal.default_color = self.color;
 	}
 
 	pub fn set_mode(&mut self, mode: WriteMode) {
@@ -357,16 +649,22 @@ impl Writer {
 }
 
 pub fn change_display(display: usize) {
-	if WRITER.lock().current_display == display {
+	// One lock for the switch, so a task writing to its terminal does not land in between
+	let mut writer = WRITER.lock();
+	if writer.current_display == display {
 		return;
 	}
-	WRITER.lock().backup_display();
-	WRITER.lock().restore_display(display);
-	WRITER.lock().current_display = display;
-	if display != SERIAL_SCREEN {
+	writer.backup_display();
+	writer.restore_display(display);
+	writer.current_display = display;
+	if display == SERIAL_SCREEN {
+		writer.clear_row(VGA_LAST_LINE);
+		return;
+	}
+	drop(writer);
+	if !tty::wants_input(display) {
+		// The screen of a program that reads its terminal is left as the program drew it
 		prompt::init();
-	} else {
-		WRITER.lock().clear_row(VGA_LAST_LINE);
 	}
 }
 
@@ -418,3 +716,64 @@ impl fmt::Write for Writer {
 		Ok(())
 	}
 }
+
+// Draws on display 3, hidden while the kernel boots, and resets it afterwards
+pub fn vga_test() {
+	println_srl!("\n");
+	log!(LogLevel::Info, "\t\tTesting escape sequences\n");
+	const DISPLAY: usize = 3;
+	fn write(bytes: &[u8]) {
+		WRITER.lock().write_display(DISPLAY, bytes);
+	}
+	fn cell(row: usize, column: usize) -> (u8, u8) {
+		let writer = WRITER.lock();
+		let character = writer.screen[DISPLAY].cells[row][column];
+		(character.ascii_character, character.color.0)
+	}
+	fn cursor() -> (usize, usize) {
+		let writer = WRITER.lock();
+		let screen = &writer.screen[DISPLAY];
+		(screen.row_position, screen.column_position)
+	}
+	let default = Color::new(ColorCode::Yellow, ColorCode::Red).0;
+
+	// Cursor movement and erasing
+	write(b"\x1bc\x1b[3;5Habc");
+	assert!(cursor() == (2, 7));
+	assert!(cell(2, 4) == (b'a', default));
+	write(b"\x1b[2D\x1b[K");
+	assert!((cell(2, 4).0, cell(2, 5).0) == (b'a', b' '));
+	write(b"\x1b[A\x1b[10Cz\x1b[;H\x1b[2B\x1b[G");
+	assert!(cell(1, 15).0 == b'z');
+	assert!(cursor() == (2, 0));
+	write(b"\x1b[2J");
+	assert!((cell(1, 15).0, cell(2, 4).0) == (b' ', b' '));
+
+	// Colors, bold as bright and reverse video
+	write(b"\x1b[H\x1b[31;44mr\x1b[1mb\x1b[0;7mv\x1b[m\x1b[96;100mc");
+	assert!(cell(0, 0) == (b'r', Color::new(ColorCode::Red, ColorCode::Blue).0));
+	assert!(cell(0, 1).1 == Color::new(ColorCode::LightRed, ColorCode::Blue).0);
+	assert!(cell(0, 2).1 == Color::new(ColorCode::Red, ColorCode::Yellow).0);
+	assert!(cell(0, 3).1 == Color::new(ColorCode::LightCyan, ColorCode::DarkGray).0);
+	write(b"\x1b[39;49m\x1b[2K");
+	assert!(cell(0, 3) == (b' ', default));
+
+	// Saved cursor, scroll region and the strings programs send
+	write(b"\x1b[5;1Hx\x1b7\x1b[20;1Hy\x1b8z\x1b[s\x1b[24;80H\x1b[u!");
+	assert!((cell(4, 1).0, cell(4, 2).0, cell(19, 0).0) == (b'z', b'!', b'y'));
+	write(b"\x1b[5;6r\x1b[6;1Hp\nq\x1b]0;title\x07");
+	assert!((cell(3, 0).0, cell(4, 0).0, cell(5, 0).0) == (b' ', b'p', b'q'));
+	assert!(cell(19, 0).0 == b'y');
+	assert!(cursor() == (5, 1));
+	write(b"\x1b[r\x1b[M");
+	assert!((cell(18, 0).0, cursor()) == (b'y', (0, 0)));
+
+	// Oversized parameters saturate, NUL and BEL draw nothing
+	write(b"\x1b[10;1H\x1b[100000A\x00\x07");
+	assert!(cursor() == (0, 0) && cell(0, 0).0 == b' ');
+
+	write(b"\x1bc\x1b[25;1H");
+	assert!(cursor() == (VGA_LAST_LINE, 0));
+	assert!(cell(4, 0) == (b' ', default));
+	log!(LogLevel::Info, "\t\tEnd of escape sequences test\n");
+}